@@ -38,7 +38,7 @@ fn main() {
     // Set HRTF renderer instead of default.
     context.lock()
         .unwrap()
-        .set_renderer(Renderer::HrtfRenderer(HrtfRenderer::new(hrtf)));
+        .set_renderer(Renderer::HrtfRenderer(HrtfRenderer::new(hrtf).unwrap()));
 
     // Create some sounds.
     let sound_buffer = SoundBuffer::new_generic(DataSource::from_file("examples/data/door_open.wav").unwrap()).unwrap();
@@ -72,8 +72,7 @@ fn main() {
         // thread::sleep will be called so context can actually work in background thread.
         {
             let mut context = context.lock().unwrap();
-            let sound = context.source_mut(source_handle);
-            if let SoundSource::Spatial(spatial) = sound {
+            if let Some(SoundSource::Spatial(spatial)) = context.source_mut(source_handle) {
                 let axis = Vec3::new(0.0, 1.0, 0.0);
                 let rotation_matrix = Mat4::from_quat(Quat::from_axis_angle(axis, angle.to_radians()));
                 spatial.set_position(&rotation_matrix.transform_vector(Vec3::new(0.0, 0.0, 3.0)));
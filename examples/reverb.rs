@@ -45,7 +45,7 @@ fn main() {
     // Set HRTF renderer instead of default for binaural sound.
     context.lock()
         .unwrap()
-        .set_renderer(Renderer::HrtfRenderer(HrtfRenderer::new(hrtf)));
+        .set_renderer(Renderer::HrtfRenderer(HrtfRenderer::new(hrtf).unwrap()));
 
     let base_effect = BaseEffect::default();
 
@@ -73,6 +73,7 @@ fn main() {
     context.lock()
         .unwrap()
         .effect_mut(reverb_handle)
+        .unwrap()
         .base_mut()
         .add_input(EffectInput::direct(door_sound));
 
@@ -91,6 +92,7 @@ fn main() {
     context.lock()
         .unwrap()
         .effect_mut(reverb_handle)
+        .unwrap()
         .base_mut()
         .add_input(EffectInput::direct(drop_sound_handle));
 
@@ -103,7 +105,7 @@ fn main() {
         {
             let mut context = context.lock().unwrap();
 
-            if let SoundSource::Spatial(sound) = context.source_mut(drop_sound_handle) {
+            if let Some(SoundSource::Spatial(sound)) = context.source_mut(drop_sound_handle) {
                 let axis = Vec3::new(0.0, 1.0, 0.0);
                 let rotation_matrix = Mat4::from_quat(Quat::from_axis_angle(axis, angle.to_radians()));
                 sound.set_position(&rotation_matrix.transform_vector(Vec3::new(0.0, 0.0, 1.0)));
@@ -0,0 +1,197 @@
+//! Ambisonics renderer module.
+//!
+//! # Overview
+//!
+//! [Ambisonics](https://en.wikipedia.org/wiki/Ambisonics) is a full-sphere surround technique, each spatial
+//! source is encoded into first-order B-format (W, X, Y, Z channels) using its direction relative to listener
+//! and then B-format is decoded to output channels using decode matrix. Default decode matrix emulates pair
+//! of virtual cardioid microphones pointed to the left and to the right of listener, but it can be replaced
+//! by any other matrix (see `AmbisonicRenderer::set_decode_matrix`).
+//!
+//! B-format uses following axes: X - forward (look axis of listener), Y - left (ear axis of listener),
+//! Z - up (up axis of listener). W channel is scaled by `1 / sqrt(2)`.
+//!
+//! # Usage
+//!
+//! ```no_run
+//! use rg3d_sound::context::Context;
+//! use rg3d_sound::ambisonics::AmbisonicRenderer;
+//! use rg3d_sound::renderer::Render;
+//!
+//! fn use_ambisonics(context: &mut Context) {
+//!     let renderer: Box<dyn Render> = Box::new(AmbisonicRenderer::new());
+//!     context.set_renderer(renderer);
+//! }
+//! ```
+//!
+//! # Notes
+//!
+//! Since encoding and decoding are linear, each source is encoded and decoded separately, so there is
+//! no intermediate B-format buffer. Generic (non-spatial) sources are rendered exactly as default renderer
+//! does.
+
+use crate::{
+    renderer::{
+        Render,
+        render_source_default,
+        render_with_params,
+        flat_gains,
+    },
+    source::SoundSource,
+    listener::Listener,
+    context::DistanceModel,
+    math,
+};
+
+/// Decode matrix, each row contains weights of W, X, Y, Z channels for left and right output
+/// channels respectively.
+pub type DecodeMatrix = [[f32; 4]; 2];
+
+/// Encodes direction to first-order B-format gains (W, X, Y, Z). Direction is given in coordinate
+/// system of listener and should be normalized.
+pub fn encode(forward: f32, left: f32, up: f32) -> [f32; 4] {
+    [std::f32::consts::FRAC_1_SQRT_2, forward, left, up]
+}
+
+/// Makes decode matrix of two virtual microphones in horizontal plane. `angle` (in radians) is
+/// angle between look axis of listener and each microphone (left is at `+angle`, right is at `-angle`).
+/// `pattern` defines polar pattern of microphones: 0.0 - figure-of-eight, 0.5 - cardioid,
+/// 1.0 - omnidirectional.
+pub fn virtual_microphones(angle: f32, pattern: f32) -> DecodeMatrix {
+    let pattern = pattern.clamp(0.0, 1.0);
+    let w = pattern * std::f32::consts::SQRT_2;
+    let directivity = 1.0 - pattern;
+    let (sin, cos) = angle.sin_cos();
+    [
+        [w, directivity * cos, directivity * sin, 0.0],
+        [w, directivity * cos, -directivity * sin, 0.0],
+    ]
+}
+
+/// See module docs.
+pub struct AmbisonicRenderer {
+    decode_matrix: DecodeMatrix,
+}
+
+impl Default for AmbisonicRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AmbisonicRenderer {
+    /// Creates new renderer with decode matrix of two cardioid microphones pointed to the left
+    /// and to the right of listener.
+    pub fn new() -> Self {
+        Self {
+            decode_matrix: virtual_microphones(std::f32::consts::FRAC_PI_2, 0.5),
+        }
+    }
+
+    /// Sets new decode matrix. See `virtual_microphones` for simple way of making decode matrix.
+    pub fn set_decode_matrix(&mut self, decode_matrix: DecodeMatrix) {
+        self.decode_matrix = decode_matrix;
+    }
+
+    /// Returns current decode matrix.
+    pub fn decode_matrix(&self) -> DecodeMatrix {
+        self.decode_matrix
+    }
+
+    /// Decodes B-format gains into gains of left and right channels.
+    pub fn decode(&self, b_format: [f32; 4]) -> (f32, f32) {
+        let dot = |row: &[f32; 4]| row.iter().zip(b_format.iter()).map(|(a, b)| a * b).sum::<f32>();
+        (dot(&self.decode_matrix[0]), dot(&self.decode_matrix[1]))
+    }
+}
+
+impl Render for AmbisonicRenderer {
+    fn render_source(&mut self, source: &mut SoundSource, listener: &Listener, distance_model: &DistanceModel, out_buf: &mut [(f32, f32)]) {
+        match source {
+            SoundSource::Generic(_) => render_source_default(source, listener, distance_model, out_buf),
+            SoundSource::Spatial(spatial) => {
+                let b_format = match (spatial.position() - listener.position()).normalized() {
+                    Some(dir) => encode(dir.dot(&listener.look_axis()), dir.dot(&listener.ear_axis()), dir.dot(&listener.up_axis())),
+                    // Source at the same point as listener - it has no direction, so only omnidirectional
+                    // component is left.
+                    None => encode(0.0, 0.0, 0.0),
+                };
+                let (left, right) = self.decode(b_format);
+                let gain = spatial.get_distance_gain(listener, distance_model) * spatial.generic().smoothed_gain();
+                let blend = spatial.spatial_blend();
+                let (flat_left_gain, flat_right_gain) = flat_gains(spatial.generic());
+                let left_gain = math::lerpf(flat_left_gain, gain * left, blend) * listener.effective_gain();
+                let right_gain = math::lerpf(flat_right_gain, gain * right, blend) * listener.effective_gain();
+                render_with_params(spatial.generic_mut(), left_gain, right_gain, out_buf);
+                spatial.generic_mut().last_left_gain = Some(left_gain);
+                spatial.generic_mut().last_right_gain = Some(right_gain);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rg3d_core::math::vec3::Vec3;
+    use crate::{
+        ambisonics::{AmbisonicRenderer, encode},
+        context::DistanceModel,
+        listener::Listener,
+        renderer::Render,
+        source::{
+            SoundSource,
+            Status,
+            generic::GenericSourceBuilder,
+            spatial::SpatialSourceBuilder,
+        },
+        test_support,
+    };
+
+    fn render_at(position: Vec3) -> (f32, f32) {
+        let mut listener = Listener::new();
+        // Look along +Z, right is +X.
+        listener.set_orientation_lh(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0));
+        let generic = GenericSourceBuilder::new(test_support::make_buffer(vec![1.0; 4096], 44100))
+            .with_status(Status::Playing)
+            .build()
+            .unwrap();
+        let mut source = SoundSource::Spatial(SpatialSourceBuilder::new(generic)
+            .with_position(position)
+            .build());
+        let mut buf = vec![(0.0, 0.0); 512];
+        source.generic_mut().render(buf.len());
+        AmbisonicRenderer::new().render_source(&mut source, &listener, &DistanceModel::None, &mut buf);
+        buf[256]
+    }
+
+    #[test]
+    fn decode_places_directions_correctly() {
+        let renderer = AmbisonicRenderer::new();
+
+        let (left, right) = renderer.decode(encode(0.0, 1.0, 0.0));
+        assert!((left - 1.0).abs() < 1.0e-5);
+        assert!(right.abs() < 1.0e-5);
+
+        let (left, right) = renderer.decode(encode(0.0, -1.0, 0.0));
+        assert!(left.abs() < 1.0e-5);
+        assert!((right - 1.0).abs() < 1.0e-5);
+
+        let (left, right) = renderer.decode(encode(1.0, 0.0, 0.0));
+        assert!((left - right).abs() < 1.0e-5);
+        assert!((left - 0.5).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn source_at_right_is_decoded_to_right_channel() {
+        let (left, right) = render_at(Vec3::new(1.0, 0.0, 0.0));
+        assert!(right > 0.9, "right = {}", right);
+        assert!(left.abs() < 1.0e-3, "left = {}", left);
+
+        let (left, right) = render_at(Vec3::new(-1.0, 0.0, 0.0));
+        assert!(left > 0.9, "left = {}", left);
+        assert!(right.abs() < 1.0e-3, "right = {}", right);
+
+        let (left, right) = render_at(Vec3::new(0.0, 0.0, 1.0));
+        assert!((left - right).abs() < 1.0e-3);
+    }
+}
@@ -0,0 +1,268 @@
+//! Automation module.
+//!
+//! # Overview
+//!
+//! Automation allows you to smoothly change parameter of source or effect from its current value
+//! to some other value over given period of time. Typical example is to fade reverb wet part over
+//! two seconds when player enters a cave. Automations are stepped by context on each render pass,
+//! so you don't need to write per-frame interpolation by yourself.
+//!
+//! # Usage
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use rg3d_sound::context::Context;
+//! use rg3d_sound::automation::{AutomationTarget, AutomationCurve};
+//! use rg3d_sound::pool::Handle;
+//! use rg3d_sound::effects::Effect;
+//!
+//! fn fade_in_reverb(context: &mut Context, reverb: Handle<Effect>) {
+//!     context.automate(AutomationTarget::ReverbWet(reverb), 1.0, Duration::from_secs(2), AutomationCurve::EaseInOut);
+//! }
+//! ```
+
+use std::time::Duration;
+use rg3d_core::{
+    pool::Handle,
+    math,
+};
+use crate::{
+    source::SoundSource,
+    effects::{
+        Effect,
+        EffectTrait,
+    },
+};
+
+/// Parameter that can be automated.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum AutomationTarget {
+    /// Master gain of context.
+    MasterGain,
+    /// Gain of a source.
+    SourceGain(Handle<SoundSource>),
+    /// Pitch of a source.
+    SourcePitch(Handle<SoundSource>),
+    /// Gain of an effect.
+    EffectGain(Handle<Effect>),
+    /// Dry part of reverb effect. Does nothing for other kinds of effects.
+    ReverbDry(Handle<Effect>),
+    /// Wet part of reverb effect. Does nothing for other kinds of effects.
+    ReverbWet(Handle<Effect>),
+    /// Decay time (in seconds) of reverb effect. Does nothing for other kinds of effects.
+    ReverbDecayTime(Handle<Effect>),
+    /// Stereo width of reverb effect. Does nothing for other kinds of effects.
+    ReverbStereoWidth(Handle<Effect>),
+    /// Normalized cutoff frequency of lowpass filter of reverb effect. Does nothing for other kinds
+    /// of effects.
+    ReverbFc(Handle<Effect>),
+    /// Scale of delays of early reflections of reverb effect. Does nothing for other kinds of
+    /// effects.
+    ReverbRoomSize(Handle<Effect>),
+    /// Scale of gains of early reflections of reverb effect. Does nothing for other kinds of
+    /// effects.
+    ReverbReflectionsGain(Handle<Effect>),
+    /// Threshold (in decibels) of noise gate effect. Does nothing for other kinds of effects.
+    NoiseGateThresholdDb(Handle<Effect>),
+    /// Attack time (in seconds) of noise gate effect. Does nothing for other kinds of effects.
+    NoiseGateAttack(Handle<Effect>),
+    /// Release time (in seconds) of noise gate effect. Does nothing for other kinds of effects.
+    NoiseGateRelease(Handle<Effect>),
+    /// Hold time (in seconds) of noise gate effect. Does nothing for other kinds of effects.
+    NoiseGateHold(Handle<Effect>),
+    /// Bit depth of bit crusher effect, value is rounded to nearest integer. Does nothing for other
+    /// kinds of effects.
+    BitCrusherBitDepth(Handle<Effect>),
+    /// Downsample factor of bit crusher effect, value is rounded to nearest integer. Does nothing
+    /// for other kinds of effects.
+    BitCrusherDownsample(Handle<Effect>),
+    /// Drive of distortion effect. Does nothing for other kinds of effects.
+    DistortionDrive(Handle<Effect>),
+    /// Output gain of distortion effect. Does nothing for other kinds of effects.
+    DistortionOutputGain(Handle<Effect>),
+    /// Mix ratio of distortion effect. Does nothing for other kinds of effects.
+    DistortionMix(Handle<Effect>),
+    /// Oversampling factor of distortion effect, value is rounded to nearest integer. Does nothing
+    /// for other kinds of effects.
+    ///
+    /// # Notes
+    ///
+    /// Change of oversampling factor re-creates oversampling filters, so it should not be
+    /// automated, set it directly instead.
+    DistortionOversampling(Handle<Effect>),
+}
+
+impl AutomationTarget {
+    /// Returns handle of effect whose parameter is automated, None for parameters of context and
+    /// sources.
+    pub(in crate) fn effect(self) -> Option<Handle<Effect>> {
+        match self {
+            AutomationTarget::MasterGain |
+            AutomationTarget::SourceGain(_) |
+            AutomationTarget::SourcePitch(_) => None,
+            AutomationTarget::EffectGain(handle) |
+            AutomationTarget::ReverbDry(handle) |
+            AutomationTarget::ReverbWet(handle) |
+            AutomationTarget::ReverbDecayTime(handle) |
+            AutomationTarget::ReverbStereoWidth(handle) |
+            AutomationTarget::ReverbFc(handle) |
+            AutomationTarget::ReverbRoomSize(handle) |
+            AutomationTarget::ReverbReflectionsGain(handle) |
+            AutomationTarget::NoiseGateThresholdDb(handle) |
+            AutomationTarget::NoiseGateAttack(handle) |
+            AutomationTarget::NoiseGateRelease(handle) |
+            AutomationTarget::NoiseGateHold(handle) |
+            AutomationTarget::BitCrusherBitDepth(handle) |
+            AutomationTarget::BitCrusherDownsample(handle) |
+            AutomationTarget::DistortionDrive(handle) |
+            AutomationTarget::DistortionOutputGain(handle) |
+            AutomationTarget::DistortionMix(handle) |
+            AutomationTarget::DistortionOversampling(handle) => Some(handle),
+        }
+    }
+}
+
+/// Returns value of parameter of given effect (which must be the effect of target, see
+/// `AutomationTarget::effect`). None if target is not a parameter of effect of this kind.
+pub(in crate) fn effect_parameter(effect: &Effect, target: AutomationTarget) -> Option<f32> {
+    Some(match (target, effect) {
+        (AutomationTarget::EffectGain(_), effect) => effect.base().gain(),
+        (AutomationTarget::ReverbDry(_), Effect::Reverb(reverb)) => reverb.get_dry(),
+        (AutomationTarget::ReverbWet(_), Effect::Reverb(reverb)) => reverb.get_wet(),
+        (AutomationTarget::ReverbDecayTime(_), Effect::Reverb(reverb)) => reverb.decay_time().as_secs_f32(),
+        (AutomationTarget::ReverbStereoWidth(_), Effect::Reverb(reverb)) => reverb.stereo_width(),
+        (AutomationTarget::ReverbFc(_), Effect::Reverb(reverb)) => reverb.fc(),
+        (AutomationTarget::ReverbRoomSize(_), Effect::Reverb(reverb)) => reverb.room_size(),
+        (AutomationTarget::ReverbReflectionsGain(_), Effect::Reverb(reverb)) => reverb.reflections_gain(),
+        (AutomationTarget::NoiseGateThresholdDb(_), Effect::NoiseGate(gate)) => gate.threshold_db(),
+        (AutomationTarget::NoiseGateAttack(_), Effect::NoiseGate(gate)) => gate.attack().as_secs_f32(),
+        (AutomationTarget::NoiseGateRelease(_), Effect::NoiseGate(gate)) => gate.release().as_secs_f32(),
+        (AutomationTarget::NoiseGateHold(_), Effect::NoiseGate(gate)) => gate.hold().as_secs_f32(),
+        (AutomationTarget::BitCrusherBitDepth(_), Effect::BitCrusher(crusher)) => crusher.bit_depth() as f32,
+        (AutomationTarget::BitCrusherDownsample(_), Effect::BitCrusher(crusher)) => crusher.downsample() as f32,
+        (AutomationTarget::DistortionDrive(_), Effect::Distortion(distortion)) => distortion.drive(),
+        (AutomationTarget::DistortionOutputGain(_), Effect::Distortion(distortion)) => distortion.output_gain(),
+        (AutomationTarget::DistortionMix(_), Effect::Distortion(distortion)) => distortion.mix(),
+        (AutomationTarget::DistortionOversampling(_), Effect::Distortion(distortion)) => distortion.oversampling() as f32,
+        _ => return None,
+    })
+}
+
+/// Sets value of parameter of given effect (which must be the effect of target, see
+/// `AutomationTarget::effect`). Returns false if target is not a parameter of effect of this kind.
+/// Integer parameters are rounded, cast saturates and NaN gives zero.
+pub(in crate) fn set_effect_parameter(effect: &mut Effect, target: AutomationTarget, value: f32) -> bool {
+    match (target, effect) {
+        (AutomationTarget::EffectGain(_), effect) => effect.base_mut().set_gain(value),
+        (AutomationTarget::ReverbDry(_), Effect::Reverb(reverb)) => reverb.set_dry(value),
+        (AutomationTarget::ReverbWet(_), Effect::Reverb(reverb)) => reverb.set_wet(value),
+        (AutomationTarget::ReverbDecayTime(_), Effect::Reverb(reverb)) => reverb.set_decay_time(seconds_to_duration(value)),
+        (AutomationTarget::ReverbStereoWidth(_), Effect::Reverb(reverb)) => reverb.set_stereo_width(value),
+        (AutomationTarget::ReverbFc(_), Effect::Reverb(reverb)) => reverb.set_fc(value),
+        (AutomationTarget::ReverbRoomSize(_), Effect::Reverb(reverb)) => reverb.set_room_size(value),
+        (AutomationTarget::ReverbReflectionsGain(_), Effect::Reverb(reverb)) => reverb.set_reflections_gain(value),
+        (AutomationTarget::NoiseGateThresholdDb(_), Effect::NoiseGate(gate)) => gate.set_threshold_db(value),
+        (AutomationTarget::NoiseGateAttack(_), Effect::NoiseGate(gate)) => gate.set_attack(seconds_to_duration(value)),
+        (AutomationTarget::NoiseGateRelease(_), Effect::NoiseGate(gate)) => gate.set_release(seconds_to_duration(value)),
+        (AutomationTarget::NoiseGateHold(_), Effect::NoiseGate(gate)) => gate.set_hold(seconds_to_duration(value)),
+        (AutomationTarget::BitCrusherBitDepth(_), Effect::BitCrusher(crusher)) => crusher.set_bit_depth(value.round() as u32),
+        (AutomationTarget::BitCrusherDownsample(_), Effect::BitCrusher(crusher)) => crusher.set_downsample(value.round() as u32),
+        (AutomationTarget::DistortionDrive(_), Effect::Distortion(distortion)) => distortion.set_drive(value),
+        (AutomationTarget::DistortionOutputGain(_), Effect::Distortion(distortion)) => distortion.set_output_gain(value),
+        (AutomationTarget::DistortionMix(_), Effect::Distortion(distortion)) => distortion.set_mix(value),
+        (AutomationTarget::DistortionOversampling(_), Effect::Distortion(distortion)) => distortion.set_oversampling(value.round() as u32),
+        _ => return false,
+    }
+    true
+}
+
+/// Defines how value changes over time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum AutomationCurve {
+    /// Value changes with constant speed.
+    #[default]
+    Linear,
+    /// Value changes slowly at the beginning and faster at the end.
+    EaseIn,
+    /// Value changes fast at the beginning and slower at the end.
+    EaseOut,
+    /// Value changes slowly at the beginning and at the end (smoothstep).
+    EaseInOut,
+}
+
+impl AutomationCurve {
+    /// Maps linear time `t` in [0; 1] range to interpolation coefficient in [0; 1] range.
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            AutomationCurve::Linear => t,
+            AutomationCurve::EaseIn => t * t,
+            AutomationCurve::EaseOut => t * (2.0 - t),
+            AutomationCurve::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// Converts time in seconds to duration. Negative and NaN values give zero duration, too large
+/// values (including infinity) are clamped to one hour, so any value that came from automation or
+/// command is safe to use.
+pub(in crate) fn seconds_to_duration(seconds: f32) -> Duration {
+    const MAX_SECONDS: f32 = 3600.0;
+    if seconds.is_nan() {
+        Duration::from_secs(0)
+    } else {
+        Duration::from_secs_f32(seconds.clamp(0.0, MAX_SECONDS))
+    }
+}
+
+pub(in crate) struct Automation {
+    pub target: AutomationTarget,
+    from: f32,
+    to: f32,
+    duration: Duration,
+    elapsed: Duration,
+    curve: AutomationCurve,
+}
+
+impl Automation {
+    pub fn new(target: AutomationTarget, from: f32, to: f32, duration: Duration, curve: AutomationCurve) -> Self {
+        Self {
+            target,
+            from,
+            to,
+            duration,
+            elapsed: Duration::from_secs(0),
+            curve,
+        }
+    }
+
+    /// Advances automation by given time and returns new value of parameter.
+    pub fn step(&mut self, dt: Duration) -> f32 {
+        self.elapsed += dt;
+        let t = if self.duration.as_secs_f32() > 0.0 {
+            self.elapsed.as_secs_f32() / self.duration.as_secs_f32()
+        } else {
+            1.0
+        };
+        math::lerpf(self.from, self.to, self.curve.apply(t))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::automation::seconds_to_duration;
+
+    #[test]
+    fn seconds_are_clamped_to_finite_duration() {
+        assert_eq!(seconds_to_duration(1.5), Duration::from_millis(1500));
+        assert_eq!(seconds_to_duration(-1.0), Duration::from_secs(0));
+        assert_eq!(seconds_to_duration(f32::NAN), Duration::from_secs(0));
+        assert_eq!(seconds_to_duration(f32::INFINITY), Duration::from_secs(3600));
+        assert_eq!(seconds_to_duration(1.0e30), Duration::from_secs(3600));
+    }
+}
@@ -34,6 +34,54 @@ use crate::{
     decoder::Decoder,
 };
 
+/// Defines how samples of buffer are interpolated when buffer is played with sample rate different
+/// from sample rate of output device (or with pitch other than 1.0). Higher quality costs more CPU.
+///
+/// # Notes
+///
+/// Interpolation is continuous across loop points of looping sources (end of buffer is followed by its
+/// beginning) and across blocks of streaming buffers (they keep few samples of previous and next blocks).
+/// Beginning of the first playthrough and end of the last one are padded with silence. The only exception
+/// is end of looping streaming buffer - beginning of the stream is not decoded yet, so it is padded with
+/// silence too.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum ResampleQuality {
+    /// Linear interpolation between two adjacent samples. It is the cheapest one, but has noticeable
+    /// aliasing and dulls high frequencies. Good enough for short sound effects.
+    Linear,
+
+    /// Cubic (Catmull-Rom) interpolation over four adjacent samples. Default one, good balance between
+    /// quality and performance.
+    #[default]
+    Cubic,
+
+    /// Windowed sinc interpolation over sixteen adjacent samples. Best quality with minimal
+    /// aliasing, but it is the most expensive one. Use it for music and other tonal content.
+    Sinc,
+}
+
+impl ResampleQuality {
+    fn id(self) -> u32 {
+        match self {
+            ResampleQuality::Linear => 0,
+            ResampleQuality::Cubic => 1,
+            ResampleQuality::Sinc => 2,
+        }
+    }
+
+    fn from_id(id: u32) -> Self {
+        match id {
+            0 => ResampleQuality::Linear,
+            2 => ResampleQuality::Sinc,
+            _ => ResampleQuality::Cubic,
+        }
+    }
+}
+
+/// Half-width (in frames) of windowed sinc kernel used by `ResampleQuality::Sinc`. It is also the
+/// maximum amount of frames that interpolation needs before and after current frame.
+pub(in crate) const SINC_HALF_WIDTH: isize = 8;
+
 /// Generic sound buffer that contains decoded samples and allows random access.
 pub struct GenericBuffer {
     /// Interleaved decoded samples (mono sounds: L..., stereo sounds: LR...)
@@ -43,6 +91,8 @@ pub struct GenericBuffer {
     pub(in crate) channel_count: usize,
     pub(in crate) sample_rate: usize,
     pub(in crate) external_source_path: Option<PathBuf>,
+    pub(in crate) normalization_gain: f32,
+    pub(in crate) resample_quality: ResampleQuality,
 }
 
 impl Default for GenericBuffer {
@@ -52,6 +102,8 @@ impl Default for GenericBuffer {
             channel_count: 0,
             sample_rate: 0,
             external_source_path: None,
+            normalization_gain: 1.0,
+            resample_quality: Default::default(),
         }
     }
 }
@@ -61,6 +113,15 @@ impl Visit for GenericBuffer {
         visitor.enter_region(name)?;
 
         self.external_source_path.visit("Path", visitor)?;
+        let mut resample_quality = self.resample_quality.id();
+        if resample_quality.visit("ResampleQuality", visitor).is_ok() {
+            if visitor.is_reading() {
+                self.resample_quality = ResampleQuality::from_id(resample_quality);
+            }
+        } else if visitor.is_reading() {
+            // Older versions have no resample quality.
+            self.resample_quality = Default::default();
+        }
 
         visitor.leave_region()
     }
@@ -75,6 +136,23 @@ impl GenericBuffer {
     /// Data source with raw samples must have sample count multiple of channel count, otherwise this
     /// function will return `Err`.
     pub fn new(source: DataSource) -> Result<Self, DataSource> {
+        Self::load(source, None)
+    }
+
+    /// Same as `new`, but integer samples will be dithered using TPDF dither while converting
+    /// them to floating point. Dither noise is produced by deterministic generator initialized
+    /// with `seed`, so same data source and seed will always give exactly the same samples.
+    ///
+    /// # Notes
+    ///
+    /// Dithering is useful mostly for low-bit sources (8-bit assets) which otherwise may have
+    /// audible quantization distortion on quiet parts. Data sources with raw or float samples are
+    /// not dithered.
+    pub fn new_dithered(source: DataSource, seed: u64) -> Result<Self, DataSource> {
+        Self::load(source, Some(seed))
+    }
+
+    fn load(source: DataSource, dither_seed: Option<u64>) -> Result<Self, DataSource> {
         match source {
             DataSource::Raw { sample_rate, channel_count, samples } => {
                 if samples.len() % channel_count != 0 {
@@ -89,6 +167,8 @@ impl GenericBuffer {
                         channel_count,
                         sample_rate,
                         external_source_path: None,
+                        normalization_gain: 1.0,
+                        resample_quality: Default::default(),
                     })
                 }
             },
@@ -100,13 +180,16 @@ impl GenericBuffer {
                         None
                     };
 
-                let decoder = Decoder::new(source)?;
+                let mut decoder = Decoder::new(source)?;
+                decoder.set_dither(dither_seed);
 
                 Ok(Self {
                     sample_rate: decoder.get_sample_rate(),
                     channel_count: decoder.get_channel_count(),
                     samples: decoder.into_samples(),
                     external_source_path,
+                    normalization_gain: 1.0,
+                    resample_quality: Default::default(),
                 })
             }
         }
@@ -137,6 +220,44 @@ impl GenericBuffer {
         &mut self.samples
     }
 
+    /// Scales all samples so peak (maximum absolute sample value) of the buffer will be equal to
+    /// given target peak. Returns gain that was applied to samples. Silent buffers are left
+    /// untouched (gain will be 1.0). This is load-time transform, it can be used to balance
+    /// sounds that were recorded with very different levels.
+    pub fn normalize(&mut self, target_peak: f32) -> f32 {
+        let peak = self.samples.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        if peak <= f32::EPSILON {
+            return 1.0;
+        }
+
+        let gain = target_peak / peak;
+        for sample in self.samples.iter_mut() {
+            *sample *= gain;
+        }
+        self.normalization_gain *= gain;
+
+        gain
+    }
+
+    /// Returns total gain that was applied to samples by `normalize`. It is 1.0 for buffers that
+    /// were not normalized.
+    #[inline]
+    pub fn normalization_gain(&self) -> f32 {
+        self.normalization_gain
+    }
+
+    /// Sets quality of resampling which will be used by every source that plays this buffer. See
+    /// `ResampleQuality` docs for more info.
+    pub fn set_resample_quality(&mut self, quality: ResampleQuality) {
+        self.resample_quality = quality;
+    }
+
+    /// Returns quality of resampling of the buffer.
+    #[inline]
+    pub fn resample_quality(&self) -> ResampleQuality {
+        self.resample_quality
+    }
+
     /// Returns exact amount of channels in the buffer.
     #[inline]
     pub fn channel_count(&self) -> usize {
@@ -34,7 +34,7 @@ use std::{
 };
 use crate::buffer::{
     streaming::StreamingBuffer,
-    generic::GenericBuffer,
+    generic::{GenericBuffer, ResampleQuality},
 };
 
 pub mod generic;
@@ -53,7 +53,7 @@ pub enum DataSource {
         data: BufReader<File>,
     },
 
-    /// Data source is a memory block. Memory block must be in valid format (wav or vorbis/ogg). This variant can
+    /// Data source is a memory block. Memory block must be in valid format (wav, vorbis/ogg or mp3). This variant can
     /// be used together with virtual file system.
     Memory(Cursor<Vec<u8>>),
 
@@ -144,6 +144,38 @@ impl SoundBuffer {
         Ok(Arc::new(Mutex::new(SoundBuffer::Generic(GenericBuffer::new(data_source)?))))
     }
 
+    /// Same as `new_streaming`, but sets given resample quality, so it is used from the very first
+    /// rendered sample. See `ResampleQuality` for more info.
+    pub fn new_streaming_with_quality(data_source: DataSource, quality: ResampleQuality) -> Result<Arc<Mutex<Self>>, DataSource> {
+        let mut streaming = StreamingBuffer::new(data_source)?;
+        streaming.generic_mut().set_resample_quality(quality);
+        Ok(Arc::new(Mutex::new(SoundBuffer::Streaming(streaming))))
+    }
+
+    /// Same as `new_generic`, but sets given resample quality, so it is used from the very first
+    /// rendered sample. See `ResampleQuality` for more info.
+    pub fn new_generic_with_quality(data_source: DataSource, quality: ResampleQuality) -> Result<Arc<Mutex<Self>>, DataSource> {
+        let mut generic = GenericBuffer::new(data_source)?;
+        generic.set_resample_quality(quality);
+        Ok(Arc::new(Mutex::new(SoundBuffer::Generic(generic))))
+    }
+
+    /// Tries to create new generic sound buffer from a given data source and normalizes its samples so
+    /// peak of the buffer will be equal to `target_peak`. Applied gain can be fetched later on by
+    /// `GenericBuffer::normalization_gain`. Normalization happens only once at load time, so it costs
+    /// nothing at playback.
+    pub fn new_generic_normalized(data_source: DataSource, target_peak: f32) -> Result<Arc<Mutex<Self>>, DataSource> {
+        let mut generic = GenericBuffer::new(data_source)?;
+        generic.normalize(target_peak);
+        Ok(Arc::new(Mutex::new(SoundBuffer::Generic(generic))))
+    }
+
+    /// Tries to create new generic sound buffer from a given data source with TPDF dithering of integer
+    /// samples. See `GenericBuffer::new_dithered` for more info.
+    pub fn new_generic_dithered(data_source: DataSource, seed: u64) -> Result<Arc<Mutex<Self>>, DataSource> {
+        Ok(Arc::new(Mutex::new(SoundBuffer::Generic(GenericBuffer::new_dithered(data_source, seed)?))))
+    }
+
     /// Tries to create new streaming sound buffer from a given data source. It returns raw sound
     /// buffer that has to be wrapped into Arc<Mutex<>> for use with sound sources.
     pub fn raw_streaming(data_source: DataSource) -> Result<Self, DataSource> {
@@ -156,6 +188,40 @@ impl SoundBuffer {
         Ok(SoundBuffer::Generic(GenericBuffer::new(data_source)?))
     }
 
+    /// Replaces data of the buffer with data from given data source. Sources that use this buffer will
+    /// continue playing new data: playback position that is out of bounds of new data is clamped to its
+    /// end, sample rate and channel count of new data will be taken into account automatically at next
+    /// render pass. Useful for live editing of sounds without need to restart sources. Kind of buffer
+    /// (generic or streaming) and its resample quality are preserved. On error, buffer is left untouched
+    /// and data source is returned back.
+    ///
+    /// # Notes
+    ///
+    /// Buffer is usually wrapped in `Arc<Mutex<>>`, so this method must be called on locked buffer
+    /// which guarantees that mixer won't read samples while they're replaced.
+    pub fn replace_data(&mut self, data_source: DataSource) -> Result<(), DataSource> {
+        let resample_quality = self.generic().resample_quality();
+        match self {
+            SoundBuffer::Generic(generic) => {
+                *generic = GenericBuffer::new(data_source)?;
+            }
+            SoundBuffer::Streaming(streaming) => {
+                let mut new_streaming = StreamingBuffer::new(data_source)?;
+                // Sources that are already attached to buffer must still be counted.
+                new_streaming.use_count = streaming.use_count;
+                *streaming = new_streaming;
+            }
+        }
+        self.generic_mut().set_resample_quality(resample_quality);
+        Ok(())
+    }
+
+    /// Returns amount of channels of the buffer. See `generic` module docs of sources for info
+    /// how channels are mapped to output.
+    pub fn channel_count(&self) -> usize {
+        self.generic().channel_count()
+    }
+
     /// Returns shared reference to generic buffer for any enum variant. It is possible because
     /// streaming sound buffers are built on top of generic buffers.
     pub fn generic(&self) -> &GenericBuffer {
@@ -203,3 +269,38 @@ impl Visit for SoundBuffer {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::{SoundBuffer, DataSource};
+
+    fn load_normalized(samples: Vec<f32>, target_peak: f32) -> (f32, f32) {
+        let buffer = SoundBuffer::new_generic_normalized(DataSource::Raw {
+            sample_rate: 44100,
+            channel_count: 1,
+            samples,
+        }, target_peak).ok().unwrap();
+        let buffer = buffer.lock().unwrap();
+        let generic = buffer.generic();
+        let peak = generic.samples().iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        (peak, generic.normalization_gain())
+    }
+
+    #[test]
+    fn normalized_buffers_have_same_peak() {
+        let quiet = (0..1000).map(|i| 0.01 * (i as f32 * 0.1).sin()).collect();
+        let loud = (0..1000).map(|i| 0.9 * (i as f32 * 0.1).sin()).collect();
+        let (quiet_peak, quiet_gain) = load_normalized(quiet, 0.5);
+        let (loud_peak, loud_gain) = load_normalized(loud, 0.5);
+        assert!((quiet_peak - 0.5).abs() < 1e-5);
+        assert!((loud_peak - 0.5).abs() < 1e-5);
+        assert!(quiet_gain > 1.0 && loud_gain < 1.0);
+    }
+
+    #[test]
+    fn silent_buffer_is_not_normalized() {
+        let (peak, gain) = load_normalized(vec![0.0; 1000], 0.5);
+        assert_eq!(peak, 0.0);
+        assert_eq!(gain, 1.0);
+    }
+}
@@ -29,7 +29,7 @@
 use crate::{
     decoder::Decoder,
     buffer::{
-        generic::GenericBuffer,
+        generic::{GenericBuffer, SINC_HALF_WIDTH},
         DataSource
     },
     error::SoundError
@@ -49,6 +49,10 @@ pub struct StreamingBuffer {
     /// access.
     pub(in crate) use_count: usize,
     decoder: Decoder,
+    /// Last samples of previous block and first samples of next block, they're used to interpolate
+    /// samples near edges of current block.
+    history: Vec<f32>,
+    lookahead: Vec<f32>,
 }
 
 impl Default for StreamingBuffer {
@@ -57,13 +61,15 @@ impl Default for StreamingBuffer {
             generic: Default::default(),
             decoder: Decoder::Null,
             use_count: 0,
+            history: Vec::new(),
+            lookahead: Vec::new(),
         }
     }
 }
 
+/// Appends up to `count` decoded samples to given buffer.
 #[inline]
 fn read_samples(buffer: &mut Vec<f32>, decoder: &mut Decoder, count: usize) -> usize {
-    buffer.clear();
     for _ in 0..count {
         if let Some(sample) = decoder.next() {
             buffer.push(sample)
@@ -104,6 +110,8 @@ impl StreamingBuffer {
         let channel_count = decoder.get_channel_count();
         read_samples(&mut samples, &mut decoder, Self::STREAM_SAMPLE_COUNT * channel_count);
         debug_assert_eq!(samples.len() % channel_count, 0);
+        let mut lookahead = Vec::new();
+        read_samples(&mut lookahead, &mut decoder, SINC_HALF_WIDTH as usize * channel_count);
 
         Ok(Self {
             generic: GenericBuffer {
@@ -111,9 +119,13 @@ impl StreamingBuffer {
                 sample_rate: decoder.get_sample_rate(),
                 channel_count: decoder.get_channel_count(),
                 external_source_path,
+                normalization_gain: 1.0,
+                resample_quality: Default::default(),
             },
             use_count: 0,
             decoder,
+            history: Vec::new(),
+            lookahead,
         })
     }
 
@@ -135,17 +147,47 @@ impl StreamingBuffer {
 
     #[inline]
     pub(in crate) fn read_next_block(&mut self) {
-        read_samples(&mut self.generic.samples, &mut self.decoder, self.generic.channel_count * Self::STREAM_SAMPLE_COUNT);
+        let channel_count = self.generic.channel_count;
+        let margin = SINC_HALF_WIDTH as usize * channel_count;
+
+        // Tail of current block precedes new block.
+        let samples = &mut self.generic.samples;
+        self.history.clear();
+        self.history.extend_from_slice(&samples[samples.len() - margin.min(samples.len())..]);
+
+        // Samples that were read ahead are beginning of new block.
+        samples.clear();
+        samples.append(&mut self.lookahead);
+        let count = (channel_count * Self::STREAM_SAMPLE_COUNT).saturating_sub(samples.len());
+        read_samples(samples, &mut self.decoder, count);
+        read_samples(&mut self.lookahead, &mut self.decoder, margin);
     }
 
     #[inline]
     pub(in crate) fn rewind(&mut self) -> Result<(), SoundError> {
+        // Samples that were read ahead belong to the end of the stream.
+        self.lookahead.clear();
         self.decoder.rewind()
     }
 
+    /// Moves decoder to given position and loads block that starts at it.
     #[inline]
     pub(in crate) fn time_seek(&mut self, location: Duration) {
+        self.lookahead.clear();
         self.decoder.time_seek(location);
+        self.read_next_block();
+        // Tail of previous block is not adjacent to new position.
+        self.history.clear();
+    }
+
+    /// Returns samples that precede current block (tail of previous one).
+    pub(in crate) fn history(&self) -> &[f32] {
+        &self.history
+    }
+
+    /// Returns samples that follow current block (beginning of next one).
+    pub(in crate) fn lookahead(&self) -> &[f32] {
+        &self.lookahead
     }
 }
 
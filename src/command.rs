@@ -0,0 +1,198 @@
+//! Command queue module.
+//!
+//! # Overview
+//!
+//! Context is shared between game thread and audio thread through a mutex, so every change made from
+//! game thread contends with mixer. Command queue is a lock-free single-producer single-consumer ring
+//! buffer which allows game thread to send simple commands (change gain, position, play, stop) to
+//! context without locking it. Commands are applied by mixer in order at the beginning of each rendered
+//! block. Locking API of context is still available and should be used for bulk changes and queries.
+//!
+//! Parameters of effects can be changed through the queue too (see `Command::SetParameter`), they're
+//! applied between blocks as well, so effect never sees parameter change in the middle of processing.
+//!
+//! # Usage
+//!
+//! ```no_run
+//! use rg3d_sound::context::Context;
+//! use rg3d_sound::command::Command;
+//! use rg3d_sound::pool::Handle;
+//! use rg3d_sound::source::SoundSource;
+//! use rg3d_sound::math::vec3::Vec3;
+//! use rg3d_sound::effects::Effect;
+//! use rg3d_sound::automation::AutomationTarget;
+//!
+//! let context = Context::new().unwrap();
+//! // Sender can be taken only once, it is then moved to game thread.
+//! let mut sender = context.lock().unwrap().take_command_sender().unwrap();
+//!
+//! let source: Handle<SoundSource> = Handle::NONE;
+//! sender.send(Command::SetPosition(source, Vec3::new(1.0, 0.0, 0.0))).ok();
+//! sender.send(Command::Play(source)).ok();
+//!
+//! let reverb: Handle<Effect> = Handle::NONE;
+//! sender.send(Command::SetParameter(AutomationTarget::ReverbDecayTime(reverb), 3.0)).ok();
+//! ```
+
+use std::{
+    cell::UnsafeCell,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+use rg3d_core::{
+    pool::Handle,
+    math::vec3::Vec3,
+};
+use crate::{
+    source::SoundSource,
+    effects::{
+        Effect,
+        reverb::ReverbSendMode,
+        distortion::DistortionCurve,
+    },
+    automation::AutomationTarget,
+};
+
+/// Command that can be sent to context without locking it. Commands with invalid handles are ignored.
+#[derive(Copy, Clone)]
+pub enum Command {
+    /// Sets gain of source. See `GenericSource::set_gain`.
+    SetGain(Handle<SoundSource>, f32),
+    /// Sets position of spatial source. Ignored for generic sources. See `SpatialSource::set_position`.
+    SetPosition(Handle<SoundSource>, Vec3),
+    /// Starts playing source. See `GenericSource::play`.
+    Play(Handle<SoundSource>),
+    /// Stops source and rewinds it to beginning. See `GenericSource::stop`.
+    Stop(Handle<SoundSource>),
+    /// Sets value of parameter of source, effect or context. Any parameter that can be automated is
+    /// supported, running automation of the parameter is cancelled. See `AutomationTarget`.
+    SetParameter(AutomationTarget, f32),
+    /// Enables or disables (bypasses) effect. See `Effect::set_enabled`.
+    SetEffectEnabled(Handle<Effect>, bool),
+    /// Sets send mode of reverb effect. Ignored for other kinds of effects. See
+    /// `Reverb::set_send_mode`.
+    SetReverbSendMode(Handle<Effect>, ReverbSendMode),
+    /// Sets transfer curve of distortion effect. Ignored for other kinds of effects. See
+    /// `Distortion::set_curve`.
+    SetDistortionCurve(Handle<Effect>, DistortionCurve),
+}
+
+struct Ring {
+    slots: Box<[UnsafeCell<Option<Command>>]>,
+    // Index of next slot to read, modified only by consumer.
+    head: AtomicUsize,
+    // Index of next slot to write, modified only by producer.
+    tail: AtomicUsize,
+}
+
+// Each slot is accessed either by producer (before it publishes slot by incrementing tail) or by
+// consumer (after tail was published and before it releases slot by incrementing head), never by
+// both at the same time.
+unsafe impl Sync for Ring {}
+
+/// Sending half of command queue. There is only one sender per context, it cannot be cloned, so
+/// queue always has single producer. See module docs.
+pub struct CommandSender {
+    ring: Arc<Ring>,
+}
+
+impl CommandSender {
+    /// Pushes command to the queue. Never blocks. Returns command back if queue is full, this may
+    /// happen if commands are sent faster than mixer applies them (or context is not rendering).
+    pub fn send(&mut self, command: Command) -> Result<(), Command> {
+        let ring = &*self.ring;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        let head = ring.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == ring.slots.len() {
+            return Err(command);
+        }
+        unsafe {
+            *ring.slots[tail % ring.slots.len()].get() = Some(command);
+        }
+        ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Returns maximum amount of commands that can be in the queue at once.
+    pub fn capacity(&self) -> usize {
+        self.ring.slots.len()
+    }
+}
+
+pub(in crate) struct CommandReceiver {
+    ring: Arc<Ring>,
+}
+
+impl CommandReceiver {
+    pub(in crate) fn try_recv(&mut self) -> Option<Command> {
+        let ring = &*self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        let tail = ring.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let command = unsafe {
+            (*ring.slots[head % ring.slots.len()].get()).take()
+        };
+        ring.head.store(head.wrapping_add(1), Ordering::Release);
+        command
+    }
+}
+
+/// Creates new command queue with given capacity.
+pub(in crate) fn command_queue(capacity: usize) -> (CommandSender, CommandReceiver) {
+    let ring = Arc::new(Ring {
+        slots: (0..capacity.max(1)).map(|_| UnsafeCell::new(None)).collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (CommandSender { ring: ring.clone() }, CommandReceiver { ring })
+}
+
+#[cfg(test)]
+mod tests {
+    use rg3d_core::pool::Handle;
+    use crate::command::{Command, command_queue};
+
+    #[test]
+    fn full_queue_returns_command_back() {
+        let (mut sender, mut receiver) = command_queue(2);
+        assert_eq!(sender.capacity(), 2);
+        assert!(sender.send(Command::Play(Handle::NONE)).is_ok());
+        assert!(sender.send(Command::Play(Handle::NONE)).is_ok());
+        assert!(sender.send(Command::Play(Handle::NONE)).is_err());
+        assert!(receiver.try_recv().is_some());
+        assert!(sender.send(Command::Play(Handle::NONE)).is_ok());
+    }
+
+    #[test]
+    fn commands_are_received_in_order_across_threads() {
+        const COUNT: usize = 10000;
+        let (mut sender, mut receiver) = command_queue(16);
+        let producer = std::thread::spawn(move || {
+            for i in 0..COUNT {
+                let mut command = Command::SetGain(Handle::NONE, i as f32);
+                // Queue is tiny, so producer has to wait for consumer.
+                while let Err(rejected) = sender.send(command) {
+                    command = rejected;
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut received = 0;
+        while received < COUNT {
+            match receiver.try_recv() {
+                Some(Command::SetGain(_, gain)) => {
+                    assert_eq!(gain, received as f32);
+                    received += 1;
+                }
+                Some(_) => panic!("unexpected command"),
+                None => std::thread::yield_now(),
+            }
+        }
+        producer.join().unwrap();
+        assert!(receiver.try_recv().is_none());
+    }
+}
@@ -8,63 +8,122 @@
 use std::{
     sync::{
         Arc,
+        Weak,
         Mutex,
+        atomic::{
+            AtomicUsize,
+            AtomicU64,
+            Ordering as AtomicOrdering,
+        },
     },
     time::{
         self,
         Duration,
     },
+    cmp::Ordering,
+    collections::{
+        VecDeque,
+        vec_deque,
+    },
 };
 use crate::{
-    error::SoundError,
-    device::run_device,
+    error::{
+        ContextError,
+        SoundError,
+    },
+    buffer::SoundBuffer,
+    device::{
+        run_device,
+        run_null_device,
+        FeedCallback,
+        MixerThread,
+    },
     listener::Listener,
     source::{
         Status,
         SoundSource,
+        RenderState,
+        generic::GenericSourceBuilder,
+        spatial::SpatialSourceBuilder,
     },
     renderer::{
         Renderer,
-        render_source_default,
+        Render,
     },
     effects::{
         Effect,
+        EffectTrait,
         EffectRenderTrait,
+        reverb::{
+            ReverbPreset,
+            ReverbParams,
+        },
+    },
+    automation::{
+        Automation,
+        AutomationTarget,
+        AutomationCurve,
+        effect_parameter,
+        set_effect_parameter,
+    },
+    command::{
+        Command,
+        CommandSender,
+        CommandReceiver,
+        command_queue,
     },
     device,
+    dsp::{
+        self,
+        EnvelopeFollower,
+        filters::DcBlocker,
+        loudness::{
+            LoudnessMeter,
+            LoudnessStats,
+        },
+    },
+};
+use rustfft::{
+    num_complex::Complex,
+    num_traits::Zero,
+    FFTplanner,
 };
 use rg3d_core::{
     pool::{Pool, Handle},
     visitor::{Visit, VisitResult, Visitor},
+    math::{self, vec3::Vec3},
 };
 
+/// Custom distance attenuation function, see `DistanceModel::Custom`.
+pub type DistanceFunction = dyn Fn(f32) -> f32 + Send + Sync;
+
 /// Distance model defines how volume of sound will decay when distance to listener changes.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Clone)]
 pub enum DistanceModel {
     /// No distance attenuation at all.
     None,
 
     /// Distance will decay using following formula:
     ///
-    /// `clamped_distance = min(max(distance, radius), max_distance)`
+    /// `clamped_distance = max(distance, radius)`
     /// `attenuation = radius / (radius + rolloff_factor * (clamped_distance - radius))`
     ///
     /// where - `radius` - of source at which it has maximum volume,
-    ///         `max_distance` - distance at which decay will stop,
     ///         `rolloff_factor` - coefficient that defines how fast volume will decay
     ///
     /// # Notes
     ///
-    /// This is default distance model of context.
+    /// This is default distance model of context. For every distance model source is silent
+    /// if its distance to listener is larger than `max_distance` of source.
     InverseDistance,
 
     /// Distance will decay using following formula:
     ///
-    /// `clamped_distance = min(max(distance, radius), max_distance)`
+    /// `clamped_distance = max(distance, radius)`
     /// `attenuation = 1.0 - radius * (clamped_distance - radius) / (max_distance - radius)`
     ///
     /// where - `radius` - of source at which it has maximum volume,
-    ///         `max_distance` - distance at which decay will stop
+    ///         `max_distance` - distance at which source becomes silent
     ///
     /// # Notes
     ///
@@ -73,221 +132,2847 @@ pub enum DistanceModel {
 
     /// Distance will decay using following formula:
     ///
-    /// `clamped_distance = min(max(distance, radius), max_distance)`
+    /// `clamped_distance = max(distance, radius)`
     /// `(clamped_distance / radius) ^ (-rolloff_factor)`
     ///
     /// where - `radius` - of source at which it has maximum volume,
-    ///         `max_distance` - distance at which decay will stop,
     ///         `rolloff_factor` - coefficient that defines how fast volume will decay
     ExponentDistance,
+
+    /// Distance attenuation is calculated by user-defined function which takes raw distance between
+    /// source and listener and returns attenuation. Result is clamped to [0; 1] range. Radius and
+    /// rolloff factor of source are ignored, but `max_distance` is still respected. Useful for bespoke
+    /// rolloffs, for example stepped attenuation.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::sync::Arc;
+    /// use rg3d_sound::context::{Context, DistanceModel};
+    ///
+    /// fn set_stepped_attenuation(context: &mut Context) {
+    ///     context.set_distance_model(DistanceModel::Custom(Arc::new(|distance| {
+    ///         if distance < 10.0 { 1.0 } else if distance < 20.0 { 0.5 } else { 0.0 }
+    ///     })));
+    /// }
+    /// ```
+    Custom(Arc<DistanceFunction>),
+}
+
+impl std::fmt::Debug for DistanceModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DistanceModel::None => write!(f, "None"),
+            DistanceModel::InverseDistance => write!(f, "InverseDistance"),
+            DistanceModel::LinearDistance => write!(f, "LinearDistance"),
+            DistanceModel::ExponentDistance => write!(f, "ExponentDistance"),
+            DistanceModel::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+impl PartialEq for DistanceModel {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (DistanceModel::None, DistanceModel::None) => true,
+            (DistanceModel::InverseDistance, DistanceModel::InverseDistance) => true,
+            (DistanceModel::LinearDistance, DistanceModel::LinearDistance) => true,
+            (DistanceModel::ExponentDistance, DistanceModel::ExponentDistance) => true,
+            // Functions cannot be compared, so custom models are equal only if they share the same function.
+            (DistanceModel::Custom(a), DistanceModel::Custom(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// Clip mode defines what happens with samples of final mix that are out of [-1; 1] range.
+/// See `Context::set_clip_mode`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Default)]
+pub enum ClipMode {
+    /// Samples out of range are clamped to [-1; 1]. This is default clip mode.
+    #[default]
+    HardClip,
+
+    /// Samples above knee (0.8) are smoothly compressed towards 1.0, so loud parts of mix are
+    /// "rounded" instead of being cut off. Output never leaves [-1; 1] range. Samples below knee
+    /// are not changed.
+    Limit,
+
+    /// Samples are left as is, output tap will receive mix as is. Useful if you do your own
+    /// mastering via output tap.
+    ///
+    /// # Notes
+    ///
+    /// Output device accepts only samples in [-1; 1] range, so samples out of range still will
+    /// be clamped when they're sent to device.
+    None,
+}
+
+impl ClipMode {
+    const KNEE: f32 = 0.8;
+
+    fn id(self) -> u32 {
+        match self {
+            ClipMode::HardClip => 0,
+            ClipMode::Limit => 1,
+            ClipMode::None => 2,
+        }
+    }
+
+    fn from_id(id: u32) -> Self {
+        match id {
+            1 => ClipMode::Limit,
+            2 => ClipMode::None,
+            _ => ClipMode::HardClip,
+        }
+    }
+
+    fn apply(self, sample: f32) -> f32 {
+        match self {
+            ClipMode::HardClip => sample.clamp(-1.0, 1.0),
+            ClipMode::Limit => {
+                let magnitude = sample.abs();
+                if magnitude <= Self::KNEE {
+                    sample
+                } else {
+                    // Smoothly maps (knee; inf) to (knee; 1), first derivative is continuous at knee.
+                    let range = 1.0 - Self::KNEE;
+                    let limited = Self::KNEE + range * ((magnitude - Self::KNEE) / range).tanh();
+                    limited.copysign(sample)
+                }
+            }
+            ClipMode::None => sample,
+        }
+    }
+}
+
+/// Format of samples that are sent to output device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum SampleFormat {
+    /// Mixed samples are passed to device layer as is, device layer converts them to native format of
+    /// its backend. This is default format.
+    #[default]
+    F32,
+
+    /// Mixed samples are quantized to signed 16-bit integers (clamped to [-1; 1] and rounded) by context,
+    /// so output tap, meters and device layer see exactly the same values which backend will receive.
+    /// Use this for targets whose backend accepts only `i16` samples. See `dsp::sample_to_i16`.
+    I16,
+}
+
+/// Output tap is a callback that receives each fully-mixed block of samples right before it
+/// will be sent to output device. See `Context::set_output_tap` for more info.
+pub type OutputTap = dyn FnMut(&[(f32, f32)]) + Send;
+
+/// Underrun callback is called when mixer was unable to prepare new block of samples in time.
+/// Its argument is total amount of underruns. See `Context::set_underrun_callback` for more info.
+pub type UnderrunCallback = dyn FnMut(usize) + Send;
+
+/// Warning or event emitted by context during rendering. Warnings usually indicate misuse of the
+/// library which does not break rendering, but gives results that most likely are not what you want.
+/// Events notify about changes of state of sources that happened on mixer thread. See
+/// `Context::diagnostics` for more info.
+#[derive(Clone)]
+pub enum Diagnostic {
+    /// Spatial source has buffer with more than one channel, but HRTF renderer can process only
+    /// mono sounds, so channels are downmixed to mono and stereo image of buffer is lost. Reported
+    /// once per source. Not reported for sources with stereo spread, see `SpatialSource::set_stereo_spread`.
+    NonMonoHrtfSource {
+        /// Handle of source that has non-mono buffer.
+        source: Handle<SoundSource>,
+        /// Actual channel count of buffer.
+        channel_count: usize,
+    },
+
+    /// Source has stopped by itself: non-looping source reached end of its buffer, looping source
+    /// finished its last playthrough (see `GenericSource::set_loop_count`) or its envelope finished
+    /// release phase. Reported once each time source finishes, see `GenericSource::is_finished`.
+    /// Play-once sources are removed from context at next render, so their handles are invalid
+    /// at the time event is received.
+    SourceFinished {
+        /// Handle of finished source.
+        source: Handle<SoundSource>,
+    },
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Diagnostic::NonMonoHrtfSource { channel_count, .. } => {
+                write!(f, "spatial source has buffer with {} channels, HRTF renderer downmixes it to mono", channel_count)
+            }
+            Diagnostic::SourceFinished { .. } => write!(f, "source has finished playing"),
+        }
+    }
+}
+
+// Ducking of one effect by another one, see `Context::set_duck`.
+struct Duck {
+    trigger: Handle<Effect>,
+    target: Handle<Effect>,
+    amount: f32,
+    // Follows gate signal of trigger (1.0 - trigger is active, 0.0 - silent), so attack and release
+    // of ducking are defined by time constants of follower.
+    follower: EnvelopeFollower,
+    // Gains of target at the beginning and at the end of current block.
+    last_gain: f32,
+    gain: f32,
+}
+
+// Renderer which is faded out after switch of renderers, see `Context::crossfade_renderer`.
+struct RendererFade {
+    renderer: Renderer,
+    // Length of fade, amount of samples (per channel) that elapsed since its beginning and position
+    // of fade at the end of last rendered block (it differs from elapsed only in manual update mode).
+    length: usize,
+    elapsed: usize,
+    rendered: usize,
+    // Per-source state of faded out renderer by index of source in pool together with handle of
+    // source it belongs to. Sized once when fade begins so mixer never allocates; sources added
+    // during fade have no state and are rendered by new renderer only (they fade in).
+    states: Vec<Option<(Handle<SoundSource>, RenderState)>>,
+}
+
+struct EnvironmentTransition {
+    from: ReverbParams,
+    to: ReverbParams,
+    duration: Duration,
+    elapsed: Duration,
 }
 
 /// See module docs.
 pub struct Context {
     sources: Pool<SoundSource>,
     listener: Listener,
+    // Additional listeners, each one renders to its own submix.
+    listeners: Pool<Listener>,
     master_gain: f32,
+    master_balance: f32,
     render_duration: Duration,
     renderer: Renderer,
     effects: Pool<Effect>,
+    // Order in which effects are rendered.
+    effect_chain: Vec<Handle<Effect>>,
     distance_model: DistanceModel,
+    master_peak: f32,
+    master_rms: f32,
+    // Copy of last mixed block, it is used to calculate spectrum on demand.
+    last_master_block: Vec<(f32, f32)>,
+    max_voices: usize,
+    // Reusable list of (index, priority, gain) of playing sources for voice limiter.
+    voice_candidates: Vec<(usize, u8, f32)>,
+    output_tap: Option<Box<OutputTap>>,
+    // Shared with mixer callback, because it has to count underruns even if context
+    // cannot be locked.
+    underrun_count: Arc<AtomicUsize>,
+    // Amount of samples (per channel) passed to output device, shared with mixer callback.
+    samples_rendered: Arc<AtomicU64>,
+    reported_underrun_count: usize,
+    underrun_callback: Option<Box<UnderrunCallback>>,
+    // Ring of recent diagnostics, oldest ones are dropped when it is full.
+    diagnostics: VecDeque<Diagnostic>,
+    automations: Vec<Automation>,
+    // True if time-based state is advanced by `update` instead of mixer.
+    manual_update: bool,
+    command_sender: Option<CommandSender>,
+    command_receiver: CommandReceiver,
+    clip_mode: ClipMode,
+    loudness_meter: LoudnessMeter,
+    output_format: SampleFormat,
+    sanitize_output: bool,
+    nan_count: usize,
+    ducks: Vec<Duck>,
+    // Output of ducked effect before it is attenuated and added to mix.
+    effect_buf: Vec<(f32, f32)>,
+    renderer_fade: Option<RendererFade>,
+    // Finished fade which is waiting to be dropped on game thread, mixer must not free memory.
+    finished_renderer_fade: Option<RendererFade>,
+    // Output of faded out renderer.
+    fade_buf: Vec<(f32, f32)>,
+    // None only for a moment between creation of context and start of mixer thread.
+    mixer: Option<MixerThread>,
+    environment: Option<ReverbPreset>,
+    // Parameters that were last applied to reverbs, they differ from parameters of environment
+    // during transition.
+    environment_params: Option<ReverbParams>,
+    environment_transition: Option<EnvironmentTransition>,
+    environment_transition_time: Duration,
+    // Left and right DC blockers of master output, None if disabled.
+    dc_blocker: Option<(DcBlocker, DcBlocker)>,
 }
 
 impl Context {
     // TODO: This is magic constant that gives 1024 + 1 number when summed with
     //       HRTF length for faster FFT calculations. Find a better way of selecting this.
-    pub(in crate) const HRTF_BLOCK_LEN: usize = 513;
+    /// Default length of block (in samples) which is processed by HRTF renderer at once. Each
+    /// HRTF renderer can have its own block length, see `HrtfRenderer::with_block_len`.
+    pub const HRTF_BLOCK_LEN: usize = 513;
 
     pub(in crate) const HRTF_INTERPOLATION_STEPS: usize = 8;
 
-    pub(in crate) const SAMPLES_PER_CHANNEL: usize = Self::HRTF_BLOCK_LEN * Self::HRTF_INTERPOLATION_STEPS;
+    /// Length of output buffer (in samples per channel) which is rendered at once.
+    pub const SAMPLES_PER_CHANNEL: usize = Self::HRTF_BLOCK_LEN * Self::HRTF_INTERPOLATION_STEPS;
+
+    /// Level (absolute sample value) of input of trigger effect above which trigger is considered active,
+    /// see `set_duck`. It is -40 dB.
+    pub const DUCK_THRESHOLD: f32 = 0.01;
+
+    // Diagnostics that was not drained are discarded (oldest first) when there are more than this amount.
+    const MAX_DIAGNOSTICS: usize = 64;
+
+    /// Cutoff frequency (in hertz) of DC blocker, see `set_dc_blocker`.
+    pub const DC_BLOCKER_CUTOFF: f32 = 10.0;
+
+    /// Default time of transition between environments, see `set_environment`.
+    pub const DEFAULT_ENVIRONMENT_TRANSITION_TIME: Duration = Duration::from_secs(1);
+
+    /// Maximum amount of commands that can be queued between two rendered blocks.
+    pub const COMMAND_QUEUE_CAPACITY: usize = 1024;
+
+    // Each native sample is 4 bytes (16-bit stereo).
+    const DEVICE_BUFFER_LEN_BYTES: u32 = 4 * Self::SAMPLES_PER_CHANNEL as u32;
 
     /// Creates new instance of context. Internally context starts new thread which will call render all
     /// sound source and send samples to default output device. This method returns Arc<Mutex<Context>>
     /// because separate thread also uses context.
-    pub fn new() -> Result<Arc<Mutex<Self>>, SoundError> {
-        let context = Self {
+    ///
+    /// Mixer thread holds only weak reference to context, so context is destroyed when last `Arc`
+    /// is dropped. On destruction context stops mixer thread and waits until it is finished, last
+    /// played block is faded to silence so there is no click.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ContextError` which describes exact reason why output device cannot be used, it can be
+    /// used to show meaningful message to user.
+    pub fn new() -> Result<Arc<Mutex<Self>>, ContextError> {
+        let context = Arc::new(Mutex::new(Self::default_state()));
+
+        // Run device with a mixer callback. Mixer callback will mix samples
+        // from source with a fixed rate.
+        let mixer = run_device(Self::DEVICE_BUFFER_LEN_BYTES, Self::make_mixer_callback(&context))?;
+        context.lock().unwrap().mixer = Some(mixer);
+
+        Ok(context)
+    }
+
+    /// Creates new instance of context which uses null output device. Null device discards all
+    /// samples, but mixer still runs in real time on separate thread exactly as with real output
+    /// device. The rest of API behaves identically. Useful for headless environments (servers,
+    /// CI, etc.) where there is no output device.
+    pub fn new_null() -> Result<Arc<Mutex<Self>>, ContextError> {
+        let context = Arc::new(Mutex::new(Self::default_state()));
+
+        let mixer = run_null_device(Self::DEVICE_BUFFER_LEN_BYTES, Self::make_mixer_callback(&context))?;
+        context.lock().unwrap().mixer = Some(mixer);
+
+        Ok(context)
+    }
+
+    pub(in crate) fn default_state() -> Self {
+        let (command_sender, command_receiver) = command_queue(Self::COMMAND_QUEUE_CAPACITY);
+        Self {
             sources: Pool::new(),
             listener: Listener::new(),
+            listeners: Pool::new(),
             master_gain: 1.0,
+            master_balance: 0.0,
             render_duration: Default::default(),
             renderer: Renderer::Default,
             effects: Pool::new(),
+            effect_chain: Default::default(),
             distance_model: DistanceModel::InverseDistance,
-        };
+            master_peak: 0.0,
+            master_rms: 0.0,
+            last_master_block: Default::default(),
+            max_voices: usize::MAX,
+            voice_candidates: Default::default(),
+            output_tap: None,
+            underrun_count: Arc::new(AtomicUsize::new(0)),
+            samples_rendered: Arc::new(AtomicU64::new(0)),
+            reported_underrun_count: 0,
+            underrun_callback: None,
+            // Diagnostics are pushed on mixer thread, so reserve memory beforehand.
+            diagnostics: VecDeque::with_capacity(Self::MAX_DIAGNOSTICS),
+            automations: Default::default(),
+            manual_update: false,
+            command_sender: Some(command_sender),
+            command_receiver,
+            clip_mode: ClipMode::HardClip,
+            loudness_meter: LoudnessMeter::new(device::SAMPLE_RATE),
+            output_format: SampleFormat::F32,
+            sanitize_output: cfg!(debug_assertions),
+            nan_count: 0,
+            ducks: Default::default(),
+            effect_buf: Default::default(),
+            renderer_fade: None,
+            finished_renderer_fade: None,
+            fade_buf: Default::default(),
+            mixer: None,
+            environment: None,
+            environment_params: None,
+            environment_transition: None,
+            environment_transition_time: Self::DEFAULT_ENVIRONMENT_TRANSITION_TIME,
+            dc_blocker: None,
+        }
+    }
 
-        let context = Arc::new(Mutex::new(context));
+    fn make_mixer_callback(context: &Arc<Mutex<Self>>) -> Box<FeedCallback> {
+        let (underrun_count, samples_rendered) = {
+            let context = context.lock().unwrap();
+            (context.underrun_count.clone(), context.samples_rendered.clone())
+        };
+        // Weak reference, otherwise context will never be destroyed because mixer thread owns it.
+        let context = Arc::downgrade(context);
+        // Sized for blocks of output device, so mixer thread won't allocate it on first callback.
+        let mut last_block = Vec::<(f32, f32)>::with_capacity(Self::SAMPLES_PER_CHANNEL);
+        Box::new(move |buf| {
+            // Clock advances even on underrun, device consumes samples anyway.
+            samples_rendered.fetch_add(buf.len() as u64, AtomicOrdering::SeqCst);
 
-        // Run device with a mixer callback. Mixer callback will mix samples
-        // from source with a fixed rate.
-        run_device(4 * Self::SAMPLES_PER_CHANNEL as u32, {
-            let context = context.clone();
-            Box::new(move |buf| {
-                if let Ok(mut context) = context.lock() {
-                    context.render(buf);
+            // Context is destroyed and mixer thread is stopping, fade out to silence.
+            let context = match Weak::upgrade(&context) {
+                Some(context) => context,
+                None => {
+                    fade_out_last_block(buf, &mut last_block);
+                    return;
                 }
-            })
-        })?;
+            };
 
-        Ok(context)
+            // Context can be locked by other thread for a long time, we can't wait for it
+            // because output device will starve. So if context is unavailable it is underrun.
+            if let Ok(mut context) = context.try_lock() {
+                context.render(buf);
+                last_block.clear();
+                last_block.extend_from_slice(buf);
+                return;
+            }
+
+            underrun_count.fetch_add(1, AtomicOrdering::SeqCst);
+
+            // Output last known good block faded to silence instead of hard discontinuity.
+            fade_out_last_block(buf, &mut last_block);
+        })
     }
 
-    /// Sets new distance model.
-    pub fn set_distance_model(&mut self, distance_model: DistanceModel) {
-        self.distance_model = distance_model;
+    /// Returns total amount of underruns - situations when mixer was unable to prepare new block of
+    /// samples in time (it took too long to render sources or context was locked by other thread when
+    /// mixer needed it). On underrun mixer outputs last block of samples faded to silence to prevent hard
+    /// discontinuity in output signal. Frequent underruns means that CPU is overloaded or context is
+    /// locked for too long by your code, you'll hear stutter in this case.
+    pub fn underrun_count(&self) -> usize {
+        self.underrun_count.load(AtomicOrdering::SeqCst)
     }
 
-    /// Returns current distance model.
-    pub fn distance_model(&self) -> DistanceModel {
-        self.distance_model
+    /// Sets callback which will be called when mixer detects underrun(s). Callback is called on
+    /// mixer thread at the next render pass after underrun, so it must be fast and must not lock
+    /// context.
+    pub fn set_underrun_callback(&mut self, callback: Box<UnderrunCallback>) -> Option<Box<UnderrunCallback>> {
+        self.underrun_callback.replace(callback)
     }
 
-    /// Adds new effect to effects chain. Each sample from
-    pub fn add_effect(&mut self, effect: Effect) -> Handle<Effect> {
-        self.effects.spawn(effect)
+    /// Drains warnings and events that was emitted by context since last call. Only recent ones are kept,
+    /// so this method should be called periodically (i.e. once per frame of your game) and results
+    /// should be logged.
+    pub fn diagnostics(&mut self) -> vec_deque::Drain<'_, Diagnostic> {
+        self.diagnostics.drain(..)
     }
 
-    /// Removes effect by given handle.
-    pub fn remove_effect(&mut self, effect: Handle<Effect>) {
-        self.effects.free(effect);
+    fn push_diagnostic(&mut self, diagnostic: Diagnostic) {
+        if self.diagnostics.len() >= Self::MAX_DIAGNOSTICS {
+            self.diagnostics.pop_front();
+        }
+        self.diagnostics.push_back(diagnostic);
     }
 
-    /// Normalizes given frequency using context's sampling rate. Normalized frequency then can be used
-    /// to create filters.
-    pub fn normalize_frequency(&self, f: f32) -> f32 {
-        f / device::SAMPLE_RATE as f32
+    // HRTF renderer downmixes spatial sources to mono, multichannel buffer on spatial
+    // source most likely is a mistake.
+    fn check_hrtf_sources(&mut self) {
+        for i in 0..self.sources.get_capacity() {
+            let channel_count = match self.sources.at_mut(i) {
+                Some(SoundSource::Spatial(spatial)) if !spatial.non_mono_reported && spatial.generic().channel_count > 1 &&
+                    spatial.stereo_spread() == 0.0 => {
+                    spatial.non_mono_reported = true;
+                    spatial.generic().channel_count
+                }
+                _ => continue,
+            };
+            let source = self.sources.handle_from_index(i);
+            self.push_diagnostic(Diagnostic::NonMonoHrtfSource { source, channel_count });
+        }
     }
 
-    /// Returns amount of time context spent on rendering all sound sources.
-    pub fn full_render_duration(&self) -> Duration {
-        self.render_duration
+    /// Starts automation of given parameter: value of parameter will be changed from its current
+    /// value to `to_value` over `duration` time using given curve. Automations are stepped on each
+    /// render pass (or by `update` in manual mode), so parameter changes with granularity of output
+    /// buffer length. Existing
+    /// automation of the same parameter is replaced. Automation is silently discarded if its target
+    /// becomes invalid (i.e. source or effect was removed). See `automation` module docs for more info.
+    pub fn automate(&mut self, target: AutomationTarget, to_value: f32, duration: Duration, curve: AutomationCurve) {
+        if let Some(from_value) = self.automation_value(target) {
+            self.automations.retain(|a| a.target != target);
+            self.automations.push(Automation::new(target, from_value, to_value, duration, curve));
+        }
     }
 
-    /// Sets new renderer.
-    pub fn set_renderer(&mut self, renderer: Renderer) -> Renderer {
-        std::mem::replace(&mut self.renderer, renderer)
+    /// Stops automation of given parameter, parameter will keep its current value.
+    pub fn cancel_automation(&mut self, target: AutomationTarget) {
+        self.automations.retain(|a| a.target != target);
     }
 
-    /// Returns shared reference to current renderer.
-    pub fn renderer(&self) -> &Renderer {
-        &self.renderer
+    /// Returns true if given parameter is being automated.
+    pub fn is_automated(&self, target: AutomationTarget) -> bool {
+        self.automations.iter().any(|a| a.target == target)
     }
 
-    /// Returns mutable reference to current renderer.
-    pub fn renderer_mut(&mut self) -> &mut Renderer {
-        &mut self.renderer
+    fn automation_value(&self, target: AutomationTarget) -> Option<f32> {
+        match target {
+            AutomationTarget::MasterGain => Some(self.master_gain),
+            AutomationTarget::SourceGain(handle) => self.sources.try_borrow(handle)
+                .map(|s| s.generic().gain()),
+            AutomationTarget::SourcePitch(handle) => self.sources.try_borrow(handle)
+                .map(|s| s.generic().pitch() as f32),
+            _ => target.effect()
+                .and_then(|handle| self.effects.try_borrow(handle))
+                .and_then(|effect| effect_parameter(effect, target)),
+        }
     }
 
-    /// Sets new master gain. Master gain is used to control total sound volume that will be passed to output
-    /// device.
-    pub fn set_master_gain(&mut self, gain: f32) {
-        self.master_gain = gain;
+    // Returns false if target is not valid anymore.
+    fn set_automation_value(&mut self, target: AutomationTarget, value: f32) -> bool {
+        match target {
+            AutomationTarget::MasterGain => {
+                self.master_gain = value;
+                true
+            }
+            AutomationTarget::SourceGain(handle) => self.sources.try_borrow_mut(handle)
+                .map(|s| { s.generic_mut().set_gain(value); })
+                .is_some(),
+            AutomationTarget::SourcePitch(handle) => self.sources.try_borrow_mut(handle)
+                .map(|s| { s.generic_mut().set_pitch(f64::from(value)); })
+                .is_some(),
+            _ => target.effect()
+                .and_then(|handle| self.effects.try_borrow_mut(handle))
+                .is_some_and(|effect| set_effect_parameter(effect, target, value)),
+        }
     }
 
-    /// Returns master gain.
-    pub fn master_gain(&self) -> f32 {
-        self.master_gain
+    /// Advances time-based state of context by given time step. Should be called regularly (i.e. each
+    /// frame of a game), it never blocks mixer for long.
+    ///
+    /// # Relationship with mixer thread
+    ///
+    /// By default (automatic mode) time-based state - automations (see `automate`), environment
+    /// transitions (see `set_environment`) and renderer crossfade (see `crossfade_renderer`) - is
+    /// advanced by mixer thread by duration of each rendered block of samples, so it follows audio
+    /// clock and this method only does game-side housekeeping. In manual mode (see
+    /// `set_manual_update`) mixer does not advance that state, it changes only when this method is
+    /// called, which is useful for games with fixed time step which want deterministic, frame-rate
+    /// stable behaviour. Mixer keeps rendering samples in both modes, parameters that were changed by
+    /// this method are picked up by next rendered block.
+    ///
+    /// In both modes velocity of spatial sources that are moved by `SpatialSource::set_position` is
+    /// derived from their movement between calls of this method (see `SpatialSource::velocity`).
+    pub fn update(&mut self, dt: Duration) {
+        self.finished_renderer_fade = None;
+        for source in self.sources.iter_mut() {
+            if let SoundSource::Spatial(spatial) = source {
+                spatial.update_velocity(dt);
+            }
+        }
+        if self.manual_update {
+            self.update_automations(dt);
+            self.update_environment(dt);
+            if let Some(fade) = self.renderer_fade.as_mut() {
+                fade.elapsed += (dt.as_secs_f64() * f64::from(device::SAMPLE_RATE)) as usize;
+            }
+        }
     }
 
-    /// Adds new sound source and returns handle of it by which it can be accessed later on.
-    pub fn add_source(&mut self, source: SoundSource) -> Handle<SoundSource> {
-        self.sources.spawn(source)
+    /// Switches context between manual mode (time-based state is advanced by `update`) and automatic
+    /// mode (time-based state is advanced by mixer). See `update` for more info. Default is automatic
+    /// mode.
+    pub fn set_manual_update(&mut self, manual: bool) {
+        self.manual_update = manual;
     }
 
-    /// Returns shared reference to a pool with all sound sources.
-    pub fn sources(&self) -> &Pool<SoundSource> {
-        &self.sources
+    /// Returns true if context is in manual update mode.
+    pub fn is_manual_update(&self) -> bool {
+        self.manual_update
     }
 
-    /// Returns mutable reference to a pool with all sound sources.
-    pub fn sources_mut(&mut self) -> &mut Pool<SoundSource> {
-        &mut self.sources
+    fn update_automations(&mut self, dt: Duration) {
+        // Stepped in place, mixer must not allocate.
+        let mut i = 0;
+        while i < self.automations.len() {
+            let value = self.automations[i].step(dt);
+            let target = self.automations[i].target;
+            // Automation is discarded when it is finished or its target is not valid anymore.
+            if self.set_automation_value(target, value) && !self.automations[i].is_finished() {
+                i += 1;
+            } else {
+                self.automations.remove(i);
+            }
+        }
     }
 
-    /// Returns shared reference to sound source at given handle. If handle is invalid, this method will panic.
-    pub fn source(&self, handle: Handle<SoundSource>) -> &SoundSource {
-        self.sources.borrow(handle)
+    /// Sets acoustic environment (i.e. cave, hall) in which listener is. Parameters of all reverb
+    /// effects of context are smoothly changed to parameters of given preset (see
+    /// `ReverbPreset::params`) over transition time (see `set_environment_transition_time`), so
+    /// moving from one zone of a level to another does not cause abrupt change of reverberation.
+    /// Transition starts from parameters of current transition state, so environment can be changed
+    /// again before previous transition is finished.
+    ///
+    /// # Notes
+    ///
+    /// First environment is applied immediately, because there is nothing to interpolate from.
+    /// Reverbs are only changed while transition is in progress, so parameters can be tweaked
+    /// manually once it is finished. Reverbs that were added during transition will jump to
+    /// interpolated parameters. Early reflections are not interpolated, they're crossfaded from
+    /// reflections of previous environment, so they never zipper. Mixer never allocates during
+    /// transition.
+    pub fn set_environment(&mut self, preset: ReverbPreset) {
+        let to = preset.params();
+        self.environment = Some(preset);
+        match self.environment_params {
+            Some(from) if self.environment_transition_time > Duration::from_secs(0) => {
+                // Early reflections are crossfaded, their taps are prepared here, so mixer won't
+                // allocate.
+                for effect in self.effects.iter_mut() {
+                    if let Effect::Reverb(reverb) = effect {
+                        reverb.begin_transition(&to);
+                    }
+                }
+                self.environment_transition = Some(EnvironmentTransition {
+                    from,
+                    to,
+                    duration: self.environment_transition_time,
+                    elapsed: Duration::from_secs(0),
+                });
+            }
+            _ => {
+                self.environment_transition = None;
+                self.apply_environment_params(to);
+            }
+        }
     }
 
-    /// Returns mutable reference to sound source at given handle. If handle is invalid, this method will panic.
-    pub fn source_mut(&mut self, handle: Handle<SoundSource>) -> &mut SoundSource {
-        self.sources.borrow_mut(handle)
+    /// Returns current environment, see `set_environment`.
+    pub fn environment(&self) -> Option<ReverbPreset> {
+        self.environment
     }
 
-    /// Returns shared reference to listener. Engine has only one listener.
-    pub fn listener(&self) -> &Listener {
-        &self.listener
+    /// Returns parameters that were last applied to reverbs by environment, they're interpolated
+    /// while transition is in progress. Returns `None` if environment was never set.
+    pub fn environment_params(&self) -> Option<ReverbParams> {
+        self.environment_params
     }
 
-    /// Returns mutable reference to listener. Engine has only one listener.
-    pub fn listener_mut(&mut self) -> &mut Listener {
-        &mut self.listener
+    /// Returns true if transition between environments is in progress.
+    pub fn is_environment_transitioning(&self) -> bool {
+        self.environment_transition.is_some()
     }
 
-    /// Returns shared reference to effect at given handle. If handle is invalid, this method will panic.
-    pub fn effect(&self, handle: Handle<Effect>) -> &Effect {
-        self.effects.borrow(handle)
+    /// Sets time over which reverb parameters change when environment is changed, see
+    /// `set_environment`. Zero time means instant change. Does not affect transition that is in
+    /// progress. Default value is `DEFAULT_ENVIRONMENT_TRANSITION_TIME`.
+    pub fn set_environment_transition_time(&mut self, time: Duration) {
+        self.environment_transition_time = time;
     }
 
-    /// Returns mutable reference to effect at given handle. If handle is invalid, this method will panic.
-    pub fn effect_mut(&mut self, handle: Handle<Effect>) -> &mut Effect {
-        self.effects.borrow_mut(handle)
+    /// Returns time of transition between environments.
+    pub fn environment_transition_time(&self) -> Duration {
+        self.environment_transition_time
     }
 
-    fn render(&mut self, buf: &mut [(f32, f32)]) {
-        let last_time = time::Instant::now();
+    fn apply_environment_params(&mut self, params: ReverbParams) {
+        self.environment_params = Some(params);
+        for effect in self.effects.iter_mut() {
+            if let Effect::Reverb(reverb) = effect {
+                reverb.apply_params(&params);
+            }
+        }
+    }
 
-        for i in 0..self.sources.get_capacity() {
-            if let Some(source) = self.sources.at(i) {
-                if source.generic().is_play_once() && source.generic().status() == Status::Stopped {
-                    self.sources.free(self.sources.handle_from_index(i));
+    fn update_environment(&mut self, dt: Duration) {
+        if let Some(transition) = self.environment_transition.as_mut() {
+            transition.elapsed += dt;
+            let t = (transition.elapsed.as_secs_f32() / transition.duration.as_secs_f32()).min(1.0);
+            let params = transition.from.lerp(&transition.to, t);
+            if t >= 1.0 {
+                self.environment_transition = None;
+            }
+            self.environment_params = Some(params);
+            for effect in self.effects.iter_mut() {
+                if let Effect::Reverb(reverb) = effect {
+                    reverb.step_transition(&params, t);
                 }
             }
         }
+    }
 
-        for source in self.sources
-            .iter_mut()
-            .filter(|s| s.generic().status() == Status::Playing) {
-            source.generic_mut().render(buf.len());
+    /// Sets clip mode of final mix. Clip mode is applied after master gain, before level metering
+    /// and output tap. See `ClipMode` docs for more info. Default is `ClipMode::HardClip`.
+    pub fn set_clip_mode(&mut self, clip_mode: ClipMode) {
+        self.clip_mode = clip_mode;
+    }
 
-            match self.renderer {
-                Renderer::Default => {
-                    // Simple rendering path. Much faster (4-5 times) than HRTF path.
-                    render_source_default(source, &self.listener, self.distance_model, buf);
-                }
-                Renderer::HrtfRenderer(ref mut hrtf_renderer) => {
-                    hrtf_renderer.render_source(source, &self.listener, self.distance_model, buf);
-                }
+    /// Returns current clip mode.
+    pub fn clip_mode(&self) -> ClipMode {
+        self.clip_mode
+    }
+
+    /// Sets format of samples that are sent to output device. Conversion is applied after clip mode,
+    /// before level metering and output tap. See `SampleFormat` docs for more info. Default is
+    /// `SampleFormat::F32`.
+    pub fn set_output_format(&mut self, format: SampleFormat) {
+        self.output_format = format;
+    }
+
+    /// Returns current output sample format.
+    pub fn output_format(&self) -> SampleFormat {
+        self.output_format
+    }
+
+    /// Enables or disables sanitizing of output. When enabled, each non-finite sample (NaN or infinity,
+    /// i.e. produced by division by zero in custom renderer or distance model) of final mix is replaced
+    /// with silence before master gain is applied, so it won't reach output device as loud pop. Each
+    /// replaced sample is counted, see `nan_count`. Sanitizing costs one extra pass over output buffer,
+    /// so it is enabled by default only in debug builds.
+    pub fn set_output_sanitizing(&mut self, enabled: bool) {
+        self.sanitize_output = enabled;
+    }
+
+    /// Returns true if output sanitizing is enabled.
+    pub fn is_output_sanitizing(&self) -> bool {
+        self.sanitize_output
+    }
+
+    /// Returns total amount of non-finite samples that were replaced with silence by output sanitizing.
+    /// Non-zero value means that there is a bug somewhere in rendering chain. See `set_output_sanitizing`.
+    pub fn nan_count(&self) -> usize {
+        self.nan_count
+    }
+
+    /// Enables or disables DC blocker on master output. DC blocker is highpass filter with very low
+    /// cutoff frequency (see `DC_BLOCKER_CUTOFF`) which removes constant offset that can be produced
+    /// by convolution or effects. Such offset wastes headroom and clicks when sound starts or stops.
+    /// Blocker is applied after output sanitizing, before master gain and clip mode. It is inaudible
+    /// on normal content, so it is only disabled by default to keep output bit-exact.
+    pub fn set_dc_blocker(&mut self, enabled: bool) {
+        if enabled != self.dc_blocker.is_some() {
+            self.dc_blocker = if enabled {
+                let fc = self.normalize_frequency(Self::DC_BLOCKER_CUTOFF);
+                Some((DcBlocker::new(fc), DcBlocker::new(fc)))
+            } else {
+                None
+            };
+        }
+    }
+
+    /// Returns true if DC blocker is enabled.
+    pub fn is_dc_blocker_enabled(&self) -> bool {
+        self.dc_blocker.is_some()
+    }
+
+    /// Returns estimated output latency - time between the moment when block of samples is rendered
+    /// and the moment when it is heard. It is defined by size of buffer of output device which is
+    /// queried from backend when possible. Can be used to compensate delay when audio must be in
+    /// sync with gameplay or visuals.
+    ///
+    /// # Notes
+    ///
+    /// Changes of parameters made on game thread also wait for next render pass, which adds up to
+    /// one block (see `SAMPLES_PER_CHANNEL`) on top of this value. Latency of operating system mixer
+    /// and hardware cannot be queried and is not taken into account.
+    pub fn output_latency(&self) -> Duration {
+        self.mixer.as_ref().map_or_else(|| block_duration(Self::SAMPLES_PER_CHANNEL), |mixer| mixer.latency())
+    }
+
+    /// Sets new distance model.
+    pub fn set_distance_model(&mut self, distance_model: DistanceModel) {
+        self.distance_model = distance_model;
+    }
+
+    /// Returns current distance model.
+    pub fn distance_model(&self) -> DistanceModel {
+        self.distance_model.clone()
+    }
+
+    /// Adds new effect to the end of effects chain. Effects are rendered in order of the chain, see
+    /// `insert_effect` and `move_effect` to change the order. Order matters for insert effects, they
+    /// process output of every effect that goes before them, see `Effect::set_insert`.
+    pub fn add_effect(&mut self, mut effect: Effect) -> Handle<Effect> {
+        self.prepare_effect(&mut effect);
+        let handle = self.effects.spawn(effect);
+        self.effect_chain.push(handle);
+        handle
+    }
+
+    /// Inserts new effect at given position in effects chain and returns its handle. Index is clamped
+    /// to the length of the chain, so effect will be added to the end if index is out of bounds.
+    pub fn insert_effect(&mut self, index: usize, mut effect: Effect) -> Handle<Effect> {
+        self.prepare_effect(&mut effect);
+        let handle = self.effects.spawn(effect);
+        self.effect_chain.insert(index.min(self.effect_chain.len()), handle);
+        handle
+    }
+
+    // Allocates everything that effect needs, so mixer won't do it.
+    fn prepare_effect(&self, effect: &mut Effect) {
+        effect.base_mut().reserve_sources(self.sources.get_capacity());
+        if let (Effect::Reverb(reverb), Some(transition)) = (effect, self.environment_transition.as_ref()) {
+            // Reverb joins transition of environment right away.
+            if let Some(params) = self.environment_params {
+                reverb.apply_params(&params);
             }
+            reverb.begin_transition(&transition.to);
         }
+    }
 
-        for effect in self.effects.iter_mut() {
-            effect.render(&self.sources, &self.listener, self.distance_model, buf);
+    /// Removes effect by given handle.
+    pub fn remove_effect(&mut self, effect: Handle<Effect>) {
+        self.effect_chain.retain(|h| *h != effect);
+        self.ducks.retain(|duck| duck.trigger != effect && duck.target != effect);
+        self.effects.free(effect);
+    }
+
+    /// Removes effect at given position in effects chain. Does nothing if index is out of bounds.
+    pub fn remove_effect_at(&mut self, index: usize) {
+        if index < self.effect_chain.len() {
+            let handle = self.effect_chain.remove(index);
+            self.ducks.retain(|duck| duck.trigger != handle && duck.target != handle);
+            self.effects.free(handle);
         }
+    }
 
-        // Apply master gain to be able to control total sound volume.
-        for (left, right) in buf {
-            *left *= self.master_gain;
-            *right *= self.master_gain;
+    /// Moves effect at position `from` in effects chain to position `to`. Does nothing if any of
+    /// indices is out of bounds.
+    pub fn move_effect(&mut self, from: usize, to: usize) {
+        if from < self.effect_chain.len() && to < self.effect_chain.len() {
+            let handle = self.effect_chain.remove(from);
+            self.effect_chain.insert(to, handle);
         }
+    }
 
-        self.render_duration = time::Instant::now() - last_time;
+    /// Returns handles of effects in order in which effects will be rendered.
+    pub fn effect_chain(&self) -> &[Handle<Effect>] {
+        &self.effect_chain
     }
-}
 
-impl Visit for Context {
-    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
-        visitor.enter_region(name)?;
+    /// Makes `target` effect to be ducked (attenuated) when `trigger` effect has signal on its input:
+    /// when input of trigger is louder than `DUCK_THRESHOLD`, output of target is attenuated by
+    /// `amount` within `attack` time, when trigger goes quiet target is restored within `release`
+    /// time. Amount is linear and clamped to [0; 1] range: 0.5 - target is twice quieter, 1.0 - target
+    /// is muted. Typical usage is ducking music under dialogue. Existing ducking of the same pair of
+    /// effects is replaced. Target can be ducked by several triggers, their gains are multiplied.
+    ///
+    /// # Notes
+    ///
+    /// Ducking reacts on trigger with latency of one render block. Ducking is removed automatically
+    /// when any of the effects is removed. Insert effects (see `Effect::set_insert`) are never ducked.
+    pub fn set_duck(&mut self, trigger: Handle<Effect>, target: Handle<Effect>, amount: f32, attack: Duration, release: Duration) {
+        self.remove_duck(trigger, target);
+        let sample_rate = device::SAMPLE_RATE as f32;
+        self.ducks.push(Duck {
+            trigger,
+            target,
+            amount: amount.clamp(0.0, 1.0),
+            follower: EnvelopeFollower::new(attack.as_secs_f32() * sample_rate, release.as_secs_f32() * sample_rate),
+            last_gain: 1.0,
+            gain: 1.0,
+        });
+    }
 
-        if visitor.is_reading() {
-            self.sources.clear();
+    /// Removes ducking of `target` effect by `trigger` effect, see `set_duck`.
+    pub fn remove_duck(&mut self, trigger: Handle<Effect>, target: Handle<Effect>) {
+        self.ducks.retain(|duck| duck.trigger != trigger || duck.target != target);
+    }
+
+    /// Returns current gain of effect which is applied by ducking, 1.0 means that effect is not ducked.
+    pub fn duck_gain(&self, effect: Handle<Effect>) -> f32 {
+        self.ducks
+            .iter()
+            .filter(|duck| duck.target == effect)
+            .fold(1.0, |gain, duck| gain * duck.gain)
+    }
+
+    fn update_ducks(&mut self) {
+        let effects = &self.effects;
+        self.ducks.retain(|duck| effects.is_valid_handle(duck.trigger) && effects.is_valid_handle(duck.target));
+        for duck in self.ducks.iter_mut() {
+            for &(left, right) in effects.borrow(duck.trigger).base().input_samples() {
+                let active = if left.abs().max(right.abs()) > Self::DUCK_THRESHOLD { 1.0 } else { 0.0 };
+                duck.follower.feed(active);
+            }
+            duck.last_gain = duck.gain;
+            duck.gain = 1.0 - duck.amount * duck.follower.level();
         }
+    }
 
-        self.master_gain.visit("MasterGain", visitor)?;
-        self.listener.visit("Listener", visitor)?;
-        self.sources.visit("Sources", visitor)?;
-        self.effects.visit("Effects", visitor)?;
+    /// Solos given source: while there is at least one soloed source, all other sources are muted
+    /// (virtualized - their playback position advances, but they're not mixed and not passed to
+    /// effects). Multiple sources can be soloed at once. This is handy to audition single source in
+    /// isolation when debugging a mix. Does nothing if handle is invalid.
+    ///
+    /// # Notes
+    ///
+    /// Muted sources are faded out during next rendered block and faded in the same way when solo
+    /// is removed, so solo does not click.
+    pub fn solo_source(&mut self, handle: Handle<SoundSource>) {
+        if let Some(source) = self.sources.try_borrow_mut(handle) {
+            source.generic_mut().solo = true;
+        }
+    }
 
-        visitor.leave_region()
+    /// Removes solo from given source, see `solo_source`.
+    pub fn unsolo_source(&mut self, handle: Handle<SoundSource>) {
+        if let Some(source) = self.sources.try_borrow_mut(handle) {
+            source.generic_mut().solo = false;
+        }
+    }
+
+    /// Removes solo from all sources, so all sources are mixed again.
+    pub fn clear_solo(&mut self) {
+        for source in self.sources.iter_mut() {
+            source.generic_mut().solo = false;
+        }
+    }
+
+    /// Sets maximum amount of sources that can be mixed simultaneously. If there are more playing
+    /// sources, sources with lowest priority (and then quietest) will be virtualized - their playback
+    /// position will advance, but they won't be mixed. See `GenericSource::set_priority`. By default
+    /// amount of voices is unlimited.
+    pub fn set_max_voices(&mut self, max_voices: usize) {
+        self.max_voices = max_voices;
+    }
+
+    /// Returns maximum amount of sources that can be mixed simultaneously.
+    pub fn max_voices(&self) -> usize {
+        self.max_voices
     }
-}
\ No newline at end of file
+
+    fn update_voices(&mut self) {
+        let any_solo = self.sources.iter().any(|source| source.generic().solo);
+        self.voice_candidates.clear();
+        for i in 0..self.sources.get_capacity() {
+            if let Some(source) = self.sources.at_mut(i) {
+                let generic = source.generic_mut();
+                generic.solo_muted = any_solo && !generic.solo;
+                if generic.status() != Status::Playing {
+                    // There is nothing to fade.
+                    generic.solo_gain = if generic.solo_muted { 0.0 } else { 1.0 };
+                }
+                // Muted source is faded out during one block first (see `GenericSource::render`),
+                // after that it does not need a voice.
+                generic.is_virtual = generic.solo_muted && generic.solo_gain == 0.0;
+                if generic.status() == Status::Playing && !generic.is_virtual {
+                    self.voice_candidates.push((i, generic.priority(), generic.gain()));
+                }
+            }
+        }
+
+        if self.voice_candidates.len() <= self.max_voices {
+            return;
+        }
+
+        // Highest priority first, then loudest.
+        self.voice_candidates.sort_by(|a, b| {
+            b.1.cmp(&a.1).then_with(|| b.2.partial_cmp(&a.2).unwrap_or(Ordering::Equal))
+        });
+
+        for &(index, _, _) in self.voice_candidates[self.max_voices..].iter() {
+            if let Some(source) = self.sources.at_mut(index) {
+                source.generic_mut().is_virtual = true;
+            }
+        }
+    }
+
+    /// Normalizes given frequency using context's sampling rate. Normalized frequency then can be used
+    /// to create filters.
+    pub fn normalize_frequency(&self, f: f32) -> f32 {
+        f / device::SAMPLE_RATE as f32
+    }
+
+    /// Returns total amount of samples (per channel) that was passed to output device since context was
+    /// created. This is monotonic audio clock which is independent from wall clock, it can be used to
+    /// sync visuals with audio.
+    pub fn samples_rendered(&self) -> u64 {
+        self.samples_rendered.load(AtomicOrdering::SeqCst)
+    }
+
+    /// Returns time of audio clock, see `samples_rendered`.
+    pub fn time(&self) -> Duration {
+        let samples = self.samples_rendered();
+        let sample_rate = u64::from(device::SAMPLE_RATE);
+        Duration::from_secs(samples / sample_rate) +
+            Duration::from_nanos(samples % sample_rate * 1_000_000_000 / sample_rate)
+    }
+
+    /// Returns amount of time context spent on rendering all sound sources.
+    pub fn full_render_duration(&self) -> Duration {
+        self.render_duration
+    }
+
+    /// Returns amount of channels of output device. Currently output device is always opened in
+    /// stereo mode. See `layout` module for panning across virtual speakers of surround layouts with
+    /// fold down to stereo.
+    pub fn channel_count(&self) -> usize {
+        device::CHANNEL_COUNT
+    }
+
+    /// Sets new renderer and returns previous one. Accepts either one of built-in renderers or
+    /// boxed custom renderer (`Box<dyn Render>`). Per-source state of renderer (i.e. HRTF convolution
+    /// state) is reset, so new renderer starts cleanly.
+    ///
+    /// # Notes
+    ///
+    /// Renderer is switched instantly which may produce audible pop when sources are playing, use
+    /// `crossfade_renderer` to switch renderers smoothly. Running crossfade is finished immediately.
+    pub fn set_renderer<R: Into<Renderer>>(&mut self, renderer: R) -> Renderer {
+        self.renderer_fade = None;
+        self.finished_renderer_fade = None;
+        self.reset_render_states();
+        std::mem::replace(&mut self.renderer, renderer.into())
+    }
+
+    /// Switches to new renderer smoothly: during given time both renderers are rendering sources
+    /// and their outputs are crossfaded. New renderer starts with clean per-source state (i.e. HRTF
+    /// convolution state). Rendering is twice heavier during crossfade, so it should be short -
+    /// 50-100 ms is enough to hide a pop. Zero duration switches renderer instantly, see
+    /// `set_renderer`.
+    ///
+    /// # Notes
+    ///
+    /// Mixer never frees memory, so previous renderer is dropped on next call of `update`,
+    /// `set_renderer` or `crossfade_renderer` after crossfade is finished.
+    pub fn crossfade_renderer<R: Into<Renderer>>(&mut self, renderer: R, duration: Duration) {
+        let length = (duration.as_secs_f64() * f64::from(device::SAMPLE_RATE)) as usize;
+        if length == 0 {
+            self.set_renderer(renderer);
+            return;
+        }
+
+        self.finished_renderer_fade = None;
+
+        // Current state of each source belongs to renderer which will be faded out.
+        let mut states = Vec::with_capacity(self.sources.get_capacity());
+        for i in 0..self.sources.get_capacity() {
+            let handle = self.sources.handle_from_index(i);
+            states.push(self.sources.at_mut(i).map(|source| {
+                let mut state = RenderState::default();
+                source.swap_render_state(&mut state);
+                (handle, state)
+            }));
+        }
+
+        self.renderer_fade = Some(RendererFade {
+            renderer: std::mem::replace(&mut self.renderer, renderer.into()),
+            length,
+            elapsed: 0,
+            rendered: 0,
+            states,
+        });
+    }
+
+    /// Returns true if renderers are being crossfaded, see `crossfade_renderer`.
+    pub fn is_renderer_crossfading(&self) -> bool {
+        self.renderer_fade.is_some()
+    }
+
+    // Mixes output of faded out renderer with output of current renderer.
+    fn apply_renderer_fade(&mut self, buf: &mut [(f32, f32)]) {
+        let manual_update = self.manual_update;
+        let fade = match self.renderer_fade.as_mut() {
+            Some(fade) => fade,
+            None => return,
+        };
+
+        // In manual mode fade is advanced by `update`, block is ramped from last rendered position.
+        if !manual_update {
+            fade.elapsed += buf.len();
+        }
+        let step = (fade.elapsed - fade.rendered) as f32 / buf.len() as f32;
+        for (i, ((left, right), &(fade_left, fade_right))) in buf.iter_mut().zip(self.fade_buf.iter()).enumerate() {
+            let k = ((fade.rendered as f32 + i as f32 * step) / fade.length as f32).min(1.0);
+            *left = math::lerpf(fade_left, *left, k);
+            *right = math::lerpf(fade_right, *right, k);
+        }
+
+        fade.rendered = fade.elapsed;
+        if fade.rendered >= fade.length {
+            // Previous renderer is dropped on game thread, see `update`.
+            self.finished_renderer_fade = self.renderer_fade.take();
+        }
+    }
+
+    fn reset_render_states(&mut self) {
+        for i in 0..self.sources.get_capacity() {
+            if let Some(source) = self.sources.at_mut(i) {
+                source.swap_render_state(&mut RenderState::default());
+            }
+        }
+    }
+
+    /// Returns shared reference to current renderer.
+    pub fn renderer(&self) -> &Renderer {
+        &self.renderer
+    }
+
+    /// Returns mutable reference to current renderer.
+    pub fn renderer_mut(&mut self) -> &mut Renderer {
+        &mut self.renderer
+    }
+
+    /// Sets new master gain. Master gain is used to control total sound volume that will be passed to output
+    /// device.
+    pub fn set_master_gain(&mut self, gain: f32) {
+        self.master_gain = gain;
+    }
+
+    /// Returns master gain.
+    pub fn master_gain(&self) -> f32 {
+        self.master_gain
+    }
+
+    /// Sets stereo balance of master bus: -1.0 - left channel only, 0.0 - both channels (default),
+    /// 1.0 - right channel only. Balance is applied to mixed output together with master gain, it is
+    /// independent from panning of sources. Useful to compensate hearing imbalance.
+    ///
+    /// # Notes
+    ///
+    /// Balance uses simple linear attenuation of opposite channel, channel towards which balance is
+    /// shifted stays untouched. So balance of -0.5 halves right channel and keeps left one as is.
+    pub fn set_master_balance(&mut self, balance: f32) {
+        self.master_balance = balance.clamp(-1.0, 1.0);
+    }
+
+    /// Returns stereo balance of master bus.
+    pub fn master_balance(&self) -> f32 {
+        self.master_balance
+    }
+
+    /// Returns peak level (maximum absolute sample value) of last mixed block on master bus.
+    /// Master gain is taken into account.
+    pub fn master_peak(&self) -> f32 {
+        self.master_peak
+    }
+
+    /// Returns root mean square level of last mixed block on master bus. Master gain is taken
+    /// into account.
+    pub fn master_rms(&self) -> f32 {
+        self.master_rms
+    }
+
+    /// Returns loudness of master bus measured per ITU-R BS.1770 (in LUFS). Integrated loudness is
+    /// measured since creation of context or last call of `reset_loudness`. Useful to normalize game
+    /// audio to target loudness (for example -23 LUFS of EBU R128). See `dsp::loudness` module docs
+    /// for more info.
+    pub fn loudness(&self) -> LoudnessStats {
+        self.loudness_meter.stats()
+    }
+
+    /// Resets loudness measurement of master bus.
+    pub fn reset_loudness(&mut self) {
+        self.loudness_meter.reset();
+    }
+
+    /// Calculates magnitude spectrum of last mixed block on master bus. Spectrum is calculated
+    /// over mono (L + R) / 2 signal with Hann window applied and then grouped into given amount
+    /// of bins, each bin holds maximum magnitude of frequencies it covers. Bins are evenly
+    /// distributed in `0..SAMPLE_RATE / 2` range.
+    ///
+    /// # Notes
+    ///
+    /// Spectrum is calculated on demand (on caller's thread), so this method is relatively
+    /// heavy and should not be called too often.
+    pub fn spectrum(&self, bins: usize) -> Vec<f32> {
+        let mut spectrum = vec![0.0; bins];
+
+        let len = self.last_master_block.len();
+        if bins == 0 || len < 2 {
+            return spectrum;
+        }
+
+        let window = dsp::make_window(len, dsp::hann_window);
+        let mut input = self.last_master_block
+            .iter()
+            .zip(window.iter())
+            .map(|(&(left, right), w)| Complex::new((left + right) * 0.5 * w, 0.0))
+            .collect::<Vec<Complex<f32>>>();
+        let mut output = vec![Complex::zero(); len];
+        FFTplanner::new(false).plan_fft(len).process(&mut input, &mut output);
+
+        // Second half of spectrum of real signal is a mirror of first half.
+        let half = len / 2;
+        for (i, value) in output[..half].iter().enumerate() {
+            let bin = &mut spectrum[i * bins / half];
+            *bin = bin.max(value.norm() / half as f32);
+        }
+
+        spectrum
+    }
+
+    /// Sets new output tap and returns previous one (if any). Output tap is called with each fully-mixed
+    /// block of samples right before it will be sent to output device. It can be used for recording,
+    /// loudness analysis, streaming over network, etc.
+    ///
+    /// # Real-time safety
+    ///
+    /// Tap is called on mixer thread while context is locked, so it must be as fast as possible: it
+    /// should not allocate memory, block (wait for mutexes, do I/O, etc.) or do any other heavy work,
+    /// otherwise output device will starve and you'll hear stutter. The best way is to copy samples
+    /// into a preallocated ring buffer and process them on some other thread.
+    pub fn set_output_tap(&mut self, tap: Box<OutputTap>) -> Option<Box<OutputTap>> {
+        self.output_tap.replace(tap)
+    }
+
+    /// Removes output tap and returns it (if any).
+    pub fn take_output_tap(&mut self) -> Option<Box<OutputTap>> {
+        self.output_tap.take()
+    }
+
+    /// Takes sending half of lock-free command queue, it can be moved to game thread to change
+    /// sources without locking context. Returns `None` if sender was already taken. See `command`
+    /// module docs for more info.
+    pub fn take_command_sender(&mut self) -> Option<CommandSender> {
+        self.command_sender.take()
+    }
+
+    fn apply_commands(&mut self) {
+        while let Some(command) = self.command_receiver.try_recv() {
+            match command {
+                Command::SetGain(handle, gain) => {
+                    if let Some(source) = self.sources.try_borrow_mut(handle) {
+                        source.generic_mut().set_gain(gain);
+                    }
+                }
+                Command::SetPosition(handle, position) => {
+                    if let Some(SoundSource::Spatial(spatial)) = self.sources.try_borrow_mut(handle) {
+                        spatial.set_position(&position);
+                    }
+                }
+                Command::Play(handle) => {
+                    if let Some(source) = self.sources.try_borrow_mut(handle) {
+                        source.generic_mut().play();
+                    }
+                }
+                Command::Stop(handle) => {
+                    if let Some(source) = self.sources.try_borrow_mut(handle) {
+                        // Error here means that streaming buffer cannot be rewound, there is
+                        // nobody to report it to, source is stopped anyway.
+                        source.generic_mut().stop().ok();
+                    }
+                }
+                Command::SetParameter(target, value) => {
+                    self.automations.retain(|a| a.target != target);
+                    self.set_automation_value(target, value);
+                }
+                Command::SetEffectEnabled(handle, enabled) => {
+                    if let Some(effect) = self.effects.try_borrow_mut(handle) {
+                        effect.set_enabled(enabled);
+                    }
+                }
+                Command::SetReverbSendMode(handle, send_mode) => {
+                    if let Some(Effect::Reverb(reverb)) = self.effects.try_borrow_mut(handle) {
+                        reverb.set_send_mode(send_mode);
+                    }
+                }
+                Command::SetDistortionCurve(handle, curve) => {
+                    if let Some(Effect::Distortion(distortion)) = self.effects.try_borrow_mut(handle) {
+                        distortion.set_curve(curve);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds new sound source and returns handle of it by which it can be accessed later on.
+    ///
+    /// # Notes
+    ///
+    /// Context is rendered while it is locked, so source can be added only between two rendered
+    /// blocks - it never gets rendered with half-initialized state and always starts from clean
+    /// block. Per-source state that is kept outside of source (listeners, effects) is tied to handle
+    /// of source, so source that was added in place of removed one never inherits state of it.
+    pub fn add_source(&mut self, source: SoundSource) -> Handle<SoundSource> {
+        let handle = self.sources.spawn(source);
+        let capacity = self.sources.get_capacity();
+        for listener in self.listeners.iter_mut() {
+            listener.reserve_sources(capacity);
+        }
+        for effect in self.effects.iter_mut() {
+            effect.base_mut().reserve_sources(capacity);
+        }
+        handle
+    }
+
+    /// Plays given buffer once at given position. This is convenience method which builds play-once
+    /// spatial source with given gain, adds it to context and returns its handle. Source will be
+    /// automatically removed from context when it finishes playing, so handle should be checked
+    /// with `is_valid_source` before use.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::sync::{Arc, Mutex};
+    /// use rg3d_sound::context::Context;
+    /// use rg3d_sound::buffer::SoundBuffer;
+    /// use rg3d_sound::math::vec3::Vec3;
+    ///
+    /// fn explosion(context: &mut Context, buffer: Arc<Mutex<SoundBuffer>>) {
+    ///     context.play_one_shot(buffer, Vec3::new(1.0, 0.0, 5.0), 1.0).unwrap();
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails if buffer is streaming buffer which is already in use.
+    pub fn play_one_shot(&mut self, buffer: Arc<Mutex<SoundBuffer>>, position: Vec3, gain: f32) -> Result<Handle<SoundSource>, SoundError> {
+        let generic = GenericSourceBuilder::new(buffer)
+            .with_gain(gain)
+            .with_play_once(true)
+            .with_status(Status::Playing)
+            .build()?;
+        let source = SpatialSourceBuilder::new(generic)
+            .with_position(position)
+            .build_source();
+        Ok(self.add_source(source))
+    }
+
+    /// Returns shared reference to a pool with all sound sources.
+    pub fn sources(&self) -> &Pool<SoundSource> {
+        &self.sources
+    }
+
+    /// Returns mutable reference to a pool with all sound sources.
+    pub fn sources_mut(&mut self) -> &mut Pool<SoundSource> {
+        &mut self.sources
+    }
+
+    /// Returns shared reference to sound source at given handle or `None` if handle is invalid.
+    ///
+    /// # Notes
+    ///
+    /// Handles are generation-checked: if a source was removed (for example "play once" source
+    /// that finished playing) and its slot was reused by some other source, old handle will
+    /// not alias new source, this method will return `None` instead. This makes it safe to hold
+    /// handles across frames.
+    pub fn source(&self, handle: Handle<SoundSource>) -> Option<&SoundSource> {
+        self.sources.try_borrow(handle)
+    }
+
+    /// Returns mutable reference to sound source at given handle or `None` if handle is invalid.
+    /// See `source` for more info.
+    pub fn source_mut(&mut self, handle: Handle<SoundSource>) -> Option<&mut SoundSource> {
+        self.sources.try_borrow_mut(handle)
+    }
+
+    /// Calls given closure for each sound source with its handle. This is the most efficient way
+    /// of bulk updates of sources (i.e. set positions of hundreds of sources) because there is no
+    /// need to lock context and look up source by handle for each source.
+    ///
+    /// # Iteration order
+    ///
+    /// Sources are visited in order of their slots in the pool. Order is stable until some source
+    /// will be added or removed (including automatic removal of "play once" sources).
+    pub fn update_sources<F>(&mut self, mut func: F) where F: FnMut(Handle<SoundSource>, &mut SoundSource) {
+        for i in 0..self.sources.get_capacity() {
+            let handle = self.sources.handle_from_index(i);
+            if let Some(source) = self.sources.at_mut(i) {
+                func(handle, source);
+            }
+        }
+    }
+
+    /// Returns true if given handle points to an existing sound source which is playing right now.
+    /// Returns false for invalid handles, so it is safe to use with handles of play-once sources which
+    /// could be already removed.
+    pub fn is_playing(&self, handle: Handle<SoundSource>) -> bool {
+        self.sources
+            .try_borrow(handle)
+            .is_some_and(|source| source.generic().status() == Status::Playing)
+    }
+
+    /// Checks if given handle still points to an existing sound source.
+    pub fn is_valid_source(&self, handle: Handle<SoundSource>) -> bool {
+        self.sources.is_valid_handle(handle)
+    }
+
+    /// Returns shared reference to main listener.
+    pub fn listener(&self) -> &Listener {
+        &self.listener
+    }
+
+    /// Returns mutable reference to main listener.
+    pub fn listener_mut(&mut self) -> &mut Listener {
+        &mut self.listener
+    }
+
+    /// Adds new listener and returns its handle. Spatial sources are rendered relative to each
+    /// listener into separate submixes, output of context is average of submixes of main and all
+    /// additional listeners. Useful for split-screen games. See `listener` module docs for more info.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rg3d_sound::context::Context;
+    /// use rg3d_sound::math::vec3::Vec3;
+    ///
+    /// fn setup_split_screen(context: &mut Context) {
+    ///     context.listener_mut().set_position(Vec3::new(-10.0, 0.0, 0.0));
+    ///     let second = context.add_listener();
+    ///     if let Some(listener) = context.listener_by_handle_mut(second) {
+    ///         listener.set_position(Vec3::new(10.0, 0.0, 0.0));
+    ///     }
+    /// }
+    /// ```
+    pub fn add_listener(&mut self) -> Handle<Listener> {
+        let mut listener = Listener::new();
+        listener.reserve_sources(self.sources.get_capacity());
+        self.listeners.spawn(listener)
+    }
+
+    /// Removes additional listener at given handle. Main listener cannot be removed.
+    pub fn remove_listener(&mut self, handle: Handle<Listener>) {
+        self.listeners.free(handle);
+    }
+
+    /// Returns shared reference to additional listener at given handle or `None` if handle is invalid.
+    pub fn listener_by_handle(&self, handle: Handle<Listener>) -> Option<&Listener> {
+        self.listeners.try_borrow(handle)
+    }
+
+    /// Returns mutable reference to additional listener at given handle or `None` if handle is invalid.
+    pub fn listener_by_handle_mut(&mut self, handle: Handle<Listener>) -> Option<&mut Listener> {
+        self.listeners.try_borrow_mut(handle)
+    }
+
+    /// Returns submix of additional listener from last rendered block. Master gain and effects are
+    /// not applied to it. Can be used to route output of each player to separate device. Returns `None`
+    /// if handle is invalid.
+    pub fn listener_output(&self, handle: Handle<Listener>) -> Option<&[(f32, f32)]> {
+        self.listeners.try_borrow(handle).map(|listener| listener.submix.as_slice())
+    }
+
+    /// Returns shared reference to effect at given handle or `None` if handle is invalid.
+    pub fn effect(&self, handle: Handle<Effect>) -> Option<&Effect> {
+        self.effects.try_borrow(handle)
+    }
+
+    /// Returns mutable reference to effect at given handle or `None` if handle is invalid.
+    pub fn effect_mut(&mut self, handle: Handle<Effect>) -> Option<&mut Effect> {
+        self.effects.try_borrow_mut(handle)
+    }
+
+    pub(in crate) fn render(&mut self, buf: &mut [(f32, f32)]) {
+        let last_time = time::Instant::now();
+
+        // Sources and effects are mixed on top of buffer, so it must be silent beforehand. Do not rely
+        // on caller here - empty context (no sources, no effects) must produce exact silence.
+        for (left, right) in buf.iter_mut() {
+            *left = 0.0;
+            *right = 0.0;
+        }
+
+        self.apply_commands();
+
+        for i in 0..self.sources.get_capacity() {
+            if let Some(source) = self.sources.at(i) {
+                if source.generic().is_play_once() && source.generic().status() == Status::Stopped {
+                    self.sources.free(self.sources.handle_from_index(i));
+                }
+            }
+        }
+
+        if !self.manual_update {
+            self.update_automations(block_duration(buf.len()));
+            self.update_environment(block_duration(buf.len()));
+        }
+
+        self.update_voices();
+
+        for listener in self.listeners.iter_mut() {
+            listener.begin_submix(buf.len());
+        }
+
+        if self.renderer_fade.is_some() {
+            self.fade_buf.clear();
+            self.fade_buf.resize(buf.len(), (0.0, 0.0));
+        }
+
+        for i in 0..self.sources.get_capacity() {
+            let handle = self.sources.handle_from_index(i);
+            let source = match self.sources.at_mut(i) {
+                Some(source) if source.generic().status() == Status::Playing => source,
+                Some(source) => {
+                    // Source is not rendered, so effects must not pick up its stale samples.
+                    source.generic_mut().clear_frame();
+                    continue;
+                }
+                None => continue,
+            };
+
+            source.generic_mut().render(buf.len());
+            // Only playing sources are rendered and flag is reset when source starts playing, so
+            // source is finished right in this block.
+            let finished = source.generic().is_finished();
+
+            // Virtual sources only advance their playback position.
+            if source.generic().is_virtual() {
+                if finished {
+                    self.push_diagnostic(Diagnostic::SourceFinished { source: handle });
+                }
+                continue;
+            }
+
+            self.renderer.render_source(source, &self.listener, &self.distance_model, buf);
+
+            if let Some(fade) = self.renderer_fade.as_mut() {
+                // Faded out renderer uses its own per-source state.
+                if let Some(Some((owner, state))) = fade.states.get_mut(i) {
+                    if *owner == handle {
+                        source.swap_render_state(state);
+                        fade.renderer.render_source(source, &self.listener, &self.distance_model, &mut self.fade_buf);
+                        source.swap_render_state(state);
+                    }
+                }
+            }
+
+            for listener in self.listeners.iter_mut() {
+                listener.render_to_submix(handle, source, &self.distance_model);
+            }
+
+            if finished {
+                self.push_diagnostic(Diagnostic::SourceFinished { source: handle });
+            }
+        }
+
+        self.apply_renderer_fade(buf);
+
+        let listener_count = 1 + self.listeners.iter().count();
+        if listener_count > 1 {
+            let k = 1.0 / listener_count as f32;
+            for listener in self.listeners.iter() {
+                for ((left, right), &(sub_left, sub_right)) in buf.iter_mut().zip(listener.submix.iter()) {
+                    *left += sub_left;
+                    *right += sub_right;
+                }
+            }
+            for (left, right) in buf.iter_mut() {
+                *left *= k;
+                *right *= k;
+            }
+        }
+
+        if let Renderer::HrtfRenderer(_) = self.renderer {
+            self.check_hrtf_sources();
+        }
+
+        for &handle in self.effect_chain.iter() {
+            let (last_gain, gain) = self.ducks
+                .iter()
+                .filter(|duck| duck.target == handle)
+                .fold((1.0, 1.0), |(last_gain, gain), duck| (last_gain * duck.last_gain, gain * duck.gain));
+            let effect = self.effects.borrow_mut(handle);
+            effect.base_mut().handle = handle;
+            if effect.is_insert() {
+                // Insert effect processes everything that was mixed before it, ducking is not
+                // applicable to it.
+                effect.process(buf);
+            } else if last_gain == 1.0 && gain == 1.0 {
+                effect.render(&self.sources, &self.listener, &self.distance_model, buf);
+            } else {
+                // Render ducked effect separately and mix it with interpolated gain.
+                self.effect_buf.clear();
+                self.effect_buf.resize(buf.len(), (0.0, 0.0));
+                effect.render(&self.sources, &self.listener, &self.distance_model, &mut self.effect_buf);
+                let step = 1.0 / buf.len() as f32;
+                for (i, ((left, right), &(effect_left, effect_right))) in buf.iter_mut().zip(self.effect_buf.iter()).enumerate() {
+                    let k = math::lerpf(last_gain, gain, i as f32 * step);
+                    *left += effect_left * k;
+                    *right += effect_right * k;
+                }
+            }
+        }
+
+        self.update_ducks();
+
+        if self.sanitize_output {
+            for (left, right) in buf.iter_mut() {
+                for sample in [left, right].iter_mut() {
+                    if !sample.is_finite() {
+                        **sample = 0.0;
+                        self.nan_count += 1;
+                    }
+                }
+            }
+        }
+
+        if let Some((left_blocker, right_blocker)) = self.dc_blocker.as_mut() {
+            for (left, right) in buf.iter_mut() {
+                *left = left_blocker.feed(*left);
+                *right = right_blocker.feed(*right);
+            }
+        }
+
+        // Apply master gain and balance to be able to control total sound volume.
+        let clip_mode = self.clip_mode;
+        let left_gain = self.master_gain * (1.0 - self.master_balance.max(0.0));
+        let right_gain = self.master_gain * (1.0 + self.master_balance.min(0.0));
+        for (left, right) in buf.iter_mut() {
+            *left = clip_mode.apply(*left * left_gain);
+            *right = clip_mode.apply(*right * right_gain);
+        }
+
+        if self.output_format == SampleFormat::I16 {
+            for (left, right) in buf.iter_mut() {
+                *left = dsp::i16_to_sample(dsp::sample_to_i16(*left));
+                *right = dsp::i16_to_sample(dsp::sample_to_i16(*right));
+            }
+        }
+
+        let (peak, rms) = dsp::measure_levels(buf);
+        self.master_peak = peak;
+        self.master_rms = rms;
+        self.loudness_meter.feed(buf);
+        self.last_master_block.clear();
+        self.last_master_block.extend_from_slice(buf);
+
+        if let Some(tap) = self.output_tap.as_mut() {
+            tap(buf);
+        }
+
+        self.render_duration = time::Instant::now() - last_time;
+
+        if self.render_duration > block_duration(buf.len()) {
+            self.underrun_count.fetch_add(1, AtomicOrdering::SeqCst);
+        }
+
+        let underrun_count = self.underrun_count.load(AtomicOrdering::SeqCst);
+        if underrun_count != self.reported_underrun_count {
+            self.reported_underrun_count = underrun_count;
+            if let Some(callback) = self.underrun_callback.as_mut() {
+                callback(underrun_count);
+            }
+        }
+    }
+}
+
+fn block_duration(samples_per_channel: usize) -> Duration {
+    Duration::from_secs_f64(samples_per_channel as f64 / f64::from(device::SAMPLE_RATE))
+}
+
+// Fills buffer with last block faded to silence, next call (if any) will give silence.
+fn fade_out_last_block(buf: &mut [(f32, f32)], last_block: &mut Vec<(f32, f32)>) {
+    let step = 1.0 / buf.len() as f32;
+    for (i, out) in buf.iter_mut().enumerate() {
+        let k = 1.0 - i as f32 * step;
+        let last = last_block.get(i).cloned().unwrap_or((0.0, 0.0));
+        out.0 = last.0 * k;
+        out.1 = last.1 * k;
+    }
+    last_block.clear();
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        if let Some(mut mixer) = self.mixer.take() {
+            mixer.stop();
+        }
+    }
+}
+
+impl Visit for Context {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        if visitor.is_reading() {
+            self.sources.clear();
+        }
+
+        self.master_gain.visit("MasterGain", visitor)?;
+        self.listener.visit("Listener", visitor)?;
+        self.sources.visit("Sources", visitor)?;
+        self.effects.visit("Effects", visitor)?;
+        if self.effect_chain.visit("EffectChain", visitor).is_err() && visitor.is_reading() {
+            // Older versions have no explicit chain, effects were rendered in order of pool.
+            self.effect_chain = (0..self.effects.get_capacity())
+                .filter(|&i| self.effects.at(i).is_some())
+                .map(|i| self.effects.handle_from_index(i))
+                .collect();
+        }
+        // usize is not visitable, so it is stored as u64.
+        let mut max_voices = self.max_voices as u64;
+        if max_voices.visit("MaxVoices", visitor).is_ok() {
+            if visitor.is_reading() {
+                self.max_voices = max_voices as usize;
+            }
+        } else if visitor.is_reading() {
+            // Older versions have no voice limit.
+            self.max_voices = usize::MAX;
+        }
+        let mut clip_mode = self.clip_mode.id();
+        if clip_mode.visit("ClipMode", visitor).is_ok() {
+            if visitor.is_reading() {
+                self.clip_mode = ClipMode::from_id(clip_mode);
+            }
+        } else if visitor.is_reading() {
+            self.clip_mode = ClipMode::default();
+        }
+        if self.master_balance.visit("MasterBalance", visitor).is_err() && visitor.is_reading() {
+            // Older versions have no balance.
+            self.master_balance = 0.0;
+        }
+
+        visitor.leave_region()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use rg3d_core::{
+        pool::Handle,
+        visitor::Visit,
+    };
+    use crate::{
+        context::{Context, ClipMode, SampleFormat, DistanceModel, Diagnostic},
+        automation::{AutomationTarget, AutomationCurve},
+        command::Command,
+        listener::Listener,
+        renderer::Render,
+        effects::{
+            Effect,
+            EffectInput,
+            EffectTrait,
+            reverb::{Reverb, ReverbSendMode},
+            distortion::{Distortion, DistortionCurve},
+            noise_gate::NoiseGate,
+            bit_crusher::BitCrusher,
+        },
+        source::{
+            SoundSource,
+            Status,
+            generic::GenericSourceBuilder,
+            spatial::SpatialSourceBuilder,
+        },
+        math::vec3::Vec3,
+        device::{self, NativeSample},
+        test_support,
+    };
+
+    const SAMPLE_RATE: usize = 44100;
+
+    fn make_source() -> SoundSource {
+        GenericSourceBuilder::new(test_support::make_buffer(vec![0.0; 64], SAMPLE_RATE))
+            .build_source()
+            .unwrap()
+    }
+
+    // Playing source with sine of 441 Hz (period of exactly 100 samples) and given amplitude.
+    fn make_tone_source(amplitude: f32) -> SoundSource {
+        let samples = test_support::sine(441.0, SAMPLE_RATE, SAMPLE_RATE)
+            .iter()
+            .map(|s| s * amplitude)
+            .collect();
+        GenericSourceBuilder::new(test_support::make_buffer(samples, SAMPLE_RATE))
+            .with_status(Status::Playing)
+            .build_source()
+            .unwrap()
+    }
+
+    // Playing source with constant signal, so its contribution to the mix is easy to predict.
+    fn make_dc_builder(value: f32) -> GenericSourceBuilder {
+        GenericSourceBuilder::new(test_support::make_buffer(vec![value; SAMPLE_RATE], SAMPLE_RATE))
+            .with_status(Status::Playing)
+    }
+
+    fn render(context: &mut Context, len: usize) -> Vec<(f32, f32)> {
+        let mut buf = vec![(0.0, 0.0); len];
+        context.render(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn source_by_valid_and_invalid_handle() {
+        let mut context = Context::default_state();
+        let handle = context.add_source(make_source());
+        assert!(context.source(handle).is_some());
+        assert!(context.source_mut(handle).is_some());
+
+        context.sources_mut().free(handle);
+        assert!(context.source(handle).is_none());
+        assert!(context.source_mut(handle).is_none());
+
+        // Slot of removed source is reused, but stale handle must not alias new source.
+        let new_handle = context.add_source(make_source());
+        assert_eq!(new_handle.index(), handle.index());
+        assert!(context.source(handle).is_none());
+        assert!(context.source(new_handle).is_some());
+    }
+
+    #[test]
+    fn listener_and_effect_by_valid_and_invalid_handle() {
+        let mut context = Context::default_state();
+        let listener = context.add_listener();
+        let effect = context.add_effect(Effect::Reverb(Reverb::new(Default::default())));
+        assert!(context.listener_by_handle(listener).is_some());
+        assert!(context.listener_by_handle_mut(listener).is_some());
+        assert!(context.listener_output(listener).is_some());
+        assert!(context.effect(effect).is_some());
+        assert!(context.effect_mut(effect).is_some());
+
+        context.remove_listener(listener);
+        context.remove_effect(effect);
+        assert!(context.listener_by_handle(listener).is_none());
+        assert!(context.listener_by_handle_mut(listener).is_none());
+        assert!(context.listener_output(listener).is_none());
+        assert!(context.effect(effect).is_none());
+        assert!(context.effect_mut(effect).is_none());
+    }
+
+    #[test]
+    fn sources_added_from_other_thread_start_at_block_boundary() {
+        let context = Arc::new(Mutex::new(Context::default_state()));
+
+        let adder = {
+            let context = context.clone();
+            std::thread::spawn(move || {
+                let mut handles = Vec::new();
+                for _ in 0..500 {
+                    let source = make_dc_builder(0.125).build_source().unwrap();
+                    let mut context = context.lock().unwrap();
+                    handles.push(context.add_source(source));
+                    if handles.len() > 4 {
+                        context.sources_mut().free(handles.remove(0));
+                    }
+                }
+            })
+        };
+
+        // Each block must be rendered either with or without any given source, and fresh
+        // source must contribute its full level from the very first sample of a block.
+        let mut blocks = 0;
+        while !adder.is_finished() || blocks == 0 {
+            let buf = render(&mut context.lock().unwrap(), 64);
+            let first = buf[0].0;
+            assert!(buf.iter().all(|&(left, right)| left == first && right == first));
+            assert_eq!((first / 0.125).fract(), 0.0);
+            blocks += 1;
+        }
+        adder.join().unwrap();
+    }
+
+    #[test]
+    fn master_levels_of_known_tone() {
+        let mut context = Context::default_state();
+        context.add_source(make_tone_source(0.5));
+        render(&mut context, 4400);
+        assert!((context.master_peak() - 0.5).abs() < 1.0e-3);
+        assert!((context.master_rms() - 0.5 / 2.0f32.sqrt()).abs() < 1.0e-3);
+
+        // 441 Hz falls into second of 64 bins, each bin covers ~345 Hz.
+        let spectrum = context.spectrum(64);
+        let peak = spectrum.iter().cloned().fold(0.0, f32::max);
+        assert_eq!(spectrum[1], peak);
+    }
+
+    #[test]
+    fn voices_are_limited_by_priority() {
+        let mut context = Context::default_state();
+        context.set_max_voices(2);
+        let low = context.add_source(make_dc_builder(0.1).with_priority(0).build_source().unwrap());
+        context.add_source(make_dc_builder(0.2).with_priority(1).build_source().unwrap());
+        let high = context.add_source(make_dc_builder(0.4).with_priority(2).build_source().unwrap());
+
+        let buf = render(&mut context, 256);
+        assert!((buf[255].0 - 0.6).abs() < 1.0e-5);
+        assert!(context.source(low).unwrap().generic().is_virtual());
+        assert!(!context.source(high).unwrap().generic().is_virtual());
+
+        // Culled source resumes mixing when a voice frees up.
+        context.sources_mut().free(high);
+        let buf = render(&mut context, 256);
+        assert!((buf[255].0 - 0.3).abs() < 1.0e-5);
+        assert!(!context.source(low).unwrap().generic().is_virtual());
+    }
+
+    // Tests which run null device are not run in parallel, so they can count mixer threads.
+    static NULL_DEVICE_LOCK: Mutex<()> = Mutex::new(());
+
+    // Returns amount of running mixer threads of this process, `None` if it cannot be determined.
+    fn mixer_thread_count() -> Option<usize> {
+        let tasks = std::fs::read_dir("/proc/self/task").ok()?;
+        Some(tasks
+            .filter_map(|task| task.ok())
+            .filter_map(|task| std::fs::read_to_string(task.path().join("comm")).ok())
+            // Name of thread is truncated to 15 bytes.
+            .filter(|name| name.starts_with("rg3d-sound mix"))
+            .count())
+    }
+
+    #[test]
+    fn null_context_renders_in_real_time() {
+        let _guard = NULL_DEVICE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let context = Context::new_null().unwrap();
+        let handle = context.lock().unwrap().add_source(make_tone_source(0.5));
+        std::thread::sleep(Duration::from_millis(200));
+
+        let context = context.lock().unwrap();
+        assert!(context.samples_rendered() > 0);
+        assert!(context.time() > Duration::from_millis(50));
+        assert!(context.master_peak() > 0.0);
+        assert!(context.source(handle).unwrap().generic().peak_level() > 0.0);
+    }
+
+    #[test]
+    fn dropped_null_context_stops_mixer_thread() {
+        let _guard = NULL_DEVICE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        for _ in 0..5 {
+            let context = Context::new_null().unwrap();
+            context.lock().unwrap().add_source(make_tone_source(0.5));
+            std::thread::sleep(Duration::from_millis(20));
+            if let Some(count) = mixer_thread_count() {
+                assert_eq!(count, 1);
+            }
+
+            // Mixer thread holds only weak reference, so context is destroyed right here and its
+            // mixer thread is joined.
+            let weak = Arc::downgrade(&context);
+            drop(context);
+            assert!(weak.upgrade().is_none());
+            if let Some(count) = mixer_thread_count() {
+                assert_eq!(count, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn null_context_latency_matches_device_buffer() {
+        let _guard = NULL_DEVICE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let context = Context::new_null().unwrap();
+        let samples_per_channel = Context::DEVICE_BUFFER_LEN_BYTES as usize / std::mem::size_of::<NativeSample>();
+        let expected = Duration::from_secs_f64(samples_per_channel as f64 / f64::from(device::SAMPLE_RATE));
+        assert_eq!(context.lock().unwrap().output_latency(), expected);
+    }
+
+    #[test]
+    fn output_tap_receives_device_output() {
+        let mut context = Context::default_state();
+        context.add_source(make_tone_source(0.5));
+        let tapped = Arc::new(Mutex::new(Vec::new()));
+        let tap_output = tapped.clone();
+        context.set_output_tap(Box::new(move |block| {
+            tap_output.lock().unwrap().extend_from_slice(block);
+        }));
+
+        let mut output = render(&mut context, 512);
+        output.extend(render(&mut context, 512));
+        assert_eq!(*tapped.lock().unwrap(), output);
+    }
+
+    #[test]
+    fn batch_update_visits_each_source_once() {
+        let mut context = Context::default_state();
+        let handles = (0..5)
+            .map(|_| {
+                let generic = make_dc_builder(0.1).build().unwrap();
+                context.add_source(SpatialSourceBuilder::new(generic).build_source())
+            })
+            .collect::<Vec<_>>();
+
+        let mut visited = Vec::new();
+        context.update_sources(|handle, source| {
+            visited.push(handle);
+            if let SoundSource::Spatial(spatial) = source {
+                spatial.set_position(&Vec3::new(handle.index() as f32, 0.0, 0.0));
+            }
+        });
+
+        assert_eq!(visited, handles);
+        for handle in handles {
+            match context.source(handle) {
+                Some(SoundSource::Spatial(spatial)) => assert_eq!(spatial.position().x, handle.index() as f32),
+                _ => panic!("source must be spatial"),
+            }
+        }
+    }
+
+    #[test]
+    fn locked_context_is_underrun() {
+        let mut context = Context::default_state();
+        context.add_source(make_dc_builder(0.5).build_source().unwrap());
+        let context = Arc::new(Mutex::new(context));
+        let mut callback = Context::make_mixer_callback(&context);
+
+        let mut buf = vec![(0.0, 0.0); 256];
+        callback(&mut buf);
+        let last_block = buf.clone();
+        assert_eq!(context.lock().unwrap().underrun_count(), 0);
+
+        // Mixer must not wait for context, it is underrun.
+        let guard = context.lock().unwrap();
+        callback(&mut buf);
+        drop(guard);
+        assert_eq!(context.lock().unwrap().underrun_count(), 1);
+        // Last block is faded to silence.
+        assert_eq!(buf[0], last_block[0]);
+        assert!(buf[255].0.abs() < 0.01 && buf[255].1.abs() < 0.01);
+        // Next underrun gives silence, there is nothing to fade.
+        let guard = context.lock().unwrap();
+        callback(&mut buf);
+        drop(guard);
+        assert!(buf.iter().all(|&(left, right)| left == 0.0 && right == 0.0));
+        assert_eq!(context.lock().unwrap().underrun_count(), 2);
+    }
+
+    #[test]
+    fn only_recent_diagnostics_are_kept() {
+        let mut context = Context::default_state();
+        let capacity = context.diagnostics.capacity();
+        for channel_count in 0..Context::MAX_DIAGNOSTICS + 10 {
+            context.push_diagnostic(Diagnostic::NonMonoHrtfSource { source: Handle::NONE, channel_count });
+        }
+        assert_eq!(context.diagnostics.capacity(), capacity);
+        let channel_counts = context.diagnostics()
+            .map(|diagnostic| match diagnostic {
+                Diagnostic::NonMonoHrtfSource { channel_count, .. } => channel_count,
+                _ => panic!("expected NonMonoHrtfSource"),
+            })
+            .collect::<Vec<usize>>();
+        assert_eq!(channel_counts, (10..Context::MAX_DIAGNOSTICS + 10).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn audio_clock_counts_samples_passed_to_device() {
+        let context = Arc::new(Mutex::new(Context::default_state()));
+        let mut callback = Context::make_mixer_callback(&context);
+        assert_eq!(context.lock().unwrap().samples_rendered(), 0);
+        assert_eq!(context.lock().unwrap().time(), Duration::from_secs(0));
+
+        let mut buf = vec![(0.0, 0.0); Context::SAMPLES_PER_CHANNEL];
+        for _ in 0..10 {
+            callback(&mut buf);
+        }
+        let expected = 10 * Context::SAMPLES_PER_CHANNEL as u64;
+        assert_eq!(context.lock().unwrap().samples_rendered(), expected);
+
+        // Clock advances on underrun too.
+        let guard = context.lock().unwrap();
+        callback(&mut buf[..100]);
+        drop(guard);
+        let expected = expected + 100;
+        let context = context.lock().unwrap();
+        assert_eq!(context.samples_rendered(), expected);
+        let nanos = expected * 1_000_000_000 / SAMPLE_RATE as u64;
+        assert_eq!(context.time(), Duration::from_nanos(nanos));
+
+        // Offline rendering does not advance clock, its output is not passed to device.
+        let mut offline = Context::default_state();
+        render(&mut offline, 1024);
+        assert_eq!(offline.samples_rendered(), 0);
+    }
+
+    #[test]
+    fn slow_render_is_underrun() {
+        let mut context = Context::default_state();
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let callback_reported = reported.clone();
+        context.set_underrun_callback(Box::new(move |count| callback_reported.lock().unwrap().push(count)));
+        // Block of 64 samples lasts less than 2 ms.
+        context.set_output_tap(Box::new(|_| std::thread::sleep(Duration::from_millis(10))));
+        render(&mut context, 64);
+        assert_eq!(context.underrun_count(), 1);
+        assert_eq!(*reported.lock().unwrap(), vec![1]);
+    }
+
+    // Renders noise source through reverb (which takes source as input) and linear gain of 0.5 as
+    // insert effect. Gain goes before or after reverb in effects chain, or it is not used at all.
+    fn render_chain(with_reverb: bool, gain: Option<bool>) -> Vec<(f32, f32)> {
+        let mut context = Context::default_state();
+        // Quiet enough to not be clipped by master stage.
+        let samples = test_support::noise(SAMPLE_RATE, 9).iter().map(|s| s * 0.1).collect();
+        let source = context.add_source(GenericSourceBuilder::new(test_support::make_buffer(samples, SAMPLE_RATE))
+            .with_status(Status::Playing)
+            .build_source()
+            .unwrap());
+        if with_reverb {
+            let mut reverb = Reverb::new(Default::default());
+            reverb.set_dry(0.0);
+            reverb.base_mut().add_input(EffectInput::direct(source));
+            context.add_effect(Effect::Reverb(reverb));
+        }
+        if let Some(after_reverb) = gain {
+            let mut distortion = Distortion::new(Default::default());
+            distortion.set_curve(DistortionCurve::Linear);
+            distortion.set_drive(1.0);
+            distortion.set_output_gain(0.5);
+            distortion.set_mix(1.0);
+            let mut effect = Effect::Distortion(distortion);
+            effect.set_insert(true);
+            let index = if after_reverb { context.effect_chain().len() } else { 0 };
+            context.insert_effect(index, effect);
+        }
+        let mut output = Vec::new();
+        for _ in 0..4 {
+            output.extend(render(&mut context, 1024));
+        }
+        output
+    }
+
+    fn assert_close(a: &[(f32, f32)], b: &[(f32, f32)]) {
+        for (a, b) in a.iter().zip(b) {
+            assert!((a.0 - b.0).abs() < 1.0e-5 && (a.1 - b.1).abs() < 1.0e-5);
+        }
+    }
+
+    #[test]
+    fn order_of_insert_effect_matters() {
+        let dry = render_chain(false, None);
+        let plain = render_chain(true, None);
+        let gain_before = render_chain(true, Some(false));
+        let gain_after = render_chain(true, Some(true));
+
+        // Gain before reverb scales only dry signal of source.
+        let expected = plain.iter().zip(dry.iter()).map(|(p, d)| (p.0 - 0.5 * d.0, p.1 - 0.5 * d.1)).collect::<Vec<_>>();
+        assert_close(&gain_before, &expected);
+        // Gain after reverb scales reverb tail too.
+        let expected = plain.iter().map(|p| (p.0 * 0.5, p.1 * 0.5)).collect::<Vec<_>>();
+        assert_close(&gain_after, &expected);
+        assert_ne!(gain_before, gain_after);
+    }
+
+    // Dialogue (DC of 0.5 during first second) goes to trigger effect, looping music goes to target
+    // effect. Both effects pass input through as is.
+    fn make_ducking_context(with_target: bool, with_duck: bool) -> (Context, Handle<Effect>) {
+        let mut context = Context::default_state();
+        let dialogue = context.add_source(make_dc_builder(0.5).build_source().unwrap());
+        let mut music = make_tone_source(0.5);
+        music.generic_mut().set_looping(true);
+        let music = context.add_source(music);
+
+        let mut passthrough = |input| {
+            let mut distortion = Distortion::new(Default::default());
+            distortion.set_mix(0.0);
+            distortion.base_mut().add_input(EffectInput::direct(input));
+            context.add_effect(Effect::Distortion(distortion))
+        };
+        let trigger = passthrough(dialogue);
+        let target = if with_target { passthrough(music) } else { Handle::NONE };
+        if with_duck {
+            context.set_duck(trigger, target, 0.5, Duration::from_millis(10), Duration::from_millis(100));
+        }
+        (context, target)
+    }
+
+    #[test]
+    fn source_sends_to_several_buses_at_own_levels() {
+        let mut context = Context::default_state();
+        let mut reverb = Reverb::new(Default::default());
+        reverb.set_send_mode(ReverbSendMode::Send);
+        let reverb = context.add_effect(Effect::Reverb(reverb));
+        // Stub passes its input through, so it stands for any other bus, i.e. delay.
+        let delay = context.add_effect(Effect::default());
+        let source = context.add_source(make_dc_builder(0.5).build_source().unwrap());
+        let dry = context.add_source(make_dc_builder(0.25).build_source().unwrap());
+        context.source_mut(source).unwrap().generic_mut()
+            .set_send(reverb, 0.5)
+            .set_send(delay, 0.25);
+
+        render(&mut context, 256);
+        let input = |context: &Context, effect: Handle<Effect>| context.effect(effect).unwrap().base().input_samples()[255];
+        assert_eq!(input(&context, reverb), (0.25, 0.25));
+        assert_eq!(input(&context, delay), (0.125, 0.125));
+
+        // Source without sends stays dry, removed send no longer feeds the bus.
+        assert!(context.source(dry).unwrap().generic().sends().is_empty());
+        context.source_mut(source).unwrap().generic_mut().set_send(delay, 0.0);
+        render(&mut context, 256);
+        assert_eq!(input(&context, reverb), (0.25, 0.25));
+        assert_eq!(input(&context, delay), (0.0, 0.0));
+    }
+
+    #[test]
+    fn active_trigger_ducks_target_by_amount() {
+        let (mut ducked, target) = make_ducking_context(true, true);
+        let (mut plain, _) = make_ducking_context(true, false);
+        let (mut dry, _) = make_ducking_context(false, false);
+        assert_eq!(ducked.duck_gain(target), 1.0);
+
+        // Blocks of 0.1 s, ducking settles well within 0.4 s.
+        let level = |context: &mut Context| {
+            let mut output = Vec::new();
+            for _ in 0..5 {
+                output = render(context, 4410);
+            }
+            output
+        };
+        let ducked_output = level(&mut ducked);
+        let plain_output = level(&mut plain);
+        let dry_output = level(&mut dry);
+        assert!((ducked.duck_gain(target) - 0.5).abs() < 0.01);
+        // Output of target effect is attenuated by configured amount.
+        let target_level = |output: &[(f32, f32)]| {
+            let difference = output.iter().zip(dry_output.iter()).map(|(a, b)| a.0 - b.0).collect::<Vec<f32>>();
+            test_support::rms(&difference)
+        };
+        let ratio = target_level(&ducked_output) / target_level(&plain_output);
+        assert!((ratio - 0.5).abs() < 0.01, "{}", ratio);
+
+        // Dialogue ends after 1 s, ducking is released.
+        for _ in 0..10 {
+            render(&mut ducked, 4410);
+        }
+        assert!(ducked.duck_gain(target) > 0.99, "{}", ducked.duck_gain(target));
+
+        // Ducking is removed together with effect.
+        ducked.remove_effect(target);
+        assert_eq!(ducked.duck_gain(target), 1.0);
+    }
+
+    #[test]
+    fn effect_chain_can_be_reordered() {
+        let mut context = Context::default_state();
+        let a = context.add_effect(Effect::default());
+        let b = context.add_effect(Effect::default());
+        let c = context.insert_effect(0, Effect::default());
+        assert_eq!(context.effect_chain(), &[c, a, b]);
+        context.move_effect(0, 2);
+        assert_eq!(context.effect_chain(), &[a, b, c]);
+        context.remove_effect_at(1);
+        assert_eq!(context.effect_chain(), &[a, c]);
+        context.remove_effect(a);
+        assert_eq!(context.effect_chain(), &[c]);
+    }
+
+    fn reverb_decay(context: &Context, handle: Handle<Effect>) -> f32 {
+        match context.effect(handle).unwrap() {
+            Effect::Reverb(reverb) => reverb.decay_time().as_secs_f32(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn automation_ramps_reverb_decay() {
+        for &curve in [AutomationCurve::Linear, AutomationCurve::EaseInOut].iter() {
+            let mut context = Context::default_state();
+            let mut reverb = Reverb::new(Default::default());
+            reverb.set_decay_time(Duration::from_secs(1));
+            let handle = context.add_effect(Effect::Reverb(reverb));
+            context.automate(AutomationTarget::ReverbDecayTime(handle), 5.0, Duration::from_secs(1), curve);
+
+            // Each block is 0.1 s long.
+            for i in 1..=12 {
+                render(&mut context, 4410);
+                let t = (i as f32 * 0.1).min(1.0);
+                let expected = 1.0 + 4.0 * curve.apply(t);
+                assert!((reverb_decay(&context, handle) - expected).abs() < 0.01);
+            }
+        }
+    }
+
+    fn source_gain(context: &Context, handle: Handle<SoundSource>) -> f32 {
+        context.source(handle).unwrap().generic().gain()
+    }
+
+    #[test]
+    fn manual_update_completes_fade_after_cumulative_time() {
+        let mut context = Context::default_state();
+        context.set_manual_update(true);
+        let handle = context.add_source(make_tone_source(0.5));
+        let target = AutomationTarget::SourceGain(handle);
+        context.automate(target, 0.0, Duration::from_secs(1), AutomationCurve::Linear);
+
+        // Mixer does not advance automations in manual mode.
+        render(&mut context, SAMPLE_RATE);
+        assert_eq!(source_gain(&context, handle), 1.0);
+
+        for i in 1..10 {
+            context.update(Duration::from_millis(100));
+            render(&mut context, 512);
+            assert!((source_gain(&context, handle) - (1.0 - i as f32 * 0.1)).abs() < 1.0e-5);
+            assert!(context.is_automated(target));
+        }
+        context.update(Duration::from_millis(100));
+        assert_eq!(source_gain(&context, handle), 0.0);
+        assert!(!context.is_automated(target));
+    }
+
+    #[test]
+    fn update_does_not_step_automations_in_automatic_mode() {
+        let mut context = Context::default_state();
+        let handle = context.add_source(make_tone_source(0.5));
+        let target = AutomationTarget::SourceGain(handle);
+        context.automate(target, 0.0, Duration::from_secs(1), AutomationCurve::Linear);
+        context.update(Duration::from_secs(1));
+        assert!(!context.is_manual_update());
+        assert_eq!(source_gain(&context, handle), 1.0);
+        assert!(context.is_automated(target));
+    }
+
+    #[test]
+    fn update_derives_velocity_of_moved_sources() {
+        let mut context = Context::default_state();
+        let source = SpatialSourceBuilder::new(GenericSourceBuilder::new(test_support::make_buffer(vec![0.0; 64], SAMPLE_RATE))
+            .build()
+            .unwrap())
+            .build_source();
+        let handle = context.add_source(source);
+        let velocity = |context: &Context| match context.source(handle) {
+            Some(SoundSource::Spatial(spatial)) => spatial.velocity(),
+            _ => unreachable!(),
+        };
+
+        context.update(Duration::from_millis(100));
+        assert_eq!(velocity(&context), Vec3::ZERO);
+
+        if let Some(SoundSource::Spatial(spatial)) = context.source_mut(handle) {
+            spatial.set_position(&Vec3::new(1.0, 0.0, 0.0));
+        }
+        context.update(Duration::from_millis(100));
+        assert!((velocity(&context) - Vec3::new(10.0, 0.0, 0.0)).len() < 1.0e-4);
+
+        context.update(Duration::from_millis(100));
+        assert_eq!(velocity(&context), Vec3::ZERO);
+    }
+
+    #[test]
+    fn source_localizes_differently_for_each_listener() {
+        let mut context = Context::default_state();
+        context.listener_mut().set_position(Vec3::new(-10.0, 0.0, 0.0));
+        let second = context.add_listener();
+        context.listener_by_handle_mut(second).unwrap().set_position(Vec3::new(10.0, 0.0, 0.0));
+        let generic = GenericSourceBuilder::new(test_support::make_buffer(vec![0.5; SAMPLE_RATE], SAMPLE_RATE))
+            .with_status(Status::Playing)
+            .build()
+            .unwrap();
+        context.add_source(SpatialSourceBuilder::new(generic).build_source());
+
+        let buf = render(&mut context, 512);
+        let (sub_left, sub_right) = context.listener_output(second).unwrap()[256];
+        // Output is average of submixes of both listeners.
+        let (main_left, main_right) = (2.0 * buf[256].0 - sub_left, 2.0 * buf[256].1 - sub_right);
+        let balance = |left: f32, right: f32| (right - left) / (right + left);
+        let main_balance = balance(main_left, main_right);
+        let second_balance = balance(sub_left, sub_right);
+        assert!(main_balance.abs() > 0.5, "{}", main_balance);
+        // Listeners are symmetric, so source is heard at opposite sides.
+        assert!((main_balance + second_balance).abs() < 1.0e-4, "{} {}", main_balance, second_balance);
+    }
+
+    #[test]
+    fn one_shot_is_played_and_reclaimed() {
+        let mut context = Context::default_state();
+        let buffer = test_support::make_buffer(vec![0.5; 1000], SAMPLE_RATE);
+        let handle = context.play_one_shot(buffer, Vec3::new(0.0, 0.0, 1.0), 0.5).unwrap();
+        match context.source(handle) {
+            Some(SoundSource::Spatial(spatial)) => {
+                assert_eq!(spatial.position(), Vec3::new(0.0, 0.0, 1.0));
+                assert_eq!(spatial.generic().gain(), 0.5);
+                assert_eq!(spatial.generic().status(), Status::Playing);
+            }
+            _ => panic!("one-shot must be spatial source"),
+        }
+
+        let buf = render(&mut context, 512);
+        assert!(buf.iter().any(|&(left, right)| left != 0.0 || right != 0.0));
+
+        // Source is stopped at the end of buffer and removed on next render pass.
+        render(&mut context, 1024);
+        render(&mut context, 512);
+        assert!(context.source(handle).is_none());
+        assert_eq!(context.sources().iter().count(), 0);
+    }
+
+    #[test]
+    fn queued_commands_are_applied_in_order_by_render() {
+        let mut context = Context::default_state();
+        let generic = GenericSourceBuilder::new(test_support::make_buffer(vec![0.5; SAMPLE_RATE], SAMPLE_RATE))
+            .build()
+            .unwrap();
+        let handle = context.add_source(SpatialSourceBuilder::new(generic).build_source());
+        let mut sender = context.take_command_sender().unwrap();
+        assert!(context.take_command_sender().is_none());
+
+        std::thread::spawn(move || {
+            sender.send(Command::SetGain(handle, 0.2)).ok().unwrap();
+            sender.send(Command::SetPosition(handle, Vec3::new(1.0, 0.0, 0.0))).ok().unwrap();
+            sender.send(Command::SetGain(handle, 0.7)).ok().unwrap();
+            sender.send(Command::Play(handle)).ok().unwrap();
+        }).join().unwrap();
+
+        // Nothing is applied until next render pass.
+        assert_eq!(source_gain(&context, handle), 1.0);
+        assert_eq!(context.source(handle).unwrap().generic().status(), Status::Stopped);
+
+        let buf = render(&mut context, 512);
+        assert_eq!(source_gain(&context, handle), 0.7);
+        match context.source(handle) {
+            Some(SoundSource::Spatial(spatial)) => {
+                assert_eq!(spatial.position(), Vec3::new(1.0, 0.0, 0.0));
+                assert_eq!(spatial.generic().status(), Status::Playing);
+            }
+            _ => unreachable!(),
+        }
+        // Source was started by command before it was rendered.
+        assert!(buf.iter().any(|&(left, right)| left != 0.0 || right != 0.0));
+    }
+
+    #[test]
+    fn set_parameter_reaches_every_effect_parameter() {
+        let mut context = Context::default_state();
+        let reverb = context.add_effect(Effect::Reverb(Reverb::new(Default::default())));
+        let gate = context.add_effect(Effect::NoiseGate(NoiseGate::new(Default::default())));
+        let crusher = context.add_effect(Effect::BitCrusher(BitCrusher::new(Default::default())));
+        let distortion = context.add_effect(Effect::Distortion(Distortion::new(Default::default())));
+        let mut sender = context.take_command_sender().unwrap();
+
+        let parameters = [
+            (AutomationTarget::ReverbDry(reverb), 0.3),
+            (AutomationTarget::ReverbWet(reverb), 0.6),
+            (AutomationTarget::ReverbDecayTime(reverb), 2.5),
+            (AutomationTarget::ReverbStereoWidth(reverb), 0.4),
+            (AutomationTarget::ReverbFc(reverb), 0.2),
+            (AutomationTarget::ReverbRoomSize(reverb), 1.5),
+            (AutomationTarget::ReverbReflectionsGain(reverb), 0.5),
+            (AutomationTarget::NoiseGateThresholdDb(gate), -40.0),
+            (AutomationTarget::NoiseGateAttack(gate), 0.002),
+            (AutomationTarget::NoiseGateRelease(gate), 0.3),
+            (AutomationTarget::NoiseGateHold(gate), 0.05),
+            (AutomationTarget::BitCrusherBitDepth(crusher), 6.0),
+            (AutomationTarget::BitCrusherDownsample(crusher), 3.0),
+            (AutomationTarget::DistortionDrive(distortion), 4.0),
+            (AutomationTarget::DistortionOutputGain(distortion), 0.5),
+            (AutomationTarget::DistortionMix(distortion), 0.7),
+            (AutomationTarget::DistortionOversampling(distortion), 4.0),
+        ];
+        for &(target, value) in parameters.iter() {
+            sender.send(Command::SetParameter(target, value)).ok().unwrap();
+        }
+        sender.send(Command::SetReverbSendMode(reverb, ReverbSendMode::Send)).ok().unwrap();
+        sender.send(Command::SetDistortionCurve(distortion, DistortionCurve::HardClip)).ok().unwrap();
+        render(&mut context, 512);
+
+        for &(target, value) in parameters.iter() {
+            let actual = context.automation_value(target).unwrap();
+            assert!((actual - value).abs() < 1.0e-3, "{} != {}", actual, value);
+        }
+        match context.effect(reverb).unwrap() {
+            Effect::Reverb(reverb) => assert_eq!(reverb.send_mode(), ReverbSendMode::Send),
+            _ => unreachable!(),
+        }
+        match context.effect(distortion).unwrap() {
+            Effect::Distortion(distortion) => assert_eq!(distortion.curve(), DistortionCurve::HardClip),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn non_finite_decay_time_does_not_panic() {
+        let mut context = Context::default_state();
+        let handle = context.add_effect(Effect::Reverb(Reverb::new(Default::default())));
+        let mut sender = context.take_command_sender().unwrap();
+        for &value in [f32::INFINITY, f32::NAN, -1.0, 1.0e30].iter() {
+            sender.send(Command::SetParameter(AutomationTarget::ReverbDecayTime(handle), value)).ok().unwrap();
+            render(&mut context, 512);
+            assert!(reverb_decay(&context, handle).is_finite());
+        }
+    }
+
+    // Largest difference between neighbour samples of both channels.
+    fn max_step(buf: &[(f32, f32)]) -> f32 {
+        buf.windows(2)
+            .map(|w| (w[1].0 - w[0].0).abs().max((w[1].1 - w[0].1).abs()))
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn decay_time_changes_from_other_thread_do_not_click() {
+        let make_context = |with_reverb: bool| {
+            let mut context = Context::default_state();
+            let source = context.add_source(make_tone_source(0.5));
+            let mut reverb = Reverb::new(Default::default());
+            reverb.set_decay_time(Duration::from_secs(5));
+            reverb.set_dry(0.0);
+            if with_reverb {
+                reverb.base_mut().add_input(EffectInput::direct(source));
+            }
+            let handle = context.add_effect(Effect::Reverb(reverb));
+            (context, handle)
+        };
+        let blocks = 200;
+        let render_all = |context: &mut Context| {
+            let mut output = Vec::new();
+            for _ in 0..blocks {
+                output.extend(render(context, 512));
+            }
+            output
+        };
+        // Reverberated part of output, so clicks are not masked by dry tone.
+        let wet = |output: &[(f32, f32)], dry: &[(f32, f32)]| -> Vec<(f32, f32)> {
+            output.iter()
+                .zip(dry.iter())
+                .map(|(&(left, right), &(dry_left, dry_right))| (left - dry_left, right - dry_right))
+                .collect()
+        };
+
+        let dry = render_all(&mut make_context(false).0);
+        // Same signal without parameter changes gives bound for regular sample-to-sample step.
+        let reference = max_step(&wet(&render_all(&mut make_context(true).0), &dry));
+        assert!(reference > 0.0);
+
+        let (mut context, handle) = make_context(true);
+        let mut sender = context.take_command_sender().unwrap();
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let spammer = {
+            let done = done.clone();
+            std::thread::spawn(move || {
+                let mut sent = 0;
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    let decay_time = if sent % 2 == 0 { 0.5 } else { 5.0 };
+                    let command = Command::SetParameter(AutomationTarget::ReverbDecayTime(handle), decay_time);
+                    if sender.send(command).is_ok() {
+                        sent += 1;
+                    }
+                    std::thread::yield_now();
+                }
+                sent
+            })
+        };
+        let spammed = render_all(&mut context);
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+        assert!(spammer.join().unwrap() > 0);
+
+        assert!(spammed.iter().all(|&(left, right)| left.is_finite() && right.is_finite()));
+        let step = max_step(&wet(&spammed, &dry));
+        assert!(step < 1.5 * reference, "{} vs {}", step, reference);
+    }
+
+    #[test]
+    fn only_soloed_sources_are_heard() {
+        let levels = [0.05, 0.1, 0.2];
+        let mut context = Context::default_state();
+        let handles = levels.iter()
+            .map(|&level| context.add_source(make_dc_builder(level).build_source().unwrap()))
+            .collect::<Vec<Handle<SoundSource>>>();
+        // Mix of given sources alone, in the middle of a block.
+        let expected = |indices: &[usize]| {
+            let mut context = Context::default_state();
+            for &i in indices {
+                context.add_source(make_dc_builder(levels[i]).build_source().unwrap());
+            }
+            render(&mut context, 512)[256]
+        };
+        let everything = expected(&[0, 1, 2]);
+        assert_eq!(render(&mut context, 512)[256], everything);
+
+        context.solo_source(handles[1]);
+        // Other sources are faded out during first block.
+        render(&mut context, 512);
+        assert_eq!(render(&mut context, 512)[256], expected(&[1]));
+        assert!(context.source(handles[0]).unwrap().generic().is_virtual());
+        assert!(context.source(handles[1]).unwrap().generic().is_soloed());
+
+        // Solos are additive.
+        context.solo_source(handles[2]);
+        render(&mut context, 512);
+        assert_eq!(render(&mut context, 512)[256], expected(&[1, 2]));
+
+        context.unsolo_source(handles[1]);
+        render(&mut context, 512);
+        assert_eq!(render(&mut context, 512)[256], expected(&[2]));
+
+        context.clear_solo();
+        render(&mut context, 512);
+        assert_eq!(render(&mut context, 512)[256], everything);
+        assert!(handles.iter().all(|&h| !context.source(h).unwrap().generic().is_virtual()));
+    }
+
+    #[test]
+    fn solo_does_not_click() {
+        let mut context = Context::default_state();
+        let soloed = context.add_source(make_dc_builder(0.1).build_source().unwrap());
+        context.add_source(make_dc_builder(0.3).build_source().unwrap());
+        let mut output = render(&mut context, 512);
+        context.solo_source(soloed);
+        for _ in 0..3 {
+            output.extend(render(&mut context, 512));
+        }
+        context.clear_solo();
+        for _ in 0..3 {
+            output.extend(render(&mut context, 512));
+        }
+        // Muted source is much louder than step of its fade.
+        assert!(max_step(&output) < 0.01, "{}", max_step(&output));
+    }
+
+    #[test]
+    fn dc_blocker_removes_offset_of_master_output() {
+        let render_offset_tone = |dc_blocker: bool| {
+            let mut context = Context::default_state();
+            context.set_dc_blocker(dc_blocker);
+            assert_eq!(context.is_dc_blocker_enabled(), dc_blocker);
+            let samples = test_support::sine(441.0, SAMPLE_RATE, SAMPLE_RATE)
+                .iter()
+                .map(|s| s * 0.3 + 0.2)
+                .collect();
+            context.add_source(GenericSourceBuilder::new(test_support::make_buffer(samples, SAMPLE_RATE))
+                .with_status(Status::Playing)
+                .build_source()
+                .unwrap());
+            let mut output = Vec::new();
+            for _ in 0..40 {
+                output.extend(render(&mut context, 1024));
+            }
+            // Last half a second, whole periods of tone.
+            output[output.len() - 22050..].iter().map(|s| s.0).collect::<Vec<f32>>()
+        };
+        let mean = |samples: &[f32]| samples.iter().sum::<f32>() / samples.len() as f32;
+
+        let plain = render_offset_tone(false);
+        assert!(mean(&plain) > 0.05);
+        let blocked = render_offset_tone(true);
+        assert!(mean(&blocked).abs() < 1.0e-3, "{}", mean(&blocked));
+        // Tone itself passes through.
+        let deviation = |samples: &[f32]| {
+            let m = mean(samples);
+            test_support::rms(&samples.iter().map(|s| s - m).collect::<Vec<f32>>())
+        };
+        assert!((deviation(&blocked) - deviation(&plain)).abs() < 0.01 * deviation(&plain));
+    }
+
+    fn render_dc(clip_mode: ClipMode, level: f32) -> f32 {
+        let mut context = Context::default_state();
+        context.set_clip_mode(clip_mode);
+        context.add_source(GenericSourceBuilder::new(test_support::make_buffer(vec![level; SAMPLE_RATE], SAMPLE_RATE))
+            .with_status(Status::Playing)
+            .build_source()
+            .unwrap());
+        render(&mut context, 512)[256].0
+    }
+
+    #[test]
+    fn master_balance_attenuates_opposite_channel() {
+        let render_with_balance = |balance: f32| {
+            let mut context = Context::default_state();
+            context.set_master_balance(balance);
+            context.add_source(GenericSourceBuilder::new(test_support::make_buffer(vec![0.5; SAMPLE_RATE], SAMPLE_RATE))
+                .with_status(Status::Playing)
+                .build_source()
+                .unwrap());
+            render(&mut context, 512)[256]
+        };
+
+        let (left, right) = render_with_balance(0.0);
+        assert!(left > 0.1 && right > 0.1);
+
+        assert_eq!(render_with_balance(-1.0), (left, 0.0));
+        assert_eq!(render_with_balance(1.0), (0.0, right));
+        let (half_left, half_right) = render_with_balance(-0.5);
+        assert_eq!(half_left, left);
+        assert!((half_right - right * 0.5).abs() < 1.0e-6);
+
+        let mut context = Context::default_state();
+        assert_eq!(context.master_balance(), 0.0);
+        context.set_master_balance(-3.0);
+        assert_eq!(context.master_balance(), -1.0);
+    }
+
+    #[test]
+    fn over_unity_mix_follows_clip_mode() {
+        assert_eq!(render_dc(ClipMode::HardClip, 1.5), 1.0);
+        assert_eq!(render_dc(ClipMode::HardClip, -1.5), -1.0);
+        assert!((render_dc(ClipMode::None, 1.5) - 1.5).abs() < 1.0e-5);
+
+        let limited = render_dc(ClipMode::Limit, 1.5);
+        assert!(limited > 0.8 && limited < 1.0, "{}", limited);
+        // Samples below knee are not changed.
+        assert!((render_dc(ClipMode::Limit, 0.5) - 0.5).abs() < 1.0e-5);
+        assert!((render_dc(ClipMode::HardClip, 0.5) - 0.5).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn limit_is_monotonic_and_bounded() {
+        let mut prev = ClipMode::Limit.apply(0.0);
+        for i in 1..1000 {
+            let sample = ClipMode::Limit.apply(i as f32 * 0.01);
+            assert!(sample >= prev && sample <= 1.0);
+            assert_eq!(ClipMode::Limit.apply(-i as f32 * 0.01), -sample);
+            prev = sample;
+        }
+    }
+
+    #[test]
+    fn is_playing_follows_one_shot() {
+        let mut context = Context::default_state();
+        let handle = context.add_source(GenericSourceBuilder::new(test_support::make_buffer(vec![0.5; 1000], SAMPLE_RATE))
+            .with_status(Status::Playing)
+            .build_source()
+            .unwrap());
+        assert!(context.is_playing(handle));
+        for _ in 0..3 {
+            render(&mut context, 512);
+        }
+        assert!(!context.is_playing(handle));
+        assert!(context.source(handle).unwrap().generic().is_finished());
+        assert!(!context.is_playing(Handle::NONE));
+    }
+
+    #[test]
+    fn loudness_of_master_bus_is_measured() {
+        let mut context = Context::default_state();
+        assert_eq!(context.loudness().integrated, f32::NEG_INFINITY);
+        // 997 Hz tone with peak level of -20 dBFS in both channels has loudness of -20 LUFS.
+        let samples = test_support::sine(997.0, SAMPLE_RATE, 3 * SAMPLE_RATE)
+            .iter()
+            .map(|s| s * 0.1)
+            .collect();
+        context.add_source(GenericSourceBuilder::new(test_support::make_buffer(samples, SAMPLE_RATE))
+            .with_status(Status::Playing)
+            .build_source()
+            .unwrap());
+        for _ in 0..29 {
+            render(&mut context, SAMPLE_RATE / 10);
+        }
+        let loudness = context.loudness();
+        assert!((loudness.integrated + 20.0).abs() < 0.1, "{:?}", loudness);
+        assert!((loudness.momentary + 20.0).abs() < 0.1, "{:?}", loudness);
+
+        context.reset_loudness();
+        assert_eq!(context.loudness().integrated, f32::NEG_INFINITY);
+    }
+
+    fn render_dc_i16(level: f32) -> f32 {
+        let mut context = Context::default_state();
+        context.set_clip_mode(ClipMode::None);
+        context.set_output_format(SampleFormat::I16);
+        assert_eq!(context.output_format(), SampleFormat::I16);
+        context.add_source(GenericSourceBuilder::new(test_support::make_buffer(vec![level; SAMPLE_RATE], SAMPLE_RATE))
+            .with_status(Status::Playing)
+            .build_source()
+            .unwrap());
+        let buf = render(&mut context, 512);
+        assert_eq!(buf[256].0, buf[256].1);
+        buf[256].0
+    }
+
+    #[test]
+    fn i16_output_format_quantizes_master_bus() {
+        // Output is clamped even if clipping is disabled, and it is exactly representable as i16.
+        let expected = [(0.3, 9830), (0.123_456, 4045), (-0.75, -24575), (1.5, 32767), (-2.0, -32767)];
+        for &(level, quantized) in expected.iter() {
+            assert_eq!(render_dc_i16(level), quantized as f32 / 32767.0);
+        }
+    }
+
+    // Writes NaN into every other sample of left channel and infinity into first sample of right
+    // channel, the rest of output is 0.25.
+    struct NonFiniteRenderer;
+
+    impl Render for NonFiniteRenderer {
+        fn render_source(&mut self, _: &mut SoundSource, _: &Listener, _: &DistanceModel, out_buf: &mut [(f32, f32)]) {
+            for (i, (left, right)) in out_buf.iter_mut().enumerate() {
+                *left = if i % 2 == 0 { f32::NAN } else { 0.25 };
+                *right = if i == 0 { f32::INFINITY } else { 0.25 };
+            }
+        }
+    }
+
+    fn make_non_finite_context(sanitize: bool) -> Context {
+        let mut context = Context::default_state();
+        context.set_output_sanitizing(sanitize);
+        assert_eq!(context.is_output_sanitizing(), sanitize);
+        let renderer: Box<dyn Render> = Box::new(NonFiniteRenderer);
+        context.set_renderer(renderer);
+        context.add_source(make_tone_source(0.5));
+        context
+    }
+
+    #[test]
+    fn non_finite_output_is_scrubbed_and_counted() {
+        let mut context = make_non_finite_context(true);
+        let buf = render(&mut context, 512);
+        for (i, &(left, right)) in buf.iter().enumerate() {
+            assert_eq!(left, if i % 2 == 0 { 0.0 } else { 0.25 });
+            assert_eq!(right, if i == 0 { 0.0 } else { 0.25 });
+        }
+        assert_eq!(context.nan_count(), 256 + 1);
+        render(&mut context, 512);
+        assert_eq!(context.nan_count(), 2 * (256 + 1));
+
+        let mut context = make_non_finite_context(false);
+        context.set_clip_mode(ClipMode::None);
+        let buf = render(&mut context, 512);
+        assert_eq!(context.nan_count(), 0);
+        assert!(buf[0].0.is_nan());
+    }
+
+    #[test]
+    fn finished_event_is_reported_once() {
+        let mut context = Context::default_state();
+        let handle = context.add_source(GenericSourceBuilder::new(test_support::make_buffer(vec![0.5; 100], SAMPLE_RATE))
+            .with_status(Status::Playing)
+            .with_looping(true)
+            .with_loop_count(Some(3))
+            .build_source()
+            .unwrap());
+        render(&mut context, 256);
+        assert_eq!(context.diagnostics().count(), 0);
+        render(&mut context, 256);
+        let diagnostics = context.diagnostics().collect::<Vec<Diagnostic>>();
+        assert_eq!(diagnostics.len(), 1);
+        match diagnostics[0] {
+            Diagnostic::SourceFinished { source } => assert_eq!(source, handle),
+            _ => panic!("expected SourceFinished"),
+        }
+        render(&mut context, 256);
+        assert_eq!(context.diagnostics().count(), 0);
+
+        // Replayed source is reported again.
+        context.source_mut(handle).unwrap().generic_mut().play();
+        for _ in 0..2 {
+            render(&mut context, 256);
+        }
+        assert_eq!(context.diagnostics().count(), 1);
+    }
+
+    #[test]
+    fn settings_are_visited() {
+        let mut visitor = test_support::reload(|visitor| {
+            let mut context = Context::default_state();
+            context.set_max_voices(12);
+            context.set_clip_mode(ClipMode::Limit);
+            context.set_master_balance(-0.5);
+            context.visit("Context", visitor)
+        });
+        let mut context = Context::default_state();
+        context.visit("Context", &mut visitor).unwrap();
+        assert_eq!(context.max_voices(), 12);
+        assert_eq!(context.clip_mode(), ClipMode::Limit);
+        assert_eq!(context.master_balance(), -0.5);
+    }
+
+    #[test]
+    fn missing_settings_fall_back_to_defaults() {
+        let mut visitor = test_support::reload(|visitor| {
+            // Fields that were saved by older versions.
+            let mut context = Context::default_state();
+            visitor.enter_region("Context")?;
+            context.master_gain.visit("MasterGain", visitor)?;
+            context.listener.visit("Listener", visitor)?;
+            context.sources.visit("Sources", visitor)?;
+            context.effects.visit("Effects", visitor)?;
+            visitor.leave_region()
+        });
+        let mut context = Context::default_state();
+        context.set_max_voices(12);
+        context.set_clip_mode(ClipMode::None);
+        context.set_master_balance(-0.5);
+        context.visit("Context", &mut visitor).unwrap();
+        assert_eq!(context.max_voices(), usize::MAX);
+        assert_eq!(context.clip_mode(), ClipMode::HardClip);
+        assert_eq!(context.master_balance(), 0.0);
+    }
+}
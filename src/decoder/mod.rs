@@ -3,18 +3,21 @@ use crate::{
     buffer::DataSource,
     decoder::{
         wav::WavDecoder,
-        vorbis::OggDecoder
+        vorbis::OggDecoder,
+        mp3::Mp3Decoder,
     }
 };
 use std::time::Duration;
 
 mod vorbis;
 mod wav;
+mod mp3;
 
 pub(in crate) enum Decoder {
     Null,
     Wav(WavDecoder),
     Ogg(OggDecoder),
+    Mp3(Mp3Decoder),
 }
 
 impl Iterator for Decoder {
@@ -24,6 +27,7 @@ impl Iterator for Decoder {
         match self {
             Decoder::Wav(wav) => wav.next(),
             Decoder::Ogg(ogg) => ogg.next(),
+            Decoder::Mp3(mp3) => mp3.next(),
             Decoder::Null => None,
         }
     }
@@ -41,6 +45,11 @@ impl Decoder {
             Ok(ogg_decoder) => return Ok(Decoder::Ogg(ogg_decoder)),
             Err(source) => source,
         };
+        // Try MP3, it is last because its detection is the weakest.
+        let source = match Mp3Decoder::new(source) {
+            Ok(mp3_decoder) => return Ok(Decoder::Mp3(mp3_decoder)),
+            Err(source) => source,
+        };
         Err(source)
     }
 
@@ -48,6 +57,7 @@ impl Decoder {
         match self {
             Decoder::Wav(wav) => wav.rewind(),
             Decoder::Ogg(ogg) => ogg.rewind(),
+            Decoder::Mp3(mp3) => mp3.rewind(),
             Decoder::Null => Ok(()),
         }
     }
@@ -56,6 +66,7 @@ impl Decoder {
         match self {
             Decoder::Wav(wav) => wav.time_seek(location),
             Decoder::Ogg(ogg) => ogg.time_seek(location),
+            Decoder::Mp3(mp3) => mp3.time_seek(location),
             Decoder::Null => ()
         }
     }
@@ -64,6 +75,7 @@ impl Decoder {
         match self {
             Decoder::Wav(wav) => wav.channel_count,
             Decoder::Ogg(ogg) => ogg.channel_count,
+            Decoder::Mp3(mp3) => mp3.channel_count,
             Decoder::Null => 0
         }
     }
@@ -72,10 +84,19 @@ impl Decoder {
         match self {
             Decoder::Wav(wav) => wav.sample_rate,
             Decoder::Ogg(ogg) => ogg.sample_rate,
+            Decoder::Mp3(mp3) => mp3.sample_rate,
             Decoder::Null => 0
         }
     }
 
+    /// Enables TPDF dithering of decoded samples if `seed` is some. Only formats that store integer
+    /// samples are affected.
+    pub fn set_dither(&mut self, seed: Option<u64>) {
+        if let Decoder::Wav(wav) = self {
+            wav.set_dither(seed)
+        }
+    }
+
     pub fn into_samples(self) -> Vec<f32> {
         self.collect()
     }
@@ -84,6 +105,7 @@ impl Decoder {
         match self {
             Decoder::Wav(wav) => wav.duration(),
             Decoder::Ogg(ogg) => ogg.duration(),
+            Decoder::Mp3(mp3) => mp3.duration(),
             Decoder::Null => None
         }
     }
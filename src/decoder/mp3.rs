@@ -0,0 +1,252 @@
+use minimp3::{
+    Decoder as Mp3Reader,
+    Frame,
+    Error as Mp3Error,
+};
+use crate::{
+    buffer::DataSource,
+    error::SoundError
+};
+use std::{
+    io::{
+        Seek,
+        SeekFrom,
+        Read
+    },
+    vec,
+    time::Duration
+};
+
+pub struct Mp3Decoder {
+    // Option is needed to be able to extract data source on rewind.
+    reader: Option<Mp3Reader<DataSource>>,
+    samples: vec::IntoIter<f32>,
+    pub channel_count: usize,
+    pub sample_rate: usize,
+}
+
+fn frame_to_samples(frame: Frame) -> vec::IntoIter<f32> {
+    const SCALE: f32 = 1.0 / 32768.0;
+    frame.data
+        .into_iter()
+        .map(|s| f32::from(s) * SCALE)
+        .collect::<Vec<f32>>()
+        .into_iter()
+}
+
+/// Reads next frame, frames with different layout than first one (VBR files may change sample rate
+/// in theory, but it is not allowed by standard) are skipped, so output layout is always the same.
+fn next_frame(reader: &mut Mp3Reader<DataSource>, channel_count: usize, sample_rate: usize) -> Result<Option<Frame>, SoundError> {
+    loop {
+        match reader.next_frame() {
+            Ok(frame) => {
+                if frame.channels == channel_count && frame.sample_rate as usize == sample_rate {
+                    return Ok(Some(frame));
+                }
+            }
+            Err(Mp3Error::Eof) => return Ok(None),
+            Err(Mp3Error::SkippedData) => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+impl Iterator for Mp3Decoder {
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(sample) = self.samples.next() {
+            Some(sample)
+        } else {
+            if let Some(reader) = self.reader.as_mut() {
+                if let Ok(Some(frame)) = next_frame(reader, self.channel_count, self.sample_rate) {
+                    self.samples = frame_to_samples(frame);
+                }
+            }
+            self.samples.next()
+        }
+    }
+}
+
+/// Checks for ID3v2 tag or MPEG audio frame sync (11 set bits) at the beginning of data.
+fn is_mp3(source: &mut DataSource) -> Result<bool, SoundError> {
+    let pos = source.stream_position()?;
+
+    let mut header = [0u8; 3];
+    let is_mp3 = if source.read_exact(&mut header).is_ok() {
+        &header == b"ID3" || (header[0] == 0xFF && header[1] & 0xE0 == 0xE0)
+    } else {
+        false
+    };
+
+    source.seek(SeekFrom::Start(pos))?;
+
+    Ok(is_mp3)
+}
+
+impl Mp3Decoder {
+    pub fn new(mut source: DataSource) -> Result<Self, DataSource> {
+        // Source which fails to read or seek cannot be decoded, it is returned back like any other
+        // unsupported data.
+        if !is_mp3(&mut source).unwrap_or(false) {
+            return Err(source);
+        }
+
+        let mut reader = Mp3Reader::new(source);
+
+        // Layout of stream is defined by first valid frame.
+        let first_frame = loop {
+            match reader.next_frame() {
+                Ok(frame) => break frame,
+                Err(Mp3Error::SkippedData) => (),
+                Err(_) => {
+                    let mut source = reader.into_inner();
+                    source.seek(SeekFrom::Start(0)).unwrap();
+                    return Err(source);
+                }
+            }
+        };
+
+        Ok(Self {
+            channel_count: first_frame.channels,
+            sample_rate: first_frame.sample_rate as usize,
+            samples: frame_to_samples(first_frame),
+            reader: Some(reader),
+        })
+    }
+
+    pub fn rewind(&mut self) -> Result<(), SoundError> {
+        let mut source = match self.reader.take() {
+            Some(reader) => reader.into_inner(),
+            None => return Err(SoundError::UnsupportedFormat),
+        };
+        source.seek(SeekFrom::Start(0))?;
+        *self = match Self::new(source) {
+            Ok(mp3_decoder) => mp3_decoder,
+            Err(_) => return Err(SoundError::UnsupportedFormat),
+        };
+        Ok(())
+    }
+
+    pub fn time_seek(&mut self, location: Duration) {
+        // MP3 has no index of frames (VBR frames have variable size), so the only reliable way
+        // to seek is to decode frames from the beginning.
+        if self.rewind().is_err() {
+            return;
+        }
+        let mut samples_to_skip = (location.as_secs_f64() * self.sample_rate as f64) as usize * self.channel_count;
+        while samples_to_skip > self.samples.len() {
+            samples_to_skip -= self.samples.len();
+            let frame = match self.reader.as_mut() {
+                Some(reader) => next_frame(reader, self.channel_count, self.sample_rate),
+                None => return,
+            };
+            match frame {
+                Ok(Some(frame)) => self.samples = frame_to_samples(frame),
+                _ => {
+                    self.samples = Vec::new().into_iter();
+                    return;
+                }
+            }
+        }
+        for _ in 0..samples_to_skip {
+            self.samples.next();
+        }
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::{
+        buffer::{DataSource, SoundBuffer},
+        decoder::mp3::{Mp3Decoder, is_mp3},
+    };
+
+    const SAMPLES_PER_FRAME: usize = 1152;
+
+    // Makes stream of MPEG-1 Layer III frames (mono, 44100 Hz, 128 kbps, no CRC). Side info and main
+    // data are zeroed, such frames are valid and decode to silence.
+    fn make_silent_mp3(frame_count: usize) -> Vec<u8> {
+        // 144 * bitrate / sample_rate
+        const FRAME_LEN: usize = 417;
+        let mut bytes = Vec::with_capacity(frame_count * FRAME_LEN);
+        for _ in 0..frame_count {
+            let mut frame = vec![0u8; FRAME_LEN];
+            frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0xC0]);
+            bytes.extend(frame);
+        }
+        bytes
+    }
+
+    #[test]
+    fn silent_stream_matches_reference() {
+        let frame_count = 20;
+        let decoder = Mp3Decoder::new(DataSource::Memory(Cursor::new(make_silent_mp3(frame_count)))).ok().unwrap();
+        assert_eq!(decoder.sample_rate, 44100);
+        assert_eq!(decoder.channel_count, 1);
+        let samples = decoder.collect::<Vec<f32>>();
+        // Reference is silence of the same duration, decoder may drop single frame while it syncs.
+        let reference_len = frame_count * SAMPLES_PER_FRAME;
+        assert!(samples.len() <= reference_len && samples.len() + SAMPLES_PER_FRAME >= reference_len, "{}", samples.len());
+        assert!(samples.iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn mp3_is_detected_by_data_source() {
+        let buffer = SoundBuffer::new_generic(DataSource::Memory(Cursor::new(make_silent_mp3(20)))).ok().unwrap();
+        let buffer = buffer.lock().unwrap();
+        assert_eq!(buffer.generic().sample_rate(), 44100);
+        assert_eq!(buffer.generic().channel_count(), 1);
+    }
+
+    #[test]
+    fn garbage_is_not_mp3() {
+        let source = DataSource::Memory(Cursor::new(vec![0x12; 4096]));
+        assert!(Mp3Decoder::new(source).is_err());
+        // Frame sync without valid frames is rejected too.
+        let mut bytes = vec![0xFF, 0xFB];
+        bytes.extend(vec![0x12; 4096]);
+        assert!(Mp3Decoder::new(DataSource::Memory(Cursor::new(bytes))).is_err());
+    }
+
+    fn load_samples(file_name: &str) -> (usize, usize, Vec<f32>) {
+        let path = format!("{}/examples/data/{}", env!("CARGO_MANIFEST_DIR"), file_name);
+        let buffer = SoundBuffer::new_generic(DataSource::from_file(path).unwrap()).unwrap();
+        let buffer = buffer.lock().unwrap();
+        let buffer = buffer.generic();
+        (buffer.sample_rate(), buffer.channel_count(), buffer.samples().to_vec())
+    }
+
+    #[test]
+    fn tone_matches_wav_reference() {
+        // MP3 is encoded from WAV reference (one second of 440 Hz sine).
+        let (sample_rate, channel_count, samples) = load_samples("sine_440hz.mp3");
+        let (reference_sample_rate, reference_channel_count, reference) = load_samples("sine_440hz.wav");
+        assert_eq!(sample_rate, reference_sample_rate);
+        assert_eq!(channel_count, reference_channel_count);
+
+        // Encoder and decoder add delay and output is padded to whole frames.
+        let len_difference = samples.len().abs_diff(reference.len());
+        assert!(len_difference < 2 * SAMPLES_PER_FRAME, "{} vs {}", samples.len(), reference.len());
+
+        let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        let (peak, reference_peak) = (peak(&samples), peak(&reference));
+        assert!((peak - reference_peak).abs() < 0.05, "{} vs {}", peak, reference_peak);
+    }
+
+    #[test]
+    fn detection_keeps_position_of_source() {
+        let mut source = DataSource::Memory(Cursor::new(make_silent_mp3(1)));
+        assert!(is_mp3(&mut source).unwrap());
+        match source {
+            DataSource::Memory(cursor) => assert_eq!(cursor.position(), 0),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -1,6 +1,10 @@
 use crate::{
     buffer::DataSource,
-    error::SoundError
+    dsp::Random,
+    error::{
+        SoundError,
+        DecoderError
+    }
 };
 use std::io::{
     Read,
@@ -27,23 +31,84 @@ struct WavHeader {
     bits_per_sample: u16,
     data_chunk_id: [u8; 4],
     data_chunk_size: u32,
+    // Offset of first sample in the source.
+    data_offset: u64,
+}
+
+/// Format of samples stored in data chunk.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum SampleFormat {
+    /// 8-bit unsigned integer, 128 is zero level.
+    U8,
+    /// 16-bit signed integer.
+    I16,
+    /// 24-bit signed integer packed in 3 bytes.
+    I24,
+    /// 32-bit signed integer.
+    I32,
+    /// 32-bit IEEE float.
+    F32,
+    /// 64-bit IEEE float.
+    F64,
+}
+
+impl SampleFormat {
+    const PCM: u16 = 1;
+    const IEEE_FLOAT: u16 = 3;
+    const EXTENSIBLE: u16 = 0xFFFE;
+
+    fn from_header(audio_format: u16, bits_per_sample: u16) -> Option<Self> {
+        match (audio_format, bits_per_sample) {
+            (Self::PCM, 8) => Some(SampleFormat::U8),
+            (Self::PCM, 16) => Some(SampleFormat::I16),
+            (Self::PCM, 24) => Some(SampleFormat::I24),
+            (Self::PCM, 32) => Some(SampleFormat::I32),
+            (Self::IEEE_FLOAT, 32) => Some(SampleFormat::F32),
+            (Self::IEEE_FLOAT, 64) => Some(SampleFormat::F64),
+            _ => None
+        }
+    }
+
+    fn byte_per_sample(self) -> usize {
+        match self {
+            SampleFormat::U8 => 1,
+            SampleFormat::I16 => 2,
+            SampleFormat::I24 => 3,
+            SampleFormat::I32 => 4,
+            SampleFormat::F32 => 4,
+            SampleFormat::F64 => 8,
+        }
+    }
+
+    /// Returns size of one quantization step in [-1; 1] range, float formats have no meaningful
+    /// quantization step.
+    fn quantization_step(self) -> Option<f32> {
+        match self {
+            SampleFormat::U8 => Some(1.0 / 128.0),
+            SampleFormat::I16 => Some(1.0 / 32768.0),
+            SampleFormat::I24 => Some(1.0 / 8_388_608.0),
+            SampleFormat::I32 => Some(1.0 / 2_147_483_648.0),
+            SampleFormat::F32 | SampleFormat::F64 => None,
+        }
+    }
 }
 
-/// Wav decoder for uncompressed PCM data.
+/// Wav decoder for uncompressed PCM data. Supports 8-bit unsigned, 16/24/32-bit signed integer
+/// and 32/64-bit float samples, all of them are converted to f32 in [-1; 1] range.
 /// TODO: Add support for RLE-compressed samples.
 pub(in crate) struct WavDecoder {
     pub channel_count: usize,
     pub sample_rate: usize,
-    byte_per_sample: usize,
+    sample_format: SampleFormat,
+    data_offset: u64,
     samples_left: usize,
     total_samples: usize,
     source: DataSource,
+    dither: Option<Random>,
 }
 
 impl WavDecoder {
-    const HEADER_SIZE: u64 = 44;
-
-    fn read_header(source: &mut DataSource) -> Result<WavHeader, SoundError> {
+    fn read_header(source: &mut DataSource) -> Result<(WavHeader, SampleFormat), SoundError> {
         let mut header = WavHeader {
             chunk_id: [0, 0, 0, 0],
             chunk_size: 0,
@@ -58,6 +123,7 @@ impl WavDecoder {
             bits_per_sample: 0,
             data_chunk_id: [0, 0, 0, 0],
             data_chunk_size: 0,
+            data_offset: 0,
         };
         source.read_exact(&mut header.chunk_id)?;
         if header.chunk_id.as_ref() != b"RIFF" {
@@ -73,51 +139,91 @@ impl WavDecoder {
             return Err(SoundError::InvalidHeader);
         }
         header.fmt_chunk_size = source.read_u32::<LittleEndian>()?;
-        header.audio_format = source.read_u16::<LittleEndian>()?;
-        if header.audio_format != 1 {
+        if header.fmt_chunk_size < 16 {
             return Err(SoundError::InvalidHeader);
         }
+        header.audio_format = source.read_u16::<LittleEndian>()?;
         header.num_channels = source.read_u16::<LittleEndian>()?;
         header.sample_rate = source.read_u32::<LittleEndian>()?;
         header.byte_rate = source.read_u32::<LittleEndian>()?;
         header.block_align = source.read_u16::<LittleEndian>()?;
         header.bits_per_sample = source.read_u16::<LittleEndian>()?;
-        source.read_exact(&mut header.data_chunk_id)?;
-        header.data_chunk_size = source.read_u32::<LittleEndian>()?;
-        Ok(header)
+        let mut extra_fmt_bytes = i64::from(header.fmt_chunk_size - 16);
+        if header.audio_format == SampleFormat::EXTENSIBLE && extra_fmt_bytes >= 24 {
+            // WAVE_FORMAT_EXTENSIBLE: cbSize, valid bits per sample, channel mask and then
+            // sub-format GUID, first two bytes of which is actual format code.
+            let _cb_size = source.read_u16::<LittleEndian>()?;
+            let _valid_bits_per_sample = source.read_u16::<LittleEndian>()?;
+            let _channel_mask = source.read_u32::<LittleEndian>()?;
+            header.audio_format = source.read_u16::<LittleEndian>()?;
+            extra_fmt_bytes -= 10;
+        }
+        // Skip rest of fmt chunk, chunks are padded to even size.
+        source.seek(SeekFrom::Current(extra_fmt_bytes + i64::from(header.fmt_chunk_size & 1)))?;
+        let sample_format = SampleFormat::from_header(header.audio_format, header.bits_per_sample)
+            .ok_or(SoundError::DecoderError(DecoderError::UnsupportedWavFormat {
+                audio_format: header.audio_format,
+                bits_per_sample: header.bits_per_sample,
+            }))?;
+        if header.num_channels == 0 {
+            return Err(SoundError::InvalidHeader);
+        }
+        // Skip every chunk that goes before data chunk (LIST, fact, etc.)
+        loop {
+            source.read_exact(&mut header.data_chunk_id)?;
+            header.data_chunk_size = source.read_u32::<LittleEndian>()?;
+            if header.data_chunk_id.as_ref() == b"data" {
+                break;
+            }
+            source.seek(SeekFrom::Current(i64::from(header.data_chunk_size) + i64::from(header.data_chunk_size & 1)))?;
+        }
+        header.data_offset = source.stream_position()?;
+        Ok((header, sample_format))
     }
 
     pub fn new(mut source: DataSource) -> Result<Self, DataSource> {
         let pos = source.seek(SeekFrom::Current(0)).unwrap();
-        let header = match Self::read_header(&mut source) {
+        let (header, sample_format) = match Self::read_header(&mut source) {
             Ok(h) => h,
             Err(_) => {
                 source.seek(SeekFrom::Start(pos)).unwrap();
                 return Err(source);
             }
         };
-        let byte_per_sample = (header.bits_per_sample / 8) as usize;
-        let total_samples = (header.data_chunk_size / byte_per_sample as u32) as usize;
+        let total_samples = header.data_chunk_size as usize / sample_format.byte_per_sample();
         Ok(Self {
             channel_count: header.num_channels as usize,
             sample_rate: header.sample_rate as usize,
-            byte_per_sample,
+            sample_format,
+            data_offset: header.data_offset,
             total_samples,
             samples_left: total_samples,
             source,
+            dither: None,
         })
     }
 
     pub fn rewind(&mut self) -> Result<(), SoundError> {
-        self.source.seek(SeekFrom::Start(Self::HEADER_SIZE))?;
+        self.source.seek(SeekFrom::Start(self.data_offset))?;
         self.samples_left = self.total_samples;
         Ok(())
     }
 
     pub fn time_seek(&mut self, location: Duration) {
-        let byte_index = self.channel_count as f64 * location.as_secs_f64() *
-            self.sample_rate as f64 * self.byte_per_sample as f64;
-        let _ = self.source.seek(SeekFrom::Start(Self::HEADER_SIZE + byte_index as u64));
+        // Seek to beginning of a frame, otherwise channels will be swapped.
+        let frame_index = (location.as_secs_f64() * self.sample_rate as f64) as usize;
+        let sample_index = (frame_index * self.channel_count).min(self.total_samples);
+        let byte_index = sample_index * self.sample_format.byte_per_sample();
+        if self.source.seek(SeekFrom::Start(self.data_offset + byte_index as u64)).is_ok() {
+            self.samples_left = self.total_samples - sample_index;
+        }
+    }
+
+    /// Enables or disables TPDF dithering of integer samples. Dither noise has amplitude of one
+    /// quantization step of source format, it is generated by deterministic generator with
+    /// given seed. Float samples are never dithered.
+    pub fn set_dither(&mut self, seed: Option<u64>) {
+        self.dither = seed.map(Random::new);
     }
 
     pub fn duration(&self) -> Option<Duration> {
@@ -132,24 +238,143 @@ impl Iterator for WavDecoder {
     fn next(&mut self) -> Option<Self::Item> {
         if self.samples_left > 0 {
             self.samples_left -= 1;
-            if self.byte_per_sample == 1 {
-                Some(f32::from(self.source.read_i8().ok()?) / 127.0)
-            } else if self.byte_per_sample == 2 {
-                Some(f32::from(self.source.read_i16::<LittleEndian>().ok()?) / 32767.0)
-            } else if self.byte_per_sample == 3 {
-                let a = self.source.read_u8().ok()? as i32;
-                let b = self.source.read_u8().ok()? as i32;
-                let c = self.source.read_u8().ok()? as i32;
-                let ival = a | (b << 8) | (c << 16);
-                let sign = if ival & 0x0080_0000 != 0 { -1.0 } else { 1.0 };
-                Some(sign * ival as f32 / 8_388_608.0)
-            } else if self.byte_per_sample == 4 {
-                Some(self.source.read_f32::<LittleEndian>().ok()?)
-            } else {
-                None
+            let sample = match self.sample_format {
+                SampleFormat::U8 => {
+                    (f32::from(self.source.read_u8().ok()?) - 128.0) / 128.0
+                }
+                SampleFormat::I16 => {
+                    f32::from(self.source.read_i16::<LittleEndian>().ok()?) / 32768.0
+                }
+                SampleFormat::I24 => {
+                    self.source.read_i24::<LittleEndian>().ok()? as f32 / 8_388_608.0
+                }
+                SampleFormat::I32 => {
+                    self.source.read_i32::<LittleEndian>().ok()? as f32 / 2_147_483_648.0
+                }
+                SampleFormat::F32 => {
+                    self.source.read_f32::<LittleEndian>().ok()?
+                }
+                SampleFormat::F64 => {
+                    self.source.read_f64::<LittleEndian>().ok()? as f32
+                }
+            };
+            match (self.dither.as_mut(), self.sample_format.quantization_step()) {
+                (Some(dither), Some(step)) => Some(sample + dither.tpdf() * step),
+                _ => Some(sample)
             }
         } else {
             None
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{
+        WriteBytesExt,
+        LittleEndian,
+    };
+    use crate::{
+        buffer::{DataSource, generic::GenericBuffer},
+        decoder::wav::{WavDecoder, SampleFormat},
+        error::{SoundError, DecoderError},
+        test_support,
+    };
+
+    // Mono 44100 Hz WAV with given format and raw bytes of data chunk.
+    fn make_wav(audio_format: u16, bits_per_sample: u16, data: &[u8]) -> Vec<u8> {
+        let byte_per_sample = u32::from(bits_per_sample / 8);
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.write_u32::<LittleEndian>(36 + data.len() as u32).unwrap();
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"fmt ");
+        bytes.write_u32::<LittleEndian>(16).unwrap();
+        bytes.write_u16::<LittleEndian>(audio_format).unwrap();
+        bytes.write_u16::<LittleEndian>(1).unwrap();
+        bytes.write_u32::<LittleEndian>(44100).unwrap();
+        bytes.write_u32::<LittleEndian>(44100 * byte_per_sample).unwrap();
+        bytes.write_u16::<LittleEndian>(byte_per_sample as u16).unwrap();
+        bytes.write_u16::<LittleEndian>(bits_per_sample).unwrap();
+        bytes.extend_from_slice(b"data");
+        bytes.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn decode(bytes: Vec<u8>) -> Vec<f32> {
+        WavDecoder::new(DataSource::from_memory(bytes)).ok().unwrap().collect()
+    }
+
+    fn tone() -> Vec<f32> {
+        test_support::sine(441.0, 44100, 1000).iter().map(|s| s * 0.9).collect()
+    }
+
+    fn assert_matches(decoded: &[f32], expected: &[f32], tolerance: f32) {
+        assert_eq!(decoded.len(), expected.len());
+        for (a, b) in decoded.iter().zip(expected) {
+            assert!((a - b).abs() <= tolerance, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn integer_and_float_tones_match() {
+        let tone = tone();
+
+        let mut f32_data = Vec::new();
+        let mut i16_data = Vec::new();
+        let mut i24_data = Vec::new();
+        let mut u8_data = Vec::new();
+        for &s in tone.iter() {
+            f32_data.write_f32::<LittleEndian>(s).unwrap();
+            i16_data.write_i16::<LittleEndian>((s * 32768.0).round() as i16).unwrap();
+            i24_data.write_i24::<LittleEndian>((s * 8_388_608.0).round() as i32).unwrap();
+            u8_data.push((s * 128.0 + 128.0).round() as u8);
+        }
+
+        let float = decode(make_wav(SampleFormat::IEEE_FLOAT, 32, &f32_data));
+        assert_eq!(float, tone);
+        // Rounding gives error of half of quantization step at most.
+        assert_matches(&decode(make_wav(SampleFormat::PCM, 16, &i16_data)), &float, 0.5 / 32768.0);
+        assert_matches(&decode(make_wav(SampleFormat::PCM, 24, &i24_data)), &float, 0.5 / 8_388_608.0);
+        assert_matches(&decode(make_wav(SampleFormat::PCM, 8, &u8_data)), &float, 0.5 / 128.0);
+    }
+
+    #[test]
+    fn unsupported_format_is_reported() {
+        let mut source = DataSource::from_memory(make_wav(SampleFormat::PCM, 12, &[0, 0, 0, 0]));
+        match WavDecoder::read_header(&mut source) {
+            Err(SoundError::DecoderError(DecoderError::UnsupportedWavFormat { audio_format, bits_per_sample })) => {
+                assert_eq!(audio_format, SampleFormat::PCM);
+                assert_eq!(bits_per_sample, 12);
+            }
+            _ => panic!("unsupported format must be reported"),
+        }
+    }
+
+    fn load_dithered(data: &[u8], seed: u64) -> Vec<f32> {
+        let source = DataSource::from_memory(make_wav(SampleFormat::PCM, 8, data));
+        GenericBuffer::new_dithered(source, seed).ok().unwrap().samples().to_vec()
+    }
+
+    #[test]
+    fn dither_is_deterministic() {
+        let silence = vec![128u8; 8192];
+        let first = load_dithered(&silence, 42);
+        assert_eq!(first, load_dithered(&silence, 42));
+        assert_ne!(first, load_dithered(&silence, 43));
+        // Without dithering silence stays silent.
+        let plain = GenericBuffer::new(DataSource::from_memory(make_wav(SampleFormat::PCM, 8, &silence))).ok().unwrap();
+        assert!(plain.samples().iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn dither_noise_floor() {
+        let step = 1.0 / 128.0;
+        let dithered = load_dithered(&vec![128u8; 65536], 1);
+        // TPDF noise with amplitude of one quantization step has RMS of step / sqrt(6).
+        let rms = test_support::rms(&dithered);
+        assert!((rms / (step / 6.0f32.sqrt()) - 1.0).abs() < 0.05);
+        assert!(dithered.iter().all(|s| s.abs() < step));
+    }
+}
@@ -1,11 +1,12 @@
 use crate::{
-    error::SoundError,
+    error::ContextError,
     device::{
         Device,
         FeedCallback,
         SAMPLE_RATE,
         NativeSample,
-        MixContext
+        MixContext,
+        samples_to_duration,
     }
 };
 use alsa_sys::*;
@@ -13,6 +14,7 @@ use std::{
     ffi::{CStr, CString},
     os::raw::c_int,
     mem::size_of,
+    time::Duration,
 };
 
 pub struct AlsaSoundDevice {
@@ -21,6 +23,8 @@ pub struct AlsaSoundDevice {
     callback: Box<FeedCallback>,
     out_data: Vec<NativeSample>,
     mix_buffer: Vec<(f32, f32)>,
+    // Actual size of device buffer as it was negotiated with device.
+    latency: Duration,
 }
 
 unsafe impl Send for AlsaSoundDevice {}
@@ -34,30 +38,40 @@ pub fn err_code_to_string(err_code: c_int) -> String {
     }
 }
 
-pub fn check(err_code: c_int) -> Result<(), SoundError> {
+pub fn check(err_code: c_int) -> Result<(), ContextError> {
     if err_code < 0 {
-        Err(SoundError::FailedToInitializeDevice(err_code_to_string(err_code)))
+        Err(ContextError::BackendInitFailure(err_code_to_string(err_code)))
+    } else {
+        Ok(())
+    }
+}
+
+fn check_format(err_code: c_int) -> Result<(), ContextError> {
+    if err_code < 0 {
+        Err(ContextError::UnsupportedFormat)
     } else {
         Ok(())
     }
 }
 
 impl AlsaSoundDevice {
-    pub fn new(buffer_len_bytes: u32, callback: Box<FeedCallback>) -> Result<Self, SoundError> {
+    pub fn new(buffer_len_bytes: u32, callback: Box<FeedCallback>) -> Result<Self, ContextError> {
         unsafe {
             // 16-bit stereo is 4 bytes, so frame count is bufferHalfSize / 4
             let frame_count = buffer_len_bytes / 4;
             let mut playback_device = std::ptr::null_mut();
-            check(snd_pcm_open(&mut playback_device, CString::new("default").unwrap().as_ptr() as *const _, SND_PCM_STREAM_PLAYBACK, 0))?;
+            if snd_pcm_open(&mut playback_device, CString::new("default").unwrap().as_ptr() as *const _, SND_PCM_STREAM_PLAYBACK, 0) < 0 {
+                return Err(ContextError::NoDefaultDevice);
+            }
             let mut hw_params = std::ptr::null_mut();
             check(snd_pcm_hw_params_malloc(&mut hw_params))?;
             check(snd_pcm_hw_params_any(playback_device, hw_params))?;
             let access = SND_PCM_ACCESS_RW_INTERLEAVED;
             check(snd_pcm_hw_params_set_access(playback_device, hw_params, access))?;
-            check(snd_pcm_hw_params_set_format(playback_device, hw_params, SND_PCM_FORMAT_S16_LE))?;
+            check_format(snd_pcm_hw_params_set_format(playback_device, hw_params, SND_PCM_FORMAT_S16_LE))?;
             let mut exact_rate = SAMPLE_RATE;
-            check(snd_pcm_hw_params_set_rate_near(playback_device, hw_params, &mut exact_rate, std::ptr::null_mut()))?;
-            check(snd_pcm_hw_params_set_channels(playback_device, hw_params, 2))?;
+            check_format(snd_pcm_hw_params_set_rate_near(playback_device, hw_params, &mut exact_rate, std::ptr::null_mut()))?;
+            check_format(snd_pcm_hw_params_set_channels(playback_device, hw_params, 2))?;
             let mut exact_size = (frame_count * 2) as u64;
             check(snd_pcm_hw_params_set_buffer_size_near(playback_device, hw_params, &mut exact_size))?;
             check(snd_pcm_hw_params(playback_device, hw_params))?;
@@ -77,6 +91,7 @@ impl AlsaSoundDevice {
                 callback,
                 out_data: vec![Default::default(); samples_per_channel],
                 mix_buffer: vec![(0.0, 0.0); samples_per_channel],
+                latency: samples_to_duration(exact_size as usize, exact_rate),
             })
         }
     }
@@ -102,6 +117,16 @@ impl Device for AlsaSoundDevice {
             }
         }
     }
+
+    fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    fn drain(&mut self) {
+        unsafe {
+            snd_pcm_drain(self.playback_device);
+        }
+    }
 }
 
 impl Drop for AlsaSoundDevice {
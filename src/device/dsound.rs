@@ -1,6 +1,9 @@
 #![allow(non_snake_case)]
 
-use std::mem::size_of;
+use std::{
+    mem::size_of,
+    time::Duration,
+};
 use winapi::{
     um::{
         dsound::*,
@@ -16,8 +19,8 @@ use winapi::{
     ctypes::c_void,
 };
 use crate::{
-    device::{NativeSample, FeedCallback, SAMPLE_RATE, Device, MixContext},
-    error::SoundError,
+    device::{NativeSample, FeedCallback, SAMPLE_RATE, Device, MixContext, samples_to_duration},
+    error::ContextError,
 };
 
 // Declare missing structs and interfaces.
@@ -46,20 +49,21 @@ pub struct DirectSoundDevice {
 
 unsafe impl Send for DirectSoundDevice {}
 
-fn check<S: Into<String>>(code: i32, message: S) -> Result<(), SoundError> {
+fn check<S: Into<String>>(code: i32, message: S) -> Result<(), ContextError> {
     if code == DS_OK {
         Ok(())
     } else {
-        Err(SoundError::FailedToInitializeDevice(message.into()))
+        Err(ContextError::BackendInitFailure(message.into()))
     }
 }
 
 impl DirectSoundDevice {
-    pub fn new(buffer_len_bytes: u32, callback: Box<FeedCallback>) -> Result<Self, SoundError> {
+    pub fn new(buffer_len_bytes: u32, callback: Box<FeedCallback>) -> Result<Self, ContextError> {
         unsafe {
             let mut direct_sound = std::ptr::null_mut();
-            check(DirectSoundCreate(std::ptr::null(), &mut direct_sound, std::ptr::null_mut()),
-                  "Failed to initialize DirectSound")?;
+            if DirectSoundCreate(std::ptr::null(), &mut direct_sound, std::ptr::null_mut()) != DS_OK {
+                return Err(ContextError::NoDefaultDevice);
+            }
 
             check((*direct_sound).SetCooperativeLevel(GetForegroundWindow(), DSSCL_PRIORITY),
                   "Failed to set cooperative level")?;
@@ -88,8 +92,12 @@ impl DirectSoundDevice {
             };
 
             let mut buffer = std::ptr::null_mut();
-            check((*direct_sound).CreateSoundBuffer(&buffer_desc, &mut buffer, std::ptr::null_mut()),
-                  "Failed to create back buffer.")?;
+            if (*direct_sound).CreateSoundBuffer(&buffer_desc, &mut buffer, std::ptr::null_mut()) == DSERR_BADFORMAT {
+                return Err(ContextError::UnsupportedFormat);
+            }
+            if buffer.is_null() {
+                return Err(ContextError::BackendInitFailure("Failed to create back buffer.".to_owned()));
+            }
 
             let mut notify: *mut IDirectSoundNotify = std::ptr::null_mut();
             check((*buffer).QueryInterface(&IID_IDirectSoundNotify, ((&mut notify) as *mut *mut _) as *mut *mut c_void),
@@ -169,4 +177,20 @@ impl Device for DirectSoundDevice {
             }
         }
     }
+
+    fn latency(&self) -> Duration {
+        // Device buffer consists of two halves of `buffer_len_bytes` each.
+        let samples_per_channel = 2 * self.buffer_len_bytes as usize / size_of::<NativeSample>();
+        samples_to_duration(samples_per_channel, SAMPLE_RATE)
+    }
+
+    fn drain(&mut self) {
+        // Buffer is looped, so fill both halves with silence (callback gives silence after last
+        // block) - this waits until last fed half was played - and only then stop playback.
+        self.feed();
+        self.feed();
+        unsafe {
+            (*self.buffer).Stop();
+        }
+    }
 }
\ No newline at end of file
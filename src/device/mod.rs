@@ -5,7 +5,21 @@
 //! Device is an abstraction over output device which provides unified way of communication with
 //! output device.
 
-use crate::error::SoundError;
+use std::{
+    sync::{
+        Arc,
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+use crate::{
+    error::ContextError,
+    dsp::sample_to_i16,
+};
 
 #[cfg(target_os = "windows")]
 mod dsound;
@@ -13,9 +27,15 @@ mod dsound;
 #[cfg(target_os = "linux")]
 mod alsa;
 
+mod null;
+
 // TODO: Make this configurable, for now its set to most commonly used sample rate of 44100 Hz.
 pub const SAMPLE_RATE: u32 = 44100;
 
+// All backends are opened in 16-bit stereo mode, see NativeSample. Speaker layouts are folded
+// down to stereo by renderers, see `layout` module.
+pub const CHANNEL_COUNT: usize = 2;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct NativeSample {
@@ -32,6 +52,11 @@ impl Default for NativeSample {
     }
 }
 
+/// Returns duration of given amount of samples (per channel) at device sample rate.
+pub(in crate) fn samples_to_duration(samples_per_channel: usize, sample_rate: u32) -> Duration {
+    Duration::from_secs_f64(samples_per_channel as f64 / f64::from(sample_rate))
+}
+
 pub type FeedCallback = dyn FnMut(&mut [(f32, f32)]) + Send;
 
 pub struct MixContext<'a> {
@@ -40,23 +65,18 @@ pub struct MixContext<'a> {
     callback: &'a mut FeedCallback,
 }
 
-fn sample_to_i16(sample: f32) -> i16 {
-    const SCALE: f32 = std::i16::MAX as f32;
-    let clamped = if sample > 1.0 {
-        1.0
-    } else if sample < -1.0 {
-        -1.0
-    } else {
-        sample
-    };
-    (clamped * SCALE) as i16
-}
-
 trait Device {
     fn get_mix_context(&mut self) -> MixContext;
 
     fn feed(&mut self);
 
+    /// Returns time between the moment when block of samples is fed to device and the moment when
+    /// it is heard, it is defined by size of device buffer.
+    fn latency(&self) -> Duration;
+
+    /// Blocks until all samples that were fed to device are played.
+    fn drain(&mut self) {}
+
     fn mix(&mut self) {
         let context = self.get_mix_context();
 
@@ -78,17 +98,149 @@ trait Device {
     }
 }
 
+/// Handle of mixer thread, see `MixerThread::stop`.
+pub(in crate) struct MixerThread {
+    stop: Arc<AtomicBool>,
+    join_handle: Option<JoinHandle<()>>,
+    latency: Duration,
+}
+
+impl MixerThread {
+    /// Returns latency of device which is driven by mixer thread.
+    pub(in crate) fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    /// Signals mixer thread to stop and waits until it is finished. Mixer feeds one more block after
+    /// the signal and waits until device has played it, so callback has a chance to output fade to
+    /// silence instead of hard discontinuity.
+    ///
+    /// # Notes
+    ///
+    /// If it is called from mixer thread itself (for example when last reference to context was
+    /// dropped in mixer callback) thread is only signaled to stop, it will finish right after the
+    /// callback returns.
+    pub(in crate) fn stop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(join_handle) = self.join_handle.take() {
+            if join_handle.thread().id() != std::thread::current().id() {
+                // Mixer thread can only panic if device failed, nothing can be done with it here.
+                let _ = join_handle.join();
+            }
+        }
+    }
+}
+
 /// Transfer ownership of device to separate mixer thread. It will
 /// call the callback with a specified rate to get data to send to a physical device.
-pub(in crate) fn run_device(buffer_len_bytes: u32, callback: Box<FeedCallback>) -> Result<(), SoundError> {
+pub(in crate) fn run_device(buffer_len_bytes: u32, callback: Box<FeedCallback>) -> Result<MixerThread, ContextError> {
     #[cfg(target_os = "windows")]
-        let mut device = dsound::DirectSoundDevice::new(buffer_len_bytes, callback)?;
+        let open = || dsound::DirectSoundDevice::new(buffer_len_bytes, callback);
     #[cfg(target_os = "linux")]
-        let mut device = alsa::AlsaSoundDevice::new(buffer_len_bytes, callback)?;
-    std::thread::spawn(move || {
-        loop {
-            device.feed()
+        let open = || alsa::AlsaSoundDevice::new(buffer_len_bytes, callback);
+    spawn_mixer_thread(mixer_thread_builder(), open)
+}
+
+/// Same as `run_device`, but uses null device which discards all samples. Mixer callback still
+/// will be called in real time.
+pub(in crate) fn run_null_device(buffer_len_bytes: u32, callback: Box<FeedCallback>) -> Result<MixerThread, ContextError> {
+    spawn_mixer_thread(mixer_thread_builder(), || Ok(null::NullDevice::new(buffer_len_bytes, callback)))
+}
+
+fn mixer_thread_builder() -> std::thread::Builder {
+    std::thread::Builder::new().name("rg3d-sound mixer".to_owned())
+}
+
+/// Opens device by given function and spawns mixer thread with given builder. Any error of device
+/// initialization is returned as is, so caller gets exact reason of failure.
+fn spawn_mixer_thread<D, F>(builder: std::thread::Builder, open: F) -> Result<MixerThread, ContextError>
+    where D: Device + Send + 'static,
+          F: FnOnce() -> Result<D, ContextError> {
+    let mut device = open()?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let latency = device.latency();
+    let join_handle = {
+        let stop = stop.clone();
+        builder
+            .spawn(move || {
+                while !stop.load(Ordering::SeqCst) {
+                    device.feed()
+                }
+                // Last block, callback fades out to silence here.
+                device.feed();
+                device.drain();
+            })
+            .map_err(ContextError::ThreadSpawnFailure)?
+    };
+    Ok(MixerThread {
+        stop,
+        join_handle: Some(join_handle),
+        latency,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::{
+        error::ContextError,
+        device::{
+            Device,
+            SAMPLE_RATE,
+            mixer_thread_builder,
+            spawn_mixer_thread,
+            null::NullDevice,
+        },
+    };
+
+    fn open_null() -> Result<NullDevice, ContextError> {
+        Ok(NullDevice::new(4 * 512, Box::new(|_| {})))
+    }
+
+    fn open_failing(error: ContextError) -> Result<NullDevice, ContextError> {
+        Err(error)
+    }
+
+    #[test]
+    fn device_failures_are_returned_as_is() {
+        match spawn_mixer_thread(mixer_thread_builder(), || open_failing(ContextError::NoDefaultDevice)) {
+            Err(ContextError::NoDefaultDevice) => (),
+            _ => panic!("expected NoDefaultDevice"),
+        }
+        match spawn_mixer_thread(mixer_thread_builder(), || open_failing(ContextError::UnsupportedFormat)) {
+            Err(ContextError::UnsupportedFormat) => (),
+            _ => panic!("expected UnsupportedFormat"),
         }
-    });
-    Ok(())
-}
\ No newline at end of file
+        match spawn_mixer_thread(mixer_thread_builder(), || open_failing(ContextError::BackendInitFailure("foo".to_owned()))) {
+            Err(ContextError::BackendInitFailure(reason)) => assert_eq!(reason, "foo"),
+            _ => panic!("expected BackendInitFailure"),
+        }
+    }
+
+    #[test]
+    fn thread_spawn_failure() {
+        // Stack of such size can't be allocated.
+        let builder = mixer_thread_builder().stack_size(1 << 46);
+        match spawn_mixer_thread(builder, open_null) {
+            Err(ContextError::ThreadSpawnFailure(_)) => (),
+            _ => panic!("expected ThreadSpawnFailure"),
+        }
+    }
+
+    #[test]
+    fn null_device_runs() {
+        let mut mixer = spawn_mixer_thread(mixer_thread_builder(), open_null).unwrap();
+        mixer.stop();
+    }
+
+    #[test]
+    fn null_device_latency_is_buffer_duration() {
+        // 4 bytes per stereo sample, so 512 samples per channel.
+        let expected = Duration::from_secs_f64(512.0 / f64::from(SAMPLE_RATE));
+        assert_eq!(open_null().unwrap().latency(), expected);
+
+        let mut mixer = spawn_mixer_thread(mixer_thread_builder(), open_null).unwrap();
+        assert_eq!(mixer.latency(), expected);
+        mixer.stop();
+    }
+}
@@ -0,0 +1,66 @@
+//! Null device. It does not output anything, but runs mixer with the same rate as real device
+//! would do. Useful for headless environments (servers, CI, etc.) where there is no output device.
+
+use std::{
+    mem::size_of,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use crate::device::{
+    Device,
+    FeedCallback,
+    SAMPLE_RATE,
+    NativeSample,
+    MixContext,
+};
+
+pub struct NullDevice {
+    callback: Box<FeedCallback>,
+    out_data: Vec<NativeSample>,
+    mix_buffer: Vec<(f32, f32)>,
+    block_duration: Duration,
+    next_feed_time: Option<Instant>,
+}
+
+impl NullDevice {
+    pub fn new(buffer_len_bytes: u32, callback: Box<FeedCallback>) -> Self {
+        let samples_per_channel = buffer_len_bytes as usize / size_of::<NativeSample>();
+        Self {
+            callback,
+            out_data: vec![Default::default(); samples_per_channel],
+            mix_buffer: vec![(0.0, 0.0); samples_per_channel],
+            block_duration: Duration::from_secs_f64(samples_per_channel as f64 / f64::from(SAMPLE_RATE)),
+            next_feed_time: None,
+        }
+    }
+}
+
+impl Device for NullDevice {
+    fn get_mix_context(&mut self) -> MixContext<'_> {
+        MixContext {
+            mix_buffer: self.mix_buffer.as_mut_slice(),
+            out_data: &mut self.out_data,
+            callback: &mut self.callback,
+        }
+    }
+
+    fn feed(&mut self) {
+        self.mix();
+
+        // Emulate real-time consumption of samples. Next feed time is accumulated to
+        // prevent drift caused by time spent in mixer.
+        let now = Instant::now();
+        let next_feed_time = self.next_feed_time.unwrap_or(now) + self.block_duration;
+        if next_feed_time > now {
+            std::thread::sleep(next_feed_time - now);
+        }
+        self.next_feed_time = Some(next_feed_time);
+    }
+
+    fn latency(&self) -> Duration {
+        // Block is "played" during the time until next feed.
+        self.block_duration
+    }
+}
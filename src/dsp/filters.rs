@@ -62,6 +62,41 @@ impl OnePole {
     }
 }
 
+/// DC blocker - first-order highpass filter with very low cutoff frequency which removes constant
+/// offset from signal. `y[n] = x[n] - x[n - 1] + R * y[n - 1]`.
+/// For details see - https://ccrma.stanford.edu/~jos/filters/DC_Blocker.html
+#[derive(Clone, Debug)]
+pub struct DcBlocker {
+    pole: f32,
+    last_input: f32,
+    last_output: f32,
+}
+
+impl DcBlocker {
+    /// Creates new DC blocker with given normalized cutoff frequency.
+    pub fn new(fc: f32) -> Self {
+        Self {
+            pole: get_b1(fc),
+            last_input: 0.0,
+            last_output: 0.0,
+        }
+    }
+
+    /// Resets state of filter.
+    pub fn reset(&mut self) {
+        self.last_input = 0.0;
+        self.last_output = 0.0;
+    }
+
+    /// Processes single sample.
+    pub fn feed(&mut self, sample: f32) -> f32 {
+        let result = sample - self.last_input + self.pole * self.last_output;
+        self.last_input = sample;
+        self.last_output = result;
+        result
+    }
+}
+
 impl Visit for OnePole {
     fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
         visitor.enter_region(name)?;
@@ -378,4 +413,36 @@ impl Visit for Biquad {
 
         visitor.leave_region()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::{
+        dsp::filters::DcBlocker,
+        test_support,
+    };
+
+    const SAMPLE_RATE: usize = 44100;
+
+    fn mean(samples: &[f32]) -> f32 {
+        samples.iter().sum::<f32>() / samples.len() as f32
+    }
+
+    #[test]
+    fn dc_blocker_removes_offset_and_passes_tone() {
+        let tone = test_support::sine(441.0, SAMPLE_RATE, SAMPLE_RATE * 2);
+        let mut blocker = DcBlocker::new(10.0 / SAMPLE_RATE as f32);
+        let output = tone.iter().map(|s| blocker.feed(s + 0.3)).collect::<Vec<f32>>();
+
+        // Offset is gone after a few time constants (~16 ms each).
+        let tail = &output[SAMPLE_RATE..];
+        assert!(mean(tail).abs() < 1.0e-3, "{}", mean(tail));
+        // Tone itself is unchanged (cutoff is far below it).
+        assert!((test_support::rms(tail) - test_support::rms(&tone[SAMPLE_RATE..])).abs() < 0.01);
+
+        // Clone keeps state, reset clears it.
+        let mut copy = blocker.clone();
+        assert_eq!(copy.feed(0.5), blocker.feed(0.5));
+        blocker.reset();
+        assert_eq!(blocker.feed(0.0), 0.0);
+    }
+}
@@ -0,0 +1,263 @@
+//! Loudness measurement module.
+//!
+//! # Overview
+//!
+//! Implements loudness measurement per ITU-R BS.1770 (same algorithm is used by EBU R128): signal is
+//! passed through K-weighting filter (high shelf + high pass), then mean square power is calculated
+//! over 400 ms blocks. Loudness is given in LUFS (loudness units relative to full scale), 1 LU equals
+//! to 1 dB.
+//!
+//! Momentary loudness is loudness of last 400 ms. Integrated loudness is loudness of whole measured
+//! program with gating: blocks quieter than -70 LUFS (absolute gate) are ignored, then blocks quieter
+//! than 10 LU below loudness of remaining blocks (relative gate) are ignored too.
+
+use crate::dsp::filters::Biquad;
+
+/// Results of loudness measurement, all values are in LUFS. Silence gives negative infinity.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LoudnessStats {
+    /// Loudness of last 400 ms of signal.
+    pub momentary: f32,
+    /// Gated loudness of whole signal since start (or last reset) of measurement.
+    pub integrated: f32,
+}
+
+impl Default for LoudnessStats {
+    fn default() -> Self {
+        Self {
+            momentary: f32::NEG_INFINITY,
+            integrated: f32::NEG_INFINITY,
+        }
+    }
+}
+
+// Gating blocks have 75% overlap, so each block consists of four sub-blocks of 100 ms.
+const SUB_BLOCKS_PER_BLOCK: usize = 4;
+
+// Histogram of block loudness is used instead of list of all blocks, so memory usage does not grow
+// with time. Bins are 0.1 LU wide in [-70; 5] LUFS range.
+const HISTOGRAM_MIN: f32 = -70.0;
+const HISTOGRAM_MAX: f32 = 5.0;
+const HISTOGRAM_STEP: f32 = 0.1;
+
+const ABSOLUTE_GATE: f32 = -70.0;
+const RELATIVE_GATE: f32 = -10.0;
+
+fn power_to_loudness(power: f32) -> f32 {
+    if power > 0.0 {
+        -0.691 + 10.0 * power.log10()
+    } else {
+        f32::NEG_INFINITY
+    }
+}
+
+fn loudness_to_power(loudness: f32) -> f32 {
+    10.0f32.powf((loudness + 0.691) / 10.0)
+}
+
+/// Creates K-weighting filter stages (high shelf that models acoustic effect of head and high pass)
+/// for given sample rate. Coefficients are calculated from analog prototypes of BS.1770, so they match
+/// reference coefficients given for 48 kHz.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = f64::from(sample_rate);
+
+    let f0 = 1_681.974_450_955_533;
+    let gain_db = 3.999_843_853_973_347;
+    let q = 0.707_175_236_955_419_6;
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let vh = 10.0f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.499_666_774_154_541_6);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::from_coefficients(
+        ((vh + vb * k / q + k * k) / a0) as f32,
+        (2.0 * (k * k - vh) / a0) as f32,
+        ((vh - vb * k / q + k * k) / a0) as f32,
+        (2.0 * (k * k - 1.0) / a0) as f32,
+        ((1.0 - k / q + k * k) / a0) as f32,
+    );
+
+    let f0 = 38.135_470_876_024_44;
+    let q = 0.500_327_037_323_877_3;
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::from_coefficients(
+        1.0,
+        -2.0,
+        1.0,
+        (2.0 * (k * k - 1.0) / a0) as f32,
+        ((1.0 - k / q + k * k) / a0) as f32,
+    );
+
+    [shelf, high_pass]
+}
+
+/// Measures loudness of stereo signal. See module docs.
+pub struct LoudnessMeter {
+    sample_rate: u32,
+    left_filters: [Biquad; 2],
+    right_filters: [Biquad; 2],
+    sub_block_len: usize,
+    sub_block_pos: usize,
+    // Sum of squares of K-weighted samples of both channels in current sub-block.
+    sub_block_sum: f64,
+    // Mean square power of last sub-blocks, ring buffer.
+    sub_blocks: [f32; SUB_BLOCKS_PER_BLOCK],
+    sub_block_count: usize,
+    histogram: Vec<u32>,
+    momentary: f32,
+}
+
+impl LoudnessMeter {
+    /// Creates new loudness meter for signal with given sample rate.
+    pub fn new(sample_rate: u32) -> Self {
+        let bin_count = ((HISTOGRAM_MAX - HISTOGRAM_MIN) / HISTOGRAM_STEP).round() as usize;
+        Self {
+            sample_rate,
+            left_filters: k_weighting(sample_rate),
+            right_filters: k_weighting(sample_rate),
+            sub_block_len: (sample_rate as usize / 10).max(1),
+            sub_block_pos: 0,
+            sub_block_sum: 0.0,
+            sub_blocks: [0.0; SUB_BLOCKS_PER_BLOCK],
+            sub_block_count: 0,
+            histogram: vec![0; bin_count],
+            momentary: f32::NEG_INFINITY,
+        }
+    }
+
+    /// Resets measurement, integrated loudness will be measured from scratch.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.sample_rate);
+    }
+
+    /// Feeds stereo samples to the meter.
+    pub fn feed(&mut self, samples: &[(f32, f32)]) {
+        for &(left, right) in samples {
+            let left = self.left_filters.iter_mut().fold(left, |s, filter| filter.feed(s));
+            let right = self.right_filters.iter_mut().fold(right, |s, filter| filter.feed(s));
+            // Both front channels have weight of 1.0.
+            self.sub_block_sum += f64::from(left * left + right * right);
+            self.sub_block_pos += 1;
+
+            if self.sub_block_pos == self.sub_block_len {
+                self.end_sub_block();
+            }
+        }
+    }
+
+    fn end_sub_block(&mut self) {
+        let power = (self.sub_block_sum / self.sub_block_len as f64) as f32;
+        self.sub_blocks[self.sub_block_count % SUB_BLOCKS_PER_BLOCK] = power;
+        self.sub_block_count += 1;
+        self.sub_block_pos = 0;
+        self.sub_block_sum = 0.0;
+
+        if self.sub_block_count >= SUB_BLOCKS_PER_BLOCK {
+            let block_power = self.sub_blocks.iter().sum::<f32>() / SUB_BLOCKS_PER_BLOCK as f32;
+            self.momentary = power_to_loudness(block_power);
+            if self.momentary > ABSOLUTE_GATE {
+                let bin = ((self.momentary - HISTOGRAM_MIN) / HISTOGRAM_STEP) as usize;
+                let last = self.histogram.len() - 1;
+                self.histogram[bin.min(last)] += 1;
+            }
+        }
+    }
+
+    fn bin_loudness(index: usize) -> f32 {
+        HISTOGRAM_MIN + (index as f32 + 0.5) * HISTOGRAM_STEP
+    }
+
+    // Returns loudness of blocks which are louder than given gate.
+    fn gated_loudness(&self, gate: f32) -> f32 {
+        let mut power_sum = 0.0f64;
+        let mut count = 0u64;
+        for (i, &n) in self.histogram.iter().enumerate() {
+            let loudness = Self::bin_loudness(i);
+            if n > 0 && loudness > gate {
+                power_sum += f64::from(n) * f64::from(loudness_to_power(loudness));
+                count += u64::from(n);
+            }
+        }
+        if count > 0 {
+            power_to_loudness((power_sum / count as f64) as f32)
+        } else {
+            f32::NEG_INFINITY
+        }
+    }
+
+    /// Returns current results of measurement.
+    pub fn stats(&self) -> LoudnessStats {
+        let absolute = self.gated_loudness(ABSOLUTE_GATE);
+        LoudnessStats {
+            momentary: self.momentary,
+            integrated: if absolute.is_finite() {
+                self.gated_loudness(absolute + RELATIVE_GATE)
+            } else {
+                absolute
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        dsp::loudness::LoudnessMeter,
+        test_support,
+    };
+
+    const SAMPLE_RATE: usize = 48000;
+
+    // Sine of 997 Hz is a calibration signal of BS.1770: stereo tone with peak level of X dBFS
+    // must have loudness of X LUFS.
+    fn tone(level_db: f32, seconds: usize) -> Vec<f32> {
+        let amplitude = 10.0f32.powf(level_db / 20.0);
+        test_support::sine(997.0, SAMPLE_RATE, SAMPLE_RATE * seconds)
+            .iter()
+            .map(|s| s * amplitude)
+            .collect()
+    }
+
+    fn measure(samples: &[(f32, f32)]) -> (f32, f32) {
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE as u32);
+        for block in samples.chunks(1000) {
+            meter.feed(block);
+        }
+        let stats = meter.stats();
+        (stats.momentary, stats.integrated)
+    }
+
+    #[test]
+    fn calibrated_tone_has_expected_loudness() {
+        for &level in [-3.0, -20.0, -40.0].iter() {
+            let (momentary, integrated) = measure(&test_support::to_stereo(&tone(level, 3)));
+            assert!((momentary - level).abs() < 0.1, "{} {}", level, momentary);
+            assert!((integrated - level).abs() < 0.1, "{} {}", level, integrated);
+        }
+    }
+
+    #[test]
+    fn tone_in_one_channel_is_three_lu_quieter() {
+        let samples = tone(-20.0, 3).iter().map(|&s| (s, 0.0)).collect::<Vec<(f32, f32)>>();
+        let (_, integrated) = measure(&samples);
+        assert!((integrated + 23.01).abs() < 0.1, "{}", integrated);
+    }
+
+    #[test]
+    fn silence_and_quiet_parts_are_gated() {
+        let (momentary, integrated) = measure(&vec![(0.0, 0.0); SAMPLE_RATE]);
+        assert_eq!(momentary, f32::NEG_INFINITY);
+        assert_eq!(integrated, f32::NEG_INFINITY);
+
+        // Long pause (below relative gate) between loud parts almost does not change integrated
+        // loudness (only few blocks at transitions pass the gate), without gating it would be
+        // ~-27 LUFS. Momentary loudness follows the signal.
+        let mut samples = tone(-20.0, 2);
+        samples.extend(tone(-60.0, 10));
+        samples.extend(tone(-20.0, 2));
+        samples.extend(tone(-60.0, 1));
+        let (momentary, integrated) = measure(&test_support::to_stereo(&samples));
+        assert!(integrated > -21.0 && integrated < -20.0, "{}", integrated);
+        assert!((momentary + 60.0).abs() < 0.5, "{}", momentary);
+    }
+}
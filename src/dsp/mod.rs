@@ -11,6 +11,8 @@
 use rg3d_core::visitor::{Visit, Visitor, VisitResult};
 
 pub mod filters;
+pub mod loudness;
+pub mod stretch;
 
 /// See more info here https://ccrma.stanford.edu/~jos/pasp/Delay_Lines.html
 pub struct DelayLine {
@@ -73,6 +75,148 @@ impl Visit for DelayLine {
     }
 }
 
+/// Delay line with fractional delay which can be changed per sample. Fractional part of delay
+/// is handled by linear interpolation between adjacent samples. Useful for small time-varying
+/// delays (interaural time difference, chorus, etc.).
+pub struct FractionalDelayLine {
+    samples: Vec<f32>,
+    pos: usize,
+}
+
+impl FractionalDelayLine {
+    /// Creates new fractional delay line which will be able to delay signal by at most `max_delay`
+    /// samples.
+    pub fn new(max_delay: usize) -> Self {
+        Self {
+            samples: vec![0.0; max_delay + 2],
+            pos: 0,
+        }
+    }
+
+    /// Returns maximum delay (in samples) of delay line.
+    pub fn max_delay(&self) -> usize {
+        self.samples.len() - 2
+    }
+
+    /// Processes single sample with given delay (in samples). Delay is clamped to `0..max_delay`
+    /// range.
+    pub fn feed(&mut self, sample: f32, delay: f32) -> f32 {
+        let len = self.samples.len();
+        self.samples[self.pos] = sample;
+
+        let delay = delay.max(0.0).min(self.max_delay() as f32);
+        let whole = delay as usize;
+        let fract = delay - whole as f32;
+        let a = self.samples[(self.pos + len - whole) % len];
+        let b = self.samples[(self.pos + len - whole - 1) % len];
+
+        self.pos = (self.pos + 1) % len;
+
+        a + (b - a) * fract
+    }
+
+    /// Returns true if delay line holds only silence, so it will output silence until non-zero
+    /// sample will be fed.
+    pub fn is_silent(&self) -> bool {
+        self.samples.iter().all(|&sample| sample == 0.0)
+    }
+}
+
+impl Default for FractionalDelayLine {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+/// Envelope follower tracks level (absolute value) of signal: its output rises with attack time
+/// constant when input is louder than current level and falls with release time constant otherwise.
+/// Useful for dynamics processing - ducking, compression, gating.
+#[derive(Clone, Debug)]
+pub struct EnvelopeFollower {
+    attack: f32,
+    release: f32,
+    level: f32,
+}
+
+impl EnvelopeFollower {
+    /// Creates new envelope follower with given attack and release time constants (in samples).
+    /// Zero time constant means that level follows input instantly.
+    pub fn new(attack: f32, release: f32) -> Self {
+        Self {
+            attack: Self::coefficient(attack),
+            release: Self::coefficient(release),
+            level: 0.0,
+        }
+    }
+
+    fn coefficient(time: f32) -> f32 {
+        if time > 0.0 {
+            (-1.0 / time).exp()
+        } else {
+            0.0
+        }
+    }
+
+    /// Processes single sample and returns new level.
+    pub fn feed(&mut self, sample: f32) -> f32 {
+        let input = sample.abs();
+        let k = if input > self.level { self.attack } else { self.release };
+        self.level = input + (self.level - input) * k;
+        self.level
+    }
+
+    /// Returns current level.
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+}
+
+/// Small and fast pseudo-random number generator (xorshift64*). It is not suitable for cryptography,
+/// but it is deterministic - same seed will always produce same sequence of numbers on every platform,
+/// which is important for reproducible offline renders.
+#[derive(Clone, Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    /// Creates new generator with given seed. Zero seed is allowed, it will be replaced with some
+    /// non-zero constant because xorshift cannot leave zero state.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed }
+        }
+    }
+
+    /// Returns next pseudo-random number.
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns next pseudo-random number uniformly distributed in [0; 1) range.
+    pub fn next_f32(&mut self) -> f32 {
+        // Take 24 most significant bits - exactly as much as f32 mantissa can hold.
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Returns next pseudo-random number uniformly distributed in [min; max) range.
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// Returns next pseudo-random number with triangular probability density function in (-1; 1)
+    /// range. Such noise is used for dithering (TPDF dither), because it makes quantization error
+    /// independent from signal.
+    pub fn tpdf(&mut self) -> f32 {
+        self.next_f32() - self.next_f32()
+    }
+}
+
 /// Calculates single coefficient of Hamming window.
 /// https://en.wikipedia.org/wiki/Window_function#Hamming_window
 pub fn hamming_window(i: usize, sample_count: usize) -> f32 {
@@ -89,4 +233,88 @@ pub fn hann_window(i: usize, sample_count: usize) -> f32 {
 /// https://en.wikipedia.org/wiki/Window_function
 pub fn make_window<W: Fn(usize, usize) -> f32>(sample_count: usize, func: W) -> Vec<f32> {
     (0..sample_count).map(|i| func(i, sample_count)).collect()
-}
\ No newline at end of file
+}
+
+/// Converts sample to signed 16-bit integer. Sample is clamped to [-1; 1] range first and then
+/// rounded to nearest integer, so 1.0 gives `i16::MAX` and -1.0 gives `-i16::MAX`.
+pub fn sample_to_i16(sample: f32) -> i16 {
+    const SCALE: f32 = i16::MAX as f32;
+    (sample.clamp(-1.0, 1.0) * SCALE).round() as i16
+}
+
+/// Converts signed 16-bit integer sample back to floating point. This is exact inverse of
+/// `sample_to_i16` for samples in [-1; 1] range that was produced by it.
+pub fn i16_to_sample(sample: i16) -> f32 {
+    f32::from(sample) / i16::MAX as f32
+}
+
+/// Calculates peak and root mean square (RMS) levels of given stereo samples. Both channels
+/// are taken into account. Returns `(peak, rms)` pair, both values are zero for empty input.
+pub fn measure_levels(samples: &[(f32, f32)]) -> (f32, f32) {
+    if samples.is_empty() {
+        return (0.0, 0.0);
+    }
+
+    let mut peak = 0.0f32;
+    let mut sqr_sum = 0.0f32;
+    for &(left, right) in samples {
+        peak = peak.max(left.abs()).max(right.abs());
+        sqr_sum += left * left + right * right;
+    }
+
+    (peak, (sqr_sum / (2 * samples.len()) as f32).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dsp::{sample_to_i16, i16_to_sample, EnvelopeFollower};
+
+    #[test]
+    fn envelope_follower_has_attack_and_release_time_constants() {
+        let mut follower = EnvelopeFollower::new(10.0, 100.0);
+        for _ in 0..10 {
+            follower.feed(-1.0);
+        }
+        // Level rises by 1 - 1/e within attack time, sign of input does not matter.
+        assert!((follower.level() - (1.0 - (-1.0f32).exp())).abs() < 1.0e-4);
+        for _ in 0..1000 {
+            follower.feed(1.0);
+        }
+        for _ in 0..100 {
+            follower.feed(0.0);
+        }
+        assert!((follower.level() - (-1.0f32).exp()).abs() < 1.0e-4);
+
+        // Zero time constants follow input instantly.
+        let mut instant = EnvelopeFollower::new(0.0, 0.0);
+        assert_eq!(instant.feed(0.5), 0.5);
+        assert_eq!(instant.feed(0.0), 0.0);
+    }
+
+    #[test]
+    fn samples_are_quantized_with_rounding() {
+        assert_eq!(sample_to_i16(0.0), 0);
+        assert_eq!(sample_to_i16(1.0), 32767);
+        assert_eq!(sample_to_i16(-1.0), -32767);
+        assert_eq!(sample_to_i16(0.5), 16384); // 16383.5 rounds away from zero
+        assert_eq!(sample_to_i16(-0.25), -8192); // -8191.75
+        assert_eq!(sample_to_i16(1.0 / 32767.0 * 0.49), 0);
+        assert_eq!(sample_to_i16(1.0 / 32767.0 * 0.51), 1);
+    }
+
+    #[test]
+    fn out_of_range_samples_are_clamped() {
+        assert_eq!(sample_to_i16(1.5), 32767);
+        assert_eq!(sample_to_i16(100.0), 32767);
+        assert_eq!(sample_to_i16(-1.5), -32767);
+        assert_eq!(sample_to_i16(f32::INFINITY), 32767);
+        assert_eq!(sample_to_i16(f32::NEG_INFINITY), -32767);
+    }
+
+    #[test]
+    fn conversion_back_is_exact_inverse() {
+        for &sample in [-32767i16, -12345, -1, 0, 1, 777, 32767].iter() {
+            assert_eq!(sample_to_i16(i16_to_sample(sample)), sample);
+        }
+    }
+}
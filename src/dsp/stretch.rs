@@ -0,0 +1,142 @@
+//! Time stretch module.
+//!
+//! # Overview
+//!
+//! Implements WSOLA (waveform similarity overlap-add) time stretching: output is composed of windowed
+//! grains of input which are overlapped with fixed output hop, while input hop is scaled by stretch
+//! factor. Exact position of each grain is searched in small neighbourhood of its ideal position to find
+//! the one that is most similar to natural continuation of previous grain, this preserves periodicity
+//! of signal and so its pitch.
+
+use std::collections::VecDeque;
+
+/// Length of grain in samples (~23 ms at 44100 Hz).
+const GRAIN_LEN: usize = 1024;
+
+/// Output hop, grains overlap by 50%.
+const HOP: usize = GRAIN_LEN / 2;
+
+/// Maximum offset (in samples) of grain from its ideal position.
+const TOLERANCE: usize = 256;
+
+/// Time stretcher, see module docs.
+pub struct TimeStretcher {
+    factor: f32,
+    window: Vec<f32>,
+    // Input frames which are not consumed yet.
+    input: VecDeque<(f32, f32)>,
+    // Processed frames which are ready for output.
+    output: VecDeque<(f32, f32)>,
+    // Second (windowed) half of last grain, it will be overlapped with next grain.
+    overlap: Vec<(f32, f32)>,
+    // Ideal position of next grain in input, relative to beginning of `input`.
+    analysis_pos: f64,
+    // Actual position of last grain in input, relative to beginning of `input`.
+    prev_pos: Option<usize>,
+}
+
+impl TimeStretcher {
+    /// Creates new time stretcher with given stretch factor: 2.0 - output is twice longer than input,
+    /// 0.5 - twice shorter. Factor is clamped to [0.25; 4.0] range.
+    pub fn new(factor: f32) -> Self {
+        Self {
+            factor: factor.clamp(0.25, 4.0),
+            // Periodic Hann window, it sums to one with 50% overlap.
+            window: (0..GRAIN_LEN)
+                .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / GRAIN_LEN as f32).cos())
+                .collect(),
+            input: Default::default(),
+            output: Default::default(),
+            overlap: vec![(0.0, 0.0); HOP],
+            analysis_pos: 0.0,
+            prev_pos: None,
+        }
+    }
+
+    /// Returns stretch factor.
+    pub fn factor(&self) -> f32 {
+        self.factor
+    }
+
+    /// Discards all internal state, next output will start from scratch.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.factor);
+    }
+
+    /// Produces given amount of stretched frames and appends them to `out`. Input frames are pulled from
+    /// `pull` closure when needed.
+    pub fn process<F>(&mut self, amount: usize, out: &mut Vec<(f32, f32)>, mut pull: F)
+        where F: FnMut() -> (f32, f32) {
+        while self.output.len() < amount {
+            let ideal = self.analysis_pos as usize;
+            let natural = self.prev_pos.map_or(0, |prev| prev + HOP);
+            let required = (ideal + TOLERANCE).max(natural) + GRAIN_LEN;
+            while self.input.len() < required {
+                self.input.push_back(pull());
+            }
+            self.process_grain();
+        }
+        for _ in 0..amount {
+            // Loop above guarantees that there is enough frames.
+            out.push(self.output.pop_front().unwrap_or((0.0, 0.0)));
+        }
+    }
+
+    fn mid(&self, i: usize) -> f32 {
+        let (left, right) = self.input[i];
+        left + right
+    }
+
+    fn process_grain(&mut self) {
+        let ideal = self.analysis_pos as usize;
+
+        let pos = match self.prev_pos {
+            None => ideal,
+            Some(prev) => {
+                // Find position of grain which first half is most similar to natural continuation
+                // of previous grain. Every second sample is used to halve the cost of search.
+                let natural = prev + HOP;
+                let mut best = ideal;
+                let mut best_correlation = f32::MIN;
+                for candidate in ideal.saturating_sub(TOLERANCE)..=(ideal + TOLERANCE) {
+                    let mut correlation = 0.0;
+                    for k in (0..HOP).step_by(2) {
+                        correlation += self.mid(natural + k) * self.mid(candidate + k);
+                    }
+                    if correlation > best_correlation {
+                        best_correlation = correlation;
+                        best = candidate;
+                    }
+                }
+                best
+            }
+        };
+
+        for k in 0..GRAIN_LEN {
+            let (left, right) = self.input[pos + k];
+            if k < HOP {
+                let frame = if self.prev_pos.is_some() {
+                    let w = self.window[k];
+                    let (overlap_left, overlap_right) = self.overlap[k];
+                    (overlap_left + left * w, overlap_right + right * w)
+                } else {
+                    // There is nothing to overlap with first grain, so it must not be faded in.
+                    (left, right)
+                };
+                self.output.push_back(frame);
+            } else {
+                let w = self.window[k];
+                self.overlap[k - HOP] = (left * w, right * w);
+            }
+        }
+
+        self.prev_pos = Some(pos);
+        self.analysis_pos += HOP as f64 / f64::from(self.factor);
+
+        // Discard input that won't be needed anymore.
+        let consumed = pos.min((self.analysis_pos as usize).saturating_sub(TOLERANCE));
+        self.input.drain(..consumed);
+        self.prev_pos = Some(pos - consumed);
+        self.analysis_pos -= consumed as f64;
+    }
+}
@@ -0,0 +1,196 @@
+//! Bit crusher module
+//!
+//! # Overview
+//!
+//! Bit crusher is lo-fi effect which simulates low-quality digital audio: amplitude of signal is
+//! quantized to given bit depth and samples are held for given amount of samples which simulates lower
+//! sample rate (without anti-aliasing filter, so aliasing is part of the effect). With full bit depth
+//! and downsample factor of 1 effect is transparent.
+//!
+//! # Usage
+//!
+//! ```
+//! use rg3d_sound::context::Context;
+//! use rg3d_sound::effects::bit_crusher::BitCrusher;
+//! use rg3d_sound::effects::Effect;
+//!
+//! fn add_crusher(context: &mut Context) {
+//!     let mut crusher = BitCrusher::new(Default::default());
+//!     crusher.set_bit_depth(8);
+//!     crusher.set_downsample(4);
+//!     context.add_effect(Effect::BitCrusher(crusher));
+//! }
+//! ```
+
+use rg3d_core::visitor::{
+    Visit,
+    Visitor,
+    VisitResult
+};
+use crate::effects::{
+    EffectTrait,
+    BaseEffect,
+    EffectRenderTrait
+};
+
+/// See module docs.
+pub struct BitCrusher {
+    base: BaseEffect,
+    bit_depth: u32,
+    downsample: u32,
+    // Currently held frame and amount of samples for which it was held.
+    held: (f32, f32),
+    hold_counter: u32,
+}
+
+impl Default for BitCrusher {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl BitCrusher {
+    /// Bit depth at which no quantization happens, samples are 32-bit floats anyway.
+    pub const FULL_BIT_DEPTH: u32 = 32;
+
+    /// Creates new bit crusher with full bit depth and downsample factor of 1, so it is transparent
+    /// until parameters are changed.
+    pub fn new(base: BaseEffect) -> Self {
+        Self {
+            base,
+            bit_depth: Self::FULL_BIT_DEPTH,
+            downsample: 1,
+            held: (0.0, 0.0),
+            hold_counter: 0,
+        }
+    }
+
+    /// Sets bit depth to which amplitude of signal will be quantized, signal in [-1; 1] range will
+    /// have `2 ^ bits` levels - from -1.0 to `1.0 - 2 ^ (1 - bits)` like in signed integer formats. Value is clamped to [1; 32] range, 32 (default) means no quantization.
+    pub fn set_bit_depth(&mut self, bits: u32) {
+        self.bit_depth = bits.clamp(1, Self::FULL_BIT_DEPTH);
+    }
+
+    /// Returns bit depth.
+    pub fn bit_depth(&self) -> u32 {
+        self.bit_depth
+    }
+
+    /// Sets downsample factor - each input sample is held for given amount of samples, which
+    /// simulates sample rate that is `factor` times lower. Zero is treated as 1, 1 (default) means
+    /// no downsampling.
+    pub fn set_downsample(&mut self, factor: u32) {
+        self.downsample = factor.max(1);
+    }
+
+    /// Returns downsample factor.
+    pub fn downsample(&self) -> u32 {
+        self.downsample
+    }
+
+    // Quantizes sample to one of `2 * scale` levels, same as conversion to signed integer does:
+    // values beyond the range are clamped to lowest or highest level.
+    fn quantize(sample: f32, scale: f32) -> f32 {
+        (sample * scale).round().max(-scale).min(scale - 1.0) / scale
+    }
+}
+
+impl Visit for BitCrusher {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        self.base.visit("Base", visitor)?;
+        self.bit_depth.visit("BitDepth", visitor)?;
+        self.downsample.visit("Downsample", visitor)?;
+
+        visitor.leave_region()
+    }
+}
+
+impl EffectRenderTrait for BitCrusher {
+    fn process_accumulated(&mut self, mix_buf: &mut [(f32, f32)]) {
+        // Half of levels are for negative values.
+        let scale = if self.bit_depth < Self::FULL_BIT_DEPTH {
+            Some((1u64 << (self.bit_depth - 1)) as f32)
+        } else {
+            None
+        };
+
+        for ((out_left, out_right), &(left, right)) in mix_buf.iter_mut().zip(self.base.frame_samples.iter()) {
+            if self.hold_counter == 0 {
+                self.held = match scale {
+                    Some(scale) => (Self::quantize(left, scale), Self::quantize(right, scale)),
+                    None => (left, right),
+                };
+            }
+            self.hold_counter = (self.hold_counter + 1) % self.downsample;
+
+            *out_left += self.held.0;
+            *out_right += self.held.1;
+        }
+    }
+}
+
+impl EffectTrait for BitCrusher {
+    fn base(&self) -> &BaseEffect {
+        &self.base
+    }
+
+    fn base_mut(&mut self) -> &mut BaseEffect {
+        &mut self.base
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        effects::{
+            Effect,
+            bit_crusher::BitCrusher,
+        },
+        test_support,
+    };
+
+    fn crush(bits: u32, factor: u32, input: &[f32]) -> Vec<f32> {
+        let mut crusher = BitCrusher::new(Default::default());
+        crusher.set_bit_depth(bits);
+        crusher.set_downsample(factor);
+        let mut buf = test_support::to_stereo(input);
+        Effect::BitCrusher(crusher).process(&mut buf);
+        buf.iter().map(|s| s.0).collect()
+    }
+
+    fn distinct_levels(samples: &[f32]) -> Vec<f32> {
+        let mut levels = samples.to_vec();
+        levels.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        levels.dedup();
+        levels
+    }
+
+    #[test]
+    fn bit_depth_gives_expected_amount_of_levels() {
+        // Full scale ramp, a bit beyond [-1; 1] to check clamping.
+        let ramp = (0..4096).map(|i| -1.1 + 2.2 * i as f32 / 4095.0).collect::<Vec<f32>>();
+        for &bits in [1, 3, 8].iter() {
+            let levels = distinct_levels(&crush(bits, 1, &ramp));
+            assert_eq!(levels.len(), 1 << bits);
+            assert_eq!(levels[0], -1.0);
+            assert_eq!(levels[levels.len() - 1], 1.0 - 2.0 / (1 << bits) as f32);
+        }
+    }
+
+    #[test]
+    fn downsample_holds_samples() {
+        let input = test_support::noise(1000, 9);
+        let output = crush(BitCrusher::FULL_BIT_DEPTH, 4, &input);
+        for (i, chunk) in output.chunks(4).enumerate() {
+            assert!(chunk.iter().all(|&s| s == input[i * 4]));
+        }
+    }
+
+    #[test]
+    fn full_settings_are_transparent() {
+        let input = test_support::noise(1000, 10);
+        assert_eq!(crush(BitCrusher::FULL_BIT_DEPTH, 1, &input), input);
+    }
+}
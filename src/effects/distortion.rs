@@ -0,0 +1,373 @@
+//! Distortion module
+//!
+//! # Overview
+//!
+//! Distortion is waveshaper effect: each sample is amplified by drive and then passed through
+//! non-linear transfer curve (see `DistortionCurve`), which adds harmonics to the signal. Useful for
+//! engines, weapons, radio voices, etc. Waveshaping produces harmonics above Nyquist frequency which
+//! fold back as aliasing, so effect can be oversampled (see `Distortion::set_oversampling`) at the
+//! cost of CPU.
+//!
+//! # Usage
+//!
+//! ```
+//! use rg3d_sound::context::Context;
+//! use rg3d_sound::effects::distortion::{Distortion, DistortionCurve};
+//! use rg3d_sound::effects::Effect;
+//!
+//! fn add_distortion(context: &mut Context) {
+//!     let mut distortion = Distortion::new(Default::default());
+//!     distortion.set_curve(DistortionCurve::Tanh);
+//!     distortion.set_drive(8.0);
+//!     distortion.set_output_gain(0.5);
+//!     context.add_effect(Effect::Distortion(distortion));
+//! }
+//! ```
+
+use rg3d_core::visitor::{
+    Visit,
+    Visitor,
+    VisitResult
+};
+use crate::{
+    effects::{
+        EffectTrait,
+        BaseEffect,
+        EffectRenderTrait
+    },
+    dsp::filters::{
+        Biquad,
+        BiquadKind,
+    },
+};
+
+/// Transfer curve of distortion.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum DistortionCurve {
+    /// No shaping at all, only drive and output gain are applied.
+    Linear,
+
+    /// Cubic soft clipping `x - x^3 / 3` which smoothly saturates to ±2/3. Gives mostly odd
+    /// harmonics with soft knee.
+    SoftClip,
+
+    /// Signal is clamped to [-1; 1] range. Harsh, gives lots of high harmonics.
+    HardClip,
+
+    /// Hyperbolic tangent, classic "tube-like" saturation.
+    #[default]
+    Tanh,
+}
+
+impl DistortionCurve {
+    fn shape(self, x: f32) -> f32 {
+        match self {
+            DistortionCurve::Linear => x,
+            DistortionCurve::SoftClip => {
+                let x = x.clamp(-1.0, 1.0);
+                x - x * x * x / 3.0
+            }
+            DistortionCurve::HardClip => x.clamp(-1.0, 1.0),
+            DistortionCurve::Tanh => x.tanh(),
+        }
+    }
+
+    fn id(self) -> u32 {
+        match self {
+            DistortionCurve::Linear => 0,
+            DistortionCurve::SoftClip => 1,
+            DistortionCurve::HardClip => 2,
+            DistortionCurve::Tanh => 3,
+        }
+    }
+
+    fn from_id(id: u32) -> Self {
+        match id {
+            0 => DistortionCurve::Linear,
+            1 => DistortionCurve::SoftClip,
+            2 => DistortionCurve::HardClip,
+            _ => DistortionCurve::Tanh,
+        }
+    }
+}
+
+// Upsampling and downsampling filters of single channel.
+#[derive(Default)]
+struct Oversampler {
+    // Removes images of zero-stuffed signal.
+    up: [Biquad; 2],
+    // Removes harmonics above Nyquist frequency of output before decimation.
+    down: [Biquad; 2],
+}
+
+impl Oversampler {
+    fn new(factor: u32) -> Self {
+        // Cutoff is slightly below Nyquist frequency of original sample rate.
+        let fc = 0.45 / factor as f32;
+        let make = || Biquad::new(BiquadKind::LowPass, fc, 1.0, std::f32::consts::FRAC_1_SQRT_2);
+        Self {
+            up: [make(), make()],
+            down: [make(), make()],
+        }
+    }
+
+    fn process(&mut self, sample: f32, factor: u32, shape: impl Fn(f32) -> f32) -> f32 {
+        let mut output = 0.0;
+        for i in 0..factor {
+            // Zero stuffing, gain is compensated by factor.
+            let stuffed = if i == 0 { sample * factor as f32 } else { 0.0 };
+            let upsampled = self.up.iter_mut().fold(stuffed, |s, filter| filter.feed(s));
+            let shaped = shape(upsampled);
+            output = self.down.iter_mut().fold(shaped, |s, filter| filter.feed(s));
+        }
+        output
+    }
+}
+
+/// See module docs.
+pub struct Distortion {
+    base: BaseEffect,
+    curve: DistortionCurve,
+    drive: f32,
+    output_gain: f32,
+    mix: f32,
+    oversampling: u32,
+    left: Oversampler,
+    right: Oversampler,
+}
+
+impl Default for Distortion {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl Distortion {
+    /// Maximum oversampling factor.
+    pub const MAX_OVERSAMPLING: u32 = 8;
+
+    /// Creates new distortion with tanh curve, drive of 1.0, unit output gain, fully wet mix and
+    /// without oversampling.
+    pub fn new(base: BaseEffect) -> Self {
+        Self {
+            base,
+            curve: DistortionCurve::Tanh,
+            drive: 1.0,
+            output_gain: 1.0,
+            mix: 1.0,
+            oversampling: 1,
+            left: Default::default(),
+            right: Default::default(),
+        }
+    }
+
+    /// Sets transfer curve of distortion.
+    pub fn set_curve(&mut self, curve: DistortionCurve) {
+        self.curve = curve;
+    }
+
+    /// Returns transfer curve of distortion.
+    pub fn curve(&self) -> DistortionCurve {
+        self.curve
+    }
+
+    /// Sets drive - linear gain which is applied to signal before shaping, higher drive gives more
+    /// distortion. Negative values are clamped to zero. Default value is 1.0.
+    pub fn set_drive(&mut self, drive: f32) {
+        self.drive = drive.max(0.0);
+    }
+
+    /// Returns drive.
+    pub fn drive(&self) -> f32 {
+        self.drive
+    }
+
+    /// Sets linear gain which is applied to distorted signal. Default value is 1.0.
+    pub fn set_output_gain(&mut self, gain: f32) {
+        self.output_gain = gain.max(0.0);
+    }
+
+    /// Returns output gain.
+    pub fn output_gain(&self) -> f32 {
+        self.output_gain
+    }
+
+    /// Sets ratio of distorted signal in output: 0.0 - dry signal only, 1.0 (default) - distorted
+    /// signal only. Value is clamped to [0; 1] range.
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    /// Returns mix ratio.
+    pub fn mix(&self) -> f32 {
+        self.mix
+    }
+
+    /// Sets oversampling factor: shaping is done at sample rate which is `factor` times higher,
+    /// which reduces aliasing of harmonics. Factor is clamped to [1; 8] range, 1 (default) means no
+    /// oversampling. Cost of effect grows linearly with factor.
+    ///
+    /// # Notes
+    ///
+    /// Oversampling filters add small phase shift to distorted signal, so with mix other than 1.0
+    /// there will be slight comb filtering.
+    pub fn set_oversampling(&mut self, factor: u32) {
+        let factor = factor.clamp(1, Self::MAX_OVERSAMPLING);
+        if factor != self.oversampling {
+            self.oversampling = factor;
+            self.left = Oversampler::new(factor);
+            self.right = Oversampler::new(factor);
+        }
+    }
+
+    /// Returns oversampling factor.
+    pub fn oversampling(&self) -> u32 {
+        self.oversampling
+    }
+}
+
+impl Visit for Distortion {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        self.base.visit("Base", visitor)?;
+        let mut curve = self.curve.id();
+        curve.visit("Curve", visitor)?;
+        self.drive.visit("Drive", visitor)?;
+        self.output_gain.visit("OutputGain", visitor)?;
+        self.mix.visit("Mix", visitor)?;
+        let mut oversampling = self.oversampling;
+        oversampling.visit("Oversampling", visitor)?;
+
+        if visitor.is_reading() {
+            self.curve = DistortionCurve::from_id(curve);
+            self.set_oversampling(oversampling);
+        }
+
+        visitor.leave_region()
+    }
+}
+
+impl EffectRenderTrait for Distortion {
+    fn process_accumulated(&mut self, mix_buf: &mut [(f32, f32)]) {
+        let curve = self.curve;
+        let drive = self.drive;
+        let shape = |x: f32| curve.shape(x * drive);
+        let wet = self.mix * self.output_gain;
+        let dry = 1.0 - self.mix;
+
+        for ((out_left, out_right), &(left, right)) in mix_buf.iter_mut().zip(self.base.frame_samples.iter()) {
+            let (shaped_left, shaped_right) = if self.oversampling > 1 {
+                (self.left.process(left, self.oversampling, shape), self.right.process(right, self.oversampling, shape))
+            } else {
+                (shape(left), shape(right))
+            };
+
+            *out_left += shaped_left * wet + left * dry;
+            *out_right += shaped_right * wet + right * dry;
+        }
+    }
+}
+
+impl EffectTrait for Distortion {
+    fn base(&self) -> &BaseEffect {
+        &self.base
+    }
+
+    fn base_mut(&mut self) -> &mut BaseEffect {
+        &mut self.base
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rg3d_core::visitor::Visit;
+    use crate::{
+        effects::{
+            Effect,
+            distortion::{Distortion, DistortionCurve},
+        },
+        test_support,
+    };
+
+    const LEN: usize = 4096;
+
+    // Sine which falls exactly into given bin of spectrum of `LEN` samples.
+    fn bin_sine(bin: usize, amplitude: f32, len: usize) -> Vec<f32> {
+        test_support::sine(bin as f32 * 44100.0 / LEN as f32, 44100, len)
+            .iter()
+            .map(|s| s * amplitude)
+            .collect()
+    }
+
+    fn distort(curve: DistortionCurve, drive: f32, oversampling: u32, input: &[f32]) -> Vec<f32> {
+        let mut distortion = Distortion::new(Default::default());
+        distortion.set_curve(curve);
+        distortion.set_drive(drive);
+        distortion.set_oversampling(oversampling);
+        let mut buf = test_support::to_stereo(input);
+        Effect::Distortion(distortion).process(&mut buf);
+        buf.iter().map(|s| s.0).collect()
+    }
+
+    #[test]
+    fn harmonics_grow_with_drive() {
+        let input = bin_sine(40, 0.5, LEN);
+        for &curve in [DistortionCurve::SoftClip, DistortionCurve::HardClip, DistortionCurve::Tanh].iter() {
+            let mut prev_ratio = 0.0;
+            for &drive in [1.0, 2.0, 4.0, 8.0].iter() {
+                let output = distort(curve, drive, 1, &input);
+                let fundamental = test_support::bin_energy(&output, 40);
+                let third = test_support::bin_energy(&output, 120) / fundamental;
+                // Curves are symmetric, so there are odd harmonics only.
+                let second = test_support::bin_energy(&output, 80) / fundamental;
+                assert!(third >= prev_ratio, "{:?} at drive {}", curve, drive);
+                assert!(second < 1.0e-6);
+                prev_ratio = third;
+            }
+            assert!(prev_ratio > 1.0e-2, "{:?}", curve);
+        }
+    }
+
+    #[test]
+    fn linear_curve_with_unit_drive_is_transparent() {
+        let input = test_support::noise(LEN, 4);
+        assert_eq!(distort(DistortionCurve::Linear, 1.0, 1, &input), input);
+    }
+
+    #[test]
+    fn oversampling_reduces_aliasing() {
+        // Third harmonic of ~10 kHz sine is at ~30 kHz and folds back to bin 4096 - 2787 = 1309.
+        let input = bin_sine(929, 0.5, 4 * LEN);
+        let alias = |oversampling: u32| {
+            let output = distort(DistortionCurve::Tanh, 2.0, oversampling, &input);
+            let tail = &output[output.len() - LEN..];
+            test_support::bin_energy(tail, 1309) / test_support::bin_energy(tail, 929)
+        };
+        let plain = alias(1);
+        assert!(plain > 1.0e-3);
+        for &oversampling in [2, 4, 8].iter() {
+            assert!(alias(oversampling) < plain * 0.2, "{}", oversampling);
+        }
+    }
+
+    #[test]
+    fn settings_are_visited() {
+        let mut visitor = test_support::reload(|visitor| {
+            let mut distortion = Distortion::default();
+            distortion.set_curve(DistortionCurve::SoftClip);
+            distortion.set_drive(3.0);
+            distortion.set_output_gain(0.5);
+            distortion.set_mix(0.25);
+            distortion.set_oversampling(4);
+            distortion.visit("Distortion", visitor)
+        });
+        let mut distortion = Distortion::default();
+        distortion.visit("Distortion", &mut visitor).unwrap();
+        assert_eq!(distortion.curve(), DistortionCurve::SoftClip);
+        assert_eq!(distortion.drive(), 3.0);
+        assert_eq!(distortion.output_gain(), 0.5);
+        assert_eq!(distortion.mix(), 0.25);
+        assert_eq!(distortion.oversampling(), 4);
+    }
+}
@@ -3,10 +3,36 @@
 //! # Overview
 //!
 //! Provides unified way of creating and using effects.
+//!
+//! # Bypass
+//!
+//! Any effect can be temporarily bypassed by `Effect::set_enabled(false)` - it stays in the chain
+//! with all its inputs and settings, but passes its input to output unprocessed. This is handy for
+//! A/B comparisons. What happens with internal state (i.e. reverb tail) of bypassed effect is defined
+//! by `BypassMode`.
+//!
+//! # Sends
+//!
+//! Besides inputs, effect receives signal of every source which sends to it, see
+//! `GenericSource::set_send`. This allows to use effects as buses: source can send to several
+//! effects at once with individual levels, i.e. to reverb and delay simultaneously.
+//!
+//! # Insert effects
+//!
+//! By default effect processes only its inputs and sends and adds its output to the mix, so order
+//! of such effects does not matter. Insert effect (see `Effect::set_insert`) instead processes
+//! everything that was mixed before it - sources and output of effects that go before it in effects
+//! chain of context, and replaces the mix with its output. This way order of effects matters: EQ
+//! inserted after reverb processes reverb tail too, while EQ inserted before reverb does not.
 
 use crate::{
-    effects::reverb::Reverb,
-    source::{SoundSource, Status},
+    effects::{
+        reverb::Reverb,
+        noise_gate::NoiseGate,
+        bit_crusher::BitCrusher,
+        distortion::Distortion,
+    },
+    source::SoundSource,
     dsp::filters::Biquad,
     listener::Listener,
     context::DistanceModel
@@ -18,8 +44,11 @@ use rg3d_core::{
 };
 
 pub mod reverb;
+pub mod noise_gate;
+pub mod bit_crusher;
+pub mod distortion;
 
-/// Stub effect that does nothing.
+/// Stub effect that does nothing - its input is passed to output unchanged.
 #[derive(Default)]
 pub struct StubEffect {
     base: BaseEffect
@@ -36,7 +65,12 @@ impl Visit for StubEffect {
 }
 
 impl EffectRenderTrait for StubEffect {
-    fn render(&mut self, _sources: &Pool<SoundSource>, _listener: &Listener, _distance_model: DistanceModel, _mix_buf: &mut [(f32, f32)]) {}
+    fn process_accumulated(&mut self, mix_buf: &mut [(f32, f32)]) {
+        for ((out_left, out_right), &(left, right)) in mix_buf.iter_mut().zip(self.base.frame_samples.iter()) {
+            *out_left += left;
+            *out_right += right;
+        }
+    }
 }
 
 impl EffectTrait for StubEffect {
@@ -55,6 +89,12 @@ pub enum Effect {
     Stub(StubEffect),
     /// Reberberation effect. See corresponding module for more info.
     Reverb(Reverb),
+    /// Noise gate effect. See corresponding module for more info.
+    NoiseGate(NoiseGate),
+    /// Bit crusher effect. See corresponding module for more info.
+    BitCrusher(BitCrusher),
+    /// Distortion effect. See corresponding module for more info.
+    Distortion(Distortion),
 }
 
 impl Default for Effect {
@@ -64,10 +104,74 @@ impl Default for Effect {
 }
 
 impl Effect {
+    /// Enables or disables effect. Disabled (bypassed) effect passes its input straight through,
+    /// see module docs. Effects are enabled by default.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.base_mut().bypassed = !enabled;
+    }
+
+    /// Returns true if effect is enabled (not bypassed).
+    pub fn is_enabled(&self) -> bool {
+        !self.base().bypassed
+    }
+
+    /// Sets what happens with internal state of effect while it is bypassed.
+    pub fn set_bypass_mode(&mut self, mode: BypassMode) {
+        self.base_mut().bypass_mode = mode;
+    }
+
+    /// Returns current bypass mode.
+    pub fn bypass_mode(&self) -> BypassMode {
+        self.base().bypass_mode
+    }
+
+    /// Makes effect an insert effect, see module docs. Inputs and sends of insert effect are ignored,
+    /// it processes the mix at its position in effects chain of context (see `Context::insert_effect`).
+    /// Effects are not inserts by default.
+    pub fn set_insert(&mut self, insert: bool) {
+        self.base_mut().insert = insert;
+    }
+
+    /// Returns true if effect is an insert effect.
+    pub fn is_insert(&self) -> bool {
+        self.base().insert
+    }
+
+    /// Processes given buffer by the effect without context: samples of buffer are used as input of
+    /// effect and then they're replaced with output of effect (it includes dry signal if effect has
+    /// it). Inputs of effect (sources) are ignored. This is exactly the same processing that context
+    /// does each block, so effects can be used standalone - for offline processing, in tools, etc.
+    /// Bypassed effect leaves buffer unchanged.
+    pub fn process(&mut self, buf: &mut [(f32, f32)]) {
+        let base = self.base_mut();
+        base.frame_samples.clear();
+        base.frame_samples.extend_from_slice(buf);
+
+        if base.bypassed {
+            if base.bypass_mode == BypassMode::Tick {
+                let mut bypass_buf = std::mem::take(&mut base.bypass_buf);
+                bypass_buf.clear();
+                bypass_buf.resize(buf.len(), (0.0, 0.0));
+                self.process_accumulated(&mut bypass_buf);
+                self.base_mut().bypass_buf = bypass_buf;
+            }
+            return;
+        }
+
+        for (left, right) in buf.iter_mut() {
+            *left = 0.0;
+            *right = 0.0;
+        }
+        self.process_accumulated(buf);
+    }
+
     fn id(&self) -> u32 {
         match self {
             Effect::Stub(_) => 0,
             Effect::Reverb(_) => 1,
+            Effect::NoiseGate(_) => 2,
+            Effect::BitCrusher(_) => 3,
+            Effect::Distortion(_) => 4,
         }
     }
 
@@ -75,6 +179,9 @@ impl Effect {
         match id {
             0 => Ok(Effect::Stub(Default::default())),
             1 => Ok(Effect::Reverb(Default::default())),
+            2 => Ok(Effect::NoiseGate(Default::default())),
+            3 => Ok(Effect::BitCrusher(Default::default())),
+            4 => Ok(Effect::Distortion(Default::default())),
             _ => Err(format!("Unknown effect id {}", id))
         }
     }
@@ -86,11 +193,16 @@ impl Visit for Effect {
 
         let mut id = self.id();
         id.visit("Id", visitor)?;
-        *self = Self::from_id(id)?;
+        if visitor.is_reading() {
+            *self = Self::from_id(id)?;
+        }
 
         match self {
             Effect::Stub(v) => v.visit("Data", visitor)?,
             Effect::Reverb(v) => v.visit("Data", visitor)?,
+            Effect::NoiseGate(v) => v.visit("Data", visitor)?,
+            Effect::BitCrusher(v) => v.visit("Data", visitor)?,
+            Effect::Distortion(v) => v.visit("Data", visitor)?,
         }
 
         visitor.leave_region()
@@ -108,7 +220,51 @@ pub trait EffectTrait {
 }
 
 pub(in crate) trait EffectRenderTrait: EffectTrait {
-    fn render(&mut self, sources: &Pool<SoundSource>, listener: &Listener, distance_model: DistanceModel, mix_buf: &mut [(f32, f32)]);
+    fn render(&mut self, sources: &Pool<SoundSource>, listener: &Listener, distance_model: &DistanceModel, mix_buf: &mut [(f32, f32)]) {
+        self.accumulate_input(sources, listener, distance_model, mix_buf.len());
+        self.process_accumulated(mix_buf);
+    }
+
+    /// Processes input which was accumulated in `BaseEffect::frame_samples` and adds result to
+    /// given buffer.
+    fn process_accumulated(&mut self, mix_buf: &mut [(f32, f32)]);
+
+    /// Accumulates input of effect into `BaseEffect::frame_samples` without processing it. Effects
+    /// which scale their inputs (i.e. by send level) should override this, so bypassed effect will
+    /// pass exactly the same signal which it would process.
+    fn accumulate_input(&mut self, sources: &Pool<SoundSource>, listener: &Listener, distance_model: &DistanceModel, amount: usize) {
+        self.base_mut().render(sources, listener, distance_model, amount)
+    }
+}
+
+/// Defines what happens with internal state of bypassed effect.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum BypassMode {
+    /// Effect is not processed at all, its state is frozen and it will continue from the same point
+    /// when it will be enabled again (i.e. reverb tail which was cut on bypass will be heard on
+    /// enable). This is cheapest mode and it is default.
+    #[default]
+    Freeze,
+
+    /// Effect is still processed but its output is discarded, so its state is up-to-date when it is
+    /// enabled again. This gives seamless transition, but bypassed effect costs as much as enabled.
+    Tick,
+}
+
+impl BypassMode {
+    fn id(self) -> u32 {
+        match self {
+            BypassMode::Freeze => 0,
+            BypassMode::Tick => 1,
+        }
+    }
+
+    fn from_id(id: u32) -> Self {
+        match id {
+            1 => BypassMode::Tick,
+            _ => BypassMode::Freeze,
+        }
+    }
 }
 
 /// Base effect for all other kinds of effects. It contains set of inputs (direct
@@ -119,10 +275,52 @@ pub struct BaseEffect {
     filters: Pool<InputFilter>,
     inputs: Vec<EffectInput>,
     frame_samples: Vec<(f32, f32)>,
+    bypassed: bool,
+    bypass_mode: BypassMode,
+    insert: bool,
+    // Output of effect which is discarded in `BypassMode::Tick`.
+    bypass_buf: Vec<(f32, f32)>,
+    // Handle of effect in context, it is used to find sources which send to this effect. It is set
+    // by context before each render.
+    pub(in crate) handle: Handle<Effect>,
+    // Gains of sends from last frame indexed by slot of source in pool, they're used to interpolate
+    // gain of sends from frame to frame the same way as for inputs. Handle of source is stored too,
+    // so source that was added in place of removed one won't inherit its gain.
+    send_gains: Vec<(Handle<SoundSource>, Option<f32>)>,
+}
+
+fn distance_gain(source: &SoundSource, listener: &Listener, distance_model: &DistanceModel) -> f32 {
+    listener.effective_gain() * match source {
+        SoundSource::Generic(_) => 1.0,
+        SoundSource::Spatial(spatial) => spatial.get_distance_gain(listener, distance_model),
+    }
+}
+
+fn mix_interpolated(accum: &mut [(f32, f32)], input: &[(f32, f32)], prev_gain: f32, gain: f32) {
+    let step = 1.0 / accum.len() as f32;
+    let mut k = 0.0;
+    for ((accum_left, accum_right), &(input_left, input_right)) in accum.iter_mut().zip(input) {
+        let g = math::lerpf(prev_gain, gain, k);
+        *accum_left += input_left * g;
+        *accum_right += input_right * g;
+        k += step;
+    }
 }
 
 impl BaseEffect {
-    pub(in crate) fn render(&mut self, sources: &Pool<SoundSource>, listener: &Listener, distance_model: DistanceModel, amount: usize) {
+    pub(in crate) fn render(&mut self, sources: &Pool<SoundSource>, listener: &Listener, distance_model: &DistanceModel, amount: usize) {
+        self.render_with_sends(sources, listener, distance_model, amount, |_| 1.0)
+    }
+
+    /// Same as `render`, but each input is additionally scaled by send level of its source which
+    /// is provided by `send_level` closure.
+    pub(in crate) fn render_with_sends<F>(&mut self,
+                                          sources: &Pool<SoundSource>,
+                                          listener: &Listener,
+                                          distance_model: &DistanceModel,
+                                          amount: usize,
+                                          send_level: F)
+        where F: Fn(&SoundSource) -> f32 {
         // First of all check that inputs are still lead to valid sound sources.
         // We use some sort of weak coupling here - it is ok to leave sound source
         // connected to effect and delete source, such "dangling" inputs will be
@@ -144,14 +342,13 @@ impl BaseEffect {
         for input in self.inputs.iter_mut() {
             let source = sources.borrow(input.source);
 
-            if source.generic().status() != Status::Playing {
+            // Status is not checked here: source that was stopped during this render pass (i.e. reached
+            // end of its buffer) still has its last block, sources that were not rendered have none.
+            if source.generic().frame_samples().is_empty() || source.generic().is_virtual() {
                 continue;
             }
 
-            let distance_gain = match source {
-                SoundSource::Generic(_) => 1.0,
-                SoundSource::Spatial(spatial) => spatial.get_distance_gain(listener, distance_model),
-            };
+            let distance_gain = send_level(source) * distance_gain(source, listener, distance_model);
 
             let prev_distance_gain = input.last_distance_gain.unwrap_or(distance_gain);
 
@@ -162,13 +359,7 @@ impl BaseEffect {
 
             match self.filters.try_borrow_mut(input.filter) {
                 None => {
-                    for ((accum_left, accum_right), &(input_left, input_right)) in self
-                        .frame_samples.iter_mut().zip(source.generic().frame_samples()) {
-                        let g = math::lerpf(prev_distance_gain, distance_gain, k);
-                        *accum_left += input_left * g;
-                        *accum_right += input_right * g;
-                        k += step;
-                    }
+                    mix_interpolated(&mut self.frame_samples, source.generic().frame_samples(), prev_distance_gain, distance_gain);
                 },
                 Some(filter) => {
                     for ((accum_left, accum_right), &(input_left, input_right)) in self
@@ -182,6 +373,52 @@ impl BaseEffect {
                 },
             }
         }
+
+        // Then add sources which send to this effect, see `GenericSource::set_send`.
+        if self.handle == Handle::NONE {
+            return;
+        }
+        for i in 0..sources.get_capacity() {
+            let handle = sources.handle_from_index(i);
+            let source = match sources.at(i) {
+                Some(source) if !source.generic().frame_samples().is_empty() && !source.generic().is_virtual() => source,
+                _ => continue,
+            };
+
+            let level = source.generic().send(self.handle);
+            let gain = level * distance_gain(source, listener, distance_model);
+            // Slot can be missing only if source was spawned directly into pool, gain is not
+            // interpolated for such source.
+            let prev_gain = match self.send_gains.get_mut(i) {
+                Some(entry) => {
+                    let prev_gain = match *entry {
+                        (owner, Some(prev_gain)) if owner == handle => prev_gain,
+                        _ => gain,
+                    };
+                    *entry = (handle, if level == 0.0 { None } else { Some(gain) });
+                    prev_gain
+                }
+                None => gain,
+            };
+
+            if level != 0.0 {
+                mix_interpolated(&mut self.frame_samples, source.generic().frame_samples(), prev_gain, gain);
+            }
+        }
+    }
+
+    /// Makes sure that there is place for per-source send state of sources of pool with given
+    /// capacity. Called by context on game thread when source or effect is added, so render pass
+    /// does not allocate.
+    pub(in crate) fn reserve_sources(&mut self, capacity: usize) {
+        if self.send_gains.len() < capacity {
+            self.send_gains.resize(capacity, (Handle::NONE, None));
+        }
+    }
+
+    /// Returns input of effect accumulated in last render pass.
+    pub(in crate) fn input_samples(&self) -> &[(f32, f32)] {
+        &self.frame_samples
     }
 
     /// Returns current gain of effect.
@@ -224,6 +461,22 @@ impl Visit for BaseEffect {
 
         self.filters.visit("Filters", visitor)?;
         self.inputs.visit("Inputs", visitor)?;
+        if self.insert.visit("Insert", visitor).is_err() && visitor.is_reading() {
+            // Older versions have no insert effects.
+            self.insert = false;
+        }
+        if self.bypassed.visit("Bypassed", visitor).is_err() && visitor.is_reading() {
+            // Older versions have no bypass.
+            self.bypassed = false;
+        }
+        let mut bypass_mode = self.bypass_mode.id();
+        if bypass_mode.visit("BypassMode", visitor).is_ok() {
+            if visitor.is_reading() {
+                self.bypass_mode = BypassMode::from_id(bypass_mode);
+            }
+        } else if visitor.is_reading() {
+            self.bypass_mode = Default::default();
+        }
 
         visitor.leave_region()
     }
@@ -322,13 +575,47 @@ macro_rules! static_dispatch {
         match $self {
             Effect::Stub(v) => v.$func($($args),*),
             Effect::Reverb(v) => v.$func($($args),*),
+            Effect::NoiseGate(v) => v.$func($($args),*),
+            Effect::BitCrusher(v) => v.$func($($args),*),
+            Effect::Distortion(v) => v.$func($($args),*),
         }
     };
 }
 
 impl EffectRenderTrait for Effect {
-    fn render(&mut self, sources: &Pool<SoundSource>, listener: &Listener, distance_model: DistanceModel, mix_buf: &mut [(f32, f32)]) {
-        static_dispatch!(self, render, sources, listener, distance_model, mix_buf)
+    fn render(&mut self, sources: &Pool<SoundSource>, listener: &Listener, distance_model: &DistanceModel, mix_buf: &mut [(f32, f32)]) {
+        if !self.base().bypassed {
+            static_dispatch!(self, render, sources, listener, distance_model, mix_buf);
+            return;
+        }
+
+        match self.base().bypass_mode {
+            BypassMode::Freeze => {
+                self.accumulate_input(sources, listener, distance_model, mix_buf.len());
+            }
+            BypassMode::Tick => {
+                // Render effect as usual, but into temporary buffer which is then discarded. Input is
+                // accumulated by effect itself in this case.
+                let mut bypass_buf = std::mem::take(&mut self.base_mut().bypass_buf);
+                bypass_buf.clear();
+                bypass_buf.resize(mix_buf.len(), (0.0, 0.0));
+                static_dispatch!(self, render, sources, listener, distance_model, &mut bypass_buf);
+                self.base_mut().bypass_buf = bypass_buf;
+            }
+        }
+
+        for ((out_left, out_right), &(left, right)) in mix_buf.iter_mut().zip(self.base().frame_samples.iter()) {
+            *out_left += left;
+            *out_right += right;
+        }
+    }
+
+    fn process_accumulated(&mut self, mix_buf: &mut [(f32, f32)]) {
+        static_dispatch!(self, process_accumulated, mix_buf)
+    }
+
+    fn accumulate_input(&mut self, sources: &Pool<SoundSource>, listener: &Listener, distance_model: &DistanceModel, amount: usize) {
+        static_dispatch!(self, accumulate_input, sources, listener, distance_model, amount)
     }
 }
 
@@ -340,4 +627,112 @@ impl EffectTrait for Effect {
     fn base_mut(&mut self) -> &mut BaseEffect {
         static_dispatch!(self, base_mut,)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use rg3d_core::visitor::Visit;
+    use crate::{
+        effects::{
+            BaseEffect,
+            BypassMode,
+            Effect,
+            reverb::Reverb,
+        },
+        test_support,
+    };
+
+    fn make_reverb() -> Effect {
+        let mut reverb = Reverb::new(BaseEffect::default());
+        reverb.set_dry(0.0);
+        Effect::Reverb(reverb)
+    }
+
+    #[test]
+    fn stub_passes_buffer_through() {
+        let input = test_support::to_stereo(&test_support::noise(1024, 7));
+        let mut effect = Effect::default();
+        let mut buf = input.clone();
+        effect.process(&mut buf);
+        assert_eq!(buf, input);
+    }
+
+    #[test]
+    fn standalone_reverb_produces_decaying_tail_of_impulse() {
+        let mut effect = make_reverb();
+        let mut buf = test_support::to_stereo(&test_support::impulse(44100));
+        effect.process(&mut buf);
+
+        // Energy of tail decreases over time: compare consecutive 100 ms windows.
+        let window = 4410;
+        let energies = buf.chunks(window)
+            .map(|chunk| test_support::rms(&chunk.iter().map(|s| s.0).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        assert!(energies[0] > 1.0e-3);
+        assert!(energies.windows(2).all(|pair| pair[1] > 0.0 && pair[1] < pair[0]));
+    }
+
+    #[test]
+    fn bypassed_reverb_is_dry_and_enabled_is_wet() {
+        let input = test_support::to_stereo(&test_support::noise(8192, 3));
+        let mut effect = make_reverb();
+
+        effect.set_enabled(false);
+        assert!(!effect.is_enabled());
+        let mut buf = input.clone();
+        effect.process(&mut buf);
+        assert_eq!(buf, input);
+
+        effect.set_enabled(true);
+        let mut buf = input.clone();
+        effect.process(&mut buf);
+        // Dry part is zero, so output is purely wet.
+        assert_ne!(buf, input);
+        let wet = buf.iter().map(|s| s.0).collect::<Vec<f32>>();
+        assert!(test_support::rms(&wet) > 0.0);
+    }
+
+    #[test]
+    fn bypassed_effect_passes_input_in_context_path() {
+        let input = test_support::to_stereo(&test_support::noise(4096, 4));
+        let mut effect = make_reverb();
+        effect.set_enabled(false);
+        let output = test_support::run_effect(&mut effect, &input, 512);
+        let enabled = test_support::run_effect(&mut make_reverb(), &input, 512);
+        // Bypassed effect outputs its input, enabled one - only wet signal.
+        let bypassed_rms = test_support::rms(&output.iter().map(|s| s.0).collect::<Vec<f32>>());
+        let input_rms = test_support::rms(&input.iter().map(|s| s.0).collect::<Vec<f32>>());
+        assert!((bypassed_rms - input_rms).abs() < 0.05 * input_rms);
+        assert_ne!(output, enabled);
+    }
+
+    #[test]
+    fn bypass_state_is_visited() {
+        let mut visitor = test_support::reload(|visitor| {
+            let mut effect = make_reverb();
+            effect.set_enabled(false);
+            effect.set_bypass_mode(BypassMode::Tick);
+            effect.visit("Effect", visitor)
+        });
+        let mut effect = Effect::default();
+        effect.visit("Effect", &mut visitor).unwrap();
+        match effect {
+            Effect::Reverb(_) => (),
+            _ => panic!("effect must be loaded as reverb"),
+        }
+        assert!(!effect.is_enabled());
+        assert_eq!(effect.bypass_mode(), BypassMode::Tick);
+    }
+
+    #[test]
+    fn writing_effect_keeps_its_state() {
+        let mut effect = make_reverb();
+        effect.set_enabled(false);
+        test_support::reload(|visitor| effect.visit("Effect", visitor));
+        if let Effect::Reverb(reverb) = &effect {
+            assert_eq!(reverb.get_dry(), 0.0);
+        } else {
+            panic!("effect must stay reverb");
+        }
+        assert!(!effect.is_enabled());
+    }
+}
@@ -0,0 +1,249 @@
+//! Noise gate module
+//!
+//! # Overview
+//!
+//! Noise gate attenuates signal which level is below threshold, it is used to remove background hiss
+//! or noise between sounds. Signal above threshold passes unchanged. Level of signal is tracked by
+//! envelope follower, when it falls below threshold gate stays open for hold time and then closes
+//! within release time, when level exceeds threshold again gate opens within attack time.
+//!
+//! # Usage
+//!
+//! ```
+//! use std::time::Duration;
+//! use rg3d_sound::context::Context;
+//! use rg3d_sound::effects::noise_gate::NoiseGate;
+//! use rg3d_sound::effects::Effect;
+//!
+//! fn add_gate(context: &mut Context) {
+//!     let mut gate = NoiseGate::new(Default::default());
+//!     gate.set_threshold_db(-50.0);
+//!     gate.set_release(Duration::from_millis(200));
+//!     context.add_effect(Effect::NoiseGate(gate));
+//! }
+//! ```
+
+use std::time::Duration;
+use rg3d_core::visitor::{
+    Visit,
+    Visitor,
+    VisitResult
+};
+use crate::{
+    effects::{
+        EffectTrait,
+        BaseEffect,
+        EffectRenderTrait
+    },
+    dsp::EnvelopeFollower,
+    device,
+    util::visit_duration,
+};
+
+/// See module docs.
+pub struct NoiseGate {
+    base: BaseEffect,
+    threshold_db: f32,
+    threshold: f32,
+    attack: Duration,
+    release: Duration,
+    hold: Duration,
+    detector: EnvelopeFollower,
+    // Current gain of gate, 0.0 - gate is closed, 1.0 - gate is open.
+    gain: f32,
+    // Amount of samples left before gate will start closing.
+    hold_samples_left: usize,
+}
+
+impl Default for NoiseGate {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+fn duration_to_samples(duration: Duration) -> f32 {
+    duration.as_secs_f32() * device::SAMPLE_RATE as f32
+}
+
+impl NoiseGate {
+    /// Release time of level detector, it smooths level between zero crossings of signal so gate
+    /// won't chatter on low frequencies.
+    const DETECTOR_RELEASE: Duration = Duration::from_millis(10);
+
+    const DEFAULT_ATTACK: Duration = Duration::from_millis(1);
+    const DEFAULT_RELEASE: Duration = Duration::from_millis(100);
+    const DEFAULT_HOLD: Duration = Duration::from_millis(50);
+
+    /// Creates new noise gate with threshold of -60 dB, attack of 1 ms, hold of 50 ms and release
+    /// of 100 ms.
+    pub fn new(base: BaseEffect) -> Self {
+        let mut gate = Self {
+            base,
+            threshold_db: 0.0,
+            threshold: 0.0,
+            attack: Self::DEFAULT_ATTACK,
+            release: Self::DEFAULT_RELEASE,
+            hold: Self::DEFAULT_HOLD,
+            detector: EnvelopeFollower::new(0.0, duration_to_samples(Self::DETECTOR_RELEASE)),
+            gain: 1.0,
+            hold_samples_left: 0,
+        };
+        gate.set_threshold_db(-60.0);
+        gate
+    }
+
+    /// Sets threshold (in decibels relative to full scale) below which signal is attenuated.
+    pub fn set_threshold_db(&mut self, threshold_db: f32) {
+        self.threshold_db = threshold_db;
+        self.threshold = 10.0f32.powf(threshold_db / 20.0);
+    }
+
+    /// Returns threshold in decibels.
+    pub fn threshold_db(&self) -> f32 {
+        self.threshold_db
+    }
+
+    /// Sets time during which gate fully opens when signal exceeds threshold.
+    pub fn set_attack(&mut self, attack: Duration) {
+        self.attack = attack;
+    }
+
+    /// Returns attack time.
+    pub fn attack(&self) -> Duration {
+        self.attack
+    }
+
+    /// Sets time during which gate fully closes after hold time has passed.
+    pub fn set_release(&mut self, release: Duration) {
+        self.release = release;
+    }
+
+    /// Returns release time.
+    pub fn release(&self) -> Duration {
+        self.release
+    }
+
+    /// Sets time during which gate stays open after signal fell below threshold. Prevents gate from
+    /// closing on short pauses.
+    pub fn set_hold(&mut self, hold: Duration) {
+        self.hold = hold;
+    }
+
+    /// Returns hold time.
+    pub fn hold(&self) -> Duration {
+        self.hold
+    }
+}
+
+impl Visit for NoiseGate {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        self.base.visit("Base", visitor)?;
+        self.threshold_db.visit("ThresholdDb", visitor)?;
+        visit_duration(&mut self.attack, Self::DEFAULT_ATTACK, "Attack", visitor);
+        visit_duration(&mut self.release, Self::DEFAULT_RELEASE, "Release", visitor);
+        visit_duration(&mut self.hold, Self::DEFAULT_HOLD, "Hold", visitor);
+
+        if visitor.is_reading() {
+            let threshold_db = self.threshold_db;
+            self.set_threshold_db(threshold_db);
+        }
+
+        visitor.leave_region()
+    }
+}
+
+impl EffectRenderTrait for NoiseGate {
+    fn process_accumulated(&mut self, mix_buf: &mut [(f32, f32)]) {
+        let attack_samples = duration_to_samples(self.attack);
+        let release_samples = duration_to_samples(self.release);
+        let attack_step = if attack_samples > 1.0 { 1.0 / attack_samples } else { 1.0 };
+        let release_step = if release_samples > 1.0 { 1.0 / release_samples } else { 1.0 };
+        let hold_samples = duration_to_samples(self.hold) as usize;
+
+        for ((out_left, out_right), &(left, right)) in mix_buf.iter_mut().zip(self.base.frame_samples.iter()) {
+            let level = self.detector.feed(left.abs().max(right.abs()));
+            if level >= self.threshold {
+                self.hold_samples_left = hold_samples;
+                self.gain = (self.gain + attack_step).min(1.0);
+            } else if self.hold_samples_left > 0 {
+                self.hold_samples_left -= 1;
+            } else {
+                self.gain = (self.gain - release_step).max(0.0);
+            }
+
+            *out_left += left * self.gain;
+            *out_right += right * self.gain;
+        }
+    }
+}
+
+impl EffectTrait for NoiseGate {
+    fn base(&self) -> &BaseEffect {
+        &self.base
+    }
+
+    fn base_mut(&mut self) -> &mut BaseEffect {
+        &mut self.base
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use rg3d_core::visitor::Visit;
+    use crate::{
+        effects::{
+            Effect,
+            noise_gate::NoiseGate,
+        },
+        test_support,
+    };
+
+    #[test]
+    fn gate_closes_after_release_and_opens_on_loud_section() {
+        let loud = |len: usize| test_support::sine(441.0, 44100, len).iter().map(|s| s * 0.5).collect::<Vec<f32>>();
+        // -80 dB hiss between two loud sections.
+        let mut input = loud(22050);
+        input.extend(test_support::noise(22050, 3).iter().map(|s| s * 1.0e-4));
+        input.extend(loud(11025));
+        let input = test_support::to_stereo(&input);
+
+        let mut gate = NoiseGate::new(Default::default());
+        gate.set_threshold_db(-60.0);
+        gate.set_attack(Duration::from_millis(1));
+        gate.set_hold(Duration::from_millis(50));
+        gate.set_release(Duration::from_millis(100));
+        let mut effect = Effect::NoiseGate(gate);
+        let mut output = input.clone();
+        effect.process(&mut output);
+
+        // Transparent above threshold.
+        assert_eq!(&output[..22050], &input[..22050]);
+        // Still open during hold.
+        assert_eq!(&output[22050..22050 + 1000], &input[22050..22050 + 1000]);
+        // Closed once detected level has decayed below threshold and hold and release have passed.
+        assert!(output[22050 + 11025..44100].iter().all(|&s| s == (0.0, 0.0)));
+        // Opens again within attack time of loud section.
+        assert_eq!(&output[44100 + 100..], &input[44100 + 100..]);
+    }
+
+    #[test]
+    fn times_are_visited() {
+        let mut visitor = test_support::reload(|visitor| {
+            let mut gate = NoiseGate::default();
+            gate.set_threshold_db(-40.0);
+            gate.set_attack(Duration::from_millis(3));
+            gate.set_release(Duration::from_millis(250));
+            gate.set_hold(Duration::from_millis(20));
+            gate.visit("Gate", visitor)
+        });
+        let mut gate = NoiseGate::default();
+        gate.visit("Gate", &mut visitor).unwrap();
+        assert_eq!(gate.threshold_db(), -40.0);
+        assert_eq!(gate.attack(), Duration::from_millis(3));
+        assert_eq!(gate.release(), Duration::from_millis(250));
+        assert_eq!(gate.hold(), Duration::from_millis(20));
+    }
+}
@@ -14,14 +14,50 @@
 //! use rg3d_sound::effects::Effect;
 //!
 //! fn set_reverberator(context: &mut Context) {
-//!     let mut reverb = Reverb::new();
+//!     let mut reverb = Reverb::new(Default::default());
 //!     reverb.set_decay_time(Duration::from_secs_f32(10.0));
-//!     context.lock()
-//!         .unwrap()
-//!         .add_effect(Effect::Reverb(reverb));
+//!     context.add_effect(Effect::Reverb(reverb));
 //! }
 //! ```
 //!
+//! # Presets
+//!
+//! Instead of tuning each parameter, reverb can be created from one of presets of common spaces, see
+//! `ReverbPreset`. Preset only sets initial parameters, any of them can be changed afterwards.
+//!
+//! ```
+//! use rg3d_sound::effects::reverb::{Reverb, ReverbPreset};
+//!
+//! let mut reverb = Reverb::preset(ReverbPreset::Hall);
+//! // Slightly darker hall.
+//! reverb.set_fc(4000.0 / 44100.0);
+//! ```
+//!
+//! Context can switch presets of all its reverbs smoothly when listener moves from one acoustic zone
+//! to another, see `Context::set_environment`.
+//!
+//! # Send levels
+//!
+//! By default reverb is an insert effect - each source connected to it (see `BaseEffect::add_input`)
+//! is fully processed and reverb mixes dry part of its input by itself (see `Reverb::set_dry`). In
+//! `ReverbSendMode::Send` mode each source contributes to reverb according to its reverb send level
+//! (see `GenericSource::set_reverb_send`) and reverb outputs only wet signal, dry signal of sources
+//! is mixed directly by renderer. Send level of 1.0 means that input is fully processed by reverb,
+//! send level of 0.0 means that source is fully dry.
+//!
+//! ```
+//! use rg3d_sound::effects::reverb::{Reverb, ReverbSendMode};
+//!
+//! let mut reverb = Reverb::new(Default::default());
+//! reverb.set_send_mode(ReverbSendMode::Send);
+//! ```
+//!
+//! # Early reflections
+//!
+//! Before diffuse tail, reverb produces discrete early reflections - short echoes from nearby walls
+//! which give sense of room geometry. They are modelled by a bank of delay taps with individual gains,
+//! see `Reverb::set_early_reflections`. Early reflections are fed into the tail as well.
+//!
 //! # Known problems
 //!
 //! This reverberator has little "metallic" tone, but since this is one of the simplest reverberators this
@@ -30,6 +66,7 @@
 use std::time::Duration;
 use rg3d_core::{
     pool::Pool,
+    math,
     visitor::{
         Visit,
         Visitor,
@@ -48,7 +85,8 @@ use crate::{
         AllPass,
     },
     source::SoundSource,
-    context::DistanceModel
+    context::DistanceModel,
+    automation::seconds_to_duration,
 };
 
 #[derive(Default)]
@@ -63,6 +101,152 @@ struct ChannelReverb {
 /// 60 decibels
 const DB60: f32 = 0.001;
 
+/// Multi-tap delay line which produces early reflections.
+#[derive(Default)]
+struct EarlyReflections {
+    // Ring buffer of past input samples.
+    buffer: Vec<f32>,
+    write_pos: usize,
+    // (delay, gain) pairs as they were set by user.
+    reflections: Vec<(Duration, f32)>,
+    // Scales of delays and gains of reflections.
+    room_size: f32,
+    gain: f32,
+    // (delay in samples, gain) pairs.
+    taps: Vec<(usize, f32)>,
+    // Scales and taps to which reflections are crossfaded, see `Reverb::begin_transition`.
+    next_scale: Option<(f32, f32)>,
+    next_taps: Vec<(usize, f32)>,
+    // Weight of next taps in output.
+    next_weight: f32,
+    sample_rate: u32,
+}
+
+impl EarlyReflections {
+    fn new(reflections: &[(Duration, f32)], sample_rate: u32) -> Self {
+        let mut early = Self {
+            sample_rate,
+            room_size: 1.0,
+            gain: 1.0,
+            ..Default::default()
+        };
+        early.set_reflections(reflections);
+        early
+    }
+
+    fn set_reflections(&mut self, reflections: &[(Duration, f32)]) {
+        self.reflections.clear();
+        self.reflections.extend_from_slice(reflections);
+        self.update_taps();
+    }
+
+    fn set_scale(&mut self, room_size: f32, gain: f32) {
+        self.room_size = room_size;
+        self.gain = gain;
+        self.update_taps();
+    }
+
+    fn update_taps(&mut self) {
+        // Direct change cancels crossfade.
+        self.next_scale = None;
+        self.next_taps.clear();
+        self.next_weight = 0.0;
+        self.taps.clear();
+        compute_taps(&self.reflections, self.sample_rate, self.room_size, self.gain, &mut self.taps);
+        let len = self.taps.iter().map(|(delay, _)| *delay + 1).max().unwrap_or(1);
+        self.resize_buffer(len);
+    }
+
+    fn resize_buffer(&mut self, len: usize) {
+        if len != self.buffer.len() {
+            // Keep most recent history, so reflections can be changed while reverb is playing
+            // without gap in output. Newest sample goes to the end, write position is at the
+            // beginning.
+            let old_len = self.buffer.len();
+            let kept = old_len.min(len);
+            let mut buffer = vec![0.0; len];
+            for i in 0..kept {
+                buffer[len - kept + i] = self.buffer[(self.write_pos + old_len - kept + i) % old_len];
+            }
+            self.buffer = buffer;
+            self.write_pos = 0;
+        }
+    }
+
+    // Prepares crossfade from current taps to taps of given scales, may allocate.
+    fn begin_crossfade(&mut self, room_size: f32, gain: f32) {
+        if self.next_scale.is_some() {
+            // Crossfade is in progress, so current output is weighted sum of both sets of taps,
+            // which is the same as output of single set which contains all taps with weighted
+            // gains. New crossfade starts from it, so there is no jump.
+            let weight = self.next_weight;
+            for tap in self.taps.iter_mut() {
+                tap.1 *= 1.0 - weight;
+            }
+            self.taps.extend(self.next_taps.iter().map(|&(delay, gain)| (delay, gain * weight)));
+            self.taps.retain(|&(_, gain)| gain != 0.0);
+        }
+        self.next_taps.clear();
+        compute_taps(&self.reflections, self.sample_rate, room_size, gain, &mut self.next_taps);
+        self.next_scale = Some((room_size, gain));
+        self.next_weight = 0.0;
+        let len = self.taps.iter().chain(self.next_taps.iter()).map(|(delay, _)| *delay + 1).max().unwrap_or(1);
+        if len > self.buffer.len() {
+            self.resize_buffer(len);
+        }
+    }
+
+    // Sets weight of next taps, never allocates. Crossfade is finished when weight reaches 1.0.
+    fn crossfade(&mut self, weight: f32) {
+        if let Some((room_size, gain)) = self.next_scale {
+            if weight >= 1.0 {
+                std::mem::swap(&mut self.taps, &mut self.next_taps);
+                self.next_taps.clear();
+                self.next_scale = None;
+                self.next_weight = 0.0;
+                self.room_size = room_size;
+                self.gain = gain;
+            } else {
+                self.next_weight = weight.max(0.0);
+            }
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.update_taps();
+    }
+
+    fn feed(&mut self, sample: f32) -> f32 {
+        let len = self.buffer.len();
+        self.buffer[self.write_pos] = sample;
+        let mut result = 0.0;
+        for &(delay, gain) in self.taps.iter() {
+            result += gain * self.buffer[(self.write_pos + len - delay) % len];
+        }
+        if self.next_scale.is_some() {
+            let mut next = 0.0;
+            for &(delay, gain) in self.next_taps.iter() {
+                next += gain * self.buffer[(self.write_pos + len - delay) % len];
+            }
+            result = math::lerpf(result, next, self.next_weight);
+        }
+        self.write_pos = (self.write_pos + 1) % len;
+        result
+    }
+}
+
+/// Converts reflections to (delay in samples, gain) taps with given scales of delays and gains.
+fn compute_taps(reflections: &[(Duration, f32)], sample_rate: u32, room_size: f32, gain: f32, taps: &mut Vec<(usize, f32)>) {
+    let scale = f64::from(sample_rate) * f64::from(room_size);
+    // Muted reflections are not processed at all.
+    if gain != 0.0 {
+        taps.extend(reflections
+            .iter()
+            .map(|(delay, tap_gain)| ((delay.as_secs_f64() * scale) as usize, tap_gain * gain)));
+    }
+}
+
 /// Sample rate for which this reverb was designed.
 const DESIGN_SAMPLE_RATE: u32 = 44100;
 
@@ -112,6 +296,18 @@ impl ChannelReverb {
         }
     }
 
+    fn decay_time(&self) -> Duration {
+        // Inverse of `calculate_decay`.
+        let comb = &self.lp_fb_comb_filters[0];
+        let time_len = comb.len() as f32 / self.sample_rate as f32;
+        let feedback = comb.feedback();
+        if feedback > 0.0 && feedback < 1.0 {
+            Duration::from_secs_f32(time_len * DB60.ln() / feedback.ln())
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+
     fn set_fc(&mut self, fc: f32) {
         self.fc = fc;
         for comb in self.lp_fb_comb_filters.iter_mut() {
@@ -145,13 +341,116 @@ impl Visit for ChannelReverb {
     }
 }
 
+/// Defines how sources contribute to reverb. See module docs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum ReverbSendMode {
+    /// Every input is fully processed by reverb, reverb send levels of sources are ignored. Reverb
+    /// mixes dry part of input by itself, see `Reverb::set_dry`. This is default mode.
+    #[default]
+    Insert,
+
+    /// Every input is scaled by reverb send level of its source, see `GenericSource::set_reverb_send`.
+    /// Reverb outputs only wet signal, dry part is ignored.
+    Send,
+}
+
+impl ReverbSendMode {
+    fn id(self) -> u32 {
+        match self {
+            ReverbSendMode::Insert => 0,
+            ReverbSendMode::Send => 1,
+        }
+    }
+
+    fn from_id(id: u32) -> Self {
+        match id {
+            1 => ReverbSendMode::Send,
+            _ => ReverbSendMode::Insert,
+        }
+    }
+}
+
+/// Preset of reverb parameters for common kinds of spaces. See `Reverb::preset`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReverbPreset {
+    /// Small room (i.e. bathroom or office) - short bright tail, dense close reflections.
+    SmallRoom,
+    /// Medium-sized living room - moderate tail. Closest to default parameters of reverb.
+    Room,
+    /// Concert hall - long, slightly dark tail and distant reflections.
+    Hall,
+    /// Plate reverb - classic studio effect, long bright tail with no discrete reflections.
+    Plate,
+    /// Cave - very long dark tail and late strong reflections.
+    Cave,
+}
+
+impl ReverbPreset {
+    /// Returns parameters of preset.
+    pub fn params(self) -> ReverbParams {
+        // (decay time in seconds, damping cutoff in Hz, room size, reflections gain, stereo width, dry)
+        let (decay_time, cutoff, room_size, reflections_gain, stereo_width, dry) = match self {
+            ReverbPreset::SmallRoom => (0.5, 9000.0, 0.5, 1.0, 0.7, 1.0),
+            ReverbPreset::Room => (1.2, 7000.0, 1.0, 1.0, 1.0, 1.0),
+            ReverbPreset::Hall => (2.8, 5500.0, 2.2, 0.7, 1.0, 0.9),
+            ReverbPreset::Plate => (1.8, 11000.0, 0.0, 0.0, 1.0, 1.0),
+            ReverbPreset::Cave => (6.0, 3500.0, 3.5, 0.9, 1.0, 0.8),
+        };
+        ReverbParams {
+            decay_time,
+            cutoff,
+            room_size,
+            reflections_gain,
+            stereo_width,
+            dry,
+        }
+    }
+}
+
+/// Set of reverb parameters which is defined by preset, see `ReverbPreset::params` and
+/// `Reverb::apply_params`. Unlike preset it can be interpolated, which is used for smooth
+/// transitions between environments (see `Context::set_environment`).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ReverbParams {
+    /// Decay time in seconds, see `Reverb::set_decay_time`.
+    pub decay_time: f32,
+    /// Damping cutoff frequency in hertz, see `Reverb::set_fc`.
+    pub cutoff: f32,
+    /// Scale of delays of early reflections, see `Reverb::set_room_size`.
+    pub room_size: f32,
+    /// Scale of gains of early reflections, zero disables early reflections. See
+    /// `Reverb::set_reflections_gain`.
+    pub reflections_gain: f32,
+    /// See `Reverb::set_stereo_width`.
+    pub stereo_width: f32,
+    /// See `Reverb::set_dry`.
+    pub dry: f32,
+}
+
+impl ReverbParams {
+    /// Linearly interpolates each parameter between `self` and `other`, `t` is in [0; 1] range.
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            decay_time: math::lerpf(self.decay_time, other.decay_time, t),
+            cutoff: math::lerpf(self.cutoff, other.cutoff, t),
+            room_size: math::lerpf(self.room_size, other.room_size, t),
+            reflections_gain: math::lerpf(self.reflections_gain, other.reflections_gain, t),
+            stereo_width: math::lerpf(self.stereo_width, other.stereo_width, t),
+            dry: math::lerpf(self.dry, other.dry, t),
+        }
+    }
+}
+
 /// See module docs.
 pub struct Reverb {
     base: BaseEffect,
     dry: f32,
     wet: f32,
+    stereo_width: f32,
+    send_mode: ReverbSendMode,
     left: ChannelReverb,
     right: ChannelReverb,
+    early: EarlyReflections,
 }
 
 impl Default for Reverb {
@@ -171,6 +470,17 @@ impl Reverb {
     /// 2.0 here because left and right signals will be mixed together.
     const GAIN: f32 = 1.0 / (2.0 * Self::TOTAL_FILTERS_COUNT);
 
+    /// Default early reflections of medium-sized room, delays are mutually prime (in ms) to
+    /// prevent periodic pattern.
+    pub const DEFAULT_EARLY_REFLECTIONS: [(Duration, f32); 6] = [
+        (Duration::from_millis(7), 0.45),
+        (Duration::from_millis(11), 0.38),
+        (Duration::from_millis(17), 0.32),
+        (Duration::from_millis(23), 0.26),
+        (Duration::from_millis(29), 0.21),
+        (Duration::from_millis(37), 0.16),
+    ];
+
     /// Creates new instance of reberb effect with cutoff frequency of ~11.2 kHz and
     /// 5 seconds decay time.
     pub fn new(base: BaseEffect) -> Self {
@@ -181,13 +491,111 @@ impl Reverb {
             base,
             dry: 1.0,
             wet: 1.0,
+            stereo_width: 1.0,
+            send_mode: Default::default(),
             left: ChannelReverb::new(0, fc, feedback),
             right: ChannelReverb::new(23, fc, feedback),
+            early: EarlyReflections::new(&Self::DEFAULT_EARLY_REFLECTIONS, DESIGN_SAMPLE_RATE),
         }
     }
 
+    /// Creates new reverb configured for given kind of space. Each parameter of preset can be
+    /// overridden afterwards by corresponding setter.
+    pub fn preset(preset: ReverbPreset) -> Self {
+        let mut reverb = Self::new(Default::default());
+        reverb.apply_preset(preset);
+        reverb
+    }
+
+    /// Sets decay time, damping (see `set_fc`), room size and reflections gain, stereo width and dry
+    /// part of reverb from given preset. Other parameters (including early reflections themselves,
+    /// see `set_early_reflections`) are left untouched.
+    pub fn apply_preset(&mut self, preset: ReverbPreset) {
+        self.apply_params(&preset.params());
+    }
+
+    /// Same as `apply_preset`, but takes arbitrary set of parameters (i.e. interpolated between
+    /// two presets).
+    pub fn apply_params(&mut self, params: &ReverbParams) {
+        self.set_decay_time(seconds_to_duration(params.decay_time));
+        self.set_fc(params.cutoff / DESIGN_SAMPLE_RATE as f32);
+        self.early.set_scale(params.room_size.max(0.0), params.reflections_gain.max(0.0));
+        self.set_stereo_width(params.stereo_width);
+        self.set_dry(params.dry);
+    }
+
+    /// Prepares smooth transition of early reflections to room size and reflections gain of given
+    /// parameters, reflections are crossfaded by `step_transition`. This method may allocate, so it
+    /// must not be called by mixer.
+    pub(in crate) fn begin_transition(&mut self, to: &ReverbParams) {
+        self.early.begin_crossfade(to.room_size.max(0.0), to.reflections_gain.max(0.0));
+    }
+
+    /// Applies interpolated parameters of transition at given point `t` (in [0; 1] range) of it,
+    /// early reflections are crossfaded between ones that were used at the beginning and ones that
+    /// were prepared by `begin_transition`. Transition is finished when `t` reaches 1.0. Never
+    /// allocates, unlike `apply_params`.
+    pub(in crate) fn step_transition(&mut self, params: &ReverbParams, t: f32) {
+        self.set_decay_time(seconds_to_duration(params.decay_time));
+        self.set_fc(params.cutoff / DESIGN_SAMPLE_RATE as f32);
+        self.early.crossfade(t);
+        self.set_stereo_width(params.stereo_width);
+        self.set_dry(params.dry);
+    }
+
+    /// Sets early reflections as list of (delay, gain) pairs. Each pair gives discrete echo of input
+    /// signal delayed by given time and scaled by given gain, all echoes go to output (as wet part)
+    /// and to reverberation tail. Reflections should be short (tens of milliseconds) and quiet, they
+    /// give sense of room geometry. Empty list disables early reflections. Default reflections are
+    /// `DEFAULT_EARLY_REFLECTIONS`. Delays and gains are scaled by room size and reflections gain,
+    /// see `set_room_size` and `set_reflections_gain`.
+    pub fn set_early_reflections(&mut self, reflections: &[(Duration, f32)]) {
+        self.early.set_reflections(reflections);
+    }
+
+    /// Returns current early reflections as list of (delay, gain) pairs, as they were set by
+    /// `set_early_reflections` (without scaling).
+    pub fn early_reflections(&self) -> &[(Duration, f32)] {
+        &self.early.reflections
+    }
+
+    /// Sets scale of delays of early reflections: larger room gives more distant echoes. Negative
+    /// values are clamped to zero. Default value is 1.0.
+    pub fn set_room_size(&mut self, room_size: f32) {
+        let gain = self.early.gain;
+        self.early.set_scale(room_size.max(0.0), gain);
+    }
+
+    /// Returns scale of delays of early reflections.
+    pub fn room_size(&self) -> f32 {
+        self.early.room_size
+    }
+
+    /// Sets scale of gains of early reflections, zero disables them. Negative values are clamped
+    /// to zero. Default value is 1.0.
+    pub fn set_reflections_gain(&mut self, gain: f32) {
+        let room_size = self.early.room_size;
+        self.early.set_scale(room_size, gain.max(0.0));
+    }
+
+    /// Returns scale of gains of early reflections.
+    pub fn reflections_gain(&self) -> f32 {
+        self.early.gain
+    }
+
+    /// Sets how sources contribute to reverb, see `ReverbSendMode` for more info. Default mode is
+    /// `ReverbSendMode::Insert`.
+    pub fn set_send_mode(&mut self, send_mode: ReverbSendMode) {
+        self.send_mode = send_mode;
+    }
+
+    /// Returns current send mode.
+    pub fn send_mode(&self) -> ReverbSendMode {
+        self.send_mode
+    }
+
     /// Sets how much of input signal should be passed to output without any processing.
-    /// Default value is 1.0.
+    /// Default value is 1.0. Ignored in `ReverbSendMode::Send` mode.
     pub fn set_dry(&mut self, dry: f32) {
         self.dry = dry.min(1.0).max(0.0);
     }
@@ -211,11 +619,26 @@ impl Reverb {
         self.wet
     }
 
+    /// Sets stereo width of reverberation tail. Left and right channels of reverb have
+    /// decorrelated filter networks, so tail is spacious by default.
+    /// 0.0 - tail is mono (centered), left and right tails are equal.
+    /// 1.0 - tail is fully stereo, left and right tails are decorrelated.
+    /// Default value is 1.0. Dry part of signal is not affected by this parameter.
+    pub fn set_stereo_width(&mut self, width: f32) {
+        self.stereo_width = width.clamp(0.0, 1.0);
+    }
+
+    /// Returns stereo width of reverberation tail.
+    pub fn stereo_width(&self) -> f32 {
+        self.stereo_width
+    }
+
     /// Sets actual sample rate of effect. It was designed to 44100 Hz sampling rate.
     /// TODO: This shouldn't be in public API.
     pub fn set_sample_rate(&mut self, sample_rate: usize) {
         self.left.set_sample_rate(sample_rate);
         self.right.set_sample_rate(sample_rate);
+        self.early.set_sample_rate(sample_rate as u32);
     }
 
     /// Sets desired duration of reverberation, the more size your environment has,
@@ -225,6 +648,11 @@ impl Reverb {
         self.right.set_decay_time(decay_time)
     }
 
+    /// Returns current duration of reverberation.
+    pub fn decay_time(&self) -> Duration {
+        self.left.decay_time()
+    }
+
     /// Sets cutoff frequency for lowpass filter in comb filters. Basically this parameter defines
     /// "tone" of reflections, when frequency is higher - then more high frequencies will be in
     /// output signal, and vice versa. For example if you have environment with high absorption of
@@ -240,6 +668,11 @@ impl Reverb {
         self.left.set_fc(fc);
         self.right.set_fc(fc);
     }
+
+    /// Returns normalized cutoff frequency of lowpass filter in comb filters.
+    pub fn fc(&self) -> f32 {
+        self.left.fc
+    }
 }
 
 impl Visit for Reverb {
@@ -251,27 +684,96 @@ impl Visit for Reverb {
         self.left.visit("Left", visitor)?;
         self.dry.visit("Dry", visitor)?;
         self.wet.visit("Wet", visitor)?;
+        if self.stereo_width.visit("StereoWidth", visitor).is_err() && visitor.is_reading() {
+            // Older versions have no stereo width, use default.
+            self.stereo_width = 1.0;
+        }
+        let mut send_mode = self.send_mode.id();
+        if send_mode.visit("SendMode", visitor).is_ok() {
+            if visitor.is_reading() {
+                self.send_mode = ReverbSendMode::from_id(send_mode);
+            }
+        } else if visitor.is_reading() {
+            // Older versions have no send mode, use default.
+            self.send_mode = Default::default();
+        }
+        // Duration is not visitable, so reflections are stored as two lists of delays (in
+        // nanoseconds, to keep them exact) and gains.
+        let mut delays = self.early.reflections.iter().map(|(delay, _)| delay.as_nanos() as u64).collect::<Vec<u64>>();
+        let mut gains = self.early.reflections.iter().map(|(_, gain)| *gain).collect::<Vec<f32>>();
+        if visitor.is_reading() {
+            delays.clear();
+            gains.clear();
+        }
+        let mut has_early = false;
+        if visitor.enter_region("EarlyReflections").is_ok() {
+            has_early = delays.visit("Delays", visitor).is_ok() && gains.visit("Gains", visitor).is_ok();
+            visitor.leave_region()?;
+        }
+        if visitor.is_reading() {
+            if has_early && delays.len() == gains.len() {
+                let reflections = delays.iter()
+                    .zip(gains.iter())
+                    .map(|(&delay, &gain)| (Duration::from_nanos(delay), gain))
+                    .collect::<Vec<(Duration, f32)>>();
+                self.early.set_reflections(&reflections);
+            } else {
+                // Older versions have no early reflections, use default.
+                self.early.set_reflections(&Self::DEFAULT_EARLY_REFLECTIONS);
+            }
+        }
+        let mut room_size = self.early.room_size;
+        let mut reflections_gain = self.early.gain;
+        let has_scale = room_size.visit("RoomSize", visitor).is_ok() && reflections_gain.visit("ReflectionsGain", visitor).is_ok();
+        if visitor.is_reading() {
+            if has_scale {
+                self.early.set_scale(room_size, reflections_gain);
+            } else {
+                // Older versions have no scale of reflections.
+                self.early.set_scale(1.0, 1.0);
+            }
+        }
 
         visitor.leave_region()
     }
 }
 
 impl EffectRenderTrait for Reverb {
-    fn render(&mut self, sources: &Pool<SoundSource>, listener: &Listener, distance_model: DistanceModel, mix_buf: &mut [(f32, f32)]) {
-        self.base.render(sources, listener, distance_model, mix_buf.len());
-
+    fn process_accumulated(&mut self, mix_buf: &mut [(f32, f32)]) {
         let wet1 = self.wet;
         let wet2 = 1.0 - self.wet;
+        // Dry signal of sources is already mixed by renderer in send mode.
+        let dry = match self.send_mode {
+            ReverbSendMode::Insert => self.dry,
+            ReverbSendMode::Send => 0.0,
+        };
 
         for ((out_left, out_right), &(left, right)) in mix_buf.iter_mut().zip(self.base.frame_samples.iter()) {
             let mid = (left + right) * 0.5;
-            let input = mid * Self::GAIN;
+            let early = self.early.feed(mid);
+            let input = (mid + early) * Self::GAIN;
 
-            let processed_left = self.left.feed(input);
-            let processed_right = self.right.feed(input);
+            let tail_left = self.left.feed(input);
+            let tail_right = self.right.feed(input);
+
+            // Mid-side processing of tail to control its width.
+            let tail_mid = (tail_left + tail_right) * 0.5;
+            let tail_side = (tail_left - tail_right) * 0.5 * self.stereo_width;
+            let processed_left = tail_mid + tail_side + early;
+            let processed_right = tail_mid - tail_side + early;
+
+            *out_left += processed_left * wet1 + processed_right * wet2 + dry * left;
+            *out_right += processed_right * wet1 + processed_left * wet2 + dry * right;
+        }
+    }
 
-            *out_left += processed_left * wet1 + processed_right * wet2 + self.dry * left;
-            *out_right += processed_right * wet1 + processed_left * wet2 + self.dry * right;
+    fn accumulate_input(&mut self, sources: &Pool<SoundSource>, listener: &Listener, distance_model: &DistanceModel, amount: usize) {
+        match self.send_mode {
+            ReverbSendMode::Insert => self.base.render(sources, listener, distance_model, amount),
+            // Each source contributes to reverb according to its reverb send level.
+            ReverbSendMode::Send => self.base.render_with_sends(sources, listener, distance_model, amount, |source| {
+                source.generic().reverb_send()
+            }),
         }
     }
 }
@@ -284,4 +786,370 @@ impl EffectTrait for Reverb {
     fn base_mut(&mut self) -> &mut BaseEffect {
         &mut self.base
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use rg3d_core::{
+        pool::Pool,
+        visitor::Visit,
+    };
+    use crate::{
+        effects::{
+            Effect,
+            EffectInput,
+            EffectRenderTrait,
+            EffectTrait,
+            reverb::{Reverb, ReverbSendMode, ReverbPreset, EarlyReflections},
+        },
+        source::{
+            Status,
+            generic::GenericSourceBuilder,
+        },
+        listener::Listener,
+        context::DistanceModel,
+        test_support,
+    };
+
+    // Normalized cross-correlation of two signals at zero lag.
+    fn correlation(a: &[f32], b: &[f32]) -> f32 {
+        let ab = a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>();
+        let aa = a.iter().map(|x| x * x).sum::<f32>();
+        let bb = b.iter().map(|y| y * y).sum::<f32>();
+        ab / (aa * bb).sqrt()
+    }
+
+    fn tail_correlation(width: f32) -> f32 {
+        let mut reverb = Reverb::new(Default::default());
+        reverb.set_dry(0.0);
+        reverb.set_early_reflections(&[]);
+        reverb.set_stereo_width(width);
+        let input = test_support::to_stereo(&test_support::noise(32768, 1));
+        let output = test_support::run_effect(&mut Effect::Reverb(reverb), &input, 1024);
+        let left = output.iter().map(|s| s.0).collect::<Vec<f32>>();
+        let right = output.iter().map(|s| s.1).collect::<Vec<f32>>();
+        correlation(&left, &right)
+    }
+
+    #[test]
+    fn stereo_tail_of_mono_input_is_decorrelated() {
+        assert!(tail_correlation(1.0).abs() < 0.5);
+        assert!(tail_correlation(0.0) > 0.999);
+    }
+
+    #[test]
+    fn stereo_width_is_visited() {
+        let mut visitor = test_support::reload(|visitor| {
+            let mut reverb = Reverb::default();
+            reverb.set_stereo_width(0.3);
+            reverb.visit("Reverb", visitor)
+        });
+        let mut reverb = Reverb::default();
+        reverb.visit("Reverb", &mut visitor).unwrap();
+        assert_eq!(reverb.stereo_width(), 0.3);
+    }
+
+    #[test]
+    fn send_mode_is_visited() {
+        let mut visitor = test_support::reload(|visitor| {
+            let mut reverb = Reverb::default();
+            reverb.set_send_mode(ReverbSendMode::Send);
+            reverb.visit("Reverb", visitor)
+        });
+        let mut reverb = Reverb::default();
+        reverb.visit("Reverb", &mut visitor).unwrap();
+        assert_eq!(reverb.send_mode(), ReverbSendMode::Send);
+    }
+
+    #[test]
+    fn missing_stereo_width_falls_back_to_default() {
+        let mut visitor = test_support::reload(|visitor| {
+            let mut reverb = Reverb::default();
+            visitor.enter_region("Reverb")?;
+            reverb.base.visit("Base", visitor)?;
+            reverb.right.visit("Right", visitor)?;
+            reverb.left.visit("Left", visitor)?;
+            reverb.dry.visit("Dry", visitor)?;
+            reverb.wet.visit("Wet", visitor)?;
+            visitor.leave_region()
+        });
+        let mut reverb = Reverb::default();
+        reverb.set_stereo_width(0.3);
+        reverb.set_send_mode(ReverbSendMode::Send);
+        reverb.set_early_reflections(&[]);
+        reverb.visit("Reverb", &mut visitor).unwrap();
+        assert_eq!(reverb.stereo_width(), 1.0);
+        assert_eq!(reverb.send_mode(), ReverbSendMode::Insert);
+        assert_eq!(reverb.early_reflections(), &Reverb::DEFAULT_EARLY_REFLECTIONS);
+    }
+
+    #[test]
+    fn early_reflections_are_visited() {
+        let reflections = [(Duration::from_millis(3), 0.7), (Duration::from_micros(12345), 0.1)];
+        let mut visitor = test_support::reload(|visitor| {
+            let mut reverb = Reverb::default();
+            reverb.set_early_reflections(&reflections);
+            reverb.visit("Reverb", visitor)
+        });
+        let mut reverb = Reverb::default();
+        reverb.visit("Reverb", &mut visitor).unwrap();
+        assert_eq!(reverb.early_reflections(), &reflections);
+    }
+
+    // Returns indices of `count` loudest samples of left channel, loudest first.
+    fn loudest(output: &[(f32, f32)], count: usize) -> Vec<usize> {
+        let mut indices = (0..output.len()).collect::<Vec<usize>>();
+        indices.sort_by(|&a, &b| output[b].0.abs().partial_cmp(&output[a].0.abs()).unwrap());
+        indices.truncate(count);
+        indices
+    }
+
+    #[test]
+    fn early_reflections_precede_diffuse_tail() {
+        let mut input = vec![(0.0, 0.0); 8192];
+        input[100] = (1.0, 1.0);
+
+        // Position of the impulse itself in output of effect, zero-delay reflection passes
+        // input as is and is much louder than diffuse part.
+        let mut direct = Reverb::new(Default::default());
+        direct.set_dry(0.0);
+        direct.set_early_reflections(&[(Duration::from_millis(0), 1.0)]);
+        let direct = loudest(&test_support::run_effect(&mut Effect::Reverb(direct), &input, 1024)[..1000], 1)[0];
+        assert_eq!(direct, 100);
+
+        let mut reverb = Reverb::new(Default::default());
+        reverb.set_dry(0.0);
+        reverb.set_early_reflections(&[(Duration::from_millis(10), 0.5), (Duration::from_millis(20), 0.25)]);
+        let output = test_support::run_effect(&mut Effect::Reverb(reverb), &input, 1024);
+
+        // Shortest comb filter is longer than 1100 samples, so before that only discrete echoes
+        // stand out, diffuse part is only sparse and quiet output of allpass filters.
+        let early = &output[..direct + 1100];
+        let mut echoes = loudest(early, 3);
+        assert_eq!(&echoes[..2], &[direct + 441, direct + 882]);
+        assert!((output[direct + 441].0 - 0.5).abs() < 0.05);
+        assert!((output[direct + 882].0 - 0.25).abs() < 0.05);
+        let third = echoes.pop().unwrap();
+        assert!(output[third].0.abs() < 0.1);
+        // Dense diffuse tail follows.
+        let tail = &output[direct + 1100..];
+        assert!(tail.iter().filter(|s| s.0.abs() > 1.0e-6).count() > tail.len() / 2);
+    }
+
+    #[test]
+    fn room_size_and_reflections_gain_scale_echoes() {
+        let mut input = vec![(0.0, 0.0); 4096];
+        input[0] = (1.0, 1.0);
+
+        let mut reverb = Reverb::new(Default::default());
+        reverb.set_dry(0.0);
+        reverb.set_early_reflections(&[(Duration::from_millis(10), 0.5)]);
+        reverb.set_room_size(2.0);
+        reverb.set_reflections_gain(0.5);
+        // Scales do not change reflections as they were set.
+        assert_eq!(reverb.early_reflections(), &[(Duration::from_millis(10), 0.5)]);
+        let output = test_support::run_effect(&mut Effect::Reverb(reverb), &input, 1024);
+        assert_eq!(loudest(&output[..1100], 1)[0], 882);
+        assert!((output[882].0 - 0.25).abs() < 0.05);
+
+        let mut reverb = Reverb::new(Default::default());
+        reverb.set_dry(0.0);
+        reverb.set_early_reflections(&[(Duration::from_millis(10), 0.5)]);
+        reverb.set_reflections_gain(0.0);
+        let output = test_support::run_effect(&mut Effect::Reverb(reverb), &input, 1024);
+        assert!(output[..1100].iter().all(|s| s.0.abs() < 0.1));
+    }
+
+    // Feeds impulse to early reflections and returns their response of given length.
+    fn impulse_response(early: &mut EarlyReflections, len: usize) -> Vec<f32> {
+        (0..len).map(|i| early.feed(if i == 0 { 1.0 } else { 0.0 })).collect()
+    }
+
+    #[test]
+    fn early_reflections_are_crossfaded() {
+        let mut early = EarlyReflections::new(&[(Duration::from_millis(10), 0.5)], 44100);
+        early.begin_crossfade(2.0, 1.0);
+        // Output does not change until crossfade is stepped.
+        let response = impulse_response(&mut early, 1500);
+        assert_eq!(response[441], 0.5);
+        assert_eq!(response[882], 0.0);
+
+        early.crossfade(0.5);
+        let response = impulse_response(&mut early, 1500);
+        assert!((response[441] - 0.25).abs() < 1.0e-6);
+        assert!((response[882] - 0.25).abs() < 1.0e-6);
+
+        // New crossfade starts from current mix of taps, so there is no jump.
+        early.begin_crossfade(3.0, 1.0);
+        let response = impulse_response(&mut early, 1500);
+        assert!((response[441] - 0.25).abs() < 1.0e-6);
+        assert!((response[882] - 0.25).abs() < 1.0e-6);
+        assert_eq!(response[1323], 0.0);
+
+        early.crossfade(1.0);
+        assert_eq!((early.room_size, early.gain), (3.0, 1.0));
+        let response = impulse_response(&mut early, 1500);
+        assert_eq!(response.iter().filter(|&&s| s != 0.0).count(), 1);
+        assert_eq!(response[1323], 0.5);
+    }
+
+    #[test]
+    fn transition_does_not_allocate() {
+        let mut reverb = Reverb::new(Default::default());
+        reverb.apply_params(&ReverbPreset::SmallRoom.params());
+        let to = ReverbPreset::Hall.params();
+        reverb.begin_transition(&to);
+        let buffer = reverb.early.buffer.as_ptr();
+        let taps = [reverb.early.taps.as_ptr(), reverb.early.next_taps.as_ptr()];
+        for i in 0..=10 {
+            let t = i as f32 / 10.0;
+            reverb.step_transition(&ReverbPreset::SmallRoom.params().lerp(&to, t), t);
+            assert_eq!(reverb.early.buffer.as_ptr(), buffer);
+            assert!(taps.contains(&reverb.early.taps.as_ptr()));
+            assert!(taps.contains(&reverb.early.next_taps.as_ptr()));
+        }
+        assert_eq!(reverb.room_size(), to.room_size);
+        assert_eq!(reverb.reflections_gain(), to.reflections_gain);
+        assert!((reverb.decay_time().as_secs_f32() - to.decay_time).abs() < 0.01);
+    }
+
+    #[test]
+    fn transition_keeps_custom_early_reflections() {
+        let reflections = [(Duration::from_millis(10), 0.5)];
+        let mut reverb = Reverb::new(Default::default());
+        reverb.set_early_reflections(&reflections);
+        let to = ReverbPreset::Hall.params();
+        reverb.begin_transition(&to);
+        reverb.step_transition(&to, 1.0);
+        assert_eq!(reverb.early_reflections(), &reflections);
+        assert_eq!(reverb.room_size(), to.room_size);
+        assert_eq!(reverb.reflections_gain(), to.reflections_gain);
+    }
+
+    #[test]
+    fn params_keep_custom_early_reflections() {
+        let reflections = [(Duration::from_millis(10), 0.5)];
+        let mut reverb = Reverb::new(Default::default());
+        reverb.set_early_reflections(&reflections);
+        let params = ReverbPreset::Hall.params();
+        reverb.apply_params(&params);
+        assert_eq!(reverb.early_reflections(), &reflections);
+        assert_eq!(reverb.room_size(), params.room_size);
+        assert_eq!(reverb.reflections_gain(), params.reflections_gain);
+    }
+
+    #[test]
+    fn room_size_and_reflections_gain_are_visited() {
+        let mut visitor = test_support::reload(|visitor| {
+            let mut reverb = Reverb::default();
+            reverb.set_room_size(1.5);
+            reverb.set_reflections_gain(0.25);
+            reverb.visit("Reverb", visitor)
+        });
+        let mut reverb = Reverb::default();
+        reverb.visit("Reverb", &mut visitor).unwrap();
+        assert_eq!(reverb.room_size(), 1.5);
+        assert_eq!(reverb.reflections_gain(), 0.25);
+    }
+
+    // Time (in seconds) in which energy of reverb tail of impulse drops by 20 dB.
+    fn measure_t20(mut reverb: Reverb) -> f32 {
+        const WINDOW: usize = 2205; // 50 ms
+        reverb.set_dry(0.0);
+        reverb.set_early_reflections(&[]);
+        let mut input = vec![(0.0, 0.0); 44100 * 4];
+        input[10] = (1.0, 1.0);
+        let output = test_support::run_effect(&mut Effect::Reverb(reverb), &input, 4096);
+        let energies = output.chunks(WINDOW)
+            .map(|w| w.iter().map(|s| s.0 * s.0 + s.1 * s.1).sum::<f32>())
+            .collect::<Vec<f32>>();
+        // First window contains only sparse output of allpass filters, so reference is the second.
+        let reference = energies[1];
+        let window = energies.iter().skip(1).position(|&e| e < reference * 0.01).expect("tail must decay");
+        (window * WINDOW) as f32 / 44100.0
+    }
+
+    #[test]
+    fn presets_have_distinct_sane_decay() {
+        let presets = [ReverbPreset::SmallRoom, ReverbPreset::Room, ReverbPreset::Plate, ReverbPreset::Hall, ReverbPreset::Cave];
+        let mut last_t20 = 0.0;
+        for &preset in presets.iter() {
+            let params = preset.params();
+            let reverb = Reverb::preset(preset);
+            assert!((reverb.decay_time().as_secs_f32() - params.decay_time).abs() < 1.0e-3);
+            assert_eq!(reverb.get_dry(), params.dry);
+            assert_eq!(reverb.stereo_width(), params.stereo_width);
+            assert_eq!(reverb.early_reflections(), &Reverb::DEFAULT_EARLY_REFLECTIONS);
+            assert_eq!(reverb.room_size(), params.room_size);
+            assert_eq!(reverb.reflections_gain(), params.reflections_gain);
+
+            // Presets are listed by increasing decay time, measured decay must follow it. Decay time is
+            // set per comb filter, so the tail as a whole falls by 20 dB well before a third of it.
+            let t20 = measure_t20(reverb);
+            assert!(t20 > last_t20, "{:?} {}", preset, t20);
+            assert!(t20 > params.decay_time / 20.0 && t20 < params.decay_time / 2.0, "{:?} {}", preset, t20);
+            last_t20 = t20;
+        }
+    }
+
+    #[test]
+    fn preset_parameters_can_be_overridden() {
+        let mut reverb = Reverb::preset(ReverbPreset::Cave);
+        reverb.set_decay_time(Duration::from_millis(300));
+        reverb.set_dry(0.25);
+        reverb.set_early_reflections(&[]);
+        assert!((reverb.decay_time().as_secs_f32() - 0.3).abs() < 1.0e-3);
+        assert_eq!(reverb.get_dry(), 0.25);
+        assert!(reverb.early_reflections().is_empty());
+        assert!(measure_t20(reverb) < measure_t20(Reverb::preset(ReverbPreset::Cave)));
+    }
+
+    // Renders noise source with given reverb send level through reverb, returns left channel.
+    fn render_with_send(mode: ReverbSendMode, send: f32) -> Vec<f32> {
+        let mut reverb = Reverb::new(Default::default());
+        reverb.set_send_mode(mode);
+        let source = GenericSourceBuilder::new(test_support::make_buffer(test_support::noise(16384, 5), 44100))
+            .with_status(Status::Playing)
+            .build_source()
+            .unwrap();
+        let mut sources = Pool::new();
+        let handle = sources.spawn(source);
+        sources.borrow_mut(handle).generic_mut().set_reverb_send(send);
+        reverb.base_mut().add_input(EffectInput::direct(handle));
+
+        let listener = Listener::new();
+        let mut output = vec![(0.0, 0.0); 16384];
+        for block in output.chunks_mut(1024) {
+            sources.borrow_mut(handle).generic_mut().render(block.len());
+            reverb.render(&sources, &listener, &DistanceModel::None, block);
+        }
+        output.iter().map(|s| s.0).collect()
+    }
+
+    #[test]
+    fn send_levels_scale_reverb_contribution() {
+        let full = render_with_send(ReverbSendMode::Send, 1.0);
+        let quarter = render_with_send(ReverbSendMode::Send, 0.25);
+        let ratio = test_support::rms(&quarter) / test_support::rms(&full);
+        assert!((ratio - 0.25).abs() < 1.0e-3);
+        // Zero send is fully dry - nothing goes to output of reverb.
+        assert!(render_with_send(ReverbSendMode::Send, 0.0).iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn insert_mode_ignores_send_levels() {
+        assert_eq!(render_with_send(ReverbSendMode::Insert, 0.25), render_with_send(ReverbSendMode::Insert, 1.0));
+    }
+
+    #[test]
+    fn send_mode_outputs_only_wet_signal() {
+        let insert = render_with_send(ReverbSendMode::Insert, 1.0);
+        let send = render_with_send(ReverbSendMode::Send, 1.0);
+        // Difference is dry part of input.
+        let dry = insert.iter().zip(send.iter()).map(|(a, b)| a - b).collect::<Vec<f32>>();
+        let input = test_support::noise(16384, 5);
+        let ratio = test_support::rms(&dry[..8192]) / test_support::rms(&input[..8192]);
+        assert!((ratio - 1.0).abs() < 1.0e-3);
+    }
+}
@@ -13,8 +13,53 @@ pub enum DecoderError {
     /// WAV specific decoder error.
     Wav,
 
+    /// WAV file contains samples in format that is not supported by decoder. Supported formats
+    /// are: 8-bit unsigned, 16/24/32-bit signed integer PCM and 32/64-bit IEEE float.
+    UnsupportedWavFormat {
+        /// Format code from fmt chunk (1 - PCM, 3 - IEEE float, etc.)
+        audio_format: u16,
+        /// Bits per sample from fmt chunk.
+        bits_per_sample: u16,
+    },
+
     /// Ogg/vorbis (lewton) specific error.
-    Ogg(lewton::VorbisError)
+    Ogg(lewton::VorbisError),
+
+    /// MP3 (minimp3) specific error.
+    Mp3(minimp3::Error),
+}
+
+/// Describes why sound context cannot be created.
+#[derive(Debug)]
+pub enum ContextError {
+    /// There is no default output device or it cannot be opened.
+    NoDefaultDevice,
+
+    /// Default output device does not support required format (16-bit stereo with
+    /// `SAMPLE_RATE` sampling rate).
+    UnsupportedFormat,
+
+    /// Backend failed to initialize by some other reason, exact reason stored in inner value.
+    BackendInitFailure(String),
+
+    /// Mixer thread cannot be spawned, exact reason stored in inner value.
+    ThreadSpawnFailure(std::io::Error),
+
+    /// There is no backend for current platform.
+    NoBackend,
+}
+
+impl Display for ContextError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match self {
+            ContextError::NoDefaultDevice => write!(f, "there is no default output device")?,
+            ContextError::UnsupportedFormat => write!(f, "default output device does not support required format")?,
+            ContextError::BackendInitFailure(reason) => write!(f, "failed to initialize backend. reason: {}", reason)?,
+            ContextError::ThreadSpawnFailure(io) => write!(f, "failed to spawn mixer thread. reason: {}", io)?,
+            ContextError::NoBackend => write!(f, "no backend implemented for current platform")?,
+        }
+        Ok(())
+    }
 }
 
 /// Generic error enumeration for each error in this engine.
@@ -48,6 +93,15 @@ pub enum SoundError {
     /// sound from streaming buffer.
     StreamingBufferAlreadyInUse,
 
+    /// Output matrix has invalid dimensions. Matrix must have one row per output channel of device
+    /// and one column per channel of buffer (at most two).
+    InvalidOutputMatrix {
+        /// Actual amount of rows and columns.
+        size: (usize, usize),
+        /// Expected amount of rows and columns.
+        expected: (usize, usize),
+    },
+
     /// Decoder specific error, can occur in the decoder by any reason (invalid format,
     /// insufficient data, etc.). Exact reason stored in inner value.
     DecoderError(DecoderError),
@@ -65,12 +119,27 @@ impl<'a, T> From<std::sync::PoisonError<std::sync::MutexGuard<'a, T>>> for Sound
     }
 }
 
+impl From<ContextError> for SoundError {
+    fn from(ce: ContextError) -> Self {
+        match ce {
+            ContextError::NoBackend => SoundError::NoBackend,
+            _ => SoundError::FailedToInitializeDevice(ce.to_string()),
+        }
+    }
+}
+
 impl From<lewton::VorbisError> for SoundError {
     fn from(ve: VorbisError) -> Self {
         SoundError::DecoderError(DecoderError::Ogg(ve))
     }
 }
 
+impl From<minimp3::Error> for SoundError {
+    fn from(me: minimp3::Error) -> Self {
+        SoundError::DecoderError(DecoderError::Mp3(me))
+    }
+}
+
 impl Display for SoundError {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         match self {
@@ -82,6 +151,9 @@ impl Display for SoundError {
             SoundError::PoisonedMutex => write!(f, "attempt to use poisoned mutex")?,
             SoundError::MathError(reason) => write!(f, "math error has occurred. reason: {}", reason)?,
             SoundError::StreamingBufferAlreadyInUse => write!(f, "streaming buffer in already in use")?,
+            SoundError::InvalidOutputMatrix { size, expected } => {
+                write!(f, "output matrix has size {}x{}, expected {}x{}", size.0, size.1, expected.0, expected.1)?
+            }
             SoundError::DecoderError(de) => write!(f, "internal decoder error: {:?}", de)?,
         }
         Ok(())
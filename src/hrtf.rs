@@ -14,6 +14,19 @@
 //! for left and right ears which will be used to modify samples from each spatial sound source to create binaural
 //! sound. HRIR spheres can be found [here](https://github.com/mrDIMAS/hrir_sphere_builder/tree/master/hrtf_base/IRCAM)
 //!
+//! # File format
+//!
+//! HRIR sphere files have simple binary format (all values are little-endian):
+//!
+//! - Magic `HRIR` for legacy files (version 1) or magic `HRVR` followed by `u32` version for newer files.
+//! - `u32` sample rate, `u32` HRIR length, `u32` vertex count, `u32` index count.
+//! - `index count` of `u32` indices of triangles.
+//! - For each vertex: three `f32` coordinates, `HRIR length` of `f32` for left ear, `HRIR length` of `f32`
+//!   for right ear. Version 2 adds two `f32` interaural time delays (in samples) for left and right ears.
+//!
+//! Spheres can be written back to this format by `HrtfSphere::save`, so spheres that were transformed
+//! or resampled can be persisted.
+//!
 //! # Usage
 //!
 //! To use HRTF you need to change default renderer to HRTF renderer like so:
@@ -29,7 +42,7 @@
 //!     // from base mentioned above.
 //!     let hrtf = HrtfSphere::new(Path::new("IRC_1002_C.bin")).unwrap();
 //!
-//!     context.set_renderer(Renderer::HrtfRenderer(HrtfRenderer::new(hrtf)));
+//!     context.set_renderer(Renderer::HrtfRenderer(HrtfRenderer::new(hrtf).unwrap()));
 //! }
 //! ```
 //!
@@ -49,6 +62,22 @@
 //! [here](http://csoundjournal.com/issue9/newHRTFOpcodes.html)
 //!
 //! Clicks can be reproduced by using clean sine wave of 440 Hz on some source moving around listener.
+//!
+//! # Sample rate
+//!
+//! HRIR spheres can be loaded with any sample rate, but renderer requires sphere with sample rate of
+//! output device. Use `HrtfSphere::resample` to convert sphere to required sample rate:
+//!
+//! ```no_run
+//! use rg3d_sound::hrtf::{HrtfSphere, HrtfRenderer};
+//!
+//! fn make_renderer(mut hrtf: HrtfSphere, device_sample_rate: u32) -> HrtfRenderer {
+//!     if hrtf.sample_rate() != device_sample_rate {
+//!         hrtf.resample(device_sample_rate);
+//!     }
+//!     HrtfRenderer::new(hrtf).unwrap()
+//! }
+//! ```
 
 use rustfft::{
     num_complex::Complex,
@@ -60,7 +89,9 @@ use std::{
     path::Path,
     io::{
         BufReader,
+        BufWriter,
         Read,
+        Write,
         Error,
     },
 };
@@ -71,6 +102,7 @@ use rg3d_core::math::{
 };
 use byteorder::{
     ReadBytesExt,
+    WriteBytesExt,
     LittleEndian,
 };
 use crate::{
@@ -79,27 +111,64 @@ use crate::{
         Context,
     },
     listener::Listener,
-    renderer::render_source_default,
+    renderer::{
+        render_source_default,
+        render_with_params,
+        flat_gains,
+        Render,
+        INAUDIBLE_GAIN,
+    },
     device,
     source::{
-        spatial::SpatialSource,
+        spatial::{SpatialSource, SourceQuality},
         SoundSource,
     },
     math::{
         self,
         mat4::Mat4,
     },
+    dsp::FractionalDelayLine,
 };
 
 /// Single point of HRTF sphere. See module docs for more info.
 pub struct HrtfPoint {
     /// Position of point in cartesian coordinate space.
     pub pos: Vec3,
+    // Original impulse responses, spectra are rebuilt from them on resampling and when block length
+    // changes.
+    left_hrir: Vec<f32>,
+    right_hrir: Vec<f32>,
     left_hrtf: Vec<Complex<f32>>,
     right_hrtf: Vec<Complex<f32>>,
+    left_delay: f32,
+    right_delay: f32,
 }
 
 impl HrtfPoint {
+    /// Returns interaural time delay (in samples, can be fractional) for left ear. Legacy
+    /// (version 1) spheres does not contain delays, so it will be zero for them.
+    pub fn left_delay(&self) -> f32 {
+        self.left_delay
+    }
+
+    /// Returns interaural time delay (in samples, can be fractional) for right ear. Legacy
+    /// (version 1) spheres does not contain delays, so it will be zero for them.
+    pub fn right_delay(&self) -> f32 {
+        self.right_delay
+    }
+
+    /// Returns original (time-domain) impulse response for left ear, as it was loaded from file (or
+    /// resampled, see `HrtfSphere::resample`). Its spectrum (zero-padded to block length of renderer)
+    /// is `left_hrtf`.
+    pub fn left_hrir(&self) -> &[f32] {
+        &self.left_hrir
+    }
+
+    /// Returns original (time-domain) impulse response for right ear. See `left_hrir`.
+    pub fn right_hrir(&self) -> &[f32] {
+        &self.right_hrir
+    }
+
     /// Returns shared reference to spectrum for left ear.
     pub fn left_hrtf(&self) -> &[Complex<f32>] {
         &self.left_hrtf
@@ -111,17 +180,102 @@ impl HrtfPoint {
     }
 }
 
+/// HRTF pair which was sampled from HRTF sphere for fixed direction, see `HrtfSphere::bake`. Baked
+/// filter can be shared between many spatial sources (see `SpatialSource::set_hrtf_filter`), they
+/// won't sample HRTF sphere each block in this case.
+#[derive(Clone, Debug)]
+pub struct HrtfFilter {
+    direction: Vec3,
+    left_hrtf: Vec<Complex<f32>>,
+    right_hrtf: Vec<Complex<f32>>,
+}
+
+impl HrtfFilter {
+    /// Returns direction for which filter was baked.
+    pub fn direction(&self) -> Vec3 {
+        self.direction
+    }
+
+    /// Returns spectrum for left ear.
+    pub fn left_hrtf(&self) -> &[Complex<f32>] {
+        &self.left_hrtf
+    }
+
+    /// Returns spectrum for right ear.
+    pub fn right_hrtf(&self) -> &[Complex<f32>] {
+        &self.right_hrtf
+    }
+}
+
+/// Points of HRTF sphere and their weights which were used to sample HRTF for some direction, see
+/// `HrtfSphere::blend`. HRTF is weighted sum of HRTFs of points.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HrtfBlend {
+    /// Indices of points of sphere, see `HrtfSphere::points`.
+    pub points: [usize; 3],
+    /// Weights of points (barycentric coordinates of sampled direction on face), they sum to one.
+    /// When single point is used (nearest sampling), its weight is 1.0 and weights of others are zero.
+    pub weights: [f32; 3],
+}
+
+impl HrtfBlend {
+    fn single(index: usize) -> Self {
+        Self {
+            points: [index; 3],
+            weights: [1.0, 0.0, 0.0],
+        }
+    }
+}
+
 struct Face {
     a: usize,
     b: usize,
     c: usize,
 }
 
+// Precomputed data of face that is used to speed up sampling.
+struct FaceData {
+    vertices: [Vec3; 3],
+    // Each ray that intersects the face lies inside cone with this axis and cosine of half-angle.
+    // Cone is built from directions to vertices of face, so it is used for fast rejection of faces.
+    cone_axis: Vec3,
+    cone_cos: f32,
+}
+
+impl FaceData {
+    fn new(a: Vec3, b: Vec3, c: Vec3) -> Self {
+        let cone = match (a.normalized(), b.normalized(), c.normalized()) {
+            (Some(da), Some(db), Some(dc)) => (da + db + dc).normalized().map(|axis| {
+                let cos = axis.dot(&da).min(axis.dot(&db)).min(axis.dot(&dc));
+                (axis, cos)
+            }),
+            _ => None,
+        };
+        let (cone_axis, cone_cos) = match cone {
+            // Cone is convex only if its half-angle is less than 90 degrees, only in this case it
+            // contains all rays that intersect the face. Epsilon is to be on safe side with rounding.
+            Some((axis, cos)) if cos > 0.0 => (axis, cos - 1.0e-4),
+            // Never reject such faces.
+            _ => (Vec3::ZERO, -2.0),
+        };
+        Self {
+            vertices: [a, b, c],
+            cone_axis,
+            cone_cos,
+        }
+    }
+}
+
 /// See module docs.
 pub struct HrtfSphere {
+    version: u32,
+    sample_rate: u32,
     length: usize,
     points: Vec<HrtfPoint>,
     faces: Vec<Face>,
+    // Rebuilt on load, transform, bake and renderer creation, invalidated when points are accessed mutably.
+    face_data: Vec<FaceData>,
+    face_data_valid: bool,
 }
 
 /// All possible error that can occur during HRIR sphere loading.
@@ -130,9 +284,9 @@ pub enum HrtfError {
     /// Io error has occurred (file does not exists, etc.)
     IoError(std::io::Error),
 
-    /// HRIR has sample rate that differs from device sample rate.
+    /// Sphere has sample rate that differs from device sample rate, so it cannot be used by renderer.
     /// Tuple holds pair (current_sample_rate, device_sample_rate)
-    /// You should resample HRIR's first and regenerate sphere.
+    /// You should resample sphere first, see `HrtfSphere::resample`.
     InvalidSampleRate(u32, u32),
 
     /// It is not valid HRIR sphere file.
@@ -140,6 +294,15 @@ pub enum HrtfError {
 
     /// HRIR has invalid length (zero)
     InvalidLength(usize),
+
+    /// File has version of format which is not supported by this version of library.
+    UnsupportedVersion(u32),
+
+    /// Block length of renderer is zero.
+    InvalidBlockLength(usize),
+
+    /// Sphere has no points or its points have empty HRTFs, so it cannot be used for rendering.
+    EmptySphere,
 }
 
 impl From<std::io::Error> for HrtfError {
@@ -148,25 +311,56 @@ impl From<std::io::Error> for HrtfError {
     }
 }
 
-fn make_hrtf(mut hrir: Vec<Complex<f32>>, pad_length: usize, planner: &mut FFTplanner<f32>) -> Vec<Complex<f32>> {
-    for _ in hrir.len()..pad_length {
-        // Pad with zeros to length of context's output buffer.
-        hrir.push(Complex::zero());
-    }
+fn make_hrtf(hrir: &[f32], pad_length: usize, planner: &mut FFTplanner<f32>) -> Vec<Complex<f32>> {
+    let mut hrir = hrir.iter().map(|&s| Complex::new(s, 0.0)).collect::<Vec<Complex<f32>>>();
+    // Pad with zeros to length of context's output buffer.
+    hrir.resize(pad_length, Complex::zero());
     let mut hrtf = vec![Complex::zero(); pad_length];
     planner.plan_fft(pad_length).process(hrir.as_mut(), hrtf.as_mut());
     // Smooth
     hrtf
 }
 
-fn read_hrir(reader: &mut dyn Read, len: usize) -> Result<Vec<Complex<f32>>, HrtfError> {
+fn read_hrir(reader: &mut dyn Read, len: usize) -> Result<Vec<f32>, HrtfError> {
     let mut hrir = Vec::with_capacity(len);
     for _ in 0..len {
-        hrir.push(Complex::new(reader.read_f32::<LittleEndian>()?, 0.0));
+        hrir.push(reader.read_f32::<LittleEndian>()?);
     }
     Ok(hrir)
 }
 
+/// Resamples impulse response by band-limited (windowed sinc) interpolation, `ratio` is target sample
+/// rate divided by source sample rate. Impulse response is scaled by inverse of ratio, so resampled
+/// filter has the same frequency response.
+fn resample_hrir(hrir: &[f32], ratio: f64) -> Vec<f32> {
+    // Half-width of interpolation kernel in zero crossings of sinc.
+    const HALF_WIDTH: f64 = 16.0;
+
+    let new_len = ((hrir.len() as f64 * ratio).ceil() as usize).max(1);
+    // Cutoff is lowered when downsampling to prevent aliasing.
+    let cutoff = ratio.min(1.0);
+    let half = HALF_WIDTH / cutoff;
+    let last = hrir.len() as isize - 1;
+
+    (0..new_len)
+        .map(|n| {
+            // Position of output sample in source samples.
+            let t = n as f64 / ratio;
+            let from = ((t - half).ceil() as isize).max(0);
+            let to = ((t + half).floor() as isize).min(last);
+            let mut sum = 0.0;
+            for k in from..=to {
+                let x = t - k as f64;
+                let arg = std::f64::consts::PI * cutoff * x;
+                let sinc = if arg.abs() < 1.0e-9 { 1.0 } else { arg.sin() / arg };
+                let window = 0.5 + 0.5 * (std::f64::consts::PI * x / half).cos();
+                sum += f64::from(hrir[k as usize]) * cutoff * sinc * window;
+            }
+            (sum / ratio) as f32
+        })
+        .collect()
+}
+
 fn read_faces(reader: &mut dyn Read, index_count: usize) -> Result<Vec<Face>, HrtfError> {
     let mut indices = Vec::with_capacity(index_count);
     for _ in 0..index_count {
@@ -179,6 +373,9 @@ fn read_faces(reader: &mut dyn Read, index_count: usize) -> Result<Vec<Face>, Hr
 }
 
 impl HrtfSphere {
+    /// Latest version of HRIR sphere file format that is supported by the library.
+    pub const LATEST_VERSION: u32 = 2;
+
     /// Loads HRIR sphere and creates HRTF sphere from it.
     ///
     /// # Coordinate system
@@ -188,17 +385,34 @@ impl HrtfSphere {
     /// system. However this can be fixed very easily: just invert z-axis of
     /// listener basis, or use appropriate `set_orientation_XXX` method.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<HrtfSphere, HrtfError> {
-        let mut reader = BufReader::new(File::open(path)?);
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Loads HRIR sphere from given bytes, which must have the same layout as HRIR sphere file. Useful
+    /// for packed assets or platforms without file system. See `new` for more info.
+    pub fn from_bytes(bytes: &[u8]) -> Result<HrtfSphere, HrtfError> {
+        Self::from_reader(bytes)
+    }
 
+    /// Loads HRIR sphere from given reader, which must provide data with the same layout as HRIR sphere
+    /// file. Reader is not buffered internally, wrap it into `BufReader` if needed. See `new` for more
+    /// info.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<HrtfSphere, HrtfError> {
         let mut magic = [0; 4];
         reader.read_exact(&mut magic)?;
-        if magic[0] != b'H' && magic[1] != b'R' && magic[2] != b'I' && magic[3] != b'R' {
-            return Err(HrtfError::InvalidFileFormat);
+        let version = match &magic {
+            // Legacy files has no version field.
+            b"HRIR" => 1,
+            b"HRVR" => reader.read_u32::<LittleEndian>()?,
+            _ => return Err(HrtfError::InvalidFileFormat),
+        };
+        if version == 0 || version > Self::LATEST_VERSION {
+            return Err(HrtfError::UnsupportedVersion(version));
         }
 
         let sample_rate = reader.read_u32::<LittleEndian>()?;
-        if sample_rate != device::SAMPLE_RATE {
-            return Err(HrtfError::InvalidSampleRate(sample_rate, device::SAMPLE_RATE));
+        if sample_rate == 0 {
+            return Err(HrtfError::InvalidFileFormat);
         }
         let length = reader.read_u32::<LittleEndian>()? as usize;
         if length == 0 {
@@ -207,10 +421,16 @@ impl HrtfSphere {
         let vertex_count = reader.read_u32::<LittleEndian>()? as usize;
         let index_count = reader.read_u32::<LittleEndian>()? as usize;
 
+        if !index_count.is_multiple_of(3) {
+            return Err(HrtfError::InvalidFileFormat);
+        }
         let faces = read_faces(&mut reader, index_count)?;
+        if faces.iter().any(|f| f.a >= vertex_count || f.b >= vertex_count || f.c >= vertex_count) {
+            return Err(HrtfError::InvalidFileFormat);
+        }
 
         let mut planner = FFTplanner::new(false);
-        let pad_length = Context::HRTF_BLOCK_LEN + length - 1;
+        let pad_length = get_pad_len(Context::HRTF_BLOCK_LEN, length);
 
         let mut points = Vec::with_capacity(vertex_count);
         for _ in 0..vertex_count {
@@ -218,21 +438,91 @@ impl HrtfSphere {
             let y = reader.read_f32::<LittleEndian>()?;
             let z = reader.read_f32::<LittleEndian>()?;
 
-            let left_hrtf = make_hrtf(read_hrir(&mut reader, length)?, pad_length, &mut planner);
-            let right_hrtf = make_hrtf(read_hrir(&mut reader, length)?, pad_length, &mut planner);
+            let left_hrir = read_hrir(&mut reader, length)?;
+            let right_hrir = read_hrir(&mut reader, length)?;
+            let left_hrtf = make_hrtf(&left_hrir, pad_length, &mut planner);
+            let right_hrtf = make_hrtf(&right_hrir, pad_length, &mut planner);
+
+            let (left_delay, right_delay) = if version >= 2 {
+                (reader.read_f32::<LittleEndian>()?, reader.read_f32::<LittleEndian>()?)
+            } else {
+                (0.0, 0.0)
+            };
 
             points.push(HrtfPoint {
                 pos: Vec3::new(x, y, z),
+                left_hrir,
+                right_hrir,
                 left_hrtf,
                 right_hrtf,
+                left_delay,
+                right_delay,
             });
         }
 
-        Ok(Self {
+        let mut sphere = Self {
+            version,
+            sample_rate,
             points,
             length,
             faces,
-        })
+            face_data: Default::default(),
+            face_data_valid: false,
+        };
+        sphere.update_face_data();
+        Ok(sphere)
+    }
+
+    /// Writes sphere to file at given path in HRIR sphere format (see module docs). Sphere is written
+    /// in the same version of format from which it was loaded, so written file loads back identically.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), HrtfError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes sphere to given writer in HRIR sphere format, see `save` for more info. Writer is not
+    /// buffered internally, wrap it into `BufWriter` if needed.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), HrtfError> {
+        if self.version == 1 {
+            writer.write_all(b"HRIR")?;
+        } else {
+            writer.write_all(b"HRVR")?;
+            writer.write_u32::<LittleEndian>(self.version)?;
+        }
+
+        writer.write_u32::<LittleEndian>(self.sample_rate)?;
+        writer.write_u32::<LittleEndian>(self.length as u32)?;
+        writer.write_u32::<LittleEndian>(self.points.len() as u32)?;
+        writer.write_u32::<LittleEndian>(self.faces.len() as u32 * 3)?;
+
+        for face in self.faces.iter() {
+            for &index in [face.a, face.b, face.c].iter() {
+                writer.write_u32::<LittleEndian>(index as u32)?;
+            }
+        }
+
+        for pt in self.points.iter() {
+            writer.write_f32::<LittleEndian>(pt.pos.x)?;
+            writer.write_f32::<LittleEndian>(pt.pos.y)?;
+            writer.write_f32::<LittleEndian>(pt.pos.z)?;
+
+            // Length of impulse responses always matches length of sphere, unless points were
+            // modified, so they're padded or truncated to keep file valid.
+            for hrir in [&pt.left_hrir, &pt.right_hrir].iter() {
+                for i in 0..self.length {
+                    writer.write_f32::<LittleEndian>(hrir.get(i).cloned().unwrap_or(0.0))?;
+                }
+            }
+
+            if self.version >= 2 {
+                writer.write_f32::<LittleEndian>(pt.left_delay)?;
+                writer.write_f32::<LittleEndian>(pt.right_delay)?;
+            }
+        }
+
+        Ok(())
     }
 
     /// Applies specified transform to each point in sphere. Can be used
@@ -242,6 +532,79 @@ impl HrtfSphere {
         for pt in self.points.iter_mut() {
             pt.pos = matrix.transform_vector(pt.pos);
         }
+        self.face_data_valid = false;
+        self.update_face_data();
+    }
+
+    /// Rebuilds precomputed face data if it was invalidated.
+    fn update_face_data(&mut self) {
+        if self.face_data_valid {
+            return;
+        }
+        let points = &self.points;
+        self.face_data.clear();
+        self.face_data.extend(self.faces
+            .iter()
+            .map(|face| match (points.get(face.a), points.get(face.b), points.get(face.c)) {
+                (Some(a), Some(b), Some(c)) => FaceData::new(a.pos, b.pos, c.pos),
+                // Points count could be changed by user, such face will never be hit.
+                _ => FaceData::new(Vec3::ZERO, Vec3::ZERO, Vec3::ZERO),
+            }));
+        self.face_data_valid = true;
+    }
+
+    /// Recalculates spectra of each point so they can be used to process blocks of given length.
+    /// Does nothing if spectra already have required length.
+    fn set_block_len(&mut self, block_len: usize) {
+        let pad_length = get_pad_len(block_len, self.length);
+        if self.points.first().is_none_or(|pt| pt.left_hrtf.len() == pad_length) {
+            return;
+        }
+        let mut planner = FFTplanner::new(false);
+        for pt in self.points.iter_mut() {
+            pt.left_hrtf = make_hrtf(&pt.left_hrir, pad_length, &mut planner);
+            pt.right_hrtf = make_hrtf(&pt.right_hrir, pad_length, &mut planner);
+        }
+    }
+
+    /// Returns sample rate of impulse responses of sphere.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Resamples impulse responses of all points to given sample rate and rebuilds their spectra. Length
+    /// of impulse responses is scaled by ratio of sample rates, interaural time delays are scaled too.
+    /// Useful when sample rate of output device is known only after sphere was loaded. Does nothing if
+    /// sphere already has given sample rate or if it is zero.
+    pub fn resample(&mut self, sample_rate: u32) {
+        if sample_rate == self.sample_rate || sample_rate == 0 {
+            return;
+        }
+
+        // Keep block length for which spectra was built.
+        let block_len = self.points
+            .first()
+            .map_or(Context::HRTF_BLOCK_LEN, |pt| pt.left_hrtf.len() + 1 - self.length);
+
+        let ratio = f64::from(sample_rate) / f64::from(self.sample_rate);
+        self.length = ((self.length as f64 * ratio).ceil() as usize).max(1);
+        self.sample_rate = sample_rate;
+
+        let pad_length = get_pad_len(block_len, self.length);
+        let mut planner = FFTplanner::new(false);
+        for pt in self.points.iter_mut() {
+            pt.left_hrir = resample_hrir(&pt.left_hrir, ratio);
+            pt.right_hrir = resample_hrir(&pt.right_hrir, ratio);
+            pt.left_hrtf = make_hrtf(&pt.left_hrir, pad_length, &mut planner);
+            pt.right_hrtf = make_hrtf(&pt.right_hrir, pad_length, &mut planner);
+            pt.left_delay *= ratio as f32;
+            pt.right_delay *= ratio as f32;
+        }
+    }
+
+    /// Returns version of file format from which sphere was loaded.
+    pub fn version(&self) -> u32 {
+        self.version
     }
 
     /// Returns shared reference to sphere points array.
@@ -250,60 +613,168 @@ impl HrtfSphere {
     }
 
     /// Returns mutable reference to sphere points array.
+    ///
+    /// # Notes
+    ///
+    /// This invalidates precomputed data that is used to speed up sampling, it is rebuilt by
+    /// `transform`, `bake` and when renderer is created from the sphere. Until then `sample_bilinear`
+    /// uses slower path, results are the same.
     pub fn points_mut(&mut self) -> &mut [HrtfPoint] {
+        self.face_data_valid = false;
         &mut self.points
     }
 
     /// Sampling with bilinear interpolation
     /// http://www02.smt.ufrj.br/~diniz/conf/confi117.pdf
+    ///
+    /// # Notes
+    ///
+    /// Faces are hit regardless of their winding. If ray in given direction hits no face (sphere
+    /// has holes or its points were modified), HRTF of point which is nearest to the direction is
+    /// used instead, so HRTFs are always updated.
     pub fn sample_bilinear(&self, left_hrtf: &mut Vec<Complex<f32>>, right_hrtf: &mut Vec<Complex<f32>>, dir: Vec3) {
-        if let Some(ray) = Ray::from_two_points(&Vec3::ZERO, &dir.scale(10.0)) {
-            for face in self.faces.iter() {
-                let a = self.points.get(face.a).unwrap();
-                let b = self.points.get(face.b).unwrap();
-                let c = self.points.get(face.c).unwrap();
-
-                if let Some(p) = ray.triangle_intersection(&[a.pos, b.pos, c.pos]) {
-                    let (ka, kb, kc) = get_barycentric_coords(&p, &a.pos, &b.pos, &c.pos);
-
-                    let len = a.left_hrtf.len();
-
-                    left_hrtf.clear();
-                    for i in 0..len {
-                        left_hrtf.push(
-                            a.left_hrtf[i] * ka +
-                                b.left_hrtf[i] * kb +
-                                c.left_hrtf[i] * kc);
-                    }
+        if let Some(blend) = self.blend(dir) {
+            self.apply_blend(&blend, left_hrtf, right_hrtf);
+        }
+    }
 
-                    right_hrtf.clear();
-                    for i in 0..len {
-                        right_hrtf.push(
-                            a.right_hrtf[i] * ka +
-                                b.right_hrtf[i] * kb +
-                                c.right_hrtf[i] * kc);
-                    }
-                }
+    /// Returns points of sphere and their weights which `sample_bilinear` uses for given direction.
+    /// Useful to validate sphere and to diagnose weird localization. Degenerated direction gives
+    /// first point of sphere, empty sphere gives `None`.
+    pub fn blend(&self, dir: Vec3) -> Option<HrtfBlend> {
+        let (ray, dir) = match (Ray::from_two_points(&Vec3::ZERO, &dir.scale(10.0)), dir.normalized()) {
+            (Some(ray), Some(dir)) => (ray, dir),
+            _ => {
+                // In case if we have degenerated dir vector use first available point as HRTF.
+                return if self.points.is_empty() { None } else { Some(HrtfBlend::single(0)) };
             }
-        } else {
-            // In case if we have degenerated dir vector use first available point as HRTF.
-            let pt = self.points.first().unwrap();
+        };
 
-            let len = pt.left_hrtf.len();
+        for (i, face) in self.faces.iter().enumerate() {
+            let (a, b, c) = match (self.points.get(face.a), self.points.get(face.b), self.points.get(face.c)) {
+                (Some(a), Some(b), Some(c)) => (a, b, c),
+                // Sphere is validated on load, but points can be modified by user.
+                _ => continue,
+            };
 
-            left_hrtf.clear();
-            for i in 0..len {
-                left_hrtf.push(pt.left_hrtf[i])
-            }
+            let vertices = if self.face_data_valid {
+                let data = &self.face_data[i];
+                if dir.dot(&data.cone_axis) < data.cone_cos {
+                    continue;
+                }
+                data.vertices
+            } else {
+                [a.pos, b.pos, c.pos]
+            };
+
+            // Test both orientations of face, so it will be hit regardless of its winding.
+            let intersection = ray.triangle_intersection(&vertices)
+                .or_else(|| ray.triangle_intersection(&[vertices[0], vertices[2], vertices[1]]));
 
-            right_hrtf.clear();
-            for i in 0..len {
-                right_hrtf.push(pt.right_hrtf[i])
+            if let Some(p) = intersection {
+                let (ka, kb, kc) = get_barycentric_coords(&p, &a.pos, &b.pos, &c.pos);
+                return Some(HrtfBlend {
+                    points: [face.a, face.b, face.c],
+                    weights: [ka, kb, kc],
+                });
             }
         }
+
+        // No face was hit, fall back to nearest point instead of leaving stale HRTF.
+        self.nearest_blend(dir)
+    }
+
+    /// Sampling without interpolation - HRTF of point which is nearest to given direction is used.
+    /// It is much cheaper than bilinear sampling, but HRTF changes in steps when direction changes.
+    /// Empty sphere or degenerated direction leaves HRTFs unchanged.
+    pub fn sample_nearest(&self, left_hrtf: &mut Vec<Complex<f32>>, right_hrtf: &mut Vec<Complex<f32>>, dir: Vec3) {
+        if let Some(blend) = self.nearest_blend(dir) {
+            self.apply_blend(&blend, left_hrtf, right_hrtf);
+        }
+    }
+
+    fn nearest_blend(&self, dir: Vec3) -> Option<HrtfBlend> {
+        let dir = dir.normalized()?;
+        self.points
+            .iter()
+            .enumerate()
+            .filter_map(|(i, pt)| pt.pos.normalized().map(|pos| (i, pos.dot(&dir))))
+            .fold(None, |nearest: Option<(usize, f32)>, (i, cos)| match nearest {
+                Some((_, nearest_cos)) if nearest_cos >= cos => nearest,
+                _ => Some((i, cos)),
+            })
+            .map(|(i, _)| HrtfBlend::single(i))
+    }
+
+    /// Mixes HRTFs of points of given blend with their weights.
+    fn apply_blend(&self, blend: &HrtfBlend, left_hrtf: &mut Vec<Complex<f32>>, right_hrtf: &mut Vec<Complex<f32>>) {
+        let [a, b, c] = blend.points;
+        let (a, b, c) = match (self.points.get(a), self.points.get(b), self.points.get(c)) {
+            (Some(a), Some(b), Some(c)) => (a, b, c),
+            _ => return,
+        };
+
+        if blend.weights[1] == 0.0 && blend.weights[2] == 0.0 {
+            copy_point_hrtf(a, left_hrtf, right_hrtf);
+            return;
+        }
+
+        let [ka, kb, kc] = blend.weights;
+        let len = a.left_hrtf.len();
+
+        left_hrtf.clear();
+        for i in 0..len {
+            left_hrtf.push(
+                a.left_hrtf[i] * ka +
+                    b.left_hrtf[i] * kb +
+                    c.left_hrtf[i] * kc);
+        }
+
+        right_hrtf.clear();
+        for i in 0..len {
+            right_hrtf.push(
+                a.right_hrtf[i] * ka +
+                    b.right_hrtf[i] * kb +
+                    c.right_hrtf[i] * kc);
+        }
+    }
+
+    /// Samples HRTF sphere (see `sample_bilinear`) for each of given directions and returns filters
+    /// which can be cached and used by sources instead of sampling each block. Directions are in
+    /// listener space, exactly as sampling vectors of spatial sources.
+    ///
+    /// # Notes
+    ///
+    /// Spectra of filters have length which depends on block length of renderer, so filters must be
+    /// baked by sphere of renderer that will use them (see `HrtfRenderer::bake`), renderer ignores
+    /// filters with spectra of different length.
+    pub fn bake(&mut self, directions: &[Vec3]) -> Vec<HrtfFilter> {
+        self.update_face_data();
+        directions
+            .iter()
+            .map(|&direction| {
+                let mut left_hrtf = Vec::new();
+                let mut right_hrtf = Vec::new();
+                self.sample_bilinear(&mut left_hrtf, &mut right_hrtf, direction);
+                HrtfFilter {
+                    direction,
+                    left_hrtf,
+                    right_hrtf,
+                }
+            })
+            .collect()
     }
 }
 
+/// Copies HRTFs of given point without interpolation.
+fn copy_point_hrtf(pt: &HrtfPoint, left_hrtf: &mut Vec<Complex<f32>>, right_hrtf: &mut Vec<Complex<f32>>) {
+    left_hrtf.clear();
+    left_hrtf.extend_from_slice(&pt.left_hrtf);
+
+    right_hrtf.clear();
+    right_hrtf.extend_from_slice(&pt.right_hrtf);
+}
+
 fn copy_replace(prev_samples: &mut Vec<f32>, raw_buffer: &mut [Complex<f32>], segment_len: usize) {
     if prev_samples.len() != segment_len {
         *prev_samples = vec![0.0; segment_len];
@@ -333,36 +804,55 @@ fn copy_replace(prev_samples: &mut Vec<f32>, raw_buffer: &mut [Complex<f32>], se
 /// I measured performance and direct convolution was 8-10 times slower than
 /// overlap-save convolution with impulse response length of 512 and signal length
 /// of 3545 samples.
+///
+/// Signal is expected to be at `in_buffer[start + hrtf_len..]`, `start` is non-zero only for partial
+/// (shorter than block length) blocks - samples before it are zeroed and are not used by valid part of
+/// the output. Result is written back to `in_buffer` and it is already normalized, see
+/// `ifft_normalization`.
 fn convolve_overlap_save(in_buffer: &mut [Complex<f32>],
                          out_buffer: &mut [Complex<f32>],
                          hrtf: &[Complex<f32>],
                          hrtf_len: usize,
+                         start: usize,
                          prev_samples: &mut Vec<f32>,
                          fft: &mut FFTplanner<f32>,
                          ifft: &mut FFTplanner<f32>)
 {
     assert_eq!(hrtf.len(), in_buffer.len());
 
-    copy_replace(prev_samples, in_buffer, hrtf_len);
+    for sample in in_buffer[..start].iter_mut() {
+        *sample = Complex::zero();
+    }
+    copy_replace(prev_samples, &mut in_buffer[start..], hrtf_len);
 
     fft.plan_fft(in_buffer.len()).process(in_buffer, out_buffer);
 
-    // Multiply HRIR and input signal in frequency domain.
+    // Multiply HRIR and input signal in frequency domain. Normalization of inverse FFT is applied
+    // here too, it is cheaper than separate pass over output.
+    let normalization = ifft_normalization(in_buffer.len());
     for (s, h) in out_buffer.iter_mut().zip(hrtf.iter()) {
-        *s *= *h;
+        *s *= *h * normalization;
     }
 
     ifft.plan_fft(in_buffer.len()).process(out_buffer, in_buffer);
 }
 
-fn get_pad_len(hrtf_len: usize) -> usize {
+/// Returns scale factor that normalizes result of inverse FFT of given length. rustfft does not
+/// normalize transforms, so forward and inverse FFT give signal scaled by its length. Convolution
+/// result is normalized by `convolve_overlap_save`, so it has the same amplitude as result of direct
+/// convolution - callers must not scale it again.
+fn ifft_normalization(len: usize) -> f32 {
+    1.0 / len as f32
+}
+
+fn get_pad_len(block_len: usize, hrtf_len: usize) -> usize {
     // Total length for each temporary buffer.
     // The value defined by overlap-add convolution method:
     //
     // pad_length = M + N - 1,
     //
     // where M - signal length, N - hrtf length
-    Context::HRTF_BLOCK_LEN + hrtf_len - 1
+    block_len + hrtf_len - 1
 }
 
 /// See module docs.
@@ -376,34 +866,118 @@ pub struct HrtfRenderer {
     ifft: FFTplanner<f32>,
     left_hrtf: Vec<Complex<f32>>,
     right_hrtf: Vec<Complex<f32>>,
+    itd_enabled: bool,
+    crossover_frequency: Option<f32>,
+    block_len: usize,
+    reduced_interpolation_gain: f32,
+    head_radius: f32,
+    last_blend: Option<HrtfBlend>,
 }
 
-pub(in crate) fn get_raw_samples(source: &mut SpatialSource, left: &mut [Complex<f32>], right: &mut [Complex<f32>], offset: usize) {
+/// Speed of sound in air in meters per second.
+const SPEED_OF_SOUND: f32 = 343.0;
+
+/// Calculates interaural time difference (in samples) for left and right ears using Woodworth's
+/// formula: `itd = r / c * (angle + sin(angle))`, where `r` is head radius and `angle` is lateral
+/// angle of source. Panning here is projection of direction to source on ear axis (positive - source
+/// is at the left).
+fn get_itd(panning: f32, head_radius: f32) -> (f32, f32) {
+    let angle = panning.abs().min(1.0).asin();
+    let delay = head_radius / SPEED_OF_SOUND * (angle + angle.sin()) * device::SAMPLE_RATE as f32;
+    if panning > 0.0 {
+        // Source is at the left, so sound will reach right ear later.
+        (0.0, delay)
+    } else {
+        (delay, 0.0)
+    }
+}
+
+/// Splits HRTF pair in two bands. Below crossover frequency both ears will have the same (averaged)
+/// response so low frequencies will be localized by time delay only, above crossover frequency
+/// each ear has its own spectrum. There is smooth transition between bands in half an octave around
+/// crossover frequency to prevent ringing.
+fn apply_crossover(left_hrtf: &mut [Complex<f32>], right_hrtf: &mut [Complex<f32>], crossover_frequency: f32) {
+    let len = left_hrtf.len();
+    let bin_width = device::SAMPLE_RATE as f32 / len as f32;
+    let low = crossover_frequency * std::f32::consts::FRAC_1_SQRT_2;
+    let high = crossover_frequency * std::f32::consts::SQRT_2;
+    for (i, (left, right)) in left_hrtf.iter_mut().zip(right_hrtf.iter_mut()).enumerate() {
+        // Spectrum of real signal is symmetric, so bins of upper half are mirrored.
+        let frequency = i.min(len - i) as f32 * bin_width;
+        let spectral_weight = ((frequency - low) / (high - low)).clamp(0.0, 1.0);
+        if spectral_weight < 1.0 {
+            let mono = (*left + *right) * 0.5;
+            *left = mono + (*left - mono) * spectral_weight;
+            *right = mono + (*right - mono) * spectral_weight;
+        }
+    }
+}
+
+/// Fetches samples of given channel of source (`None` - downmix of both channels).
+pub(in crate) fn get_raw_samples(source: &mut SpatialSource, left: &mut [Complex<f32>], right: &mut [Complex<f32>], offset: usize, channel: Option<usize>) {
     assert_eq!(left.len(), right.len());
 
-    for ((left, right), &(raw_left, _)) in left.iter_mut().zip(right.iter_mut()).zip(&source.generic().frame_samples()[offset..]) {
-        // Ignore all channels except left. Only mono sounds can be processed by HRTF.
-        let sample = Complex::new(raw_left, 0.0);
+    for ((left, right), &(raw_left, raw_right)) in left.iter_mut().zip(right.iter_mut()).zip(&source.generic().frame_samples()[offset..]) {
+        // Only mono sounds can be processed by HRTF, so stereo frames are downmixed (mono frames
+        // have equal channels, so they're unchanged) unless channels are rendered separately.
+        // Context reports non-mono spatial sources without stereo spread via diagnostics.
+        let sample = Complex::new(match channel {
+            Some(0) => raw_left,
+            Some(_) => raw_right,
+            None => (raw_left + raw_right) * 0.5,
+        }, 0.0);
         *left = sample;
         *right = sample;
     }
 }
 
-fn is_pow2(x: usize) -> bool {
-    (x & (x - 1)) == 0
-}
-
 impl HrtfRenderer {
-    /// Creates new HRTF renderer using specified HRTF sphere. See module docs for more info.
-    pub fn new(hrtf_sphere: HrtfSphere) -> Self {
-        let pad_length = get_pad_len(hrtf_sphere.length);
+    /// Average radius of human head in meters, it is used by default.
+    pub const DEFAULT_HEAD_RADIUS: f32 = 0.0875;
+
+    /// Creates new HRTF renderer using specified HRTF sphere. See module docs for more info. Returns
+    /// `HrtfError::EmptySphere` if sphere has no points or its HRTFs are empty and
+    /// `HrtfError::InvalidSampleRate` if sample rate of sphere differs from device sample rate.
+    pub fn new(hrtf_sphere: HrtfSphere) -> Result<Self, HrtfError> {
+        Self::with_block_len(hrtf_sphere, Context::HRTF_BLOCK_LEN)
+    }
+
+    /// Creates new HRTF renderer which will process samples by blocks of given length. HRTF is
+    /// interpolated between blocks, so smaller blocks gives smoother movement of sources, but
+    /// processing will be slower because of larger amount of FFTs. Default block length is
+    /// `Context::HRTF_BLOCK_LEN`.
+    ///
+    /// # Notes
+    ///
+    /// Block length must be non-zero, otherwise `HrtfError::InvalidBlockLength` is returned. Output
+    /// buffer does not have to be multiple of block length - remainder is processed as one shorter
+    /// block, but HRTF will be interpolated less smoothly over it, so multiples of block length are
+    /// preferred. Performance is best when `block_len + hrir_len - 1` is power of two.
+    pub fn with_block_len(mut hrtf_sphere: HrtfSphere, block_len: usize) -> Result<Self, HrtfError> {
+        if block_len == 0 {
+            return Err(HrtfError::InvalidBlockLength(block_len));
+        }
+
+        if hrtf_sphere.length == 0 || hrtf_sphere.points.iter().any(|pt| pt.left_hrtf.is_empty() || pt.right_hrtf.is_empty()) {
+            return Err(HrtfError::EmptySphere);
+        }
+
+        if hrtf_sphere.sample_rate != device::SAMPLE_RATE {
+            return Err(HrtfError::InvalidSampleRate(hrtf_sphere.sample_rate, device::SAMPLE_RATE));
+        }
+
+        hrtf_sphere.set_block_len(block_len);
+        // Renderer never modifies points, so precomputed data stays valid for its lifetime.
+        hrtf_sphere.update_face_data();
+
+        let pad_length = get_pad_len(block_len, hrtf_sphere.length);
 
         // Acquire default hrtf's for left and right channels.
-        let pt = hrtf_sphere.points.first().unwrap();
+        let pt = hrtf_sphere.points.first().ok_or(HrtfError::EmptySphere)?;
         let left_hrtf = pt.left_hrtf.clone();
         let right_hrtf = pt.right_hrtf.clone();
 
-        Self {
+        Ok(Self {
             hrtf_sphere,
             left_in_buffer: vec![Complex::zero(); pad_length],
             right_in_buffer: vec![Complex::zero(); pad_length],
@@ -413,72 +987,1314 @@ impl HrtfRenderer {
             ifft: FFTplanner::new(true),
             left_hrtf,
             right_hrtf,
+            itd_enabled: false,
+            crossover_frequency: None,
+            block_len,
+            reduced_interpolation_gain: 0.0,
+            head_radius: Self::DEFAULT_HEAD_RADIUS,
+            last_blend: None,
+        })
+    }
+
+    /// Returns length of block which is processed at once.
+    pub fn block_len(&self) -> usize {
+        self.block_len
+    }
+
+    /// Sets crossover frequency (in Hz) of multiband mode. In multiband mode frequencies below
+    /// crossover are localized by interaural time difference only, while frequencies above it
+    /// are processed by spectral HRTF. This mirrors duplex theory of localization - human brain
+    /// relies mostly on time difference for low frequencies and on spectral cues for high
+    /// frequencies. Reasonable value is around 1500 Hz. `None` disables multiband mode, this is
+    /// default value.
+    ///
+    /// # Notes
+    ///
+    /// Multiband mode forces ITD stage to be enabled regardless of `set_itd_enabled`, otherwise
+    /// low frequencies would have no localization cues at all.
+    pub fn set_crossover_frequency(&mut self, frequency: Option<f32>) {
+        self.crossover_frequency = frequency
+            .map(|f| f.max(0.0).min(device::SAMPLE_RATE as f32 * 0.5));
+    }
+
+    /// Returns crossover frequency of multiband mode, `None` if multiband mode is disabled.
+    pub fn crossover_frequency(&self) -> Option<f32> {
+        self.crossover_frequency
+    }
+
+    /// Sets gain (distance gain multiplied by gain of source) below which source is considered
+    /// distant and quiet enough, so HRTF is sampled only once per output buffer instead of once
+    /// per block. Panning artifacts of such sources are inaudible, while sampling of HRTF sphere is
+    /// quite heavy, so this reclaims CPU for near sources. Sources with gain above the threshold are
+    /// rendered exactly as before. Default value is 0.0 which means that all sources are rendered
+    /// with full interpolation.
+    pub fn set_reduced_interpolation_gain(&mut self, gain: f32) {
+        self.reduced_interpolation_gain = gain.max(0.0);
+    }
+
+    /// Returns gain threshold of reduced interpolation. See `set_reduced_interpolation_gain`.
+    pub fn reduced_interpolation_gain(&self) -> f32 {
+        self.reduced_interpolation_gain
+    }
+
+    /// Returns amount of HRTF interpolation steps which will be used to render source with given
+    /// gains (gain from previous and current output buffers) into buffer of given length.
+    fn interpolation_steps(&self, prev_gain: f32, new_gain: f32, buffer_len: usize) -> usize {
+        if prev_gain < self.reduced_interpolation_gain && new_gain < self.reduced_interpolation_gain {
+            1
+        } else {
+            Self::block_count(buffer_len, self.block_len).max(1)
+        }
+    }
+
+    /// Returns amount of blocks in buffer of given length, last block may be partial.
+    fn block_count(buffer_len: usize, block_len: usize) -> usize {
+        buffer_len.div_ceil(block_len)
+    }
+
+    /// Enables or disables interaural time difference (ITD) stage. Spectral HRTF convolution
+    /// under-represents difference of time of arrival of sound to each ear, ITD stage applies
+    /// explicit fractional delay to the ear that is farther from source. This improves
+    /// lateralization, especially at low frequencies. Disabled by default.
+    pub fn set_itd_enabled(&mut self, enabled: bool) {
+        self.itd_enabled = enabled;
+    }
+
+    /// Returns true if ITD stage is enabled.
+    pub fn is_itd_enabled(&self) -> bool {
+        self.itd_enabled
+    }
+
+    /// Sets radius of listener's head in meters, it allows to tune localization for particular
+    /// listener. Head radius defines interaural time difference (larger head - larger delay between
+    /// ears), HRTF sphere is scaled proportionally too. Value is clamped to [0.05; 0.15] range which
+    /// covers real human heads, default value is `DEFAULT_HEAD_RADIUS`.
+    ///
+    /// # Notes
+    ///
+    /// Spectra of HRTF sphere are not changed, they're still measured for head of the sphere's
+    /// subject. Head radius affects ITD stage only if it is enabled, see `set_itd_enabled`.
+    pub fn set_head_radius(&mut self, radius: f32) {
+        let radius = radius.clamp(0.05, 0.15);
+        let scale = radius / self.head_radius;
+        if scale != 1.0 {
+            self.hrtf_sphere.transform(Mat4::scale(Vec3::new(scale, scale, scale)));
+            self.head_radius = radius;
         }
     }
 
-    pub(in crate) fn render_source(&mut self,
-                                   source: &mut SoundSource,
-                                   listener: &Listener,
-                                   distance_model: DistanceModel,
-                                   out_buf: &mut [(f32, f32)],
-    ) {
+    /// Returns radius of listener's head in meters.
+    pub fn head_radius(&self) -> f32 {
+        self.head_radius
+    }
+
+    /// Returns interaural time difference (in samples) for left and right ears which ITD stage applies
+    /// to source with given panning (see `SpatialSource::get_panning`). Head radius of the renderer
+    /// is taken into account.
+    pub fn itd(&self, panning: f32) -> (f32, f32) {
+        get_itd(panning, self.head_radius)
+    }
+
+    /// Returns points of HRTF sphere and their weights which were used for last sampling of HRTF
+    /// sphere, `None` if sphere was not sampled yet. Useful for debugging of localization, for
+    /// example render single source and check which points were used. Sources with baked filters
+    /// and inaudible sources don't sample sphere.
+    pub fn last_blend(&self) -> Option<HrtfBlend> {
+        self.last_blend
+    }
+
+    /// Bakes HRTF filters for given directions using sphere of the renderer, so filters will match
+    /// block length of the renderer. See `HrtfSphere::bake`.
+    pub fn bake(&mut self, directions: &[Vec3]) -> Vec<HrtfFilter> {
+        self.hrtf_sphere.bake(directions)
+    }
+}
+
+impl Render for HrtfRenderer {
+    fn render_source(&mut self, source: &mut SoundSource, listener: &Listener, distance_model: &DistanceModel, out_buf: &mut [(f32, f32)]) {
         match source {
             SoundSource::Generic(_) => {
                 render_source_default(source, listener, distance_model, out_buf)
             }
             SoundSource::Spatial(spatial) => {
-                // Still very unoptimal and heavy. TODO: Optimize.
-                let pad_length = get_pad_len(self.hrtf_sphere.length);
+                // Flat (2D) part of the source, see `SpatialSource::set_spatial_blend`.
+                let blend = spatial.spatial_blend();
+                let (flat_left_gain, flat_right_gain) = flat_gains(spatial.generic());
+                let listener_gain = listener.effective_gain();
+                let flat_left_gain = flat_left_gain * (1.0 - blend) * listener_gain;
+                let flat_right_gain = flat_right_gain * (1.0 - blend) * listener_gain;
+                render_with_params(spatial.generic_mut(), flat_left_gain, flat_right_gain, out_buf);
+                spatial.generic_mut().last_left_gain = Some(flat_left_gain);
+                spatial.generic_mut().last_right_gain = Some(flat_right_gain);
 
-                // TODO: Remove this warning when there will be ability to control output buffer length
-                //       from context.
-                if !is_pow2(pad_length) {
-                    println!("rg3d-sound PERFORMANCE WARNING: Hrtf pad length is not power of two, performance will be ~2 times worse.")
-                }
+                // Still very unoptimal and heavy. TODO: Optimize.
+                let pad_length = get_pad_len(self.block_len, self.hrtf_sphere.length);
+                let block_len = self.block_len;
+                // Last block is partial if output buffer is not multiple of block length.
+                let interpolation_steps = Self::block_count(out_buf.len(), block_len);
 
                 // Overlap-save convolution with HRTF interpolation.
                 // It divides given output buffer into N parts, fetches samples from source
                 // performs convolution and writes processed samples to output buffer. Output
                 // buffer divided into parts because of HRTF interpolation which significantly
                 // reduces distortion in output signal.
-                let new_sampling_vector = spatial.get_sampling_vector(listener);
-                let new_distance_gain = spatial.get_distance_gain(listener, distance_model);
-                for step in 0..Context::HRTF_INTERPOLATION_STEPS {
-                    let next = step + 1;
-                    let out = &mut out_buf[(step * Context::HRTF_BLOCK_LEN)..(next * Context::HRTF_BLOCK_LEN)];
-
-                    let t = next as f32 / Context::HRTF_INTERPOLATION_STEPS as f32;
-                    let sampling_vector = spatial.prev_sampling_vector.lerp(&new_sampling_vector, t);
-                    self.hrtf_sphere.sample_bilinear(&mut self.left_hrtf, &mut self.right_hrtf, sampling_vector);
-
-                    let hrtf_len = self.hrtf_sphere.length - 1;
-
-                    get_raw_samples(spatial, &mut self.left_in_buffer[hrtf_len..],
-                                    &mut self.right_in_buffer[hrtf_len..], step * Context::HRTF_BLOCK_LEN);
-
-                    convolve_overlap_save(&mut self.left_in_buffer, &mut self.left_out_buffer,
-                                          &self.left_hrtf, hrtf_len, &mut spatial.prev_left_samples,
-                                          &mut self.fft, &mut self.ifft);
-
-                    convolve_overlap_save(&mut self.right_in_buffer, &mut self.right_out_buffer,
-                                          &self.right_hrtf, hrtf_len, &mut spatial.prev_right_samples,
-                                          &mut self.fft, &mut self.ifft);
-
-                    // Mix samples into output buffer with rescaling and apply distance gain.
-                    let distance_gain = math::lerpf(spatial.prev_distance_gain.unwrap_or(new_distance_gain), new_distance_gain, t);
-                    let k = distance_gain / (pad_length as f32);
-
-                    let left_payload = &self.left_in_buffer[hrtf_len..];
-                    let right_payload = &self.right_in_buffer[hrtf_len..];
-                    for ((out_left, out_right), (processed_left, processed_right))
-                    in out.iter_mut().zip(left_payload.iter().zip(right_payload)) {
-                        *out_left += processed_left.re * k;
-                        *out_right += processed_right.re * k;
+                let itd_enabled = self.itd_enabled || self.crossover_frequency.is_some();
+                if itd_enabled {
+                    // Maximum delay is when source is exactly at the side of head.
+                    let max_itd = get_itd(1.0, self.head_radius).1.ceil() as usize;
+                    for state in spatial.hrtf_channels.iter_mut() {
+                        if state.left_itd_line.max_delay() < max_itd {
+                            state.left_itd_line = FractionalDelayLine::new(max_itd);
+                            state.right_itd_line = FractionalDelayLine::new(max_itd);
+                        }
                     }
                 }
-                spatial.prev_sampling_vector = new_sampling_vector;
+                let total_len = out_buf.len() as f32;
+                // Gains of source and listener are applied together with distance gain, so all of them
+                // will be interpolated.
+                let params = spatial.spatial_params(listener, distance_model, out_buf.len());
+                let new_distance_gain = params.distance_gain *
+                    spatial.generic().smoothed_gain() * blend * listener_gain;
+
+                // Stereo source with stereo spread is rendered as two virtual mono sources, each one
+                // has its own convolution state. Channels are summed with half gain, so zero spread gives
+                // exactly the same result as downmix.
+                let channel_count = if spatial.stereo_spread() > 0.0 && spatial.generic().channel_count >= 2 {
+                    2
+                } else {
+                    1
+                };
+                // None - downmixed source.
+                let source_channel = |channel: usize| if channel_count == 2 { Some(channel) } else { None };
+
+                // Fast path for silent blocks: if convolution history and delay lines hold only
+                // silence too, output will be exact silence, so convolution can be skipped without
+                // any change in output. Only interpolation state is updated.
+                let silent = spatial.generic().is_silent() && spatial.hrtf_channels[..channel_count]
+                    .iter()
+                    .all(|state| {
+                        state.prev_left_samples.iter().chain(state.prev_right_samples.iter()).all(|&s| s == 0.0) &&
+                            (!itd_enabled || (state.left_itd_line.is_silent() && state.right_itd_line.is_silent()))
+                    });
+
+                // Fast path for inaudible sources (i.e. beyond max distance) - skip convolution
+                // entirely.
+                let prev_distance_gain = spatial.prev_distance_gain.unwrap_or(new_distance_gain);
+                if silent || (new_distance_gain < INAUDIBLE_GAIN && prev_distance_gain < INAUDIBLE_GAIN) {
+                    for channel in 0..channel_count {
+                        let (new_sampling_vector, new_panning) = match source_channel(channel) {
+                            None => (params.sampling_vector, params.panning),
+                            channel => (spatial.channel_sampling_vector(listener, channel), spatial.channel_panning(listener, channel)),
+                        };
+                        let new_itd = if itd_enabled { get_itd(new_panning, self.head_radius) } else { (0.0, 0.0) };
+                        let state = &mut spatial.hrtf_channels[channel];
+                        // Reset convolution history, so source won't produce stale tail when
+                        // it will become audible again.
+                        for sample in state.prev_left_samples.iter_mut().chain(state.prev_right_samples.iter_mut()) {
+                            *sample = 0.0;
+                        }
+                        state.prev_itd = Some(new_itd);
+                        state.prev_sampling_vector = new_sampling_vector;
+                    }
+                    spatial.prev_distance_gain = Some(new_distance_gain);
+                    return;
+                }
+
+                // Distant quiet sources use the same HRTF for every block.
+                let quality = spatial.quality();
+                let hrtf_steps = if quality == SourceQuality::Fast {
+                    1
+                } else {
+                    self.interpolation_steps(prev_distance_gain, new_distance_gain, out_buf.len())
+                };
+                let blocks_per_hrtf_step = (interpolation_steps / hrtf_steps).max(1);
+                let hrtf_len = self.hrtf_sphere.length - 1;
+                let channel_gain = 1.0 / channel_count as f32;
+                // Baked filter replaces sampling of sphere for downmixed source.
+                let baked_filter = spatial.hrtf_filter()
+                    .filter(|filter| channel_count == 1 && filter.left_hrtf.len() == pad_length)
+                    .cloned();
+
+                for channel in 0..channel_count {
+                    let (new_sampling_vector, new_panning) = match source_channel(channel) {
+                        None => (params.sampling_vector, params.panning),
+                        channel => (spatial.channel_sampling_vector(listener, channel), spatial.channel_panning(listener, channel)),
+                    };
+                    let new_itd = if itd_enabled { get_itd(new_panning, self.head_radius) } else { (0.0, 0.0) };
+                    let prev_itd = spatial.hrtf_channels[channel].prev_itd.unwrap_or(new_itd);
+                    let prev_sampling_vector = spatial.hrtf_channels[channel].prev_sampling_vector;
+
+                    for step in 0..interpolation_steps {
+                        let begin = step * block_len;
+                        let end = (begin + block_len).min(out_buf.len());
+                        let out = &mut out_buf[begin..end];
+                        // Partial block is placed at the end of the convolution buffer.
+                        let start = block_len - out.len();
+
+                        let t = end as f32 / total_len;
+                        if let Some(filter) = baked_filter.as_ref() {
+                            // Baked filter does not change, so it is enough to copy it once.
+                            if step == 0 {
+                                self.left_hrtf.clear();
+                                self.left_hrtf.extend_from_slice(&filter.left_hrtf);
+                                self.right_hrtf.clear();
+                                self.right_hrtf.extend_from_slice(&filter.right_hrtf);
+                                if let Some(crossover_frequency) = self.crossover_frequency {
+                                    apply_crossover(&mut self.left_hrtf, &mut self.right_hrtf, crossover_frequency);
+                                }
+                            }
+                        } else if step % blocks_per_hrtf_step == 0 {
+                            let hrtf_t = (step / blocks_per_hrtf_step + 1) as f32 / hrtf_steps as f32;
+                            let sampling_vector = prev_sampling_vector.lerp(&new_sampling_vector, hrtf_t);
+                            let blend = match quality {
+                                SourceQuality::High => self.hrtf_sphere.blend(sampling_vector),
+                                SourceQuality::Fast => self.hrtf_sphere.nearest_blend(sampling_vector),
+                            };
+                            if let Some(blend) = blend {
+                                self.hrtf_sphere.apply_blend(&blend, &mut self.left_hrtf, &mut self.right_hrtf);
+                                self.last_blend = Some(blend);
+                            }
+                            if let Some(crossover_frequency) = self.crossover_frequency {
+                                apply_crossover(&mut self.left_hrtf, &mut self.right_hrtf, crossover_frequency);
+                            }
+                        }
+
+                        get_raw_samples(spatial, &mut self.left_in_buffer[start + hrtf_len..],
+                                        &mut self.right_in_buffer[start + hrtf_len..], begin, source_channel(channel));
+
+                        let state = &mut spatial.hrtf_channels[channel];
+
+                        convolve_overlap_save(&mut self.left_in_buffer, &mut self.left_out_buffer,
+                                              &self.left_hrtf, hrtf_len, start, &mut state.prev_left_samples,
+                                              &mut self.fft, &mut self.ifft);
+
+                        convolve_overlap_save(&mut self.right_in_buffer, &mut self.right_out_buffer,
+                                              &self.right_hrtf, hrtf_len, start, &mut state.prev_right_samples,
+                                              &mut self.fft, &mut self.ifft);
+
+                        // Mix samples into output buffer and apply distance gain. Convolution result
+                        // is already normalized.
+                        let distance_gain = math::lerpf(prev_distance_gain, new_distance_gain, t);
+                        let k = distance_gain * channel_gain;
+
+                        let left_payload = &self.left_in_buffer[start + hrtf_len..];
+                        let right_payload = &self.right_in_buffer[start + hrtf_len..];
+                        for (i, ((out_left, out_right), (processed_left, processed_right)))
+                        in out.iter_mut().zip(left_payload.iter().zip(right_payload)).enumerate() {
+                            let mut left = processed_left.re * k;
+                            let mut right = processed_right.re * k;
+                            if itd_enabled {
+                                let t = (begin + i) as f32 / total_len;
+                                left = state.left_itd_line.feed(left, math::lerpf(prev_itd.0, new_itd.0, t));
+                                right = state.right_itd_line.feed(right, math::lerpf(prev_itd.1, new_itd.1, t));
+                            }
+                            *out_left += left;
+                            *out_right += right;
+                        }
+                    }
+
+                    let state = &mut spatial.hrtf_channels[channel];
+                    state.prev_itd = Some(new_itd);
+                    state.prev_sampling_vector = new_sampling_vector;
+                }
                 spatial.prev_distance_gain = Some(new_distance_gain);
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use byteorder::{
+        WriteBytesExt,
+        LittleEndian,
+    };
+    use std::{
+        sync::Arc,
+        time::Duration,
+    };
+    use rustfft::{
+        num_complex::Complex,
+        num_traits::Zero,
+        FFTplanner,
+    };
+    use crate::{
+        hrtf::{
+            HrtfSphere,
+            HrtfRenderer,
+            HrtfBlend,
+            HrtfError,
+            apply_crossover,
+            get_pad_len,
+            make_hrtf,
+            convolve_overlap_save,
+            ifft_normalization,
+        },
+        context::{
+            Context,
+            DistanceModel,
+            Diagnostic,
+        },
+        buffer::{DataSource, SoundBuffer},
+        listener::Listener,
+        renderer::{Render, Renderer},
+        source::{
+            SoundSource,
+            Status,
+            generic::GenericSourceBuilder,
+            spatial::{SpatialSourceBuilder, SourceQuality},
+        },
+        math::{
+            vec3::Vec3,
+            mat4::Mat4,
+            quat::Quat,
+        },
+        test_support,
+    };
+
+    // Octahedron: +X, -X, +Y, -Y, +Z, -Z.
+    const POINTS: [[f32; 3]; 6] = [
+        [1.0, 0.0, 0.0], [-1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0], [0.0, -1.0, 0.0],
+        [0.0, 0.0, 1.0], [0.0, 0.0, -1.0],
+    ];
+    const FACES: [[u32; 3]; 8] = [
+        [0, 2, 4], [2, 1, 4], [1, 3, 4], [3, 0, 4],
+        [2, 0, 5], [1, 2, 5], [3, 1, 5], [0, 3, 5],
+    ];
+
+    // Left and right interaural delays of point with given index in spheres of version 2.
+    fn delays(index: usize) -> (f32, f32) {
+        (index as f32 * 0.5, index as f32 * 0.25)
+    }
+
+    // Makes octahedral sphere in file format of given version. Each impulse response is unit
+    // impulse followed by small value which is unique for each point and ear.
+    fn make_sphere_bytes(version: u32, length: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        if version == 1 {
+            bytes.extend_from_slice(b"HRIR");
+        } else {
+            bytes.extend_from_slice(b"HRVR");
+            bytes.write_u32::<LittleEndian>(version).unwrap();
+        }
+        bytes.write_u32::<LittleEndian>(44100).unwrap();
+        bytes.write_u32::<LittleEndian>(length as u32).unwrap();
+        bytes.write_u32::<LittleEndian>(POINTS.len() as u32).unwrap();
+        bytes.write_u32::<LittleEndian>(FACES.len() as u32 * 3).unwrap();
+        for face in FACES.iter() {
+            for &index in face.iter() {
+                bytes.write_u32::<LittleEndian>(index).unwrap();
+            }
+        }
+        for (i, pos) in POINTS.iter().enumerate() {
+            for &coord in pos.iter() {
+                bytes.write_f32::<LittleEndian>(coord).unwrap();
+            }
+            for ear in 0..2 {
+                for k in 0..length {
+                    let sample = match k {
+                        0 => 1.0,
+                        1 => (i * 2 + ear) as f32 * 0.01,
+                        _ => 0.0,
+                    };
+                    bytes.write_f32::<LittleEndian>(sample).unwrap();
+                }
+            }
+            if version >= 2 {
+                let (left, right) = delays(i);
+                bytes.write_f32::<LittleEndian>(left).unwrap();
+                bytes.write_f32::<LittleEndian>(right).unwrap();
+            }
+        }
+        bytes
+    }
+
+    // Length of impulse responses which gives power of two padded length with default block length.
+    const HRIR_LEN: usize = 512;
+
+    fn make_renderer() -> HrtfRenderer {
+        HrtfRenderer::new(HrtfSphere::from_bytes(&make_sphere_bytes(1, HRIR_LEN)).unwrap()).unwrap()
+    }
+
+    fn make_spatial(samples: Vec<f32>, position: Vec3) -> SoundSource {
+        let generic = GenericSourceBuilder::new(test_support::make_buffer(samples, 44100))
+            .with_status(Status::Playing)
+            .build()
+            .unwrap();
+        SpatialSourceBuilder::new(generic)
+            .with_position(position)
+            .build_source()
+    }
+
+    fn render(renderer: &mut HrtfRenderer, source: &mut SoundSource, listener: &Listener) -> Vec<(f32, f32)> {
+        let mut buf = vec![(0.0, 0.0); Context::SAMPLES_PER_CHANNEL];
+        source.generic_mut().render(buf.len());
+        renderer.render_source(source, listener, &DistanceModel::None, &mut buf);
+        buf
+    }
+
+    fn peak_index<F: Fn(&(f32, f32)) -> f32>(buf: &[(f32, f32)], channel: F) -> usize {
+        buf.iter()
+            .enumerate()
+            .fold((0, 0.0), |(best, best_value), (i, s)| {
+                let value = channel(s).abs();
+                if value > best_value { (i, value) } else { (best, best_value) }
+            })
+            .0
+    }
+
+    #[test]
+    fn version_2_sphere_has_interaural_delays() {
+        let sphere = HrtfSphere::from_bytes(&make_sphere_bytes(2, 16)).unwrap();
+        assert_eq!(sphere.version(), 2);
+        assert_eq!(sphere.points().len(), POINTS.len());
+        for (i, pt) in sphere.points().iter().enumerate() {
+            assert_eq!((pt.left_delay(), pt.right_delay()), delays(i));
+        }
+    }
+
+    #[test]
+    fn legacy_sphere_has_zero_delays() {
+        let sphere = HrtfSphere::from_bytes(&make_sphere_bytes(1, 16)).unwrap();
+        assert_eq!(sphere.version(), 1);
+        assert!(sphere.points().iter().all(|pt| pt.left_delay() == 0.0 && pt.right_delay() == 0.0));
+    }
+
+    #[test]
+    fn transformed_sphere_is_saved_and_loaded_back() {
+        let mut sphere = HrtfSphere::from_bytes(&make_sphere_bytes(2, 16)).unwrap();
+        let rotation = Mat4::from_quat(Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 30.0f32.to_radians()));
+        sphere.transform(rotation);
+        sphere.transform(Mat4::scale(Vec3::new(2.0, 2.0, 2.0)));
+
+        let path = std::env::temp_dir().join(format!("rg3d_sound_test_saved_sphere_{}.bin", std::process::id()));
+        sphere.save(&path).unwrap();
+        let loaded = HrtfSphere::new(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.version(), sphere.version());
+        assert_eq!(loaded.sample_rate(), sphere.sample_rate());
+        assert_eq!(loaded.points().len(), POINTS.len());
+        for ((a, b), original) in loaded.points().iter().zip(sphere.points()).zip(POINTS.iter()) {
+            let transformed = Mat4::scale(Vec3::new(2.0, 2.0, 2.0))
+                .transform_vector(rotation.transform_vector(Vec3::new(original[0], original[1], original[2])));
+            assert_eq!(a.pos, b.pos);
+            assert!((a.pos - transformed).len() < 1.0e-5);
+            assert_eq!(a.left_hrir(), b.left_hrir());
+            assert_eq!(a.right_hrir(), b.right_hrir());
+            assert_eq!(a.left_hrtf(), b.left_hrtf());
+            assert_eq!((a.left_delay(), a.right_delay()), (b.left_delay(), b.right_delay()));
+        }
+        assert_eq!(loaded.faces.len(), FACES.len());
+        for (a, b) in loaded.faces.iter().zip(sphere.faces.iter()) {
+            assert_eq!((a.a, a.b, a.c), (b.a, b.b, b.c));
+        }
+    }
+
+    #[test]
+    fn sphere_from_bytes_matches_sphere_from_file() {
+        let bytes = make_sphere_bytes(2, 16);
+        let path = std::env::temp_dir().join(format!("rg3d_sound_test_sphere_{}.bin", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let from_file = HrtfSphere::new(&path);
+        std::fs::remove_file(&path).unwrap();
+        let from_file = from_file.unwrap();
+
+        for sphere in [HrtfSphere::from_bytes(&bytes).unwrap(), HrtfSphere::from_reader(bytes.as_slice()).unwrap()].iter() {
+            assert_eq!(sphere.version(), from_file.version());
+            assert_eq!(sphere.sample_rate(), from_file.sample_rate());
+            assert_eq!(sphere.points().len(), from_file.points().len());
+            for (a, b) in sphere.points().iter().zip(from_file.points()) {
+                assert_eq!(a.left_hrir(), b.left_hrir());
+                assert_eq!(a.right_hrir(), b.right_hrir());
+                assert_eq!(a.left_hrtf(), b.left_hrtf());
+                assert_eq!(a.right_hrtf(), b.right_hrtf());
+                assert_eq!((a.left_delay(), a.right_delay()), (b.left_delay(), b.right_delay()));
+            }
+            // Serialized form covers positions and faces too.
+            let mut a = Vec::new();
+            let mut b = Vec::new();
+            sphere.write_to(&mut a).unwrap();
+            from_file.write_to(&mut b).unwrap();
+            assert_eq!(a, b);
+            assert_eq!(a, bytes);
+        }
+    }
+
+    // Direct (slow) DFT of zero-padded impulse response.
+    fn dft(hrir: &[f32], pad_length: usize) -> Vec<Complex<f32>> {
+        (0..pad_length)
+            .map(|k| {
+                hrir.iter().enumerate().fold(Complex::new(0.0, 0.0), |sum, (n, &x)| {
+                    let angle = -2.0 * std::f64::consts::PI * (k * n) as f64 / pad_length as f64;
+                    sum + Complex::new((x as f64 * angle.cos()) as f32, (x as f64 * angle.sin()) as f32)
+                })
+            })
+            .collect()
+    }
+
+    fn assert_spectrum_matches(hrir: &[f32], hrtf: &[Complex<f32>]) {
+        let expected = dft(hrir, hrtf.len());
+        for (a, b) in hrtf.iter().zip(expected.iter()) {
+            assert!((a - b).norm() < 1.0e-4, "{} {}", a, b);
+        }
+    }
+
+    #[test]
+    fn raw_impulse_responses_are_kept_and_match_spectra() {
+        let length = 16;
+        let mut sphere = HrtfSphere::from_bytes(&make_sphere_bytes(1, length)).unwrap();
+        for (i, pt) in sphere.points().iter().enumerate() {
+            for (ear, hrir) in [pt.left_hrir(), pt.right_hrir()].iter().enumerate() {
+                let mut expected = vec![0.0; length];
+                expected[0] = 1.0;
+                expected[1] = (i * 2 + ear) as f32 * 0.01;
+                assert_eq!(*hrir, &expected[..]);
+            }
+            assert_eq!(pt.left_hrtf().len(), get_pad_len(Context::HRTF_BLOCK_LEN, length));
+            assert_spectrum_matches(pt.left_hrir(), pt.left_hrtf());
+            assert_spectrum_matches(pt.right_hrir(), pt.right_hrtf());
+        }
+
+        // Spectra are rebuilt from kept impulse responses for other block length.
+        sphere.set_block_len(100);
+        for pt in sphere.points() {
+            assert_eq!(pt.left_hrtf().len(), get_pad_len(100, length));
+            assert_spectrum_matches(pt.left_hrir(), pt.left_hrtf());
+            assert_spectrum_matches(pt.right_hrir(), pt.right_hrtf());
+        }
+    }
+
+    #[test]
+    fn convolution_matches_direct_convolution() {
+        let hrir = [0.5, -0.25, 0.125, 1.0];
+        let block_len = 8;
+        let hrtf_len = hrir.len() - 1;
+        let pad_length = get_pad_len(block_len, hrir.len());
+        let mut fft = FFTplanner::new(false);
+        let mut ifft = FFTplanner::new(true);
+        let hrtf = make_hrtf(&hrir, pad_length, &mut fft);
+
+        // Impulses in both blocks, second one crosses the block boundary with its tail.
+        let mut signal = vec![0.0; 2 * block_len];
+        signal[0] = 1.0;
+        signal[block_len - 2] = -2.0;
+        signal[block_len + 3] = 0.75;
+        let expected = (0..signal.len())
+            .map(|n| (0..hrir.len()).filter(|&k| k <= n).map(|k| hrir[k] * signal[n - k]).sum::<f32>())
+            .collect::<Vec<f32>>();
+
+        let mut in_buffer = vec![Complex::zero(); pad_length];
+        let mut out_buffer = vec![Complex::zero(); pad_length];
+        let mut prev_samples = Vec::new();
+        let mut output = Vec::new();
+        for block in signal.chunks(block_len) {
+            for (dest, &sample) in in_buffer[hrtf_len..].iter_mut().zip(block) {
+                *dest = Complex::new(sample, 0.0);
+            }
+            convolve_overlap_save(&mut in_buffer, &mut out_buffer, &hrtf, hrtf_len, 0,
+                                  &mut prev_samples, &mut fft, &mut ifft);
+            output.extend(in_buffer[hrtf_len..].iter().map(|c| c.re));
+        }
+
+        // Output is not scaled by length of FFT.
+        assert_eq!(ifft_normalization(pad_length), 1.0 / pad_length as f32);
+        for (a, b) in output.iter().zip(expected.iter()) {
+            assert!((a - b).abs() < 1.0e-5, "{:?} {:?}", output, expected);
+        }
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        match HrtfSphere::from_bytes(&make_sphere_bytes(HrtfSphere::LATEST_VERSION + 1, 16)) {
+            Err(HrtfError::UnsupportedVersion(version)) => assert_eq!(version, HrtfSphere::LATEST_VERSION + 1),
+            _ => panic!("expected UnsupportedVersion"),
+        }
+    }
+
+    #[test]
+    fn itd_delays_far_ear() {
+        let mut renderer = make_renderer();
+        renderer.set_itd_enabled(true);
+        let listener = Listener::new();
+        let mut samples = vec![0.0; 4096];
+        samples[10] = 1.0;
+        // Ear axis points to the left.
+        let mut source = make_spatial(samples, listener.ear_axis());
+        let buf = render(&mut renderer, &mut source, &listener);
+
+        let (left_delay, right_delay) = renderer.itd(1.0);
+        assert_eq!(left_delay, 0.0);
+        // Woodworth's formula gives ~0.66 ms for average head.
+        assert!(right_delay > 0.5e-3 * 44100.0 && right_delay < 0.8e-3 * 44100.0);
+
+        let left_onset = peak_index(&buf[..50], |s| s.0);
+        let right_onset = peak_index(&buf[..50], |s| s.1);
+        assert!(((right_onset - left_onset) as f32 - right_delay).abs() <= 1.0);
+    }
+
+    #[test]
+    fn listener_gain_scales_convolved_and_flat_parts() {
+        let render_with_gain = |gain: f32| {
+            let mut renderer = make_renderer();
+            let mut listener = Listener::new();
+            listener.set_gain(gain);
+            let mut source = make_spatial(test_support::noise(2 * Context::SAMPLES_PER_CHANNEL, 8), Vec3::new(0.3, 0.2, 0.9));
+            if let SoundSource::Spatial(spatial) = &mut source {
+                spatial.set_spatial_blend(0.5);
+            }
+            render(&mut renderer, &mut source, &listener);
+            render(&mut renderer, &mut source, &listener)
+        };
+        let full = render_with_gain(1.0);
+        let half = render_with_gain(0.5);
+        assert!(full.iter().any(|s| s.0.abs() > 0.1));
+        for (a, b) in full.iter().zip(half.iter()) {
+            assert!((a.0 * 0.5 - b.0).abs() < 1.0e-6 && (a.1 * 0.5 - b.1).abs() < 1.0e-6);
+        }
+    }
+
+    #[test]
+    fn larger_head_has_larger_itd() {
+        let listener = Listener::new();
+        let onset_difference = |radius: f32| {
+            let mut renderer = make_renderer();
+            renderer.set_itd_enabled(true);
+            renderer.set_head_radius(radius);
+            let mut samples = vec![0.0; 4096];
+            samples[10] = 1.0;
+            let mut source = make_spatial(samples, listener.ear_axis());
+            let buf = render(&mut renderer, &mut source, &listener);
+            let itd = renderer.itd(1.0).1;
+            // Sphere is scaled with head.
+            let scale = renderer.hrtf_sphere.points[0].pos.len();
+            (itd, peak_index(&buf[..100], |s| s.1) - peak_index(&buf[..100], |s| s.0), scale)
+        };
+
+        let (small_itd, small_onset, small_scale) = onset_difference(0.06);
+        let (default_itd, _, default_scale) = onset_difference(HrtfRenderer::DEFAULT_HEAD_RADIUS);
+        let (large_itd, large_onset, large_scale) = onset_difference(0.12);
+        assert!(small_itd < default_itd && default_itd < large_itd);
+        // Woodworth's formula is linear in radius.
+        assert!((large_itd / small_itd - 2.0).abs() < 1.0e-4);
+        assert!(large_onset > small_onset);
+        assert!((default_scale - 1.0).abs() < 1.0e-6);
+        assert!((large_scale / small_scale - 2.0).abs() < 1.0e-4);
+    }
+
+    #[test]
+    fn head_radius_is_clamped() {
+        let mut renderer = make_renderer();
+        renderer.set_head_radius(1.0);
+        assert_eq!(renderer.head_radius(), 0.15);
+        renderer.set_head_radius(0.0);
+        assert_eq!(renderer.head_radius(), 0.05);
+    }
+
+    #[test]
+    fn block_length_is_per_renderer() {
+        let listener = Listener::new();
+        for &block_len in [Context::HRTF_BLOCK_LEN, 216].iter() {
+            let sphere = HrtfSphere::from_bytes(&make_sphere_bytes(1, HRIR_LEN)).unwrap();
+            let mut renderer = HrtfRenderer::with_block_len(sphere, block_len).unwrap();
+            assert_eq!(renderer.block_len(), block_len);
+            assert_eq!(renderer.left_in_buffer.len(), get_pad_len(block_len, HRIR_LEN));
+
+            // Last block of each output buffer must be filled too, including buffers which are
+            // not multiple of block length.
+            for &len in [Context::SAMPLES_PER_CHANNEL, 1000].iter() {
+                let mut source = make_spatial(vec![0.5; 3 * len], Vec3::new(0.0, 0.0, 1.0));
+                let mut buf = vec![(0.0, 0.0); len];
+                source.generic_mut().render(len);
+                renderer.render_source(&mut source, &listener, &DistanceModel::None, &mut buf);
+                assert!(buf[len - block_len.min(len) / 2..].iter().all(|s| s.0 != 0.0 && s.1 != 0.0));
+            }
+        }
+        let sphere = HrtfSphere::from_bytes(&make_sphere_bytes(1, HRIR_LEN)).unwrap();
+        match HrtfRenderer::with_block_len(sphere, 0) {
+            Err(HrtfError::InvalidBlockLength(block_len)) => assert_eq!(block_len, 0),
+            _ => panic!("expected InvalidBlockLength"),
+        }
+    }
+
+    // Largest difference between neighbour samples of both channels.
+    fn max_step(buf: &[(f32, f32)]) -> f32 {
+        buf.windows(2)
+            .map(|w| (w[1].0 - w[0].0).abs().max((w[1].1 - w[0].1).abs()))
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn renderer_crossfade_is_continuous() {
+        // Renders tone with default renderer, then switches to HRTF renderer in the middle and
+        // returns output before, during and after switch.
+        let render_switch = |crossfade: Duration| {
+            let mut context = Context::default_state();
+            let generic = GenericSourceBuilder::new(test_support::make_buffer(test_support::sine(441.0, 44100, 44100), 44100))
+                .with_status(Status::Playing)
+                .with_looping(true)
+                .build()
+                .unwrap();
+            context.add_source(SpatialSourceBuilder::new(generic)
+                .with_position(Vec3::new(1.0, 0.0, 1.0))
+                .build_source());
+            let render_blocks = |context: &mut Context, count: usize| {
+                let mut output = Vec::new();
+                for _ in 0..count {
+                    let mut buf = vec![(0.0, 0.0); 512];
+                    context.render(&mut buf);
+                    output.extend(buf);
+                }
+                output
+            };
+            let before = render_blocks(&mut context, 20);
+            context.crossfade_renderer(Renderer::HrtfRenderer(make_renderer()), crossfade);
+            let switch = render_blocks(&mut context, 10);
+            assert!(!context.is_renderer_crossfading());
+            let after = render_blocks(&mut context, 10);
+            // Last sample before switch is included, so step at block boundary is checked too.
+            (max_step(&before), max_step(&[&before[before.len() - 1..], &switch[..]].concat()), max_step(&after))
+        };
+
+        let (before, switch, after) = render_switch(Duration::from_millis(50));
+        // Make sure that renderers actually differ, otherwise test proves nothing.
+        assert!((before - after).abs() > 0.005, "{} {}", before, after);
+        assert!(switch < 1.1 * before.max(after), "{} {} {}", before, switch, after);
+
+        // Instant switch gives large jump.
+        let (before, switch, after) = render_switch(Duration::from_secs(0));
+        assert!(switch > 1.5 * before.max(after), "{} {} {}", before, switch, after);
+    }
+
+    #[test]
+    fn stereo_spatial_source_is_reported() {
+        let mut context = Context::default_state();
+        context.set_renderer(Renderer::HrtfRenderer(make_renderer()));
+        let buffer = SoundBuffer::new_generic(DataSource::Raw {
+            sample_rate: 44100,
+            channel_count: 2,
+            samples: vec![0.5; 8192],
+        }).ok().unwrap();
+        // Looping, so finish of the source won't be reported.
+        let generic = GenericSourceBuilder::new(buffer)
+            .with_status(Status::Playing)
+            .with_looping(true)
+            .build()
+            .unwrap();
+        let handle = context.add_source(SpatialSourceBuilder::new(generic).build_source());
+
+        let mut buf = vec![(0.0, 0.0); Context::SAMPLES_PER_CHANNEL];
+        context.render(&mut buf);
+        let diagnostics = context.diagnostics().collect::<Vec<Diagnostic>>();
+        assert_eq!(diagnostics.len(), 1);
+        match diagnostics[0] {
+            Diagnostic::NonMonoHrtfSource { source, channel_count } => {
+                assert_eq!(source, handle);
+                assert_eq!(channel_count, 2);
+            }
+            _ => panic!("expected NonMonoHrtfSource"),
+        }
+
+        // Reported once per source.
+        context.render(&mut buf);
+        assert_eq!(context.diagnostics().count(), 0);
+    }
+
+    #[test]
+    fn left_channel_of_wide_stereo_source_is_rendered_from_left_point() {
+        let listener = Listener::new();
+        let len = Context::SAMPLES_PER_CHANNEL;
+        // Impulse in second block of left channel, so first block initializes HRTF state.
+        let mut samples = vec![0.0; 4 * len];
+        samples[2 * (len + 100)] = 1.0;
+        let buffer = SoundBuffer::new_generic(DataSource::Raw {
+            sample_rate: 44100,
+            channel_count: 2,
+            samples,
+        }).ok().unwrap();
+        let generic = GenericSourceBuilder::new(buffer)
+            .with_status(Status::Playing)
+            .build()
+            .unwrap();
+        let position = Vec3::new(0.0, 0.0, 1.0);
+        let mut stereo = SpatialSourceBuilder::new(generic)
+            .with_position(position)
+            .with_stereo_spread(2.0)
+            .build_source();
+
+        // Each channel of stereo source is summed with half gain.
+        let mut mono_samples = vec![0.0; 2 * len];
+        mono_samples[len + 100] = 0.5;
+        let left_position = position + listener.ear_axis();
+        let mut left = make_spatial(mono_samples.clone(), left_position);
+        let mut center = make_spatial(mono_samples, position);
+
+        let mut renderer = make_renderer();
+        render(&mut renderer, &mut stereo, &listener);
+        render(&mut renderer, &mut left, &listener);
+        render(&mut renderer, &mut center, &listener);
+        let stereo_out = render(&mut renderer, &mut stereo, &listener);
+        let left_out = render(&mut renderer, &mut left, &listener);
+        let center_out = render(&mut renderer, &mut center, &listener);
+
+        let difference = |a: &[(f32, f32)], b: &[(f32, f32)]| a.iter()
+            .zip(b.iter())
+            .map(|(a, b)| (a.0 - b.0).abs().max((a.1 - b.1).abs()))
+            .fold(0.0f32, f32::max);
+        assert!(stereo_out.iter().any(|s| s.0.abs() > 0.1));
+        assert!(difference(&stereo_out, &left_out) < 1.0e-4);
+        assert!(difference(&stereo_out, &center_out) > 1.0e-3);
+    }
+
+    #[test]
+    fn empty_context_renders_silence_with_both_renderers() {
+        let renderers = vec![Renderer::Default, Renderer::HrtfRenderer(make_renderer())];
+        for renderer in renderers {
+            let mut context = Context::default_state();
+            context.set_renderer(renderer);
+            for _ in 0..4 {
+                // Output buffer is overwritten, previous content does not leak.
+                let mut buf = vec![(1.0, f32::NAN); Context::SAMPLES_PER_CHANNEL];
+                context.render(&mut buf);
+                assert!(buf.iter().all(|&(left, right)| left == 0.0 && right == 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn baked_filter_matches_sampling_on_the_fly() {
+        let listener = Listener::new();
+        let position = Vec3::new(0.3, 0.2, 0.9);
+        let samples = test_support::noise(2 * Context::SAMPLES_PER_CHANNEL, 5);
+        let mut renderer = make_renderer();
+
+        let mut with_filter = |filter_direction: Option<Vec3>| {
+            let mut source = make_spatial(samples.clone(), position);
+            if let (Some(direction), SoundSource::Spatial(spatial)) = (filter_direction, &mut source) {
+                let filter = renderer.bake(&[direction]).pop().unwrap();
+                assert_eq!(filter.direction(), direction);
+                spatial.set_hrtf_filter(Some(Arc::new(filter)));
+            }
+            // Sampled HRTF is interpolated from default direction during first block, so only second
+            // one is compared.
+            render(&mut renderer, &mut source, &listener);
+            render(&mut renderer, &mut source, &listener)
+        };
+
+        let sampled = with_filter(None);
+        let sampling_vector = match make_spatial(Vec::new(), position) {
+            SoundSource::Spatial(spatial) => spatial.get_sampling_vector(&listener),
+            _ => unreachable!(),
+        };
+        let baked = with_filter(Some(sampling_vector));
+        let other = with_filter(Some(Vec3::new(-1.0, 0.0, 0.0)));
+
+        let difference = |a: &[(f32, f32)], b: &[(f32, f32)]| a.iter()
+            .zip(b.iter())
+            .map(|(a, b)| (a.0 - b.0).abs().max((a.1 - b.1).abs()))
+            .fold(0.0f32, f32::max);
+        assert!(sampled.iter().any(|s| s.0.abs() > 0.1));
+        assert!(difference(&sampled, &baked) < 1.0e-4);
+        // Filter is actually used instead of sampling by actual direction.
+        assert!(difference(&sampled, &other) > 1.0e-3);
+    }
+
+    #[test]
+    fn crossover_removes_level_difference_of_low_band_only() {
+        let len = 1024;
+        let bin_width = 44100.0 / len as f32;
+        let mut left = vec![Complex::new(1.0, 0.0); len];
+        let mut right = vec![Complex::new(0.5, 0.0); len];
+        apply_crossover(&mut left, &mut right, 1500.0);
+        for i in 0..len {
+            let frequency = i.min(len - i) as f32 * bin_width;
+            if frequency < 1500.0 * std::f32::consts::FRAC_1_SQRT_2 {
+                // Low band is localized by delay only - both ears have the same spectrum.
+                assert_eq!(left[i], Complex::new(0.75, 0.0));
+                assert_eq!(right[i], Complex::new(0.75, 0.0));
+            } else if frequency > 1500.0 * std::f32::consts::SQRT_2 {
+                // High band keeps spectral shaping.
+                assert_eq!(left[i], Complex::new(1.0, 0.0));
+                assert_eq!(right[i], Complex::new(0.5, 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn crossover_delays_far_ear() {
+        let mut renderer = make_renderer();
+        renderer.set_itd_enabled(false);
+        renderer.set_crossover_frequency(Some(1500.0));
+        let listener = Listener::new();
+        let mut samples = vec![0.0; 4096];
+        samples[10] = 1.0;
+        let mut source = make_spatial(samples, listener.ear_axis());
+        let buf = render(&mut renderer, &mut source, &listener);
+
+        let left_onset = peak_index(&buf[..50], |s| s.0);
+        let right_onset = peak_index(&buf[..50], |s| s.1);
+        assert!(((right_onset - left_onset) as f32 - renderer.itd(1.0).1).abs() <= 1.0);
+    }
+
+    #[test]
+    fn source_beyond_max_distance_skips_convolution() {
+        let mut renderer = make_renderer();
+        let listener = Listener::new();
+        let mut source = make_spatial(test_support::noise(3 * Context::SAMPLES_PER_CHANNEL, 3), Vec3::new(0.0, 0.0, 100.0));
+        if let SoundSource::Spatial(spatial) = &mut source {
+            spatial.set_max_distance(10.0);
+        }
+        let mut buf = vec![(0.0, 0.0); Context::SAMPLES_PER_CHANNEL];
+        source.generic_mut().render(buf.len());
+        renderer.render_source(&mut source, &listener, &DistanceModel::InverseDistance, &mut buf);
+        assert!(buf.iter().all(|&(left, right)| left == 0.0 && right == 0.0));
+        // Sphere is sampled only when convolution is done.
+        assert_eq!(renderer.last_blend(), None);
+
+        if let SoundSource::Spatial(spatial) = &mut source {
+            spatial.set_position(&Vec3::new(0.0, 0.0, 5.0));
+        }
+        source.generic_mut().render(buf.len());
+        renderer.render_source(&mut source, &listener, &DistanceModel::InverseDistance, &mut buf);
+        assert!(renderer.last_blend().is_some());
+    }
+
+    // Returns weight of point with given index in blend.
+    fn weight_of(blend: &HrtfBlend, index: usize) -> f32 {
+        blend.points
+            .iter()
+            .zip(blend.weights.iter())
+            .filter(|&(&point, _)| point == index)
+            .map(|(_, &weight)| weight)
+            .sum()
+    }
+
+    #[test]
+    fn direction_of_vertex_gives_full_weight_to_it() {
+        let sphere = HrtfSphere::from_bytes(&make_sphere_bytes(1, HRIR_LEN)).unwrap();
+        for (i, point) in POINTS.iter().enumerate() {
+            let dir = Vec3::new(point[0], point[1], point[2]);
+            for &blend in [sphere.blend(dir), sphere.nearest_blend(dir)].iter() {
+                let blend = blend.unwrap();
+                assert!((weight_of(&blend, i) - 1.0).abs() < 1.0e-5, "{:?}", blend);
+                for (&point, &weight) in blend.points.iter().zip(blend.weights.iter()) {
+                    assert!(point == i || weight.abs() < 1.0e-5, "{:?}", blend);
+                }
+            }
+        }
+
+        // Center of a face gives equal weights to its points.
+        let blend = sphere.blend(Vec3::new(1.0, 1.0, 1.0)).unwrap();
+        for &index in [0, 2, 4].iter() {
+            assert!((weight_of(&blend, index) - 1.0 / 3.0).abs() < 1.0e-5, "{:?}", blend);
+        }
+        assert_eq!(sphere.blend(Vec3::ZERO), Some(HrtfBlend {
+            points: [0; 3],
+            weights: [1.0, 0.0, 0.0],
+        }));
+    }
+
+    #[test]
+    fn renderer_reports_blend_of_rendered_source() {
+        let mut renderer = make_renderer();
+        let listener = Listener::new();
+        // Default listener has identity basis, so its up axis points exactly to vertex (0, 1, 0).
+        let mut source = make_spatial(test_support::noise(Context::SAMPLES_PER_CHANNEL, 5), listener.up_axis());
+        render(&mut renderer, &mut source, &listener);
+        let blend = renderer.last_blend().unwrap();
+        assert!((weight_of(&blend, 2) - 1.0).abs() < 1.0e-5, "{:?}", blend);
+    }
+
+    #[test]
+    fn silent_blocks_skip_convolution_without_changing_output() {
+        let len = Context::SAMPLES_PER_CHANNEL;
+        let listener = Listener::new();
+        // Loud block, three silent blocks and loud block again.
+        let with_gap = |gap_value: f32| {
+            let mut samples = test_support::noise(len, 21);
+            samples.extend(vec![gap_value; 3 * len]);
+            samples.extend(test_support::noise(len, 22));
+            samples
+        };
+
+        let render_blocks = |samples: Vec<f32>| {
+            let mut renderer = make_renderer();
+            let mut source = make_spatial(samples, Vec3::new(1.0, 0.0, 1.0));
+            let mut output = Vec::new();
+            let mut convolved = Vec::new();
+            for _ in 0..5 {
+                renderer.last_blend = None;
+                output.extend(render(&mut renderer, &mut source, &listener));
+                convolved.push(renderer.last_blend().is_some());
+            }
+            (output, convolved)
+        };
+
+        let (skipped, skipped_convolved) = render_blocks(with_gap(0.0));
+        // Tiny but non-zero signal in the gap forces full processing.
+        let (processed, processed_convolved) = render_blocks(with_gap(1.0e-20));
+
+        // First silent block still convolves tail of previous loud block.
+        assert_eq!(skipped_convolved, [true, true, false, false, true]);
+        assert_eq!(processed_convolved, [true; 5]);
+        assert!(skipped[2 * len..4 * len].iter().all(|&s| s == (0.0, 0.0)));
+        assert!(skipped[4 * len..].iter().any(|s| s.0.abs() > 0.1));
+        for (a, b) in skipped.iter().zip(processed.iter()) {
+            assert!((a.0 - b.0).abs() < 1.0e-6 && (a.1 - b.1).abs() < 1.0e-6);
+        }
+    }
+
+    // Makes sphere in legacy format with given points (with unit impulse responses) and without faces.
+    fn make_faceless_sphere_bytes(points: &[[f32; 3]]) -> Vec<u8> {
+        let mut bytes = b"HRIR".to_vec();
+        for &value in [44100, HRIR_LEN as u32, points.len() as u32, 0].iter() {
+            bytes.write_u32::<LittleEndian>(value).unwrap();
+        }
+        for pos in points.iter() {
+            for &coord in pos.iter() {
+                bytes.write_f32::<LittleEndian>(coord).unwrap();
+            }
+            for _ in 0..2 {
+                for k in 0..HRIR_LEN {
+                    bytes.write_f32::<LittleEndian>(if k == 0 { 1.0 } else { 0.0 }).unwrap();
+                }
+            }
+        }
+        bytes
+    }
+
+    // Evenly distributed directions (Fibonacci sphere).
+    fn directions(count: usize) -> Vec<Vec3> {
+        let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+        (0..count).map(|i| {
+            let y = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+            let r = (1.0 - y * y).sqrt();
+            let phi = golden_angle * i as f32;
+            Vec3::new(r * phi.cos(), y, r * phi.sin())
+        }).collect()
+    }
+
+    fn assert_every_direction_hits_face(sphere: &HrtfSphere) {
+        for dir in directions(2000) {
+            let blend = sphere.blend(dir).unwrap();
+            // Direction inside of face gives non-negative weights that sum to one.
+            assert!(blend.weights.iter().all(|&w| w > -1.0e-4), "{:?} {:?}", dir, blend);
+            assert!((blend.weights.iter().sum::<f32>() - 1.0).abs() < 1.0e-4, "{:?} {:?}", dir, blend);
+            let mut left = Vec::new();
+            let mut right = Vec::new();
+            sphere.sample_bilinear(&mut left, &mut right, dir);
+            assert_eq!(left.len(), sphere.points()[0].left_hrtf().len());
+            assert!(left.iter().chain(right.iter()).all(|c| c.re.is_finite() && c.im.is_finite()));
+        }
+    }
+
+    #[test]
+    fn every_direction_is_sampled_regardless_of_winding() {
+        let bytes = make_sphere_bytes(1, 16);
+        assert_every_direction_hits_face(&HrtfSphere::from_bytes(&bytes).unwrap());
+
+        // Flip winding of every other face, indices start right after 20 bytes of header.
+        let mut flipped = bytes.clone();
+        for face in (0..FACES.len()).step_by(2) {
+            let b = 20 + face * 12 + 4;
+            for k in 0..4 {
+                flipped.swap(b + k, b + 4 + k);
+            }
+        }
+        assert_ne!(flipped, bytes);
+        assert_every_direction_hits_face(&HrtfSphere::from_bytes(&flipped).unwrap());
+    }
+
+    #[test]
+    fn direction_without_face_falls_back_to_nearest_point() {
+        let sphere = HrtfSphere::from_bytes(&make_faceless_sphere_bytes(&POINTS)).unwrap();
+        for (i, pos) in POINTS.iter().enumerate() {
+            // Slightly off the point, so it is still nearest.
+            let dir = Vec3::new(pos[0] + 0.1, pos[1] + 0.2, pos[2] - 0.1);
+            let blend = sphere.blend(dir).unwrap();
+            assert_eq!(blend.points[0], i);
+            assert_eq!(blend.weights, [1.0, 0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn resampled_sphere_has_scaled_impulse_responses() {
+        let bytes = make_sphere_bytes(2, HRIR_LEN);
+        let mut sphere = HrtfSphere::from_bytes(&bytes).unwrap();
+        let original_rate = sphere.sample_rate();
+
+        for &(rate, expected_len) in [(original_rate * 2, 2 * HRIR_LEN), (original_rate / 2, HRIR_LEN / 2)].iter() {
+            let mut resampled = HrtfSphere::from_bytes(&bytes).unwrap();
+            resampled.resample(rate);
+            assert_eq!(resampled.sample_rate(), rate);
+            let ratio = rate as f32 / original_rate as f32;
+            for (i, pt) in resampled.points().iter().enumerate() {
+                assert_eq!(pt.left_hrir().len(), expected_len);
+                assert_eq!(pt.right_hrir().len(), expected_len);
+                assert_eq!(pt.left_hrtf().len(), get_pad_len(Context::HRTF_BLOCK_LEN, expected_len));
+                assert_eq!(pt.right_hrtf().len(), pt.left_hrtf().len());
+                // Delays are given in samples, so they are scaled too.
+                assert_eq!(pt.left_delay(), delays(i).0 * ratio);
+                assert_eq!(pt.right_delay(), delays(i).1 * ratio);
+                // Impulse stays at the beginning of response.
+                let peak = pt.left_hrir()
+                    .iter()
+                    .enumerate()
+                    .fold((0, 0.0), |(best, best_value), (k, &v)| if v.abs() > best_value { (k, v.abs()) } else { (best, best_value) });
+                assert_eq!(peak.0, 0);
+                assert!(pt.left_hrir().iter().all(|v| v.is_finite()));
+            }
+
+            let mut left = Vec::new();
+            let mut right = Vec::new();
+            resampled.sample_bilinear(&mut left, &mut right, Vec3::new(0.3, 0.2, 0.9));
+            assert_eq!(left.len(), get_pad_len(Context::HRTF_BLOCK_LEN, expected_len));
+            assert!(left.iter().chain(right.iter()).all(|c| c.re.is_finite() && c.im.is_finite()));
+
+            // Renderer requires sphere of context sample rate, so convert sphere back.
+            resampled.resample(original_rate);
+            assert_eq!(resampled.points()[0].left_hrir().len(), HRIR_LEN);
+            let mut renderer = HrtfRenderer::new(resampled).unwrap();
+            let mut source = make_spatial(vec![1.0; 2 * Context::SAMPLES_PER_CHANNEL], Vec3::new(0.0, 0.0, 1.0));
+            let out = render(&mut renderer, &mut source, &Listener::new());
+            assert!(out.iter().all(|s| s.0.is_finite() && s.1.is_finite()));
+            assert!(out.iter().any(|s| s.0.abs() > 0.1));
+        }
+
+        // Resampling to same rate does nothing.
+        let before = sphere.points()[0].left_hrir().to_vec();
+        sphere.resample(original_rate);
+        assert_eq!(sphere.points()[0].left_hrir(), &before[..]);
+    }
+
+    #[test]
+    fn empty_sphere_is_reported_as_error() {
+        let sphere = HrtfSphere::from_bytes(&make_faceless_sphere_bytes(&[])).unwrap();
+        match HrtfRenderer::new(sphere) {
+            Err(HrtfError::EmptySphere) => (),
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("renderer must not be created from empty sphere"),
+        }
+    }
+
+    #[test]
+    fn sphere_without_faces_renders_without_panic() {
+        let sphere = HrtfSphere::from_bytes(&make_faceless_sphere_bytes(&[[1.0, 0.0, 0.0]])).unwrap();
+        let mut renderer = HrtfRenderer::new(sphere).unwrap();
+        let listener = Listener::new();
+        let mut source = make_spatial(test_support::noise(3 * Context::SAMPLES_PER_CHANNEL, 5), Vec3::new(0.0, 0.0, 1.0));
+        let buf = render(&mut renderer, &mut source, &listener);
+        assert!(buf.iter().all(|&(left, right)| left.is_finite() && right.is_finite()));
+    }
+
+    #[test]
+    fn distant_sources_use_fewer_interpolation_steps() {
+        let mut renderer = make_renderer();
+        renderer.set_reduced_interpolation_gain(0.2);
+        let full = HrtfRenderer::block_count(Context::SAMPLES_PER_CHANNEL, Context::HRTF_BLOCK_LEN);
+        assert!(full > 1);
+        assert_eq!(renderer.interpolation_steps(0.1, 0.1, Context::SAMPLES_PER_CHANNEL), 1);
+        // Source which is moving away is still interpolated fully.
+        assert_eq!(renderer.interpolation_steps(0.5, 0.1, Context::SAMPLES_PER_CHANNEL), full);
+        assert_eq!(renderer.interpolation_steps(1.0, 1.0, Context::SAMPLES_PER_CHANNEL), full);
+    }
+
+    #[test]
+    fn reduced_interpolation_does_not_change_near_sources() {
+        let render_near = |threshold: f32| {
+            let mut renderer = make_renderer();
+            renderer.set_reduced_interpolation_gain(threshold);
+            let listener = Listener::new();
+            let mut source = make_spatial(test_support::noise(3 * Context::SAMPLES_PER_CHANNEL, 11), Vec3::new(1.0, 0.0, 1.0));
+            let mut buf = vec![(0.0, 0.0); Context::SAMPLES_PER_CHANNEL];
+            for _ in 0..2 {
+                source.generic_mut().render(buf.len());
+                renderer.render_source(&mut source, &listener, &DistanceModel::InverseDistance, &mut buf);
+            }
+            buf
+        };
+        assert_eq!(render_near(0.0), render_near(0.2));
+    }
+
+    #[test]
+    fn fast_source_uses_nearest_point_once_per_buffer() {
+        let listener = Listener::new();
+        let from = Vec3::new(0.3, 0.2, 0.9);
+        let to = Vec3::new(-0.7, 0.1, 0.4);
+        let samples = test_support::noise(2 * Context::SAMPLES_PER_CHANNEL, 17);
+
+        // Second buffer of source which was at `first` position and then moved to `second`.
+        let render_moved = |quality: SourceQuality, first: Vec3, second: Vec3| {
+            let mut renderer = make_renderer();
+            let mut source = make_spatial(samples.clone(), first);
+            if let SoundSource::Spatial(spatial) = &mut source {
+                spatial.set_quality(quality);
+            }
+            render(&mut renderer, &mut source, &listener);
+            if let SoundSource::Spatial(spatial) = &mut source {
+                spatial.set_position(&second);
+            }
+            render(&mut renderer, &mut source, &listener)
+        };
+
+        // Fast source jumps to new HRTF at once, high quality one interpolates it over buffer.
+        assert_eq!(render_moved(SourceQuality::Fast, from, to), render_moved(SourceQuality::Fast, to, to));
+        assert_ne!(render_moved(SourceQuality::High, from, to), render_moved(SourceQuality::High, to, to));
+
+        // Fast source uses HRTF of nearest point (+Z), high quality one blends neighbour points.
+        let at_point = render_moved(SourceQuality::Fast, Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(render_moved(SourceQuality::Fast, from, from), at_point);
+        assert_ne!(render_moved(SourceQuality::High, from, from), at_point);
+    }
+
+    #[test]
+    fn quality_hint_defaults_to_high() {
+        match make_spatial(Vec::new(), Vec3::new(0.0, 0.0, 1.0)) {
+            SoundSource::Spatial(spatial) => assert_eq!(spatial.quality(), SourceQuality::High),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn transform_rebuilds_face_data_and_sampling_matches() {
+        let directions = [
+            Vec3::new(0.3, 0.2, 0.9),
+            Vec3::new(-0.7, 0.1, 0.4),
+            Vec3::new(0.1, -0.8, -0.3),
+            Vec3::new(0.5, 0.5, -0.5),
+        ];
+        let mut sphere = HrtfSphere::from_bytes(&make_sphere_bytes(1, HRIR_LEN)).unwrap();
+        assert!(sphere.face_data_valid);
+        let original = directions.iter().map(|&d| sphere.blend(d).unwrap()).collect::<Vec<_>>();
+
+        let rotation = Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 30.0f32.to_radians());
+        let matrix = Mat4::from_quat(rotation);
+        sphere.points_mut();
+        assert!(!sphere.face_data_valid);
+        sphere.transform(matrix);
+        assert!(sphere.face_data_valid);
+        assert_eq!(sphere.face_data.len(), FACES.len());
+
+        // Sampling of rotated sphere in rotated direction gives the same blend.
+        let rotated = directions.iter().map(|&d| sphere.blend(matrix.transform_vector(d)).unwrap()).collect::<Vec<_>>();
+        for (a, b) in original.iter().zip(rotated.iter()) {
+            assert_eq!(a.points, b.points);
+            for (wa, wb) in a.weights.iter().zip(b.weights.iter()) {
+                assert!((wa - wb).abs() < 1.0e-4);
+            }
+        }
+
+        // Slow path (without precomputed data) gives exactly the same results.
+        sphere.points_mut();
+        let slow = directions.iter().map(|&d| sphere.blend(matrix.transform_vector(d)).unwrap()).collect::<Vec<_>>();
+        assert_eq!(rotated, slow);
+
+        // Renderer rebuilds data once on creation, render does not touch it.
+        let mut renderer = HrtfRenderer::new(sphere).unwrap();
+        assert!(renderer.hrtf_sphere.face_data_valid);
+        let mut source = make_spatial(test_support::noise(2 * Context::SAMPLES_PER_CHANNEL, 1), Vec3::new(1.0, 0.0, 1.0));
+        render(&mut renderer, &mut source, &Listener::new());
+        assert!(renderer.hrtf_sphere.face_data_valid);
+    }
+}
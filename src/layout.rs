@@ -0,0 +1,338 @@
+//! Speaker layout module.
+//!
+//! # Overview
+//!
+//! Speaker layout describes set of virtual speakers and their placement around listener. Layouts are used together
+//! with [VBAP](https://en.wikipedia.org/wiki/Vector-based_amplitude_panning) (vector base amplitude panning)
+//! panner which distributes signal of spatial source between pair of speakers that surround direction to
+//! the source, so multichannel setups (5.1, 7.1) get proper placement of sources.
+//!
+//! # Angles
+//!
+//! Azimuth of speakers is given in degrees, zero azimuth is in front of listener (look axis), positive values
+//! are at the left (ear axis of listener), negative - at the right.
+//!
+//! # Rendering
+//!
+//! `VbapRenderer` pans each spatial source across speakers of a layout and then folds speaker feeds down
+//! to output of the device using [ITU-R BS.775](https://www.itu.int/rec/R-REC-BS.775) coefficients: front
+//! speakers go to their side, center and surround speakers go to their side with `-3 dB`, LFE is dropped.
+//!
+//! ```no_run
+//! use rg3d_sound::context::Context;
+//! use rg3d_sound::layout::{VbapRenderer, SpeakerLayout};
+//! use rg3d_sound::renderer::Render;
+//!
+//! fn use_surround_panning(context: &mut Context) {
+//!     let renderer: Box<dyn Render> = Box::new(VbapRenderer::new(SpeakerLayout::Surround51));
+//!     context.set_renderer(renderer);
+//! }
+//! ```
+//!
+//! # Notes
+//!
+//! Output device of the library works with stereo only (see `device::CHANNEL_COUNT`), so there is no
+//! discrete multichannel output - speakers of a layout are virtual and their feeds are always folded down
+//! to stereo. Custom renderers can use `VbapPanner` directly to get gains of each speaker.
+
+use crate::{
+    math::{self, vec3::Vec3},
+    listener::Listener,
+    context::DistanceModel,
+    renderer::{
+        Render,
+        render_source_default,
+        render_with_params,
+        flat_gains,
+    },
+    source::SoundSource,
+};
+
+/// Single speaker of speaker layout.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Speaker {
+    /// Azimuth of speaker in degrees, see module docs.
+    pub azimuth: f32,
+    /// True if speaker is low-frequency effects (subwoofer) channel. Such speakers are not
+    /// used for panning.
+    pub is_lfe: bool,
+}
+
+const fn speaker(azimuth: f32) -> Speaker {
+    Speaker { azimuth, is_lfe: false }
+}
+
+const LFE: Speaker = Speaker { azimuth: 0.0, is_lfe: true };
+
+// Speaker order is the same as channel order in WAVE_FORMAT_EXTENSIBLE.
+const STEREO: [Speaker; 2] = [speaker(30.0), speaker(-30.0)];
+const SURROUND_51: [Speaker; 6] = [speaker(30.0), speaker(-30.0), speaker(0.0), LFE, speaker(110.0), speaker(-110.0)];
+const SURROUND_71: [Speaker; 8] = [speaker(30.0), speaker(-30.0), speaker(0.0), LFE, speaker(150.0), speaker(-150.0), speaker(90.0), speaker(-90.0)];
+
+/// Set of virtual speakers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum SpeakerLayout {
+    /// Left, right.
+    #[default]
+    Stereo,
+    /// Front left, front right, center, LFE, back left, back right.
+    Surround51,
+    /// Front left, front right, center, LFE, back left, back right, side left, side right.
+    Surround71,
+}
+
+impl SpeakerLayout {
+    /// Returns speakers of layout in order of channels of WAVE_FORMAT_EXTENSIBLE.
+    pub fn speakers(self) -> &'static [Speaker] {
+        match self {
+            SpeakerLayout::Stereo => &STEREO,
+            SpeakerLayout::Surround51 => &SURROUND_51,
+            SpeakerLayout::Surround71 => &SURROUND_71,
+        }
+    }
+
+    /// Returns total amount of speakers in layout (including LFE).
+    pub fn speaker_count(self) -> usize {
+        self.speakers().len()
+    }
+}
+
+/// Vector base amplitude panner for horizontal speaker setups. See module docs.
+pub struct VbapPanner {
+    layout: SpeakerLayout,
+    // Indices of non-LFE speakers sorted by azimuth.
+    ring: Vec<usize>,
+}
+
+impl VbapPanner {
+    /// Creates new panner for given layout.
+    pub fn new(layout: SpeakerLayout) -> Self {
+        let speakers = layout.speakers();
+        let mut ring = (0..speakers.len())
+            .filter(|&i| !speakers[i].is_lfe)
+            .collect::<Vec<_>>();
+        ring.sort_by(|&a, &b| speakers[a].azimuth.partial_cmp(&speakers[b].azimuth).unwrap());
+        Self {
+            layout,
+            ring,
+        }
+    }
+
+    /// Returns layout of panner.
+    pub fn layout(&self) -> SpeakerLayout {
+        self.layout
+    }
+
+    /// Calculates gain of each speaker of layout for given azimuth (in radians, see module docs
+    /// for conventions). `gains` must have length equal to speaker count of layout. Gains are
+    /// normalized so total power is equal to one.
+    pub fn gains(&self, azimuth: f32, gains: &mut [f32]) {
+        assert_eq!(gains.len(), self.layout.speaker_count());
+
+        for gain in gains.iter_mut() {
+            *gain = 0.0;
+        }
+
+        let speakers = self.layout.speakers();
+        let (px, py) = (azimuth.cos(), azimuth.sin());
+
+        // Find pair of adjacent speakers for which both gains are non-negative, it means that
+        // direction lies between them.
+        let mut best = None;
+        for k in 0..self.ring.len() {
+            let a = self.ring[k];
+            let b = self.ring[(k + 1) % self.ring.len()];
+            let (ax, ay) = unit(speakers[a].azimuth);
+            let (bx, by) = unit(speakers[b].azimuth);
+            let det = ax * by - ay * bx;
+            if det.abs() < f32::EPSILON {
+                continue;
+            }
+            // p = ga * a + gb * b
+            let ga = (px * by - py * bx) / det;
+            let gb = (ax * py - ay * px) / det;
+            if ga >= -1.0e-5 && gb >= -1.0e-5 {
+                best = Some((a, ga.max(0.0), b, gb.max(0.0)));
+                break;
+            }
+        }
+
+        match best {
+            Some((a, ga, b, gb)) => {
+                let norm = (ga * ga + gb * gb).sqrt();
+                if norm > 0.0 {
+                    gains[a] = ga / norm;
+                    gains[b] = gb / norm;
+                }
+            }
+            None => {
+                // Direction is outside of any pair (i.e. behind listener with stereo layout),
+                // use the nearest speaker.
+                let nearest = self.ring.iter()
+                    .map(|&i| {
+                        let (x, y) = unit(speakers[i].azimuth);
+                        (i, x * px + y * py)
+                    })
+                    .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+                if let Some((i, _)) = nearest {
+                    gains[i] = 1.0;
+                }
+            }
+        }
+    }
+
+    /// Calculates gain of each speaker of layout for source at given position. If source is at the
+    /// same point as listener, power is distributed evenly between all non-LFE speakers.
+    pub fn gains_for_position(&self, listener: &Listener, position: Vec3, gains: &mut [f32]) {
+        match (position - listener.position()).normalized() {
+            Some(dir) => {
+                let azimuth = dir.dot(&listener.ear_axis()).atan2(dir.dot(&listener.look_axis()));
+                self.gains(azimuth, gains)
+            }
+            None => {
+                let speakers = self.layout.speakers();
+                let gain = 1.0 / (self.ring.len() as f32).sqrt();
+                for (g, speaker) in gains.iter_mut().zip(speakers) {
+                    *g = if speaker.is_lfe { 0.0 } else { gain };
+                }
+            }
+        }
+    }
+}
+
+fn unit(azimuth_degrees: f32) -> (f32, f32) {
+    let (sin, cos) = azimuth_degrees.to_radians().sin_cos();
+    (cos, sin)
+}
+
+/// Pans spatial sources across speakers of a layout and folds them down to stereo, see module docs.
+pub struct VbapRenderer {
+    panner: VbapPanner,
+    gains: Vec<f32>,
+}
+
+impl VbapRenderer {
+    /// Creates new renderer for given layout.
+    pub fn new(layout: SpeakerLayout) -> Self {
+        Self {
+            panner: VbapPanner::new(layout),
+            gains: vec![0.0; layout.speaker_count()],
+        }
+    }
+
+    /// Returns layout of renderer.
+    pub fn layout(&self) -> SpeakerLayout {
+        self.panner.layout()
+    }
+
+    /// Returns gains of each speaker of layout calculated for last rendered spatial source.
+    pub fn speaker_gains(&self) -> &[f32] {
+        &self.gains
+    }
+}
+
+/// Returns gains of left and right output channels for given speaker, see module docs.
+pub fn fold_down(speaker: &Speaker) -> (f32, f32) {
+    if speaker.is_lfe {
+        (0.0, 0.0)
+    } else if speaker.azimuth.abs() < 1.0e-3 {
+        (std::f32::consts::FRAC_1_SQRT_2, std::f32::consts::FRAC_1_SQRT_2)
+    } else {
+        let gain = if speaker.azimuth.abs() <= 45.0 { 1.0 } else { std::f32::consts::FRAC_1_SQRT_2 };
+        if speaker.azimuth > 0.0 { (gain, 0.0) } else { (0.0, gain) }
+    }
+}
+
+impl Render for VbapRenderer {
+    fn render_source(&mut self, source: &mut SoundSource, listener: &Listener, distance_model: &DistanceModel, out_buf: &mut [(f32, f32)]) {
+        match source {
+            SoundSource::Generic(_) => render_source_default(source, listener, distance_model, out_buf),
+            SoundSource::Spatial(spatial) => {
+                self.panner.gains_for_position(listener, spatial.position(), &mut self.gains);
+                let (left, right) = self.panner.layout().speakers().iter()
+                    .zip(self.gains.iter())
+                    .fold((0.0, 0.0), |(left, right), (speaker, gain)| {
+                        let (speaker_left, speaker_right) = fold_down(speaker);
+                        (left + gain * speaker_left, right + gain * speaker_right)
+                    });
+                let gain = spatial.get_distance_gain(listener, distance_model) * spatial.generic().smoothed_gain();
+                let blend = spatial.spatial_blend();
+                let (flat_left_gain, flat_right_gain) = flat_gains(spatial.generic());
+                let left_gain = math::lerpf(flat_left_gain, gain * left, blend) * listener.effective_gain();
+                let right_gain = math::lerpf(flat_right_gain, gain * right, blend) * listener.effective_gain();
+                render_with_params(spatial.generic_mut(), left_gain, right_gain, out_buf);
+                spatial.generic_mut().last_left_gain = Some(left_gain);
+                spatial.generic_mut().last_right_gain = Some(right_gain);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rg3d_core::math::vec3::Vec3;
+    use crate::{
+        context::DistanceModel,
+        layout::{SpeakerLayout, VbapPanner, VbapRenderer},
+        listener::Listener,
+        renderer::Render,
+        source::{
+            SoundSource,
+            Status,
+            generic::GenericSourceBuilder,
+            spatial::SpatialSourceBuilder,
+        },
+        test_support,
+    };
+
+    // Front left, front right, center, LFE, back left, back right.
+    const LEFT_SPEAKERS: [usize; 2] = [0, 4];
+    const RIGHT_SPEAKERS: [usize; 2] = [1, 5];
+
+    fn make_listener() -> Listener {
+        let mut listener = Listener::new();
+        // Look along +Z, right is +X.
+        listener.set_orientation_lh(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0));
+        listener
+    }
+
+    #[test]
+    fn source_at_right_feeds_right_speakers_of_51() {
+        let listener = make_listener();
+        let panner = VbapPanner::new(SpeakerLayout::Surround51);
+        let mut gains = [0.0; 6];
+        panner.gains_for_position(&listener, Vec3::new(1.0, 0.0, 0.0), &mut gains);
+        for &i in LEFT_SPEAKERS.iter() {
+            assert_eq!(gains[i], 0.0);
+        }
+        assert!(RIGHT_SPEAKERS.iter().all(|&i| gains[i] > 0.0));
+        assert_eq!(gains[3], 0.0);
+        let power = gains.iter().map(|g| g * g).sum::<f32>();
+        assert!((power - 1.0).abs() < 1.0e-5);
+
+        // Source in front of listener is played by center speaker only.
+        panner.gains_for_position(&listener, Vec3::new(0.0, 0.0, 1.0), &mut gains);
+        assert!((gains[2] - 1.0).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn renderer_folds_right_speakers_to_right_channel() {
+        let listener = make_listener();
+        let generic = GenericSourceBuilder::new(test_support::make_buffer(vec![1.0; 4096], 44100))
+            .with_status(Status::Playing)
+            .build()
+            .unwrap();
+        let mut source = SoundSource::Spatial(SpatialSourceBuilder::new(generic)
+            .with_position(Vec3::new(1.0, 0.0, 0.0))
+            .build());
+        let mut renderer = VbapRenderer::new(SpeakerLayout::Surround51);
+        let mut buf = vec![(0.0, 0.0); 512];
+        source.generic_mut().render(buf.len());
+        renderer.render_source(&mut source, &listener, &DistanceModel::None, &mut buf);
+
+        assert!(RIGHT_SPEAKERS.iter().all(|&i| renderer.speaker_gains()[i] > 0.0));
+        let (left, right) = buf[256];
+        assert_eq!(left, 0.0);
+        assert!(right > 0.5, "right = {}", right);
+    }
+}
@@ -74,6 +74,7 @@ extern crate byteorder;
 extern crate rg3d_core;
 extern crate rustfft;
 extern crate lewton;
+extern crate minimp3;
 
 pub mod error;
 pub mod buffer;
@@ -82,12 +83,19 @@ pub mod context;
 pub mod listener;
 pub mod hrtf;
 pub mod renderer;
+pub mod ambisonics;
+pub mod layout;
 pub mod effects;
 pub mod dsp;
+pub mod automation;
+pub mod command;
+#[cfg(any(test, feature = "test_support"))]
+pub mod test_support;
 
 // Reexport some modules because there some types of them in public API.
 pub use rg3d_core::math as math;
 pub use rg3d_core::pool as pool;
 
 mod decoder;
-mod device;
\ No newline at end of file
+mod device;
+mod util;
@@ -2,54 +2,146 @@
 //!
 //! # Overview
 //!
-//! Engine has only one listener which can be positioned and oriented in space. Listener defined as coordinate
+//! Engine has one main listener which can be positioned and oriented in space. Listener defined as coordinate
 //! system which is used to compute spatial properties of sound sources.
+//!
+//! # Multiple listeners
+//!
+//! Local multiplayer games (split-screen) need more than one listener. Additional listeners can be added
+//! to context using `Context::add_listener`, each listener has its own submix to which spatial sources
+//! are rendered relative to that listener. Output of device is average of submixes of all listeners.
+//! Submix of each additional listener can be fetched using `Context::listener_output`.
+//!
+//! Additional listeners always use default (panning) renderer, HRTF and custom renderers are applied
+//! only for main listener, because they keep per-source state which cannot be shared between listeners.
 
 use rg3d_core::{
     math::{
         vec3::Vec3,
-        mat3::Mat3
+        mat3::Mat3,
+        mat4::Mat4,
     },
     visitor::{
         Visit,
         VisitResult,
         Visitor
     },
+    pool::Handle,
+};
+use crate::{
+    source::SoundSource,
+    context::DistanceModel,
+    renderer,
 };
 
 /// See module docs.
 pub struct Listener {
     basis: Mat3,
-    position: Vec3
+    position: Vec3,
+    rear_attenuation: f32,
+    gain: f32,
+    muted: bool,
+    // Submix of additional listener, unused for main listener.
+    pub(in crate) submix: Vec<(f32, f32)>,
+    // Last (left, right) gains of each source rendered for additional listener, indexed by slot of
+    // source in pool. Handle is stored too, so source that was added in place of removed one won't
+    // inherit its gains. Main listener stores them in sources.
+    source_gains: Vec<(Handle<SoundSource>, Option<f32>, Option<f32>)>,
 }
 
 impl Listener {
     pub(in crate) fn new() -> Self {
         Self {
             basis: Default::default(),
-            position: Default::default()
+            position: Default::default(),
+            rear_attenuation: 1.0,
+            gain: 1.0,
+            muted: false,
+            submix: Default::default(),
+            source_gains: Default::default(),
         }
     }
 
-    /// Sets new basis from given vectors in left-handed coordinate system.
+    /// Clears submix of additional listener.
+    pub(in crate) fn begin_submix(&mut self, len: usize) {
+        self.submix.clear();
+        self.submix.resize(len, (0.0, 0.0));
+    }
+
+    /// Makes sure that there is place for per-source state of sources of pool with given capacity.
+    /// Called by context on game thread when source or listener is added, so render pass does not
+    /// allocate.
+    pub(in crate) fn reserve_sources(&mut self, capacity: usize) {
+        if self.source_gains.len() < capacity {
+            self.source_gains.resize(capacity, (Handle::NONE, None, None));
+        }
+    }
+
+    /// Renders source to submix of additional listener. Interpolation state of source is swapped
+    /// with listener's one so render for main listener is not affected.
+    pub(in crate) fn render_to_submix(&mut self, handle: Handle<SoundSource>, source: &mut SoundSource, distance_model: &DistanceModel) {
+        let mut submix = std::mem::take(&mut self.submix);
+        let index = handle.index() as usize;
+        let (left, right) = match self.source_gains.get(index) {
+            Some(&(owner, left, right)) if owner == handle => (left, right),
+            _ => (None, None),
+        };
+        let generic = source.generic_mut();
+        let main_left = std::mem::replace(&mut generic.last_left_gain, left);
+        let main_right = std::mem::replace(&mut generic.last_right_gain, right);
+        renderer::render_source_unthrottled(source, self, distance_model, &mut submix);
+        let generic = source.generic_mut();
+        let left = std::mem::replace(&mut generic.last_left_gain, main_left);
+        let right = std::mem::replace(&mut generic.last_right_gain, main_right);
+        if let Some(entry) = self.source_gains.get_mut(index) {
+            *entry = (handle, left, right);
+        }
+        self.submix = submix;
+    }
+
+    /// Sets new basis from given vectors in left-handed coordinate system. Use this method if your
+    /// application uses left-handed coordinate system, side (ear) axis of listener will be built
+    /// so sources at the right of listener will be heard at the right.
     /// See `set_basis` for more info.
+    ///
+    /// ```
+    /// use rg3d_sound::listener::Listener;
+    /// use rg3d_sound::math::vec3::Vec3;
+    ///
+    /// fn orient_listener(listener: &mut Listener) {
+    ///     // Look along +Z, right is +X in left-handed coordinate system.
+    ///     listener.set_orientation_lh(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0));
+    /// }
+    /// ```
     pub fn set_orientation_lh(&mut self, look: Vec3, up: Vec3) {
         self.basis = Mat3::from_vectors(look.cross(&up), up, look)
     }
 
-    /// Sets new basis from given vectors in right-handed coordinate system.
+    /// Sets new basis from given vectors in right-handed coordinate system. Use this method if your
+    /// application uses right-handed coordinate system, side (ear) axis of listener will be built
+    /// so sources at the right of listener will be heard at the right.
     /// See `set_basis` for more info.
+    ///
+    /// ```
+    /// use rg3d_sound::listener::Listener;
+    /// use rg3d_sound::math::vec3::Vec3;
+    ///
+    /// fn orient_listener(listener: &mut Listener) {
+    ///     // Look along -Z, right is +X in right-handed coordinate system.
+    ///     listener.set_orientation_rh(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0));
+    /// }
+    /// ```
     pub fn set_orientation_rh(&mut self, look: Vec3, up: Vec3) {
         self.basis = Mat3::from_vectors(up.cross(&look), up, look)
     }
 
-    /// Sets arbitrary basis. Basis defines orientation of the listener in space.
-    /// In your application you can take basis of camera in world coordinates and
-    /// pass it to this method. If you using HRTF, make sure your basis is in
-    /// right-handed coordinate system! You can make fake right-handed basis from
-    /// left handed, by inverting Z axis. It is fake because it will work only for
-    /// positions (engine interested in positions only), but not for rotation, shear
-    /// etc.
+    /// Sets arbitrary basis. Basis defines orientation of the listener in space and
+    /// it is taken from rotation part of given matrix, translation part is ignored.
+    /// In your application you can take world transform of camera and pass it to
+    /// this method. If you using HRTF, make sure your basis is in right-handed
+    /// coordinate system! You can make fake right-handed basis from left handed,
+    /// by inverting Z axis. It is fake because it will work only for positions
+    /// (engine interested in positions only), but not for rotation, shear etc.
     ///
     /// # Notes
     ///
@@ -57,17 +149,70 @@ impl Listener {
     ///
     /// ```
     /// use rg3d_sound::listener::Listener;
-    /// use rg3d_sound::math::mat3::Mat3;
+    /// use rg3d_sound::math::mat4::Mat4;
     /// use rg3d_sound::math::vec3::Vec3;
     /// use rg3d_sound::math::quat::Quat;
     ///
     /// fn orient_listener(listener: &mut Listener) {
-    ///     let basis = Mat3::from_quat(Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 45.0f32.to_radians()));
+    ///     let basis = Mat4::from_quat(Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 45.0f32.to_radians()));
     ///     listener.set_basis(basis);
     /// }
     /// ```
-    pub fn set_basis(&mut self, matrix: Mat3) {
-        self.basis = matrix;
+    pub fn set_basis(&mut self, matrix: Mat4) {
+        self.basis = matrix.basis();
+    }
+
+    /// Sets position and orientation of listener from given transform matrix in one call. Columns of
+    /// matrix are side (ear) axis, up axis, look axis and position, so this is the same as calling
+    /// `set_basis` and `set_position` with translation part of matrix. Axes are normalized,
+    /// so scale of transform is ignored.
+    ///
+    /// # Handedness
+    ///
+    /// Side axis must point to the left of the listener, this is what `set_orientation_rh` builds for
+    /// right-handed coordinate system (`side = up x look`). If your camera transform has side axis that
+    /// points to the right (typical for left-handed systems), negate it or use `set_orientation_lh`
+    /// together with `set_position` instead.
+    ///
+    /// ```
+    /// use rg3d_sound::listener::Listener;
+    /// use rg3d_sound::math::mat4::Mat4;
+    ///
+    /// fn sync_listener(listener: &mut Listener, camera_world_transform: Mat4) {
+    ///     listener.set_transform(camera_world_transform);
+    /// }
+    /// ```
+    pub fn set_transform(&mut self, matrix: Mat4) {
+        // Layout of basis of Mat4 matches layout of listener's basis (the same as `set_basis`), only
+        // scale has to be removed.
+        let basis = matrix.basis();
+        let normalize = |v: Vec3| v.normalized().unwrap_or(v);
+        let (side, up, look) = (normalize(basis.side()), normalize(basis.up()), normalize(basis.look()));
+        self.basis = Mat3 {
+            f: [
+                side.x, side.y, side.z,
+                up.x, up.y, up.z,
+                look.x, look.y, look.z,
+            ]
+        };
+        self.position = Vec3::new(matrix.f[12], matrix.f[13], matrix.f[14]);
+    }
+
+    /// Returns transform matrix of listener, its columns are side, up and look axes and position.
+    /// See `set_transform` for more info.
+    pub fn transform(&self) -> Mat4 {
+        let side = self.basis.side();
+        let up = self.basis.up();
+        let look = self.basis.look();
+        let position = self.position;
+        Mat4 {
+            f: [
+                side.x, side.y, side.z, 0.0,
+                up.x, up.y, up.z, 0.0,
+                look.x, look.y, look.z, 0.0,
+                position.x, position.y, position.z, 1.0,
+            ]
+        }
     }
 
     /// Returns shared reference to current basis.
@@ -85,6 +230,70 @@ impl Listener {
         self.position
     }
 
+    /// Sets gain of sources which are directly behind the listener. Sources in rear hemisphere are
+    /// attenuated smoothly: gain goes from 1.0 at the side of listener to `gain` directly behind it.
+    /// This is stylistic effect which is applied on top of distance attenuation, it is independent
+    /// from natural front/back coloration of HRTF. Default value is 1.0 (no extra attenuation).
+    pub fn set_rear_attenuation(&mut self, gain: f32) {
+        self.rear_attenuation = gain.clamp(0.0, 1.0);
+    }
+
+    /// Returns gain of sources which are directly behind the listener.
+    pub fn rear_attenuation(&self) -> f32 {
+        self.rear_attenuation
+    }
+
+    /// Sets gain of everything that listener hears. With multiple listeners this allows to have
+    /// independent volume for each player in split-screen game, for single listener this is an
+    /// alternative of master gain which is tied to the listener. Default value is 1.0.
+    ///
+    /// # Notes
+    ///
+    /// Gain is applied to final gains of each source which listener hears - spatial and generic
+    /// (2D) ones, including flat part of spatial sources (see `SpatialSource::set_spatial_blend`).
+    /// Inputs and sends of effects are attenuated too. Custom renderers should apply it as well, see
+    /// `effective_gain`.
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain.max(0.0);
+    }
+
+    /// Returns gain of the listener.
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    /// Mutes or unmutes the listener. Muted listener hears nothing, this is handy to
+    /// silence inactive viewport in split-screen game. Gain of listener is preserved.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Returns true if listener is muted.
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Returns gain of the listener with respect to mute, this is what renderers apply to final gains
+    /// of sources.
+    pub fn effective_gain(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.gain
+        }
+    }
+
+    /// Returns extra attenuation for source which is at given direction (in world space, normalized)
+    /// from listener.
+    pub(in crate) fn rear_gain(&self, direction: Vec3) -> f32 {
+        let front = direction.dot(&self.look_axis());
+        if front < 0.0 {
+            1.0 + (self.rear_attenuation - 1.0) * (-front).min(1.0)
+        } else {
+            1.0
+        }
+    }
+
     /// Returns up axis from basis.
     pub fn up_axis(&self) -> Vec3 {
         self.basis.up()
@@ -107,7 +316,204 @@ impl Visit for Listener {
 
         self.basis.visit("Basis", visitor)?;
         self.position.visit("Position", visitor)?;
+        if self.gain.visit("Gain", visitor).is_err() && visitor.is_reading() {
+            // Older versions have no listener gain, use default.
+            self.gain = 1.0;
+        }
+        if self.muted.visit("Muted", visitor).is_err() && visitor.is_reading() {
+            self.muted = false;
+        }
 
         visitor.leave_region()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use rg3d_core::visitor::Visit;
+    use crate::{
+        listener::Listener,
+        context::DistanceModel,
+        renderer::render_source_default,
+        source::{
+            SoundSource,
+            Status,
+            generic::GenericSourceBuilder,
+            spatial::{SpatialSource, SpatialSourceBuilder},
+        },
+        math::{
+            mat4::Mat4,
+            quat::Quat,
+            vec3::Vec3,
+        },
+        test_support,
+    };
+
+    fn make_spatial(position: Vec3) -> SpatialSource {
+        let generic = GenericSourceBuilder::new(test_support::make_buffer(vec![1.0; 64], 44100))
+            .build()
+            .unwrap();
+        SpatialSourceBuilder::new(generic)
+            .with_position(position)
+            .build()
+    }
+
+    // Positive panning is left, negative is right.
+    fn is_at_right(listener: &Listener, position: Vec3) -> bool {
+        make_spatial(position).get_panning(listener) < -0.9
+    }
+
+    #[test]
+    fn right_stays_right_in_left_handed_system() {
+        let mut listener = Listener::new();
+        // Look along +Z, right is +X.
+        listener.set_orientation_lh(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(is_at_right(&listener, Vec3::new(1.0, 0.0, 0.0)));
+        assert!(!is_at_right(&listener, Vec3::new(-1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn right_stays_right_in_right_handed_system() {
+        let mut listener = Listener::new();
+        // Look along -Z, right is +X.
+        listener.set_orientation_rh(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(is_at_right(&listener, Vec3::new(1.0, 0.0, 0.0)));
+        assert!(!is_at_right(&listener, Vec3::new(-1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn set_basis_takes_rotation_part_of_matrix() {
+        let mut listener = Listener::new();
+        let mut matrix = Mat4::from_quat(Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 90.0f32.to_radians()));
+        matrix.f[12] = 10.0;
+        listener.set_basis(matrix);
+        assert_eq!(listener.position(), Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(listener.look_axis(), matrix.look());
+        assert_eq!(listener.ear_axis(), matrix.side());
+    }
+
+    fn assert_vec_eq(a: Vec3, b: Vec3) {
+        assert!((a - b).len() < 1.0e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn transform_round_trips_and_matches_decomposed_setters() {
+        let rotation = Mat4::from_quat(Quat::from_axis_angle(Vec3::new(1.0, 2.0, 3.0).normalized().unwrap(), 40.0f32.to_radians()));
+        let mut matrix = rotation;
+        matrix.f[12] = 1.0;
+        matrix.f[13] = -2.0;
+        matrix.f[14] = 3.0;
+
+        let mut listener = Listener::new();
+        listener.set_transform(matrix);
+        assert_eq!(listener.position(), Vec3::new(1.0, -2.0, 3.0));
+        assert_vec_eq(listener.ear_axis(), matrix.side());
+        assert_vec_eq(listener.look_axis(), matrix.look());
+        for (a, b) in listener.transform().f.iter().zip(matrix.f.iter()) {
+            assert!((a - b).abs() < 1.0e-5);
+        }
+
+        // Same as separate setters, so sources are localized the same way.
+        let mut decomposed = Listener::new();
+        decomposed.set_basis(rotation);
+        decomposed.set_position(Vec3::new(1.0, -2.0, 3.0));
+        let source = make_spatial(Vec3::new(4.0, 1.0, -5.0));
+        assert_vec_eq(source.get_sampling_vector(&listener), source.get_sampling_vector(&decomposed));
+        assert!((source.get_panning(&listener) - source.get_panning(&decomposed)).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn scale_of_transform_is_ignored() {
+        let rotation = Mat4::from_quat(Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 30.0f32.to_radians()));
+        let mut scaled = rotation;
+        for i in 0..12 {
+            scaled.f[i] *= 3.0;
+        }
+        let mut listener = Listener::new();
+        listener.set_transform(scaled);
+        assert_vec_eq(listener.ear_axis(), rotation.side());
+        assert_vec_eq(listener.look_axis(), rotation.look());
+    }
+
+    // Renders generic source, fully spatialized source and half-blended one (see spatial blend)
+    // for given listener.
+    fn render_all(listener: &Listener) -> Vec<Vec<(f32, f32)>> {
+        let make_generic = || GenericSourceBuilder::new(test_support::make_buffer(test_support::noise(512, 1), 44100))
+            .with_status(Status::Playing)
+            .build()
+            .unwrap();
+        let mut sources = [
+            SoundSource::Generic(make_generic()),
+            SoundSource::Spatial(SpatialSourceBuilder::new(make_generic())
+                .with_position(Vec3::new(2.0, 0.0, 1.0))
+                .build()),
+            SoundSource::Spatial(SpatialSourceBuilder::new(make_generic())
+                .with_position(Vec3::new(-2.0, 0.0, 1.0))
+                .with_spatial_blend(0.5)
+                .build()),
+        ];
+        sources.iter_mut()
+            .map(|source| {
+                let mut buf = vec![(0.0, 0.0); 512];
+                source.generic_mut().render(buf.len());
+                render_source_default(source, listener, &DistanceModel::InverseDistance, &mut buf);
+                buf
+            })
+            .collect()
+    }
+
+    #[test]
+    fn listener_gain_scales_everything_it_hears() {
+        let full = render_all(&Listener::new());
+        let mut listener = Listener::new();
+        listener.set_gain(0.5);
+        for (full, half) in full.iter().zip(render_all(&listener).iter()) {
+            assert!(full.iter().any(|s| s.0 != 0.0 && s.1 != 0.0));
+            for (a, b) in full.iter().zip(half.iter()) {
+                assert_eq!((a.0 * 0.5, a.1 * 0.5), *b);
+            }
+        }
+    }
+
+    #[test]
+    fn muted_listener_hears_nothing_and_keeps_gain() {
+        let mut listener = Listener::new();
+        listener.set_gain(0.5);
+        listener.set_muted(true);
+        assert!(listener.is_muted());
+        assert_eq!(listener.gain(), 0.5);
+        for buf in render_all(&listener) {
+            assert!(buf.iter().all(|&s| s == (0.0, 0.0)));
+        }
+    }
+
+    #[test]
+    fn gain_and_mute_are_visited() {
+        let mut visitor = test_support::reload(|visitor| {
+            let mut listener = Listener::new();
+            listener.set_gain(0.25);
+            listener.set_muted(true);
+            listener.visit("Listener", visitor)
+        });
+        let mut listener = Listener::new();
+        listener.visit("Listener", &mut visitor).unwrap();
+        assert_eq!(listener.gain(), 0.25);
+        assert!(listener.is_muted());
+    }
+
+    #[test]
+    fn missing_gain_and_mute_fall_back_to_defaults() {
+        let mut visitor = test_support::reload(|visitor| {
+            let mut listener = Listener::new();
+            visitor.enter_region("Listener")?;
+            listener.basis.visit("Basis", visitor)?;
+            listener.position.visit("Position", visitor)?;
+            visitor.leave_region()
+        });
+        let mut listener = Listener::new();
+        listener.set_gain(0.25);
+        listener.set_muted(true);
+        listener.visit("Listener", &mut visitor).unwrap();
+        assert_eq!(listener.gain(), 1.0);
+        assert!(!listener.is_muted());
+    }
+}
@@ -26,17 +26,64 @@
 //! }
 //!
 //! ```
+//!
+//! # Sharing buffers
+//!
+//! Sources hold only a reference to buffer (`Arc<Mutex<SoundBuffer>>`) and their own playback cursor,
+//! so single generic buffer can back any number of sources without copying samples. This is the
+//! preferred way of playing same sound effect many times:
+//!
+//! ```no_run
+//! use std::sync::{Arc, Mutex};
+//! use rg3d_sound::buffer::SoundBuffer;
+//! use rg3d_sound::source::Status;
+//! use rg3d_sound::source::generic::GenericSourceBuilder;
+//! use rg3d_sound::context::Context;
+//!
+//! fn play_many(context: &mut Context, buffer: Arc<Mutex<SoundBuffer>>) {
+//!     for _ in 0..100 {
+//!         // Only reference counter of the buffer is incremented here.
+//!         let source = GenericSourceBuilder::new(buffer.clone())
+//!             .with_status(Status::Playing)
+//!             .with_play_once(true)
+//!             .build_source()
+//!             .unwrap();
+//!         context.add_source(source);
+//!     }
+//! }
+//! ```
+//!
+//! Streaming buffers cannot be shared, because they hold decoding state, attempt to create second source
+//! for streaming buffer will fail with `SoundError::StreamingBufferAlreadyInUse`.
+//!
+//! # Channels
+//!
+//! Source always produces stereo frames, buffers with any channel count can be attached to it. Rules are
+//! simple: mono buffers are upmixed - sample is duplicated to both channels, stereo buffers are passed through
+//! as is, for buffers with more than two channels only first two channels are used. Renderers that can process
+//! only mono signal (HRTF) downmix stereo frames as `(left + right) / 2`, unless spatial source has stereo
+//! spread (see `SpatialSource::set_stereo_spread`). Channel count of buffer can be fetched using
+//! `SoundBuffer::channel_count`.
 
 use std::{
     sync::{
         Arc,
         Mutex,
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+    },
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
     },
-    time::Duration,
 };
 use crate::{
     buffer::{
         SoundBuffer,
+        generic::{GenericBuffer, ResampleQuality, SINC_HALF_WIDTH},
         streaming::StreamingBuffer,
     },
     source::{
@@ -44,13 +91,200 @@ use crate::{
         SoundSource
     },
     error::SoundError,
+    dsp::{
+        self,
+        Random,
+        filters::{
+            Biquad,
+            BiquadKind,
+        },
+        stretch::TimeStretcher,
+    },
+    effects::Effect,
+    util::visit_duration,
 };
-use rg3d_core::visitor::{
-    Visit,
-    VisitResult,
-    Visitor,
+use rg3d_core::{
+    visitor::{
+        Visit,
+        VisitResult,
+        Visitor,
+    },
+    pool::Handle,
 };
 
+/// Simple "tone" control made of low and high shelf filters for each channel.
+#[derive(Default)]
+struct ToneControl {
+    low_shelf: (Biquad, Biquad),
+    high_shelf: (Biquad, Biquad),
+}
+
+impl ToneControl {
+    const LOW_SHELF_FREQUENCY: f32 = 250.0;
+    const HIGH_SHELF_FREQUENCY: f32 = 4000.0;
+    const QUALITY: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+    fn tune(&mut self, low_shelf_gain_db: f32, high_shelf_gain_db: f32) {
+        let sample_rate = crate::device::SAMPLE_RATE as f32;
+        // Shelf filters takes square root of linear gain (A = 10 ^ (dB / 40)).
+        let low_gain = 10.0f32.powf(low_shelf_gain_db / 40.0);
+        let high_gain = 10.0f32.powf(high_shelf_gain_db / 40.0);
+        let low_fc = Self::LOW_SHELF_FREQUENCY / sample_rate;
+        let high_fc = Self::HIGH_SHELF_FREQUENCY / sample_rate;
+        self.low_shelf.0.tune(BiquadKind::LowShelf, low_fc, low_gain, Self::QUALITY);
+        self.low_shelf.1.tune(BiquadKind::LowShelf, low_fc, low_gain, Self::QUALITY);
+        self.high_shelf.0.tune(BiquadKind::HighShelf, high_fc, high_gain, Self::QUALITY);
+        self.high_shelf.1.tune(BiquadKind::HighShelf, high_fc, high_gain, Self::QUALITY);
+    }
+
+    fn feed(&mut self, left: f32, right: f32) -> (f32, f32) {
+        (self.high_shelf.0.feed(self.low_shelf.0.feed(left)),
+         self.high_shelf.1.feed(self.low_shelf.1.feed(right)))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum EnvelopeStage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Finished,
+}
+
+impl EnvelopeStage {
+    fn id(self) -> u32 {
+        match self {
+            EnvelopeStage::Attack => 0,
+            EnvelopeStage::Decay => 1,
+            EnvelopeStage::Sustain => 2,
+            EnvelopeStage::Release => 3,
+            EnvelopeStage::Finished => 4,
+        }
+    }
+
+    fn from_id(id: u32) -> Self {
+        match id {
+            1 => EnvelopeStage::Decay,
+            2 => EnvelopeStage::Sustain,
+            3 => EnvelopeStage::Release,
+            4 => EnvelopeStage::Finished,
+            _ => EnvelopeStage::Attack,
+        }
+    }
+}
+
+/// Linear ADSR (attack, decay, sustain, release) envelope. All steps are given per sample.
+struct Envelope {
+    attack_step: f32,
+    decay_step: f32,
+    sustain: f32,
+    release: Duration,
+    release_step: f32,
+    stage: EnvelopeStage,
+    level: f32,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(0), Duration::from_secs(0), 1.0, Duration::from_secs(0))
+    }
+}
+
+impl Visit for Envelope {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        self.attack_step.visit("AttackStep", visitor)?;
+        self.decay_step.visit("DecayStep", visitor)?;
+        self.sustain.visit("Sustain", visitor)?;
+        visit_duration(&mut self.release, Duration::from_secs(0), "Release", visitor);
+        self.release_step.visit("ReleaseStep", visitor)?;
+        let mut stage = self.stage.id();
+        stage.visit("Stage", visitor)?;
+        if visitor.is_reading() {
+            self.stage = EnvelopeStage::from_id(stage);
+        }
+        self.level.visit("Level", visitor)?;
+
+        visitor.leave_region()
+    }
+}
+
+fn duration_to_samples(duration: Duration) -> f32 {
+    duration.as_secs_f32() * crate::device::SAMPLE_RATE as f32
+}
+
+impl Envelope {
+    fn new(attack: Duration, decay: Duration, sustain: f32, release: Duration) -> Self {
+        let sustain = sustain.clamp(0.0, 1.0);
+        // Zero-length stage must be passed in a single sample.
+        let attack_samples = duration_to_samples(attack).max(1.0);
+        let decay_samples = duration_to_samples(decay).max(1.0);
+        Self {
+            attack_step: 1.0 / attack_samples,
+            decay_step: (1.0 - sustain) / decay_samples,
+            sustain,
+            release,
+            release_step: 0.0,
+            stage: EnvelopeStage::Attack,
+            level: 0.0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.stage = EnvelopeStage::Attack;
+        self.level = 0.0;
+    }
+
+    fn note_off(&mut self) {
+        if self.stage != EnvelopeStage::Finished {
+            // Release starts from current level, whatever stage envelope is in.
+            self.release_step = self.level / duration_to_samples(self.release).max(1.0);
+            self.stage = EnvelopeStage::Release;
+        }
+    }
+
+    fn next_level(&mut self) -> f32 {
+        match self.stage {
+            EnvelopeStage::Attack => {
+                self.level += self.attack_step;
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = EnvelopeStage::Decay;
+                }
+            }
+            EnvelopeStage::Decay => {
+                self.level -= self.decay_step;
+                if self.level <= self.sustain {
+                    self.level = self.sustain;
+                    self.stage = EnvelopeStage::Sustain;
+                }
+            }
+            EnvelopeStage::Sustain => (),
+            EnvelopeStage::Release => {
+                self.level -= self.release_step;
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.stage = EnvelopeStage::Finished;
+                }
+            }
+            EnvelopeStage::Finished => (),
+        }
+        self.level
+    }
+}
+
+// Makes unique seed for random pitch/gain variation of each source.
+fn make_variation_seed() -> u64 {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    time ^ (COUNTER.fetch_add(1, Ordering::Relaxed) as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
 /// See module info.
 pub struct GenericSource {
     buffer: Option<Arc<Mutex<SoundBuffer>>>,
@@ -64,6 +298,10 @@ pub struct GenericSource {
     pitch: f64,
     gain: f32,
     looping: bool,
+    // None - infinite looping.
+    loop_count: Option<u32>,
+    // Amount of playthroughs finished since start of playback.
+    completed_loops: u32,
     // Important coefficient for runtime resampling. It is used to modify playback speed
     // of a source in order to match output device sampling rate. PCM data can be stored
     // in various sampling rates (22050 Hz, 44100 Hz, 88200 Hz, etc.) but output device
@@ -71,7 +309,8 @@ pub struct GenericSource {
     // data to device with rate of 22050 Hz but device is running at 44100 Hz then we'll
     // hear that sound will have high pitch (2.0), to fix that we'll just pre-multiply
     // playback speed by 0.5.
-    // However such auto-resampling has poor quality, but it is fast.
+    // Samples between two adjacent frames of buffer are interpolated with respect to resample
+    // quality of buffer.
     resampling_multiplier: f64,
     status: Status,
     play_once: bool,
@@ -83,7 +322,51 @@ pub struct GenericSource {
     // will start interpolation of gain.
     pub(in crate) last_left_gain: Option<f32>,
     pub(in crate) last_right_gain: Option<f32>,
-    frame_samples: Vec<(f32, f32)>
+    frame_samples: Vec<(f32, f32)>,
+    peak_level: f32,
+    rms_level: f32,
+    priority: u8,
+    gain_smoothing: Duration,
+    // Gain that is actually used by renderers, it follows `gain` with respect to
+    // `gain_smoothing`. None means that source has not been rendered yet.
+    smoothed_gain: Option<f32>,
+    low_shelf_gain_db: f32,
+    high_shelf_gain_db: f32,
+    // None if both shelves are flat, so there is no overhead in this case.
+    tone: Option<ToneControl>,
+    reverb_send: f32,
+    // Effects (buses) to which the source sends its signal and levels of sends.
+    sends: Vec<(Handle<Effect>, f32)>,
+    // Set by context when source was culled by voice limiter or muted by solo of other sources.
+    pub(in crate) is_virtual: bool,
+    // Set by context, see `Context::solo_source`.
+    pub(in crate) solo: bool,
+    // Set by context when other sources are soloed, source is faded out during one block and then
+    // virtualized.
+    pub(in crate) solo_muted: bool,
+    // Gain of solo fade at the end of last rendered block.
+    pub(in crate) solo_gain: f32,
+    // Channel count of buffer at last render.
+    pub(in crate) channel_count: usize,
+    envelope: Option<Envelope>,
+    pitch_variation: f32,
+    gain_variation: f32,
+    // Random multipliers for pitch and gain that was picked at last start of playback.
+    pitch_variation_factor: f64,
+    gain_variation_factor: f32,
+    variation_rng: Random,
+    start_delay: Duration,
+    // Amount of samples (per channel) of silence left before source actually starts.
+    delay_samples_left: usize,
+    // True if source stopped by itself: end of buffer was reached or envelope was released.
+    finished: bool,
+    // Output matrix converted to stereo frame form, see `set_output_matrix`.
+    output_matrix: Option<[[f32; 2]; 2]>,
+    // None if time stretch factor is 1.0.
+    time_stretcher: Option<TimeStretcher>,
+    stop_fade: Duration,
+    // (length, samples left) of fade out that was started by `stop`.
+    stop_fade_state: Option<(usize, usize)>,
 }
 
 impl Default for GenericSource {
@@ -96,12 +379,114 @@ impl Default for GenericSource {
             pitch: 1.0,
             gain: 1.0,
             looping: false,
+            loop_count: None,
+            completed_loops: 0,
             resampling_multiplier: 1.0,
             status: Status::Stopped,
             play_once: false,
             last_left_gain: None,
             last_right_gain: None,
-            frame_samples: Default::default()
+            frame_samples: Default::default(),
+            peak_level: 0.0,
+            rms_level: 0.0,
+            priority: 0,
+            gain_smoothing: Duration::from_secs(0),
+            smoothed_gain: None,
+            low_shelf_gain_db: 0.0,
+            high_shelf_gain_db: 0.0,
+            tone: None,
+            reverb_send: 1.0,
+            sends: Default::default(),
+            is_virtual: false,
+            solo: false,
+            solo_muted: false,
+            solo_gain: 1.0,
+            channel_count: 0,
+            envelope: None,
+            pitch_variation: 0.0,
+            gain_variation: 0.0,
+            pitch_variation_factor: 1.0,
+            gain_variation_factor: 1.0,
+            variation_rng: Random::new(make_variation_seed()),
+            start_delay: Duration::from_secs(0),
+            delay_samples_left: 0,
+            finished: false,
+            output_matrix: None,
+            time_stretcher: None,
+            stop_fade: GenericSource::DEFAULT_STOP_FADE,
+            stop_fade_state: None,
+        }
+    }
+}
+
+// See `resampling_multiplier` field of GenericSource.
+fn resampling_multiplier(buffer: &GenericBuffer) -> f64 {
+    let device_sample_rate = f64::from(crate::device::SAMPLE_RATE);
+    buffer.sample_rate() as f64 / device_sample_rate * buffer.channel_count() as f64
+}
+
+/// Samples of buffer together with samples that precede and follow them, so interpolation is continuous
+/// across loop points and blocks of streaming buffers. Frames beyond them are silent.
+struct Window<'a> {
+    history: &'a [f32],
+    samples: &'a [f32],
+    lookahead: &'a [f32],
+    channel_count: usize,
+}
+
+impl<'a> Window<'a> {
+    /// Returns sample of given channel of given frame, negative frames are taken from the end of
+    /// history, frames after last one - from the beginning of lookahead.
+    fn sample(&self, frame: isize, channel: usize) -> f32 {
+        let frame_count = (self.samples.len() / self.channel_count) as isize;
+        let (data, frame) = if frame < 0 {
+            (self.history, (self.history.len() / self.channel_count) as isize + frame)
+        } else if frame >= frame_count {
+            (self.lookahead, frame - frame_count)
+        } else {
+            (self.samples, frame)
+        };
+        if frame < 0 {
+            0.0
+        } else {
+            data.get(frame as usize * self.channel_count + channel).cloned().unwrap_or(0.0)
+        }
+    }
+}
+
+fn windowed_sinc(x: f32, cutoff: f32) -> f32 {
+    let half_width = SINC_HALF_WIDTH as f32;
+    if x.abs() >= half_width {
+        return 0.0;
+    }
+    let window = 0.5 + 0.5 * (std::f32::consts::PI * x / half_width).cos();
+    let t = std::f32::consts::PI * cutoff * x;
+    let sinc = if t.abs() < f32::EPSILON { 1.0 } else { t.sin() / t };
+    cutoff * sinc * window
+}
+
+/// Interpolates sample of given channel at fractional position `frame + frac`. `frame_step` is
+/// playback speed in frames per output sample, it is used to band-limit sinc interpolation when
+/// buffer is played faster than its own rate, this prevents aliasing.
+fn interpolate(quality: ResampleQuality, window: &Window, channel: usize, frame: isize, frac: f32, frame_step: f32) -> f32 {
+    let sample = |offset: isize| window.sample(frame + offset, channel);
+    match quality {
+        ResampleQuality::Linear => {
+            let a = sample(0);
+            a + (sample(1) - a) * frac
+        }
+        ResampleQuality::Cubic => {
+            let (y0, y1, y2, y3) = (sample(-1), sample(0), sample(1), sample(2));
+            let c1 = 0.5 * (y2 - y0);
+            let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+            let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+            ((c3 * frac + c2) * frac + c1) * frac + y1
+        }
+        ResampleQuality::Sinc => {
+            let cutoff = if frame_step > 1.0 { 1.0 / frame_step } else { 1.0 };
+            ((1 - SINC_HALF_WIDTH)..=SINC_HALF_WIDTH)
+                .map(|k| sample(k) * windowed_sinc(k as f32 - frac, cutoff))
+                .sum()
         }
     }
 }
@@ -131,6 +516,9 @@ fn position_to_index(position: f64, channel_count: usize) -> usize {
 }
 
 impl GenericSource {
+    /// Default duration of fade out on stop, see `set_stop_fade`.
+    pub const DEFAULT_STOP_FADE: Duration = Duration::from_millis(5);
+
     /// Changes buffer of source. Returns old buffer. Source will continue playing from beginning, old
     /// position will be discarded.
     pub fn set_buffer(&mut self, buffer: Arc<Mutex<SoundBuffer>>) -> Result<Option<Arc<Mutex<SoundBuffer>>>, SoundError> {
@@ -194,6 +582,195 @@ impl GenericSource {
         self.gain
     }
 
+    /// Sets time during which gain will smoothly reach new value set by `set_gain`. Gain is always
+    /// linearly interpolated across single render block to prevent clicks, but if gain is changed
+    /// each frame by small steps this still can give "zipper" noise. Smoothing makes gain to follow
+    /// its target value by first-order law which removes such noise. Default value is zero which
+    /// means that new gain will be reached within single render block.
+    pub fn set_gain_smoothing(&mut self, smoothing: Duration) -> &mut Self {
+        self.gain_smoothing = smoothing;
+        self
+    }
+
+    /// Returns gain smoothing time.
+    pub fn gain_smoothing(&self) -> Duration {
+        self.gain_smoothing
+    }
+
+    /// Returns gain that was used in last render pass. It can differ from `gain` if gain smoothing
+    /// or gain variation is used.
+    pub fn smoothed_gain(&self) -> f32 {
+        self.smoothed_gain.unwrap_or(self.gain) * self.gain_variation_factor
+    }
+
+    /// Sets range of random pitch variation. Each time source starts playing, its pitch is multiplied
+    /// by random value from `[1 - variation; 1 + variation]` range. This removes "machine-gun" effect
+    /// when same sound (footsteps, shots, etc.) is played many times in a row. Default value is zero
+    /// which means no variation.
+    pub fn set_pitch_variation(&mut self, variation: f32) -> &mut Self {
+        self.pitch_variation = variation.abs();
+        self
+    }
+
+    /// Returns range of random pitch variation.
+    pub fn pitch_variation(&self) -> f32 {
+        self.pitch_variation
+    }
+
+    /// Sets range of random gain variation. Each time source starts playing, its gain is multiplied
+    /// by random value from `[1 - variation; 1 + variation]` range. See `set_pitch_variation` for
+    /// more info. Default value is zero which means no variation.
+    pub fn set_gain_variation(&mut self, variation: f32) -> &mut Self {
+        self.gain_variation = variation.abs();
+        self
+    }
+
+    /// Returns range of random gain variation.
+    pub fn gain_variation(&self) -> f32 {
+        self.gain_variation
+    }
+
+    /// Sets seed of random generator that is used for pitch and gain variation. By default each source
+    /// has its own unique seed, fixed seed can be used to get reproducible results.
+    pub fn set_variation_seed(&mut self, seed: u64) -> &mut Self {
+        self.variation_rng = Random::new(seed);
+        self
+    }
+
+    /// Returns pitch multiplier that was randomly picked at last start of playback.
+    pub fn pitch_variation_factor(&self) -> f64 {
+        self.pitch_variation_factor
+    }
+
+    /// Returns gain multiplier that was randomly picked at last start of playback.
+    pub fn gain_variation_factor(&self) -> f32 {
+        self.gain_variation_factor
+    }
+
+    fn pick_variation(&mut self) {
+        self.pitch_variation_factor = if self.pitch_variation > 0.0 {
+            f64::from(self.variation_rng.range(1.0 - self.pitch_variation, 1.0 + self.pitch_variation).max(0.0))
+        } else {
+            1.0
+        };
+        self.gain_variation_factor = if self.gain_variation > 0.0 {
+            self.variation_rng.range(1.0 - self.gain_variation, 1.0 + self.gain_variation).max(0.0)
+        } else {
+            1.0
+        };
+    }
+
+    fn update_smoothed_gain(&mut self, amount: usize) {
+        let current = self.smoothed_gain.unwrap_or(self.gain);
+        let smoothing_samples = self.gain_smoothing.as_secs_f32() * crate::device::SAMPLE_RATE as f32;
+        self.smoothed_gain = Some(if smoothing_samples <= amount as f32 {
+            self.gain
+        } else {
+            current + (self.gain - current) * amount as f32 / smoothing_samples
+        });
+    }
+
+    /// Sets gain (in decibels) of low shelf filter which affects frequencies below ~250 Hz. Positive
+    /// values makes sound "bassier", negative - "thinner". Filter is applied to source samples before
+    /// spatialization. Default value is 0 dB (transparent).
+    pub fn set_low_shelf(&mut self, gain_db: f32) -> &mut Self {
+        self.low_shelf_gain_db = gain_db;
+        self.update_tone();
+        self
+    }
+
+    /// Returns gain (in decibels) of low shelf filter.
+    pub fn low_shelf(&self) -> f32 {
+        self.low_shelf_gain_db
+    }
+
+    /// Sets gain (in decibels) of high shelf filter which affects frequencies above ~4 kHz. Positive
+    /// values makes sound "brighter", negative - "duller". Filter is applied to source samples before
+    /// spatialization. Default value is 0 dB (transparent).
+    pub fn set_high_shelf(&mut self, gain_db: f32) -> &mut Self {
+        self.high_shelf_gain_db = gain_db;
+        self.update_tone();
+        self
+    }
+
+    /// Returns gain (in decibels) of high shelf filter.
+    pub fn high_shelf(&self) -> f32 {
+        self.high_shelf_gain_db
+    }
+
+    fn update_tone(&mut self) {
+        if self.low_shelf_gain_db == 0.0 && self.high_shelf_gain_db == 0.0 {
+            self.tone = None;
+        } else {
+            let (low, high) = (self.low_shelf_gain_db, self.high_shelf_gain_db);
+            self.tone
+                .get_or_insert_with(Default::default)
+                .tune(low, high);
+        }
+    }
+
+    /// Sets amount of signal of the source which will be sent to reverb effects the source is
+    /// connected to. 0.0 - source is fully dry, 1.0 - source is fully processed by reverb.
+    /// Default value is 1.0. Send level is used only by reverbs in `ReverbSendMode::Send` mode,
+    /// see `Reverb::set_send_mode`.
+    pub fn set_reverb_send(&mut self, send: f32) -> &mut Self {
+        self.reverb_send = send.max(0.0);
+        self
+    }
+
+    /// Returns reverb send level of the source.
+    pub fn reverb_send(&self) -> f32 {
+        self.reverb_send
+    }
+
+    /// Sets level at which signal of the source will be sent to given effect (bus). Unlike inputs
+    /// of effect (see `BaseEffect::add_input`), sends are set on the source side, so one source can
+    /// be easily routed to several effects at once, each with its own level, i.e. to reverb and
+    /// delay simultaneously. Signal is sent after distance attenuation. Level of 0.0 removes the send,
+    /// source without sends is fully dry.
+    ///
+    /// # Notes
+    ///
+    /// Sends are independent from inputs of effect - if source is an input of an effect and sends
+    /// to it as well, effect will receive signal of the source twice.
+    pub fn set_send(&mut self, bus: Handle<Effect>, level: f32) -> &mut Self {
+        let level = level.max(0.0);
+        match self.sends.iter_mut().position(|(handle, _)| *handle == bus) {
+            Some(index) => {
+                if level == 0.0 {
+                    self.sends.remove(index);
+                } else {
+                    self.sends[index].1 = level;
+                }
+            }
+            None => {
+                if level != 0.0 {
+                    self.sends.push((bus, level));
+                }
+            }
+        }
+        self
+    }
+
+    /// Returns level of send to given effect, 0.0 if there is no such send.
+    pub fn send(&self, bus: Handle<Effect>) -> f32 {
+        self.sends
+            .iter()
+            .find(|(handle, _)| *handle == bus)
+            .map_or(0.0, |&(_, level)| level)
+    }
+
+    /// Returns all sends of the source as pairs of effect handle and send level.
+    pub fn sends(&self) -> &[(Handle<Effect>, f32)] {
+        &self.sends
+    }
+
+    /// Removes all sends of the source, so it becomes fully dry.
+    pub fn clear_sends(&mut self) -> &mut Self {
+        self.sends.clear();
+        self
+    }
+
     /// Sets panning coefficient. Value must be in -1..+1 range. Where -1 - only left channel will be audible,
     /// 0 - both, +1 - only right.
     pub fn set_panning(&mut self, panning: f32) -> &mut Self {
@@ -206,20 +783,217 @@ impl GenericSource {
         self.panning
     }
 
+    /// Sets priority of sound source. Priority is used by voice limiter of context: when there are
+    /// more playing sources than context allows to mix, sources with lowest priority (and then
+    /// quietest ones) will be virtualized - their playback position will still advance, but they
+    /// won't be mixed into output. Virtualized source resumes mixing as soon as there is a free
+    /// voice slot. Default value is 0.
+    pub fn set_priority(&mut self, priority: u8) -> &mut Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Returns priority of sound source.
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    /// Returns true if source was virtualized (culled) by voice limiter or muted because other
+    /// sources are soloed in last render pass. See `set_priority` and `Context::solo_source` for
+    /// more info.
+    pub fn is_virtual(&self) -> bool {
+        self.is_virtual
+    }
+
+    /// Returns true if source is soloed, see `Context::solo_source`.
+    pub fn is_soloed(&self) -> bool {
+        self.solo
+    }
+
     /// Returns status of sound source.
     pub fn status(&self) -> Status {
         self.status
     }
 
-    /// Changes status to `Playing`.
+    /// Changes status to `Playing`. If source has envelope and was stopped, envelope will be
+    /// restarted from attack phase. New random pitch and gain variation is picked too and start
+    /// delay countdown begins. Source which is fading out after `stop` is stopped immediately and
+    /// starts playing from the beginning, as if fade was finished.
     pub fn play(&mut self) -> &mut Self {
+        if self.stop_fade_state.is_some() {
+            // Playback must restart just like after finished stop, error of rewinding of
+            // streaming buffer is ignored the same way as at the end of fade.
+            let _ = self.stop_immediately();
+        }
+        if self.status == Status::Stopped {
+            if let Some(envelope) = self.envelope.as_mut() {
+                envelope.reset();
+            }
+            // Source could stop by itself (end of buffer) with data of previous playback still
+            // in stretcher.
+            if let Some(stretcher) = self.time_stretcher.as_mut() {
+                stretcher.reset();
+            }
+            self.pick_variation();
+            self.reset_start_delay();
+            self.completed_loops = 0;
+        }
         self.status = Status::Playing;
+        self.finished = false;
+        self
+    }
+
+    /// Sets linear ADSR (attack, decay, sustain, release) volume envelope which will be applied as
+    /// gain multiplier to samples of the source. On start of playback gain rises from zero to one
+    /// during `attack` time, then falls to `sustain` level (in [0; 1] range) during `decay` time and
+    /// holds this level until `note_off` is called. After that gain falls to zero during `release`
+    /// time and source stops. Useful for musical or synth-like playback.
+    ///
+    /// # Notes
+    ///
+    /// Envelope starts from attack phase each time source starts to play after it was stopped.
+    /// Source without envelope (default) plays with unmodified gain.
+    pub fn set_envelope(&mut self, attack: Duration, decay: Duration, sustain: f32, release: Duration) -> &mut Self {
+        self.envelope = Some(Envelope::new(attack, decay, sustain, release));
         self
     }
 
-    /// Changes status to `Paused`
+    /// Removes volume envelope from the source.
+    pub fn remove_envelope(&mut self) -> &mut Self {
+        self.envelope = None;
+        self
+    }
+
+    /// Starts release phase of envelope from its current level. Does nothing if there is no
+    /// envelope.
+    pub fn note_off(&mut self) -> &mut Self {
+        if let Some(envelope) = self.envelope.as_mut() {
+            envelope.note_off();
+        }
+        self
+    }
+
+    /// Returns current level of volume envelope or `None` if there is no envelope.
+    pub fn envelope_level(&self) -> Option<f32> {
+        self.envelope.as_ref().map(|e| e.level)
+    }
+
+    /// Returns true if source has stopped by itself, i.e. non-looping source reached end of its
+    /// buffer, looping source finished its last playthrough (see `set_loop_count`) or its envelope
+    /// finished release phase. Infinitely looping sources are never finished. Source that was
+    /// stopped by `stop` or that was never played is not finished. Flag is reset when source starts
+    /// playing again. Useful for pooling of sources.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Sets matrix which maps channels of buffer to output channels: `output[row] = sum(matrix[row][column] * input[column])`.
+    /// Matrix must have one row per output channel (`Context::channel_count`) and one column per channel
+    /// of buffer, buffers with more than two channels are treated as stereo (see module docs). This is
+    /// low-level replacement of panning for custom panning laws, it is applied to samples of the source
+    /// before renderer, so panning and spatial properties are still applied on top of it (leave panning
+    /// at zero to have full control).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rg3d_sound::source::generic::GenericSource;
+    ///
+    /// fn swap_channels(source: &mut GenericSource) {
+    ///     // Stereo buffer, left and right channels are swapped.
+    ///     source.set_output_matrix(&[&[0.0, 1.0], &[1.0, 0.0]]).unwrap();
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `SoundError::InvalidOutputMatrix` if dimensions of matrix does not match channel counts.
+    /// Source without buffer accepts matrices with one or two columns.
+    pub fn set_output_matrix(&mut self, matrix: &[&[f32]]) -> Result<(), SoundError> {
+        let rows = crate::device::CHANNEL_COUNT;
+        let buffer_channels = self.buffer
+            .as_ref()
+            .and_then(|b| b.lock().ok().map(|b| b.generic().channel_count().min(2)));
+        let columns = matrix.first().map_or(0, |row| row.len());
+        let expected_columns = buffer_channels.unwrap_or(columns);
+        if matrix.len() != rows || columns == 0 || columns > 2 || columns != expected_columns ||
+            matrix.iter().any(|row| row.len() != columns) {
+            return Err(SoundError::InvalidOutputMatrix {
+                size: (matrix.len(), columns),
+                expected: (rows, buffer_channels.unwrap_or(2)),
+            });
+        }
+        // Frames are always stereo, mono buffer gives equal channels, so only left is used.
+        let mut frame_matrix = [[0.0; 2]; 2];
+        for (frame_row, row) in frame_matrix.iter_mut().zip(matrix) {
+            frame_row[..columns].copy_from_slice(row);
+        }
+        self.output_matrix = Some(frame_matrix);
+        Ok(())
+    }
+
+    /// Removes output matrix, channels of the source will be mapped using default rules.
+    pub fn remove_output_matrix(&mut self) -> &mut Self {
+        self.output_matrix = None;
+        self
+    }
+
+    /// Sets time stretch factor: playback is slowed down (factor > 1.0) or sped up (factor < 1.0)
+    /// without changing pitch, for example 2.0 means that sound will play twice longer with the same
+    /// pitch. Factor is clamped to [0.25; 4.0] range. Factor of 1.0 (default) disables time stretch.
+    /// Useful to fit dialogue in certain timings. Stretching is done using WSOLA algorithm, see
+    /// `dsp::stretch` module docs.
+    ///
+    /// # Performance
+    ///
+    /// Time stretch is CPU-heavy - each ~12 ms of output requires similarity search over ~500
+    /// positions, so it should be used only for few sources at once.
+    ///
+    /// # Notes
+    ///
+    /// Stretcher has latency of about 30 ms, so last few milliseconds of non-looping sound could be
+    /// lost.
+    pub fn set_time_stretch(&mut self, factor: f32) -> &mut Self {
+        self.time_stretcher = if (factor - 1.0).abs() <= f32::EPSILON {
+            None
+        } else {
+            Some(TimeStretcher::new(factor))
+        };
+        self
+    }
+
+    /// Returns time stretch factor.
+    pub fn time_stretch(&self) -> f32 {
+        self.time_stretcher.as_ref().map_or(1.0, |s| s.factor())
+    }
+
+    /// Sets delay before actual start of playback. Source that starts playing (from `Stopped` state)
+    /// emits silence and does not advance its playback position until delay elapses. Delay is counted
+    /// in samples of output device, so it is sample-accurate. Useful for choreographed audio, when
+    /// sound should start some time after it was added. Pause does not reset countdown. Default
+    /// value is zero.
+    ///
+    /// # Notes
+    ///
+    /// New delay takes effect on next start of playback.
+    pub fn set_start_delay(&mut self, delay: Duration) -> &mut Self {
+        self.start_delay = delay;
+        self
+    }
+
+    /// Returns delay before start of playback.
+    pub fn start_delay(&self) -> Duration {
+        self.start_delay
+    }
+
+    fn reset_start_delay(&mut self) {
+        self.delay_samples_left = (self.start_delay.as_secs_f64() * f64::from(crate::device::SAMPLE_RATE)) as usize;
+    }
+
+    /// Changes status to `Paused`. Cancels fade out of `stop` if it is in progress.
     pub fn pause(&mut self) -> &mut Self {
         self.status = Status::Paused;
+        self.stop_fade_state = None;
         self
     }
 
@@ -235,6 +1009,32 @@ impl GenericSource {
         self.looping
     }
 
+    /// Sets amount of playthroughs of looping source, after the last one source stops by itself and
+    /// becomes finished (see `is_finished`), context reports this by `Diagnostic::SourceFinished` event.
+    /// `None` or `Some(0)` means infinite looping, this is default.
+    /// Has no effect if looping is disabled - such source always plays once. Useful for musical stingers
+    /// that should repeat fixed amount of times.
+    ///
+    /// # Notes
+    ///
+    /// Playthrough is counted when end of buffer is reached, so seeking by `set_playback_time` does not
+    /// change amount of counted playthroughs. Counter is reset when source starts playing after it was
+    /// stopped.
+    pub fn set_loop_count(&mut self, count: Option<u32>) -> &mut Self {
+        self.loop_count = count.filter(|&count| count != 0);
+        self
+    }
+
+    /// Returns amount of playthroughs of looping source, `None` means infinite looping.
+    pub fn loop_count(&self) -> Option<u32> {
+        self.loop_count
+    }
+
+    /// Returns amount of playthroughs finished since source started playing.
+    pub fn completed_loops(&self) -> u32 {
+        self.completed_loops
+    }
+
     /// Sets sound pitch. Defines "tone" of sounds. Default value is 1.0
     pub fn set_pitch(&mut self, pitch: f64) -> &mut Self {
         self.pitch = pitch.abs();
@@ -246,9 +1046,59 @@ impl GenericSource {
         self.pitch
     }
 
+    /// Sets duration of fade out which is applied when playing source is stopped by `stop`, it
+    /// prevents click of abrupt cut of signal. Zero duration means that source stops instantly.
+    /// Default value is `DEFAULT_STOP_FADE`.
+    pub fn set_stop_fade(&mut self, fade: Duration) -> &mut Self {
+        self.stop_fade = fade;
+        self
+    }
+
+    /// Returns duration of fade out on stop.
+    pub fn stop_fade(&self) -> Duration {
+        self.stop_fade
+    }
+
+    /// Returns true if source is fading out after `stop` was called, status of such source is still
+    /// `Playing`.
+    pub fn is_stopping(&self) -> bool {
+        self.stop_fade_state.is_some()
+    }
+
     /// Stops sound source. Automatically rewinds streaming buffers.
+    ///
+    /// # Notes
+    ///
+    /// If source is playing and stop fade is not zero (see `set_stop_fade`), source keeps playing
+    /// while fading out and only then becomes `Stopped` and rewinds, so its status and playback
+    /// position does not change immediately. In this case errors of rewinding of streaming buffer
+    /// are ignored. `play` during the fade restarts source from the beginning, `pause` cancels fade.
     pub fn stop(&mut self) -> Result<(), SoundError> {
+        let fade_len = (self.stop_fade.as_secs_f64() * f64::from(crate::device::SAMPLE_RATE)) as usize;
+        if self.status == Status::Playing && self.delay_samples_left == 0 && fade_len > 0 {
+            // Fade which is already in progress must not be prolonged.
+            if self.stop_fade_state.is_none() {
+                self.stop_fade_state = Some((fade_len, fade_len));
+            }
+            Ok(())
+        } else {
+            self.stop_immediately()
+        }
+    }
+
+    fn stop_immediately(&mut self) -> Result<(), SoundError> {
+        self.stop_fade_state = None;
         self.status = Status::Stopped;
+        self.delay_samples_left = 0;
+        self.completed_loops = 0;
+
+        if let Some(stretcher) = self.time_stretcher.as_mut() {
+            stretcher.reset();
+        }
+
+        if let Some(envelope) = self.envelope.as_mut() {
+            envelope.reset();
+        }
 
         self.buf_read_pos = 0.0;
         self.playback_pos = 0.0;
@@ -274,9 +1124,12 @@ impl GenericSource {
 
     /// Sets playback duration.
     pub fn set_playback_time(&mut self, time: Duration) {
+        if let Some(stretcher) = self.time_stretcher.as_mut() {
+            stretcher.reset();
+        }
         if let Some(mut buffer) = self.buffer.as_mut().and_then(|b| b.lock().ok()) {
             if let SoundBuffer::Streaming(ref mut streaming) = *buffer {
-                // Make sure decoder is at right position.
+                // Make sure decoder is at right position and correct data is loaded into buffer.
                 streaming.time_seek(time);
             }
             // Set absolute position first.
@@ -285,9 +1138,7 @@ impl GenericSource {
             // Then adjust buffer read position.
             self.buf_read_pos =
                 match *buffer {
-                    SoundBuffer::Streaming(ref mut streaming) => {
-                        // Make sure to load correct data into buffer from decoder.
-                        streaming.read_next_block();
+                    SoundBuffer::Streaming(ref streaming) => {
                         // Streaming sources has different buffer read position because
                         // buffer contains only small portion of data.
                         self.playback_pos % streaming.generic.samples.len() as f64
@@ -301,16 +1152,11 @@ impl GenericSource {
     }
 
     fn next_sample_pair(&mut self, buffer: &mut SoundBuffer) -> (f32, f32) {
-        let step = self.pitch * self.resampling_multiplier;
-
-        self.buf_read_pos += step;
-        self.playback_pos += step;
-
+        let step = self.pitch * self.pitch_variation_factor * self.resampling_multiplier;
         let channel_count = buffer.generic().channel_count();
-        let mut i = position_to_index(self.buf_read_pos, channel_count);
 
         let len = buffer.generic().samples().len();
-        if i > buffer.generic().index_of_last_sample() {
+        if position_to_index(self.buf_read_pos, channel_count) > buffer.generic().index_of_last_sample() {
             let mut end_reached = true;
             if let SoundBuffer::Streaming(streaming) = buffer {
                 // Means that this is the last available block.
@@ -321,23 +1167,63 @@ impl GenericSource {
                 }
                 streaming.read_next_block();
             }
+            // Keep fractional part of position, so there is no discontinuity at the end of block (or at
+            // loop point).
+            let overshoot = self.buf_read_pos - len as f64;
+            self.buf_read_pos = if overshoot < buffer.generic().samples().len() as f64 { overshoot } else { 0.0 };
             if end_reached {
-                if !self.looping {
+                self.completed_loops = self.completed_loops.saturating_add(1);
+                let loops_exhausted = self.loop_count.is_some_and(|count| self.completed_loops >= count);
+                self.playback_pos = self.buf_read_pos;
+                if !self.looping || loops_exhausted {
+                    // Buffer could end before stop fade is finished, fade is over anyway.
+                    self.stop_fade_state = None;
                     self.status = Status::Stopped;
+                    self.finished = true;
+                    return (0.0, 0.0);
                 }
-                self.playback_pos = 0.0;
             }
-            self.buf_read_pos = 0.0;
-            i = 0;
         }
 
+        // Read position is in samples of interleaved data, so convert it to frames to get fractional
+        // position between two adjacent frames.
+        let frame_pos = self.buf_read_pos / channel_count as f64;
+        let frac = (frame_pos - frame_pos.floor()) as f32;
+        let frame = (position_to_index(self.buf_read_pos, channel_count) / channel_count) as isize;
+        let frame_step = (step / channel_count as f64) as f32;
+
+        self.buf_read_pos += step;
+        self.playback_pos += step;
+
+        let quality = buffer.generic().resample_quality();
         let samples = buffer.generic().samples();
-        if channel_count == 2 {
-            let left = samples[i];
-            let right = samples[i + 1];
+        let window = match &*buffer {
+            SoundBuffer::Generic(_) => {
+                // Looping buffer is preceded and followed by itself.
+                let wraps_next = self.looping && match self.loop_count {
+                    Some(count) => self.completed_loops + 1 < count,
+                    None => true,
+                };
+                Window {
+                    history: if self.completed_loops > 0 { samples } else { &[] },
+                    samples,
+                    lookahead: if wraps_next { samples } else { &[] },
+                    channel_count,
+                }
+            }
+            SoundBuffer::Streaming(streaming) => Window {
+                history: streaming.history(),
+                samples,
+                lookahead: streaming.lookahead(),
+                channel_count,
+            },
+        };
+        if channel_count >= 2 {
+            let left = interpolate(quality, &window, 0, frame, frac, frame_step);
+            let right = interpolate(quality, &window, 1, frame, frac, frame_step);
             (left, right)
         } else {
-            let sample = samples[i];
+            let sample = interpolate(quality, &window, 0, frame, frac, frame_step);
             (sample, sample)
         }
     }
@@ -349,13 +1235,53 @@ impl GenericSource {
 
         self.frame_samples.clear();
 
+        self.update_smoothed_gain(amount);
+
+        // Leading part of the block which is silent because of start delay.
+        let delayed = if self.status == Status::Playing {
+            let delayed = self.delay_samples_left.min(amount);
+            self.delay_samples_left -= delayed;
+            delayed
+        } else {
+            0
+        };
+
         if let Some(mut buffer) = self.buffer.clone().as_ref().and_then(|b| b.lock().ok().and_then(|b| if b.generic().is_empty() { None } else { Some(b) })) {
-            for _ in 0..amount {
-                if self.status == Status::Playing {
-                    let pair = self.next_sample_pair(&mut buffer);
-                    self.frame_samples.push(pair);
-                } else {
-                    self.frame_samples.push((0.0, 0.0));
+            self.channel_count = buffer.generic().channel_count();
+            // Data of buffer could be replaced (see `SoundBuffer::replace_data`), so make sure
+            // that read cursor is within bounds and resampling is correct.
+            self.resampling_multiplier = resampling_multiplier(buffer.generic());
+            // Position right after last sample means that end of buffer is reached.
+            let end = buffer.generic().samples().len() as f64;
+            if self.buf_read_pos > end {
+                self.buf_read_pos = end;
+                if let SoundBuffer::Generic(_) = *buffer {
+                    self.playback_pos = end;
+                }
+            }
+            let stretcher = if self.status == Status::Playing { self.time_stretcher.take() } else { None };
+            if let Some(mut stretcher) = stretcher {
+                let mut frame_samples = std::mem::take(&mut self.frame_samples);
+                for _ in 0..delayed {
+                    frame_samples.push((0.0, 0.0));
+                }
+                stretcher.process(amount - delayed, &mut frame_samples, || {
+                    if self.status == Status::Playing {
+                        self.next_sample_pair(&mut buffer)
+                    } else {
+                        (0.0, 0.0)
+                    }
+                });
+                self.frame_samples = frame_samples;
+                self.time_stretcher = Some(stretcher);
+            } else {
+                for i in 0..amount {
+                    if self.status == Status::Playing && i >= delayed {
+                        let pair = self.next_sample_pair(&mut buffer);
+                        self.frame_samples.push(pair);
+                    } else {
+                        self.frame_samples.push((0.0, 0.0));
+                    }
                 }
             }
         } else {
@@ -363,11 +1289,112 @@ impl GenericSource {
                 self.frame_samples.push((0.0, 0.0));
             }
         }
+
+        if let Some(tone) = self.tone.as_mut() {
+            for (left, right) in self.frame_samples.iter_mut() {
+                let (filtered_left, filtered_right) = tone.feed(*left, *right);
+                *left = filtered_left;
+                *right = filtered_right;
+            }
+        }
+
+        if let Some(m) = self.output_matrix {
+            for (left, right) in self.frame_samples.iter_mut() {
+                let (l, r) = (*left, *right);
+                *left = m[0][0] * l + m[0][1] * r;
+                *right = m[1][0] * l + m[1][1] * r;
+            }
+        }
+
+        if let Some(envelope) = self.envelope.as_mut() {
+            // Envelope starts together with actual playback.
+            for (left, right) in self.frame_samples.iter_mut().skip(delayed) {
+                let level = envelope.next_level();
+                *left *= level;
+                *right *= level;
+            }
+            // Source is done when its release phase is finished.
+            if envelope.stage == EnvelopeStage::Finished && self.status == Status::Playing {
+                let _ = self.stop_immediately();
+                self.finished = true;
+            }
+        }
+
+        if let Some((length, mut left)) = self.stop_fade_state {
+            for (left_sample, right_sample) in self.frame_samples.iter_mut() {
+                let k = left as f32 / length as f32;
+                *left_sample *= k;
+                *right_sample *= k;
+                left = left.saturating_sub(1);
+            }
+            if left == 0 {
+                let _ = self.stop_immediately();
+            } else {
+                self.stop_fade_state = Some((length, left));
+            }
+        }
+
+        // Muting and unmuting by solo is ramped over the block, so it does not click.
+        let solo_gain = if self.solo_muted { 0.0 } else { 1.0 };
+        if self.solo_gain != 1.0 || solo_gain != 1.0 {
+            let step = (solo_gain - self.solo_gain) / self.frame_samples.len().max(1) as f32;
+            for (i, (left, right)) in self.frame_samples.iter_mut().enumerate() {
+                let k = self.solo_gain + step * (i + 1) as f32;
+                *left *= k;
+                *right *= k;
+            }
+            self.solo_gain = solo_gain;
+        }
+
+        let (peak, rms) = dsp::measure_levels(&self.frame_samples);
+        self.peak_level = peak;
+        self.rms_level = rms;
+    }
+
+    /// Forgets last rendered block of samples, called for sources that are not rendered in current
+    /// render pass.
+    pub(in crate) fn clear_frame(&mut self) {
+        self.frame_samples.clear();
     }
 
     pub(in crate) fn frame_samples(&self) -> &[(f32, f32)] {
         &self.frame_samples
     }
+
+    /// Returns true if last rendered block of samples is entirely silent, so renderers can skip
+    /// mixing and convolution of the source.
+    pub(in crate) fn is_silent(&self) -> bool {
+        self.peak_level == 0.0
+    }
+
+    /// Returns most recently fetched block of samples of the source. Samples are already resampled
+    /// (pitch and sample rate of buffer are taken into account) and processed by tone filters and
+    /// volume envelope, this is exactly what will be passed to renderer which will apply gain and
+    /// spatial properties of the source. Can be used for debugging, visualizers or custom processing.
+    pub fn current_frame(&self) -> &[(f32, f32)] {
+        &self.frame_samples
+    }
+
+    /// Returns most recently fetched block of samples of the source with gain of the source applied
+    /// (see `smoothed_gain`), this is the same as `current_frame` but post-gain. Spatial properties
+    /// (distance gain and panning) are applied by renderer and are not taken into account.
+    pub fn current_frame_with_gain(&self) -> impl Iterator<Item=(f32, f32)> + '_ {
+        let gain = self.smoothed_gain();
+        self.frame_samples.iter().map(move |&(left, right)| (left * gain, right * gain))
+    }
+
+    /// Returns peak level (maximum absolute sample value) of last rendered block of samples.
+    /// Gain and spatial properties of source are not taken into account. Can be used for
+    /// visualizers and debugging.
+    pub fn peak_level(&self) -> f32 {
+        self.peak_level
+    }
+
+    /// Returns root mean square level of last rendered block of samples. Gain and spatial
+    /// properties of source are not taken into account.
+    pub fn rms_level(&self) -> f32 {
+        self.rms_level
+    }
 }
 
 impl Drop for GenericSource {
@@ -394,6 +1421,111 @@ impl Visit for GenericSource {
         self.resampling_multiplier.visit("ResamplingMultiplier", visitor)?;
         self.status.visit("Status", visitor)?;
         self.play_once.visit("PlayOnce", visitor)?;
+        if self.priority.visit("Priority", visitor).is_err() && visitor.is_reading() {
+            // Older versions have no priority.
+            self.priority = 0;
+        }
+        visit_duration(&mut self.gain_smoothing, Duration::from_secs(0), "GainSmoothing", visitor);
+        let has_shelves = self.low_shelf_gain_db.visit("LowShelf", visitor).is_ok() &&
+            self.high_shelf_gain_db.visit("HighShelf", visitor).is_ok();
+        if visitor.is_reading() {
+            if !has_shelves {
+                // Older versions have no shelves.
+                self.low_shelf_gain_db = 0.0;
+                self.high_shelf_gain_db = 0.0;
+            }
+            self.update_tone();
+        }
+        if self.reverb_send.visit("ReverbSend", visitor).is_err() && visitor.is_reading() {
+            // Older versions have no reverb send.
+            self.reverb_send = 1.0;
+        }
+        if visitor.is_reading() {
+            // Missing region means that there is no envelope (or that data is from older version).
+            // Visit of `Option` does not reset `Some` when `None` is read, so it is reset here.
+            self.envelope = None;
+        }
+        let _ = self.envelope.visit("Envelope", visitor);
+        if self.pitch_variation.visit("PitchVariation", visitor).is_err() && visitor.is_reading() {
+            // Older versions have no variation.
+            self.pitch_variation = 0.0;
+        }
+        if self.gain_variation.visit("GainVariation", visitor).is_err() && visitor.is_reading() {
+            self.gain_variation = 0.0;
+        }
+        // Factors that were picked at last start of playback, so playing source continues with
+        // the same pitch and gain.
+        if self.pitch_variation_factor.visit("PitchVariationFactor", visitor).is_err() && visitor.is_reading() {
+            self.pitch_variation_factor = 1.0;
+        }
+        if self.gain_variation_factor.visit("GainVariationFactor", visitor).is_err() && visitor.is_reading() {
+            self.gain_variation_factor = 1.0;
+        }
+        visit_duration(&mut self.start_delay, Duration::from_secs(0), "StartDelay", visitor);
+        // Countdown of delay which is in progress, usize is not visitable, so it is stored as u64.
+        let mut delay_samples_left = self.delay_samples_left as u64;
+        if delay_samples_left.visit("DelaySamplesLeft", visitor).is_ok() {
+            if visitor.is_reading() {
+                self.delay_samples_left = delay_samples_left as usize;
+            }
+        } else if visitor.is_reading() {
+            self.delay_samples_left = 0;
+        }
+        // Matrix is stored as flat list of rows, empty list means that there is no matrix. Visit
+        // of `Vec` appends items on read, so list is empty on read.
+        let mut output_matrix = match self.output_matrix {
+            Some([[a, b], [c, d]]) if !visitor.is_reading() => vec![a, b, c, d],
+            _ => Vec::new(),
+        };
+        let has_output_matrix = output_matrix.visit("OutputMatrix", visitor).is_ok();
+        if visitor.is_reading() {
+            // Older versions have no output matrix.
+            self.output_matrix = match output_matrix[..] {
+                [a, b, c, d] if has_output_matrix => Some([[a, b], [c, d]]),
+                _ => None,
+            };
+        }
+        // Only factor is stored, stretcher starts from clean state on load.
+        let mut time_stretch = self.time_stretch();
+        let has_time_stretch = time_stretch.visit("TimeStretch", visitor).is_ok();
+        if visitor.is_reading() {
+            // Older versions have no time stretch.
+            self.set_time_stretch(if has_time_stretch { time_stretch } else { 1.0 });
+        }
+        if visitor.is_reading() {
+            // Missing region means that there is no limit (or that data is from older version).
+            self.loop_count = None;
+        }
+        let _ = self.loop_count.visit("LoopCount", visitor);
+        if self.completed_loops.visit("CompletedLoops", visitor).is_err() && visitor.is_reading() {
+            self.completed_loops = 0;
+        }
+        // Sends are stored as two lists of the same length: buses and levels.
+        let (mut buses, mut levels): (Vec<Handle<Effect>>, Vec<f32>) = if visitor.is_reading() {
+            Default::default()
+        } else {
+            self.sends.iter().cloned().unzip()
+        };
+        if visitor.enter_region("Sends").is_ok() {
+            buses.visit("Buses", visitor)?;
+            levels.visit("Levels", visitor)?;
+            visitor.leave_region()?;
+        }
+        if visitor.is_reading() {
+            // Older versions have no sends, lists are empty in this case.
+            self.sends = buses.into_iter().zip(levels).collect();
+        }
+        visit_duration(&mut self.stop_fade, GenericSource::DEFAULT_STOP_FADE, "StopFade", visitor);
+        // Fade which is in progress is stored as its length and samples left, zero length means
+        // that there is no fade.
+        let (mut fade_length, mut fade_left) = self.stop_fade_state.map_or((0, 0), |(length, left)| (length as u64, left as u64));
+        if fade_length.visit("StopFadeLength", visitor).is_ok() && fade_left.visit("StopFadeLeft", visitor).is_ok() {
+            if visitor.is_reading() {
+                self.stop_fade_state = if fade_length > 0 { Some((fade_length as usize, fade_left as usize)) } else { None };
+            }
+        } else if visitor.is_reading() {
+            self.stop_fade_state = None;
+        }
 
         visitor.leave_region()
     }
@@ -435,12 +1567,19 @@ pub struct GenericSourceBuilder {
     pitch: f32,
     panning: f32,
     looping: bool,
+    loop_count: Option<u32>,
     status: Status,
     play_once: bool,
+    priority: u8,
+    pitch_variation: f32,
+    gain_variation: f32,
+    variation_seed: Option<u64>,
+    start_delay: Duration,
 }
 
 impl GenericSourceBuilder {
-    /// Creates new generic source builder with specified buffer.
+    /// Creates new generic source builder with specified buffer. Buffer is shared, not copied - see
+    /// module docs for more info.
     pub fn new(buffer: Arc<Mutex<SoundBuffer>>) -> Self {
         Self {
             buffer,
@@ -448,8 +1587,14 @@ impl GenericSourceBuilder {
             pitch: 1.0,
             panning: 0.0,
             looping: false,
+            loop_count: None,
             status: Status::Stopped,
             play_once: false,
+            priority: 0,
+            pitch_variation: 0.0,
+            gain_variation: 0.0,
+            variation_seed: None,
+            start_delay: Duration::from_secs(0),
         }
     }
 
@@ -477,6 +1622,12 @@ impl GenericSourceBuilder {
         self
     }
 
+    /// See `set_loop_count` of GenericSource
+    pub fn with_loop_count(mut self, count: Option<u32>) -> Self {
+        self.loop_count = count;
+        self
+    }
+
     /// Sets desired status of source.
     pub fn with_status(mut self, status: Status) -> Self {
         self.status = status;
@@ -489,9 +1640,38 @@ impl GenericSourceBuilder {
         self
     }
 
+    /// See `set_priority` of GenericSource
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// See `set_pitch_variation` of GenericSource
+    pub fn with_pitch_variation(mut self, variation: f32) -> Self {
+        self.pitch_variation = variation;
+        self
+    }
+
+    /// See `set_gain_variation` of GenericSource
+    pub fn with_gain_variation(mut self, variation: f32) -> Self {
+        self.gain_variation = variation;
+        self
+    }
+
+    /// See `set_variation_seed` of GenericSource
+    pub fn with_variation_seed(mut self, seed: u64) -> Self {
+        self.variation_seed = Some(seed);
+        self
+    }
+
+    /// See `set_start_delay` of GenericSource
+    pub fn with_start_delay(mut self, delay: Duration) -> Self {
+        self.start_delay = delay;
+        self
+    }
+
     /// Creates new instance of generic sound source. May fail if buffer is invalid.
     pub fn build(self) -> Result<GenericSource, SoundError> {
-        let device_sample_rate = f64::from(crate::device::SAMPLE_RATE);
         let mut locked_buffer = self.buffer.lock()?;
         if let SoundBuffer::Streaming(ref mut streaming) = *locked_buffer {
             if streaming.use_count != 0 {
@@ -499,25 +1679,809 @@ impl GenericSourceBuilder {
             }
             streaming.use_count += 1;
         }
-        let sample_rate = locked_buffer.generic().sample_rate() as f64;
-        let channel_count = locked_buffer.generic().channel_count() as f64;
-        let resampling_multiplier = sample_rate / device_sample_rate * channel_count;
-        Ok(GenericSource {
-            resampling_multiplier,
-            buffer: Some(self.buffer.clone()),
-            gain: self.gain,
-            pitch: self.pitch as f64,
-            play_once: self.play_once,
-            panning: self.panning,
-            status: self.status,
-            looping: self.looping,
-            frame_samples: Default::default(),
-            ..Default::default()
-        })
+        let resampling_multiplier = resampling_multiplier(locked_buffer.generic());
+        // Struct update syntax can't be used here - source implements Drop and has non-Copy
+        // fields, so fields are assigned one by one over default state.
+        let mut source = GenericSource::default();
+        source.resampling_multiplier = resampling_multiplier;
+        source.buffer = Some(self.buffer.clone());
+        source.gain = self.gain;
+        source.pitch = self.pitch as f64;
+        source.play_once = self.play_once;
+        source.panning = self.panning;
+        source.status = self.status;
+        source.looping = self.looping;
+        source.loop_count = self.loop_count.filter(|&count| count != 0);
+        source.priority = self.priority;
+        source.pitch_variation = self.pitch_variation.abs();
+        source.gain_variation = self.gain_variation.abs();
+        source.start_delay = self.start_delay;
+        if let Some(seed) = self.variation_seed {
+            source.variation_rng = Random::new(seed);
+        }
+        if source.status == Status::Playing {
+            source.pick_variation();
+            source.reset_start_delay();
+        }
+        Ok(source)
     }
 
     /// Creates new instance of sound source of `Generic` variant.
     pub fn build_source(self) -> Result<SoundSource, SoundError> {
         Ok(SoundSource::Generic(self.build()?))
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::Duration,
+    };
+    use byteorder::{
+        WriteBytesExt,
+        LittleEndian,
+    };
+    use rg3d_core::{
+        pool::Pool,
+        visitor::Visit,
+    };
+    use crate::{
+        effects::{
+            Effect,
+            reverb::Reverb,
+            bit_crusher::BitCrusher,
+        },
+        buffer::{
+            DataSource,
+            SoundBuffer,
+            generic::{GenericBuffer, ResampleQuality},
+        },
+        source::{
+            Status,
+            generic::{GenericSource, GenericSourceBuilder, Envelope, EnvelopeStage},
+        },
+        dsp::Random,
+        test_support,
+    };
+
+    const SAMPLE_RATE: usize = 44100;
+    const QUALITIES: [ResampleQuality; 3] = [ResampleQuality::Linear, ResampleQuality::Cubic, ResampleQuality::Sinc];
+
+    // Sine of 441 Hz has period of exactly 100 samples at 44100 Hz.
+    fn make_tone(amplitude: f32, len: usize) -> Vec<f32> {
+        test_support::sine(441.0, SAMPLE_RATE, len).iter().map(|s| s * amplitude).collect()
+    }
+
+    fn make_playing(samples: Vec<f32>) -> GenericSource {
+        GenericSourceBuilder::new(test_support::make_buffer(samples, SAMPLE_RATE))
+            .with_status(Status::Playing)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn levels_of_known_tone() {
+        let mut source = make_playing(make_tone(0.5, SAMPLE_RATE));
+        source.render(4400);
+        assert!((source.peak_level() - 0.5).abs() < 1.0e-3);
+        assert!((source.rms_level() - 0.5 / 2.0f32.sqrt()).abs() < 1.0e-3);
+    }
+
+    fn left_channel(source: &GenericSource) -> Vec<f32> {
+        source.current_frame().iter().map(|s| s.0).collect()
+    }
+
+    #[test]
+    fn stop_fades_out_before_rewind() {
+        let mut source = make_playing(vec![1.0; SAMPLE_RATE]);
+        source.render(1000);
+        source.stop().unwrap();
+        assert!(source.is_stopping());
+        assert_eq!(source.status(), Status::Playing);
+        // Position is not reset until fade is done.
+        assert!(source.playback_pos > 0.0);
+
+        // 5 ms is 220 samples.
+        source.render(512);
+        let output = left_channel(&source);
+        assert!(output.windows(2).all(|w| w[1] <= w[0]));
+        assert!(output[0] > 0.99);
+        assert!(output[219] < 0.01);
+        assert!(output[220..].iter().all(|&s| s == 0.0));
+        assert!(!source.is_stopping());
+        assert_eq!(source.status(), Status::Stopped);
+        assert_eq!(source.playback_pos, 0.0);
+    }
+
+    #[test]
+    fn stop_fade_is_cleared_when_buffer_ends() {
+        let mut source = make_playing(vec![1.0; 1000]);
+        source.set_stop_fade(Duration::from_millis(100));
+        source.render(900);
+        source.stop().unwrap();
+        assert!(source.is_stopping());
+        // Fade is 4410 samples long, but buffer ends much earlier.
+        source.render(512);
+        assert!(!source.is_stopping());
+        assert_eq!(source.status(), Status::Stopped);
+        source.play();
+        assert_eq!(source.status(), Status::Playing);
+        assert!(!source.is_stopping());
+    }
+
+    #[test]
+    fn zero_stop_fade_stops_instantly() {
+        let mut source = make_playing(vec![1.0; SAMPLE_RATE]);
+        source.set_stop_fade(Duration::from_secs(0));
+        source.render(1000);
+        source.stop().unwrap();
+        assert!(!source.is_stopping());
+        assert_eq!(source.status(), Status::Stopped);
+        assert_eq!(source.playback_pos, 0.0);
+    }
+
+    #[test]
+    fn play_during_stop_fade_restarts_source() {
+        let samples = (0..SAMPLE_RATE).map(|i| i as f32 / SAMPLE_RATE as f32).collect();
+        let mut source = make_playing(samples);
+        source.render(1000);
+        source.stop().unwrap();
+        source.render(100);
+        assert!(source.is_stopping());
+
+        source.play();
+        assert!(!source.is_stopping());
+        assert_eq!(source.status(), Status::Playing);
+        assert_eq!(source.playback_pos, 0.0);
+        // Playback starts from the beginning without fade.
+        source.render(512);
+        let output = left_channel(&source);
+        assert_eq!(output[0], 0.0);
+        assert!((output[511] - 511.0 / SAMPLE_RATE as f32).abs() < 1.0e-6);
+    }
+
+    // Energy of spectrum bins in given range of frequencies.
+    fn band_energy(samples: &[f32], from: f32, to: f32) -> f32 {
+        let hz_per_bin = SAMPLE_RATE as f32 / samples.len() as f32;
+        test_support::spectrum(samples)
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i as f32 * hz_per_bin >= from && (*i as f32 * hz_per_bin) < to)
+            .map(|(_, m)| m * m)
+            .sum()
+    }
+
+    #[test]
+    fn high_shelf_boosts_only_its_source() {
+        let noise = test_support::noise(8192, 7);
+        let mut plain = make_playing(noise.clone());
+        let mut bright = make_playing(noise.clone());
+        bright.set_high_shelf(12.0);
+        plain.render(8192);
+        bright.render(8192);
+
+        let plain = left_channel(&plain);
+        let bright = left_channel(&bright);
+        assert!(band_energy(&bright, 8000.0, 20000.0) > 4.0 * band_energy(&plain, 8000.0, 20000.0));
+        let low_ratio = band_energy(&bright, 20.0, 200.0) / band_energy(&plain, 20.0, 200.0);
+        assert!((low_ratio - 1.0).abs() < 0.2);
+    }
+
+    #[test]
+    fn zero_db_shelves_are_transparent() {
+        let noise = test_support::noise(4096, 7);
+        let mut plain = make_playing(noise.clone());
+        let mut shelved = make_playing(noise);
+        shelved.set_low_shelf(0.0);
+        shelved.set_high_shelf(0.0);
+        plain.render(4096);
+        shelved.render(4096);
+        assert_eq!(left_channel(&shelved), left_channel(&plain));
+    }
+
+    #[test]
+    fn current_frame_contains_tone() {
+        let mut source = make_playing(make_tone(1.0, SAMPLE_RATE));
+        source.set_gain(0.5);
+        source.render(4400);
+
+        let frame = left_channel(&source);
+        assert_eq!(frame.len(), 4400);
+        assert!((source.peak_level() - 1.0).abs() < 1.0e-3);
+        // 441 Hz tone gives 44 periods in 4400 samples.
+        assert_eq!(test_support::peak_bin(&frame), 44);
+
+        let gained_peak = source.current_frame_with_gain().fold(0.0f32, |peak, (left, _)| peak.max(left.abs()));
+        assert!((gained_peak - 0.5).abs() < 1.0e-3);
+        for ((left, right), &(raw_left, raw_right)) in source.current_frame_with_gain().zip(source.current_frame()) {
+            assert_eq!(left, raw_left * 0.5);
+            assert_eq!(right, raw_right * 0.5);
+        }
+    }
+
+    // Constant signal makes output of source equal to its envelope.
+    fn render_levels(source: &mut GenericSource, amount: usize) -> Vec<f32> {
+        source.render(amount);
+        left_channel(source)
+    }
+
+    #[test]
+    fn envelope_follows_adsr_shape() {
+        // 441 samples per stage.
+        let stage = Duration::from_millis(10);
+        let mut source = make_playing(vec![1.0; SAMPLE_RATE]);
+        source.set_envelope(stage, stage, 0.5, stage);
+
+        let levels = render_levels(&mut source, 2000);
+        // Attack.
+        assert!((levels[219] - 0.5).abs() < 0.01);
+        assert!((levels[440] - 1.0).abs() < 0.01);
+        assert!(levels[..441].windows(2).all(|w| w[1] >= w[0]));
+        // Decay.
+        assert!((levels[661] - 0.75).abs() < 0.01);
+        assert!(levels[441..882].windows(2).all(|w| w[1] <= w[0]));
+        // Sustain.
+        assert!(levels[900..].iter().all(|&l| l == 0.5));
+
+        // Release.
+        source.note_off();
+        let levels = render_levels(&mut source, 1000);
+        assert!((levels[219] - 0.25).abs() < 0.01);
+        assert!(levels[..441].windows(2).all(|w| w[1] <= w[0]));
+        assert!(levels[441..].iter().all(|&l| l == 0.0));
+        assert_eq!(source.status(), Status::Stopped);
+        assert!(source.is_finished());
+    }
+
+    #[test]
+    fn note_off_releases_from_current_level() {
+        let stage = Duration::from_millis(10);
+        let mut source = make_playing(vec![1.0; SAMPLE_RATE]);
+        source.set_envelope(stage, stage, 0.5, stage);
+
+        // Quarter of attack.
+        render_levels(&mut source, 110);
+        let level = source.envelope_level().unwrap();
+        assert!((level - 0.25).abs() < 0.01);
+
+        source.note_off();
+        let levels = render_levels(&mut source, 1000);
+        assert!(levels[0] < level);
+        assert!((levels[219] - level * 0.5).abs() < 0.01);
+        assert!(levels[441..].iter().all(|&l| l == 0.0));
+    }
+
+    #[test]
+    fn each_play_picks_seeded_pitch_variation() {
+        let mut source = GenericSourceBuilder::new(test_support::make_buffer(vec![1.0; 64], SAMPLE_RATE))
+            .with_pitch_variation(0.1)
+            .with_variation_seed(7)
+            .build()
+            .unwrap();
+        let mut expected = Random::new(7);
+        let mut pitches = Vec::new();
+        for _ in 0..3 {
+            source.play();
+            let pitch = source.pitch_variation_factor();
+            assert_eq!(pitch, f64::from(expected.range(0.9, 1.1)));
+            assert!((0.9..=1.1).contains(&pitch));
+            // There is no gain variation.
+            assert_eq!(source.gain_variation_factor(), 1.0);
+            pitches.push(pitch);
+            source.stop_immediately().unwrap();
+        }
+        assert!(pitches[0] != pitches[1] && pitches[1] != pitches[2]);
+    }
+
+    #[test]
+    fn zero_variation_is_deterministic() {
+        let mut source = make_playing(vec![1.0; 64]);
+        for _ in 0..3 {
+            source.stop_immediately().unwrap();
+            source.play();
+            assert_eq!(source.pitch_variation_factor(), 1.0);
+            assert_eq!(source.gain_variation_factor(), 1.0);
+        }
+    }
+
+    #[test]
+    fn many_sources_share_one_buffer() {
+        let buffer = test_support::make_buffer(make_tone(0.5, 2 * SAMPLE_RATE), SAMPLE_RATE);
+        let mut sources = (0..100)
+            .map(|_| GenericSourceBuilder::new(buffer.clone())
+                .with_status(Status::Playing)
+                .build()
+                .unwrap())
+            .collect::<Vec<_>>();
+        // Samples are not copied, each source holds only reference to the same buffer.
+        assert_eq!(Arc::strong_count(&buffer), 101);
+        assert!(sources.iter().all(|s| Arc::ptr_eq(&s.buffer().unwrap(), &buffer)));
+        // But each source has its own playback cursor.
+        sources[0].render(SAMPLE_RATE + 100);
+        assert_eq!(sources[0].playback_time(), Duration::from_secs(1));
+        assert_eq!(sources[1].playback_time(), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn replaced_data_is_played_from_clamped_position() {
+        let buffer = test_support::make_buffer(vec![1.0; 1000], SAMPLE_RATE);
+        let mut source = GenericSourceBuilder::new(buffer.clone())
+            .with_status(Status::Playing)
+            .with_looping(true)
+            .build()
+            .unwrap();
+        source.render(800);
+
+        buffer.lock().unwrap().replace_data(DataSource::Raw {
+            sample_rate: SAMPLE_RATE,
+            channel_count: 1,
+            samples: vec![0.25; 100],
+        }).ok().unwrap();
+
+        // Cursor was beyond end of new data, it is clamped and source continues to play new data.
+        let levels = render_levels(&mut source, 300);
+        assert_eq!(source.status(), Status::Playing);
+        assert!(levels.iter().all(|&s| s == 0.25));
+    }
+
+    #[test]
+    fn start_delay_postpones_first_sample() {
+        let mut source = GenericSourceBuilder::new(test_support::make_buffer(vec![1.0; SAMPLE_RATE], SAMPLE_RATE))
+            .build()
+            .unwrap();
+        source.set_start_delay(Duration::from_millis(100));
+        source.play();
+
+        let mut output = Vec::new();
+        for _ in 0..6 {
+            output.extend(render_levels(&mut source, 1000));
+        }
+        // 100 ms at 44100 Hz.
+        assert_eq!(output.iter().position(|&s| s != 0.0), Some(4410));
+        assert!(output[4410..].iter().all(|&s| s == 1.0));
+    }
+
+    #[test]
+    fn one_shot_is_finished_after_its_end_and_looping_never() {
+        let mut one_shot = make_playing(vec![0.5; 1000]);
+        let mut looping = GenericSourceBuilder::new(test_support::make_buffer(vec![0.5; 1000], SAMPLE_RATE))
+            .with_status(Status::Playing)
+            .with_looping(true)
+            .build()
+            .unwrap();
+        one_shot.render(512);
+        looping.render(512);
+        assert!(!one_shot.is_finished());
+        assert_eq!(one_shot.status(), Status::Playing);
+
+        for _ in 0..4 {
+            one_shot.render(512);
+            looping.render(512);
+        }
+        assert!(one_shot.is_finished());
+        assert_eq!(one_shot.status(), Status::Stopped);
+        assert!(!looping.is_finished());
+        assert_eq!(looping.status(), Status::Playing);
+
+        // Stopped by user is not finished, replay resets the flag.
+        one_shot.play();
+        assert!(!one_shot.is_finished());
+        one_shot.stop_immediately().unwrap();
+        assert!(!one_shot.is_finished());
+    }
+
+    fn make_raw_source(channel_count: usize, samples: Vec<f32>) -> GenericSource {
+        let buffer = SoundBuffer::new_generic(DataSource::Raw {
+            sample_rate: SAMPLE_RATE,
+            channel_count,
+            samples,
+        }).ok().unwrap();
+        assert_eq!(buffer.lock().unwrap().channel_count(), channel_count);
+        GenericSourceBuilder::new(buffer)
+            .with_status(Status::Playing)
+            .build()
+            .unwrap()
+    }
+
+    fn render_raw(channel_count: usize, samples: Vec<f32>) -> Vec<(f32, f32)> {
+        let mut source = make_raw_source(channel_count, samples);
+        source.render(256);
+        source.current_frame().to_vec()
+    }
+
+    #[test]
+    fn mono_is_played_in_both_channels() {
+        let frame = render_raw(1, test_support::noise(1000, 1));
+        assert!(frame.iter().all(|&(left, right)| left == right));
+        assert!(frame.iter().any(|&(left, _)| left != 0.0));
+    }
+
+    #[test]
+    fn stereo_channels_are_preserved() {
+        let samples = (0..1000).flat_map(|_| vec![0.25, -0.5]).collect();
+        let frame = render_raw(2, samples);
+        assert!(frame.iter().all(|&(left, right)| left == 0.25 && right == -0.5));
+    }
+
+    fn render_with_matrix(matrix: &[&[f32]]) -> Vec<(f32, f32)> {
+        let samples = (0..1000).flat_map(|_| vec![0.25, -0.5]).collect();
+        let mut source = make_raw_source(2, samples);
+        source.set_output_matrix(matrix).unwrap();
+        source.render(256);
+        source.current_frame().to_vec()
+    }
+
+    #[test]
+    fn identity_output_matrix_is_transparent() {
+        let frame = render_with_matrix(&[&[1.0, 0.0], &[0.0, 1.0]]);
+        assert_eq!(frame, render_raw(2, (0..1000).flat_map(|_| vec![0.25, -0.5]).collect()));
+    }
+
+    #[test]
+    fn swap_output_matrix_exchanges_channels() {
+        let frame = render_with_matrix(&[&[0.0, 1.0], &[1.0, 0.0]]);
+        assert!(frame.iter().all(|&(left, right)| left == -0.5 && right == 0.25));
+
+        // Removing matrix restores default mapping.
+        let mut source = make_raw_source(2, (0..1000).flat_map(|_| vec![0.25, -0.5]).collect());
+        source.set_output_matrix(&[&[0.0, 1.0], &[1.0, 0.0]]).unwrap();
+        source.remove_output_matrix();
+        source.render(256);
+        assert!(source.current_frame().iter().all(|&(left, right)| left == 0.25 && right == -0.5));
+    }
+
+    #[test]
+    fn output_matrix_dimensions_are_validated() {
+        let mut stereo = make_raw_source(2, vec![0.0; 64]);
+        // Mono matrix for stereo buffer.
+        assert!(stereo.set_output_matrix(&[&[1.0], &[1.0]]).is_err());
+        // Wrong number of output channels.
+        assert!(stereo.set_output_matrix(&[&[1.0, 0.0]]).is_err());
+        // Ragged matrix.
+        assert!(stereo.set_output_matrix(&[&[1.0, 0.0], &[1.0]]).is_err());
+
+        let mut mono = make_raw_source(1, vec![0.0; 64]);
+        assert!(mono.set_output_matrix(&[&[1.0], &[0.0]]).is_ok());
+        assert!(mono.set_output_matrix(&[&[1.0, 0.0], &[0.0, 1.0]]).is_err());
+    }
+
+    // Renders the source until it finishes, returns left channel.
+    fn render_until_finished(source: &mut GenericSource) -> Vec<f32> {
+        let mut output = Vec::new();
+        while !source.is_finished() {
+            assert!(output.len() < 10 * SAMPLE_RATE);
+            source.render(1024);
+            output.extend(source.current_frame().iter().map(|s| s.0));
+        }
+        output
+    }
+
+    // Length of signal without trailing silence.
+    fn audible_len(samples: &[f32]) -> usize {
+        samples.iter().rposition(|s| s.abs() > 1.0e-3).map_or(0, |i| i + 1)
+    }
+
+    #[test]
+    fn time_stretch_doubles_duration_and_keeps_frequency() {
+        // 441 Hz, period of exactly 100 samples, 0.5 second.
+        let tone = test_support::sine(441.0, SAMPLE_RATE, SAMPLE_RATE / 2);
+        let mut plain = make_playing(tone.clone());
+        let plain = render_until_finished(&mut plain);
+        let mut stretched = make_playing(tone);
+        stretched.set_time_stretch(2.0);
+        assert_eq!(stretched.time_stretch(), 2.0);
+        let stretched = render_until_finished(&mut stretched);
+
+        let plain_len = audible_len(&plain);
+        let stretched_len = audible_len(&stretched);
+        assert_eq!(plain_len, SAMPLE_RATE / 2);
+        // Stretcher has latency of ~30 ms.
+        let tolerance = SAMPLE_RATE * 30 / 1000;
+        assert!((stretched_len as isize - 2 * plain_len as isize).abs() < tolerance as isize, "{}", stretched_len);
+
+        // Window of 44 periods, tone is in bin 44.
+        assert_eq!(test_support::peak_bin(&plain[4400..8800]), 44);
+        assert_eq!(test_support::peak_bin(&stretched[8800..13200]), 44);
+        assert_eq!(test_support::peak_bin(&stretched[30000..34400]), 44);
+    }
+
+    #[test]
+    fn time_stretch_is_reset_when_played_again() {
+        let mut source = make_playing(test_support::sine(441.0, SAMPLE_RATE, SAMPLE_RATE / 4));
+        source.set_time_stretch(1.5);
+        let first = render_until_finished(&mut source);
+        assert_eq!(source.status(), Status::Stopped);
+        source.play();
+        let second = render_until_finished(&mut source);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn loop_count_stops_after_exact_amount_of_playthroughs() {
+        let mut source = GenericSourceBuilder::new(test_support::make_buffer(vec![0.5; 100], SAMPLE_RATE))
+            .with_status(Status::Playing)
+            .with_looping(true)
+            .with_loop_count(Some(3))
+            .build()
+            .unwrap();
+        source.render(1000);
+        assert!(source.is_finished());
+        assert_eq!(source.status(), Status::Stopped);
+        // Exactly three playthroughs of 100 samples each are heard.
+        let audible = source.current_frame().iter().filter(|s| s.0 != 0.0).count();
+        assert_eq!(audible, 300);
+        assert!(source.current_frame()[300..].iter().all(|s| s.0 == 0.0));
+
+        // Counter is reset on replay.
+        source.play();
+        source.render(250);
+        assert!(!source.is_finished());
+        source.render(250);
+        assert!(source.is_finished());
+    }
+
+    fn make_source_with_quality(samples: Vec<f32>, sample_rate: usize, quality: ResampleQuality) -> GenericSource {
+        let buffer = SoundBuffer::new_generic_with_quality(DataSource::Raw {
+            sample_rate,
+            channel_count: 1,
+            samples,
+        }, quality).ok().unwrap();
+        GenericSourceBuilder::new(buffer)
+            .with_status(Status::Playing)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn every_sample_is_played_once_at_unit_rate() {
+        for &quality in QUALITIES.iter() {
+            let samples = (0..8).map(|i| i as f32).collect::<Vec<f32>>();
+            let mut source = make_source_with_quality(samples.clone(), SAMPLE_RATE, quality);
+            let output = render_levels(&mut source, 10);
+            for (i, &sample) in output.iter().enumerate() {
+                let expected = samples.get(i).cloned().unwrap_or(0.0);
+                if quality == ResampleQuality::Sinc {
+                    assert!((sample - expected).abs() < 1.0e-5, "{:?}", output);
+                } else {
+                    assert_eq!(sample, expected, "{:?} {:?}", quality, output);
+                }
+            }
+            assert!(source.is_finished());
+        }
+    }
+
+    #[test]
+    fn interpolation_is_continuous_across_loop_point() {
+        let part = test_support::noise(1000, 3);
+        let twice = part.iter().chain(part.iter()).cloned().collect::<Vec<f32>>();
+        for &quality in QUALITIES.iter() {
+            // Two playthroughs of looping buffer must sound exactly like buffer with data repeated twice.
+            let mut looping = make_source_with_quality(part.clone(), SAMPLE_RATE, quality);
+            looping.set_looping(true);
+            looping.set_loop_count(Some(2));
+            looping.set_pitch(1.3);
+            let mut plain = make_source_with_quality(twice.clone(), SAMPLE_RATE, quality);
+            plain.set_pitch(1.3);
+
+            let looping = render_until_finished(&mut looping);
+            let plain = render_until_finished(&mut plain);
+            assert_eq!(looping.len(), plain.len());
+            for (a, b) in looping.iter().zip(plain.iter()) {
+                assert!((a - b).abs() < 1.0e-4, "{:?}: {} {}", quality, a, b);
+            }
+        }
+    }
+
+    // Mono 32-bit float WAV.
+    fn make_wav(samples: &[f32]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.write_u32::<LittleEndian>(36 + 4 * samples.len() as u32).unwrap();
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.write_u32::<LittleEndian>(16).unwrap();
+        bytes.write_u16::<LittleEndian>(3).unwrap();
+        bytes.write_u16::<LittleEndian>(1).unwrap();
+        bytes.write_u32::<LittleEndian>(SAMPLE_RATE as u32).unwrap();
+        bytes.write_u32::<LittleEndian>(4 * SAMPLE_RATE as u32).unwrap();
+        bytes.write_u16::<LittleEndian>(4).unwrap();
+        bytes.write_u16::<LittleEndian>(32).unwrap();
+        bytes.extend_from_slice(b"data");
+        bytes.write_u32::<LittleEndian>(4 * samples.len() as u32).unwrap();
+        for &sample in samples {
+            bytes.write_f32::<LittleEndian>(sample).unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn interpolation_is_continuous_across_streaming_blocks() {
+        // Longer than two blocks of streaming buffer.
+        let samples = test_support::noise(100_000, 4);
+        for &quality in QUALITIES.iter() {
+            let streaming = SoundBuffer::new_streaming_with_quality(DataSource::from_memory(make_wav(&samples)), quality)
+                .ok()
+                .unwrap();
+            assert_eq!(streaming.lock().unwrap().generic().resample_quality(), quality);
+            let mut streaming = GenericSourceBuilder::new(streaming)
+                .with_status(Status::Playing)
+                .build()
+                .unwrap();
+            streaming.set_pitch(1.3);
+            let mut generic = make_source_with_quality(samples.clone(), SAMPLE_RATE, quality);
+            generic.set_pitch(1.3);
+
+            // Streaming buffer has whole data only in generic buffer, so output must match.
+            let mut streaming_output = Vec::new();
+            let mut generic_output = Vec::new();
+            for _ in 0..70 {
+                streaming_output.extend(render_levels(&mut streaming, 1000));
+                generic_output.extend(render_levels(&mut generic, 1000));
+            }
+            for (i, (a, b)) in streaming_output.iter().zip(generic_output.iter()).enumerate() {
+                assert!((a - b).abs() < 1.0e-4, "{:?} at {}: {} {}", quality, i, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn higher_quality_has_less_aliasing() {
+        // 9 kHz tone at 22050 Hz is upsampled to 44100 Hz, its image is at 22050 - 9000 = 13050 Hz.
+        let tone = test_support::sine(9000.0, SAMPLE_RATE / 2, SAMPLE_RATE / 2);
+        let energies = QUALITIES.iter()
+            .map(|&quality| {
+                let mut source = make_source_with_quality(tone.clone(), SAMPLE_RATE / 2, quality);
+                // Skip beginning, window of 4410 samples has 10 Hz bins.
+                source.render(1000);
+                let output = render_levels(&mut source, 4410);
+                let spectrum = test_support::spectrum(&output);
+                (spectrum[900] * spectrum[900], spectrum[1305] * spectrum[1305])
+            })
+            .collect::<Vec<(f32, f32)>>();
+        // Tone of unit amplitude has magnitude of half of window length. Sinc interpolation preserves
+        // it, the others attenuate high frequencies a bit.
+        let full = 2205.0f32 * 2205.0;
+        assert!((energies[2].0 / full - 1.0).abs() < 0.05, "{:?}", energies);
+        assert!(energies.iter().all(|&(tone, _)| tone > 0.3 * full), "{:?}", energies);
+        let aliasing = energies.iter().map(|&(tone, image)| image / tone).collect::<Vec<f32>>();
+        assert!(aliasing[0] > aliasing[1] && aliasing[1] > aliasing[2], "{:?}", aliasing);
+        assert!(aliasing[2] < 1.0e-3, "{:?}", aliasing);
+    }
+
+    #[test]
+    fn resample_quality_is_kept_by_buffer() {
+        assert_eq!(ResampleQuality::default(), ResampleQuality::Cubic);
+        let make = || DataSource::Raw { sample_rate: SAMPLE_RATE, channel_count: 1, samples: vec![0.0; 10] };
+        let buffer = SoundBuffer::new_generic_with_quality(make(), ResampleQuality::Sinc).ok().unwrap();
+        let mut buffer = buffer.lock().unwrap();
+        assert_eq!(buffer.generic().resample_quality(), ResampleQuality::Sinc);
+        buffer.replace_data(make()).ok().unwrap();
+        assert_eq!(buffer.generic().resample_quality(), ResampleQuality::Sinc);
+
+        for &quality in QUALITIES.iter() {
+            let mut generic = GenericBuffer::new(make()).ok().unwrap();
+            generic.set_resample_quality(quality);
+            let mut visitor = test_support::reload(|visitor| generic.visit("Buffer", visitor));
+            let mut loaded = GenericBuffer::default();
+            loaded.visit("Buffer", &mut visitor).unwrap();
+            assert_eq!(loaded.resample_quality(), quality);
+        }
+    }
+
+    #[test]
+    fn settings_are_visited() {
+        let mut effects = Pool::new();
+        let reverb = effects.spawn(Effect::Reverb(Reverb::new(Default::default())));
+        let bit_crusher = effects.spawn(Effect::BitCrusher(BitCrusher::new(Default::default())));
+        let mut visitor = test_support::reload(|visitor| {
+            let mut source = GenericSource::default();
+            source.set_priority(7);
+            source.set_gain_smoothing(Duration::from_millis(30));
+            source.set_low_shelf(3.0);
+            source.set_high_shelf(-6.0);
+            source.set_reverb_send(0.5);
+            source.set_envelope(Duration::from_millis(10), Duration::from_millis(20), 0.5, Duration::from_millis(30));
+            source.set_pitch_variation(0.1);
+            source.set_gain_variation(0.2);
+            source.set_variation_seed(1);
+            source.set_start_delay(Duration::from_millis(100));
+            source.set_output_matrix(&[&[0.0, 1.0], &[1.0, 0.0]]).unwrap();
+            source.set_time_stretch(2.0);
+            source.set_loop_count(Some(3));
+            source.set_send(reverb, 0.5).set_send(bit_crusher, 0.25);
+            source.play();
+            source.completed_loops = 2;
+            source.set_stop_fade(Duration::from_millis(20));
+            source.stop_fade_state = Some((882, 100));
+            source.visit("Source", visitor)
+        });
+        let mut source = GenericSource::default();
+        source.visit("Source", &mut visitor).unwrap();
+        assert_eq!(source.priority(), 7);
+        assert_eq!(source.gain_smoothing(), Duration::from_millis(30));
+        assert_eq!(source.low_shelf(), 3.0);
+        assert_eq!(source.high_shelf(), -6.0);
+        assert!(source.tone.is_some());
+        assert_eq!(source.reverb_send(), 0.5);
+        let envelope = source.envelope.as_ref().unwrap();
+        let expected = Envelope::new(Duration::from_millis(10), Duration::from_millis(20), 0.5, Duration::from_millis(30));
+        assert_eq!(envelope.attack_step, expected.attack_step);
+        assert_eq!(envelope.decay_step, expected.decay_step);
+        assert_eq!(envelope.sustain, 0.5);
+        assert_eq!(envelope.release, Duration::from_millis(30));
+        assert_eq!(envelope.stage, EnvelopeStage::Attack);
+        assert_eq!(source.pitch_variation(), 0.1);
+        assert_eq!(source.gain_variation(), 0.2);
+        let mut expected = GenericSource::default();
+        expected.set_pitch_variation(0.1).set_gain_variation(0.2).set_variation_seed(1).play();
+        assert_eq!(source.pitch_variation_factor(), expected.pitch_variation_factor());
+        assert_eq!(source.gain_variation_factor(), expected.gain_variation_factor());
+        assert_ne!(source.pitch_variation_factor(), 1.0);
+        assert_eq!(source.start_delay(), Duration::from_millis(100));
+        assert_eq!(source.delay_samples_left, 4410);
+        assert_eq!(source.output_matrix, Some([[0.0, 1.0], [1.0, 0.0]]));
+        assert_eq!(source.time_stretch(), 2.0);
+        assert_eq!(source.loop_count(), Some(3));
+        assert_eq!(source.completed_loops(), 2);
+        assert_eq!(source.sends(), &[(reverb, 0.5), (bit_crusher, 0.25)]);
+        assert_eq!(source.stop_fade(), Duration::from_millis(20));
+        assert_eq!(source.stop_fade_state, Some((882, 100)));
+    }
+
+    #[test]
+    fn missing_settings_fall_back_to_defaults() {
+        let mut visitor = test_support::reload(|visitor| {
+            // Fields that were saved by older versions.
+            let mut source = GenericSource::default();
+            visitor.enter_region("Source")?;
+            source.buffer.visit("Buffer", visitor)?;
+            source.buf_read_pos.visit("BufReadPos", visitor)?;
+            source.playback_pos.visit("PlaybackPos", visitor)?;
+            source.panning.visit("Pan", visitor)?;
+            source.pitch.visit("Pitch", visitor)?;
+            source.gain.visit("Gain", visitor)?;
+            source.looping.visit("Looping", visitor)?;
+            source.resampling_multiplier.visit("ResamplingMultiplier", visitor)?;
+            source.status.visit("Status", visitor)?;
+            source.play_once.visit("PlayOnce", visitor)?;
+            visitor.leave_region()
+        });
+        let mut source = GenericSource::default();
+        source.set_priority(7);
+        source.set_gain_smoothing(Duration::from_millis(30));
+        source.set_low_shelf(3.0);
+        source.set_high_shelf(-6.0);
+        source.set_reverb_send(0.5);
+        source.set_envelope(Duration::from_millis(10), Duration::from_millis(20), 0.5, Duration::from_millis(30));
+        source.set_pitch_variation(0.1).set_gain_variation(0.2);
+        source.set_start_delay(Duration::from_millis(100)).play();
+        source.set_output_matrix(&[&[0.0, 1.0], &[1.0, 0.0]]).unwrap();
+        source.set_time_stretch(2.0);
+        source.set_loop_count(Some(3));
+        source.completed_loops = 2;
+        source.set_send(Default::default(), 0.5);
+        source.set_stop_fade(Duration::from_millis(20));
+        source.stop_fade_state = Some((882, 100));
+        source.visit("Source", &mut visitor).unwrap();
+        assert_eq!(source.priority(), 0);
+        assert_eq!(source.gain_smoothing(), Duration::from_secs(0));
+        assert_eq!(source.low_shelf(), 0.0);
+        assert_eq!(source.high_shelf(), 0.0);
+        assert!(source.tone.is_none());
+        assert_eq!(source.reverb_send(), 1.0);
+        assert!(source.envelope.is_none());
+        assert_eq!(source.pitch_variation(), 0.0);
+        assert_eq!(source.gain_variation(), 0.0);
+        assert_eq!(source.pitch_variation_factor(), 1.0);
+        assert_eq!(source.gain_variation_factor(), 1.0);
+        assert_eq!(source.start_delay(), Duration::from_secs(0));
+        assert_eq!(source.delay_samples_left, 0);
+        assert!(source.output_matrix.is_none());
+        assert_eq!(source.time_stretch(), 1.0);
+        assert!(source.time_stretcher.is_none());
+        assert_eq!(source.loop_count(), None);
+        assert_eq!(source.completed_loops(), 0);
+        assert!(source.sends().is_empty());
+        assert_eq!(source.stop_fade(), GenericSource::DEFAULT_STOP_FADE);
+        assert!(!source.is_stopping());
+    }
+}
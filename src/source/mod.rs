@@ -8,7 +8,7 @@
 
 use crate::source::{
     generic::GenericSource,
-    spatial::SpatialSource,
+    spatial::{SpatialSource, SpatialRenderState},
 };
 use rg3d_core::visitor::{
     Visit,
@@ -35,6 +35,16 @@ pub enum Status {
     Paused,
 }
 
+/// Per-source state of renderer: interpolation state of gains and spatial parameters and HRTF
+/// convolution state. Default state is clean state of source that was never rendered. Context keeps
+/// separate state for renderer which is faded out, see `Context::crossfade_renderer`.
+#[derive(Default)]
+pub(in crate) struct RenderState {
+    last_left_gain: Option<f32>,
+    last_right_gain: Option<f32>,
+    spatial: SpatialRenderState,
+}
+
 /// See module docs.
 pub enum SoundSource {
     /// See `generic` module docs.
@@ -82,6 +92,17 @@ impl SoundSource {
             SoundSource::Spatial(ref mut spatial) => spatial,
         }
     }
+
+    /// Swaps state of renderer of the source with given one, so source can be rendered by another
+    /// renderer without affecting interpolation state of current one.
+    pub(in crate) fn swap_render_state(&mut self, state: &mut RenderState) {
+        let generic = self.generic_mut();
+        std::mem::swap(&mut generic.last_left_gain, &mut state.last_left_gain);
+        std::mem::swap(&mut generic.last_right_gain, &mut state.last_right_gain);
+        if let SoundSource::Spatial(spatial) = self {
+            spatial.swap_render_state(&mut state.spatial);
+        }
+    }
 }
 
 impl Visit for Status {
@@ -18,50 +18,269 @@
 //!
 //! fn make_source(context: &mut Context, buffer: Arc<Mutex<SoundBuffer>>) -> Handle<SoundSource> {
 //!     let source = SpatialSourceBuilder::new(GenericSourceBuilder::new(buffer)
-//!         .build()
 //!         .with_status(Status::Playing)
+//!         .build()
 //!         .unwrap())
 //!         .build_source();
 //!     context.add_source(source)
 //! }
 //! ```
+//!
+//! # Stereo spread
+//!
+//! HRTF renderer can process only mono signal, so by default stereo buffers are downmixed. If stereo
+//! spread is set (see `SpatialSource::set_stereo_spread`), channels of stereo buffer are rendered as
+//! two virtual mono sources which are placed at the left and at the right of source position (relative
+//! to listener), so stereo content is preserved in 3D. Default (panning) renderer ignores stereo
+//! spread, it passes stereo frames as is.
+//!
+//! # Update throttling
+//!
+//! By default spatial parameters of source (distance gain, panning and direction for HRTF) are
+//! recalculated for each rendered block. In scenes with hundreds of distant slowly moving sources
+//! this is wasteful, so source can recalculate them only once per given amount of blocks (see
+//! `SpatialSource::set_spatial_update_interval`), parameters are interpolated in between. This adds
+//! latency of the same amount of blocks to spatial changes. Throttling is applied by default and HRTF
+//! renderers for main listener, other listeners and renderers always use fresh parameters.
+//!
+//! # Velocity
+//!
+//! Velocity of source is derived from its movement: either from timestamped positions passed to
+//! `SpatialSource::set_position_at`, or (for sources moved by `SpatialSource::set_position`) from
+//! movement between calls of `Context::update`. Velocity is informational only - renderers do not use
+//! it and there is no Doppler effect, but game code can use it to drive pitch of source or its other
+//! parameters.
+//!
+//! # Distance attenuation
+//!
+//! Distance gain is calculated each time source is rendered using current radius, rolloff factor and max
+//! distance of source and current distance model of context (see `Context::set_distance_model`). So all
+//! these parameters can be changed at any time while source is playing, new values will take effect on
+//! next rendered block. Renderers interpolate gain between blocks, so such changes won't produce clicks.
+//!
+//! ```no_run
+//! use rg3d_sound::context::Context;
+//! use rg3d_sound::pool::Handle;
+//! use rg3d_sound::source::SoundSource;
+//!
+//! fn make_less_audible(context: &mut Context, handle: Handle<SoundSource>) {
+//!     if let Some(SoundSource::Spatial(spatial)) = context.source_mut(handle) {
+//!         spatial.set_rolloff_factor(spatial.rolloff_factor() * 2.0);
+//!     }
+//! }
+//! ```
 
-use rg3d_core::visitor::{
-    Visitor,
-    VisitResult,
-    Visit,
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+use rg3d_core::{
+    visitor::{
+        Visitor,
+        VisitResult,
+        Visit,
+    },
+    pool::Handle,
 };
 use crate::{
-    math::vec3::Vec3,
+    math::{
+        self,
+        vec3::Vec3,
+    },
     source::{
         generic::GenericSource,
         SoundSource,
     },
     listener::Listener,
     context::DistanceModel,
+    dsp::FractionalDelayLine,
+    hrtf::HrtfFilter,
+    effects::Effect,
 };
 
+/// Rendering quality hint of spatial source. Renderers may use cheaper processing for sources which
+/// are not important (background, distant ambience).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum SourceQuality {
+    /// Full quality: HRTF renderer uses bilinear sampling of HRTF sphere and interpolates HRTF for
+    /// each block. This is default quality.
+    #[default]
+    High,
+
+    /// Reduced quality: HRTF renderer uses HRTF of nearest point of HRTF sphere which is sampled only
+    /// once per output buffer. Much cheaper, but movement of source is less smooth.
+    Fast,
+}
+
+/// Coordinate space in which position of spatial source is given.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum SpatialSpace {
+    /// Position is given in world space, this is default space.
+    #[default]
+    World,
+
+    /// Position is given in coordinate system of listener: x - along ear (side) axis, y - along up
+    /// axis, z - along look axis of listener, origin is at position of listener. Such source moves
+    /// and rotates together with listener, which is useful for sounds that are locked to the head
+    /// (UI, helmet radio, etc.) but still should be localized.
+    HeadRelative,
+}
+
+impl SpatialSpace {
+    fn id(self) -> u32 {
+        match self {
+            SpatialSpace::World => 0,
+            SpatialSpace::HeadRelative => 1,
+        }
+    }
+
+    fn from_id(id: u32) -> Self {
+        match id {
+            1 => SpatialSpace::HeadRelative,
+            _ => SpatialSpace::World,
+        }
+    }
+}
+
+/// Spatial parameters of the source relative to listener, which are used by renderers.
+#[derive(Copy, Clone)]
+pub(in crate) struct SpatialParams {
+    pub(in crate) distance_gain: f32,
+    pub(in crate) panning: f32,
+    pub(in crate) sampling_vector: Vec3,
+}
+
+impl SpatialParams {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        let sampling_vector = self.sampling_vector.lerp(&other.sampling_vector, t);
+        Self {
+            distance_gain: math::lerpf(self.distance_gain, other.distance_gain, t),
+            panning: math::lerpf(self.panning, other.panning, t),
+            sampling_vector: sampling_vector.normalized().unwrap_or(other.sampling_vector),
+        }
+    }
+}
+
+/// State of HRTF renderer for single virtual mono source.
+pub(in crate) struct HrtfChannelState {
+    // Some data that needed for iterative overlap-save convolution.
+    pub(in crate) prev_left_samples: Vec<f32>,
+    pub(in crate) prev_right_samples: Vec<f32>,
+    pub(in crate) prev_sampling_vector: Vec3,
+    // Interaural time difference state.
+    pub(in crate) left_itd_line: FractionalDelayLine,
+    pub(in crate) right_itd_line: FractionalDelayLine,
+    pub(in crate) prev_itd: Option<(f32, f32)>,
+}
+
+impl Default for HrtfChannelState {
+    fn default() -> Self {
+        Self {
+            prev_left_samples: Default::default(),
+            prev_right_samples: Default::default(),
+            prev_sampling_vector: Vec3::new(0.0, 0.0, 1.0),
+            left_itd_line: Default::default(),
+            right_itd_line: Default::default(),
+            prev_itd: None,
+        }
+    }
+}
+
+/// Part of per-source state of renderer that belongs to spatial source, see `RenderState`.
+#[derive(Default)]
+pub(in crate) struct SpatialRenderState {
+    spatial_params: Option<(SpatialParams, SpatialParams)>,
+    blocks_since_update: u32,
+    smoothed_distance_gain: Option<f32>,
+    hrtf_channels: [HrtfChannelState; 2],
+    prev_distance_gain: Option<f32>,
+}
+
 /// See module docs.
 pub struct SpatialSource {
     generic: GenericSource,
     radius: f32,
     position: Vec3,
+    // Derived from positions passed to `set_position_at` or from movement between `Context::update` calls.
+    velocity: Vec3,
+    // Timestamp of last `set_position_at` call, None if position was set without it.
+    position_time: Option<Duration>,
+    // Position at last `Context::update` call.
+    update_position: Option<Vec3>,
     max_distance: f32,
     rolloff_factor: f32,
-    // Some data that needed for iterative overlap-save convolution.
-    pub(in crate) prev_left_samples: Vec<f32>,
-    pub(in crate) prev_right_samples: Vec<f32>,
-    pub(in crate) prev_sampling_vector: Vec3,
+    spatial_blend: f32,
+    stereo_spread: f32,
+    min_gain: f32,
+    max_gain: f32,
+    // Breakpoints (distance, gain) sorted by distance, empty if distance model of context is used.
+    falloff_curve: Vec<(f32, f32)>,
+    spatial_update_interval: u32,
+    // Spatial parameters at the beginning and at the end of current update interval.
+    spatial_params: Option<(SpatialParams, SpatialParams)>,
+    // Amount of blocks rendered since last update of spatial parameters.
+    blocks_since_update: u32,
+    distance_gain_smoothing: Duration,
+    // Distance gain that is actually used by renderers, it follows calculated distance gain with
+    // respect to `distance_gain_smoothing`. None means that source has not been rendered yet.
+    smoothed_distance_gain: Option<f32>,
+    hrtf_filter: Option<Arc<HrtfFilter>>,
+    quality: SourceQuality,
+    space: SpatialSpace,
+    // HRTF state of downmixed source (first one) or of left and right channels if there is stereo spread.
+    pub(in crate) hrtf_channels: [HrtfChannelState; 2],
     pub(in crate) prev_distance_gain: Option<f32>,
+    // True if context already reported that buffer of source is not mono.
+    pub(in crate) non_mono_reported: bool,
 }
 
 impl SpatialSource {
-    /// Sets position of source in world space.
+    /// Sets position of source in its coordinate space (world space by default, see `set_space`).
+    /// Velocity of source is not changed, but next call of `set_position_at` will not derive velocity
+    /// from this position (useful for teleports). Velocity of source which is moved by this method is
+    /// derived by `Context::update` instead.
     pub fn set_position(&mut self, position: &Vec3) -> &mut Self {
         self.position = *position;
+        self.position_time = None;
+        self
+    }
+
+    /// Sets position of source in its coordinate space together with timestamp of this position (i.e.
+    /// game time of the frame), velocity of source is derived from two consecutive calls. Velocity
+    /// does not depend on render cadence, it depends only on given timestamps. First call (or first
+    /// one after `set_position`) gives zero velocity, calls with timestamp which is not later than
+    /// previous one leave velocity unchanged.
+    pub fn set_position_at(&mut self, position: &Vec3, time: Duration) -> &mut Self {
+        match self.position_time {
+            Some(prev_time) if time > prev_time => {
+                let dt = (time - prev_time).as_secs_f32();
+                self.velocity = (*position - self.position).scale(1.0 / dt);
+            }
+            Some(_) => (),
+            None => self.velocity = Vec3::ZERO,
+        }
+        self.position = *position;
+        self.position_time = Some(time);
         self
     }
 
+    /// Returns velocity of source (in units per second) derived by `set_position_at` or, if source is
+    /// moved by `set_position`, from its movement between calls of `Context::update`. Velocity is not
+    /// used by renderers, see module docs.
+    pub fn velocity(&self) -> Vec3 {
+        self.velocity
+    }
+
+    pub(in crate) fn update_velocity(&mut self, dt: Duration) {
+        if self.position_time.is_none() && dt > Duration::from_secs(0) {
+            self.velocity = match self.update_position {
+                Some(prev_position) => (self.position - prev_position).scale(1.0 / dt.as_secs_f32()),
+                None => Vec3::ZERO,
+            };
+        }
+        self.update_position = Some(self.position);
+    }
+
     /// Returns positions of source.
     pub fn position(&self) -> Vec3 {
         self.position
@@ -91,10 +310,9 @@ impl SpatialSource {
         self.rolloff_factor
     }
 
-    /// Sets maximum distance until which distance gain will be applicable. Basically it doing this
-    /// min(max(distance, radius), max_distance) which clamps distance in radius..max_distance range.
-    /// From listener's perspective this will sound like source has stopped decreasing its volume even
-    /// if distance continue to grow.
+    /// Sets maximum distance at which source is audible. Source that is farther than `max_distance`
+    /// from listener will be silent, renderers will skip processing of such source completely which
+    /// saves CPU in large scenes. Sources closer than `radius` will have full gain.
     pub fn set_max_distance(&mut self, max_distance: f32) -> &mut Self {
         self.max_distance = max_distance;
         self
@@ -105,6 +323,249 @@ impl SpatialSource {
         self.max_distance
     }
 
+    /// Sets minimum distance gain of the source, distance gain is clamped from below by this value so
+    /// source stays at least that loud regardless of distance. Useful for ambient loops which should
+    /// always be faintly audible. Value is clamped to [0; 1] range, default value is 0.0.
+    ///
+    /// # Notes
+    ///
+    /// Sources farther than max distance (see `set_max_distance`) are still silent.
+    pub fn set_min_gain(&mut self, gain: f32) -> &mut Self {
+        self.min_gain = gain.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Returns minimum distance gain of the source.
+    pub fn min_gain(&self) -> f32 {
+        self.min_gain
+    }
+
+    /// Sets maximum distance gain of the source, distance gain is clamped from above by this value
+    /// (it has priority over minimum gain if it is less than minimum). Value is clamped to [0; 1] range,
+    /// default value is 1.0.
+    pub fn set_max_gain(&mut self, gain: f32) -> &mut Self {
+        self.max_gain = gain.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Returns maximum distance gain of the source.
+    pub fn max_gain(&self) -> f32 {
+        self.max_gain
+    }
+
+    /// Sets custom falloff curve of the source as set of `(distance, gain)` breakpoints, it replaces
+    /// distance model of context for this source. Gain between breakpoints is linearly interpolated,
+    /// distances outside of the curve are clamped to its endpoints. Breakpoints may be given in any
+    /// order. Empty curve means that distance model of context is used, this is default.
+    ///
+    /// # Notes
+    ///
+    /// Minimum and maximum gains, max distance and rear attenuation of listener are still applied.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rg3d_sound::source::spatial::SpatialSource;
+    ///
+    /// fn setup(source: &mut SpatialSource) {
+    ///     // Full volume up to 5 meters, then quickly falls to 20% at 10 meters and slowly fades out
+    ///     // to 50 meters.
+    ///     source.set_falloff_curve(&[(5.0, 1.0), (10.0, 0.2), (50.0, 0.0)]);
+    /// }
+    /// ```
+    pub fn set_falloff_curve(&mut self, curve: &[(f32, f32)]) -> &mut Self {
+        self.falloff_curve = sorted_curve(curve);
+        self
+    }
+
+    /// Returns falloff curve of the source, empty if distance model of context is used.
+    pub fn falloff_curve(&self) -> &[(f32, f32)] {
+        &self.falloff_curve
+    }
+
+    /// Sets amount of rendered blocks between recalculations of spatial parameters of the source, they
+    /// are interpolated in between. 1 (default) means that parameters are recalculated for each block,
+    /// zero is treated as 1. Larger values save CPU for distant slowly moving sources, near and loud
+    /// sources should use per-block updates. See module docs.
+    pub fn set_spatial_update_interval(&mut self, blocks: u32) -> &mut Self {
+        self.spatial_update_interval = blocks.max(1);
+        self
+    }
+
+    /// Returns amount of rendered blocks between recalculations of spatial parameters.
+    pub fn spatial_update_interval(&self) -> u32 {
+        self.spatial_update_interval
+    }
+
+    /// Sets time constant of distance gain smoothing. Distance gain is always interpolated across single
+    /// render block, but rapid changes of distance (teleports, very fast sources) still can produce
+    /// audible warbles. With smoothing, distance gain follows its calculated value by first-order law
+    /// with given time constant regardless of block length. Default value is zero which means that new
+    /// distance gain will be reached within single render block.
+    ///
+    /// # Notes
+    ///
+    /// Smoothing is applied by default and HRTF renderers for main listener only.
+    pub fn set_distance_gain_smoothing(&mut self, smoothing: Duration) -> &mut Self {
+        self.distance_gain_smoothing = smoothing;
+        self
+    }
+
+    /// Returns time constant of distance gain smoothing.
+    pub fn distance_gain_smoothing(&self) -> Duration {
+        self.distance_gain_smoothing
+    }
+
+    /// Sets baked HRTF filter (see `HrtfSphere::bake`) which will be used by HRTF renderer instead of
+    /// sampling HRTF sphere by actual direction to the source. Useful for static emitters with known
+    /// direction, filter can be shared between many sources. Distance attenuation and interaural time
+    /// difference are still calculated from actual position. `None` (default) - HRTF is sampled each
+    /// block.
+    ///
+    /// # Notes
+    ///
+    /// Baked filter is not used for sources with stereo spread (see `set_stereo_spread`).
+    pub fn set_hrtf_filter(&mut self, filter: Option<Arc<HrtfFilter>>) -> &mut Self {
+        self.hrtf_filter = filter;
+        self
+    }
+
+    /// Returns baked HRTF filter of the source.
+    pub fn hrtf_filter(&self) -> Option<&Arc<HrtfFilter>> {
+        self.hrtf_filter.as_ref()
+    }
+
+    /// Sets rendering quality hint of the source. See `SourceQuality` docs for more info. Default
+    /// renderer ignores this hint.
+    pub fn set_quality(&mut self, quality: SourceQuality) -> &mut Self {
+        self.quality = quality;
+        self
+    }
+
+    /// Returns rendering quality hint of the source.
+    pub fn quality(&self) -> SourceQuality {
+        self.quality
+    }
+
+    /// Sets coordinate space in which position of the source is given. See `SpatialSpace` docs for
+    /// more info.
+    pub fn set_space(&mut self, space: SpatialSpace) -> &mut Self {
+        self.space = space;
+        self
+    }
+
+    /// Returns coordinate space in which position of the source is given.
+    pub fn space(&self) -> SpatialSpace {
+        self.space
+    }
+
+    /// Returns position of the source in world space with respect to its coordinate space.
+    fn world_position(&self, listener: &Listener) -> Vec3 {
+        match self.space {
+            SpatialSpace::World => self.position,
+            SpatialSpace::HeadRelative => listener.position()
+                + listener.ear_axis().scale(self.position.x)
+                + listener.up_axis().scale(self.position.y)
+                + listener.look_axis().scale(self.position.z),
+        }
+    }
+
+    /// Swaps state of renderer of the source with given one.
+    pub(in crate) fn swap_render_state(&mut self, state: &mut SpatialRenderState) {
+        std::mem::swap(&mut self.spatial_params, &mut state.spatial_params);
+        std::mem::swap(&mut self.blocks_since_update, &mut state.blocks_since_update);
+        std::mem::swap(&mut self.smoothed_distance_gain, &mut state.smoothed_distance_gain);
+        std::mem::swap(&mut self.hrtf_channels, &mut state.hrtf_channels);
+        std::mem::swap(&mut self.prev_distance_gain, &mut state.prev_distance_gain);
+    }
+
+    /// Calculates spatial parameters of the source for given listener.
+    pub(in crate) fn compute_spatial_params(&self, listener: &Listener, distance_model: &DistanceModel) -> SpatialParams {
+        SpatialParams {
+            distance_gain: self.get_distance_gain(listener, distance_model),
+            panning: self.get_panning(listener),
+            sampling_vector: self.get_sampling_vector(listener),
+        }
+    }
+
+    /// Returns spatial parameters of the source for current block of given length with respect to update
+    /// interval and distance gain smoothing. Must be called once per rendered block and only for main
+    /// listener.
+    pub(in crate) fn spatial_params(&mut self, listener: &Listener, distance_model: &DistanceModel, amount: usize) -> SpatialParams {
+        let mut params = self.throttled_params(listener, distance_model);
+        let target = params.distance_gain;
+        let current = self.smoothed_distance_gain.unwrap_or(target);
+        let smoothing_samples = self.distance_gain_smoothing.as_secs_f32() * crate::device::SAMPLE_RATE as f32;
+        params.distance_gain = if smoothing_samples > 0.0 {
+            // Exact response of first-order filter over the block, so it does not depend on block length.
+            current + (target - current) * (1.0 - (-(amount as f32) / smoothing_samples).exp())
+        } else {
+            target
+        };
+        self.smoothed_distance_gain = Some(params.distance_gain);
+        params
+    }
+
+    fn throttled_params(&mut self, listener: &Listener, distance_model: &DistanceModel) -> SpatialParams {
+        let interval = self.spatial_update_interval;
+        if interval <= 1 {
+            self.spatial_params = None;
+            return self.compute_spatial_params(listener, distance_model);
+        }
+
+        let current = match self.spatial_params {
+            Some((from, to)) => {
+                if self.blocks_since_update < interval {
+                    self.blocks_since_update += 1;
+                    return from.lerp(&to, self.blocks_since_update as f32 / interval as f32);
+                }
+                to
+            }
+            None => self.compute_spatial_params(listener, distance_model),
+        };
+
+        // Interpolate from current parameters to actual ones during next interval.
+        let target = self.compute_spatial_params(listener, distance_model);
+        self.spatial_params = Some((current, target));
+        self.blocks_since_update = 1;
+        current.lerp(&target, 1.0 / interval as f32)
+    }
+
+    /// Sets spatial blend of the source: 0.0 - source is rendered as flat (2D) source using its
+    /// stereo panning, 1.0 - source is fully spatialized, values in between crossfade these two
+    /// renders. Useful for sounds that should be only slightly localized. Default value is 1.0.
+    pub fn set_spatial_blend(&mut self, blend: f32) -> &mut Self {
+        self.spatial_blend = blend.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Returns spatial blend of the source.
+    pub fn spatial_blend(&self) -> f32 {
+        self.spatial_blend
+    }
+
+    /// Sets reverb send level of the source. See `GenericSource::set_reverb_send` for more info.
+    pub fn set_reverb_send(&mut self, send: f32) -> &mut Self {
+        self.generic.set_reverb_send(send);
+        self
+    }
+
+    /// Returns reverb send level of the source.
+    pub fn reverb_send(&self) -> f32 {
+        self.generic.reverb_send()
+    }
+
+    /// Sets level of send to given effect (bus). See `GenericSource::set_send` for more info.
+    pub fn set_send(&mut self, bus: Handle<Effect>, level: f32) -> &mut Self {
+        self.generic.set_send(bus, level);
+        self
+    }
+
+    /// Returns level of send to given effect, 0.0 if there is no such send.
+    pub fn send(&self, bus: Handle<Effect>) -> f32 {
+        self.generic.send(bus)
+    }
+
     /// Returns shared reference to inner generic source.
     pub fn generic(&self) -> &GenericSource {
         &self.generic
@@ -118,12 +579,45 @@ impl SpatialSource {
     // Distance models were taken from OpenAL Specification because it looks like they're
     // standard in industry and there is no need to reinvent it.
     // https://www.openal.org/documentation/openal-1.1-specification.pdf
-    pub(in crate) fn get_distance_gain(&self, listener: &Listener, distance_model: DistanceModel) -> f32 {
-        let distance = self.position
-            .distance(&listener.position())
-            .max(self.radius)
-            .min(self.max_distance);
-        match distance_model {
+    /// Calculates distance attenuation of the source for given listener and distance model. Result
+    /// is in [min gain; max gain] range (see `set_min_gain` and `set_max_gain`), it is zero if source is
+    /// farther than max distance. Gains of the source itself and of listener are not taken into
+    /// account (renderers apply them to final gains, see `Listener::set_gain`), but rear attenuation
+    /// of listener is (see `Listener::set_rear_attenuation`).
+    /// Can be used to implement custom renderers.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rg3d_sound::context::{Context, DistanceModel};
+    /// use rg3d_sound::source::spatial::SpatialSource;
+    /// use rg3d_sound::math::vec3::Vec3;
+    ///
+    /// fn check(context: &Context, source: &mut SpatialSource) {
+    ///     // Listener is at origin, source is 10 units away and has radius of 1 unit.
+    ///     source.set_position(&Vec3::new(0.0, 0.0, 10.0))
+    ///         .set_radius(1.0)
+    ///         .set_rolloff_factor(1.0);
+    ///     let gain = source.get_distance_gain(context.listener(), &DistanceModel::InverseDistance);
+    ///     // radius / (radius + rolloff_factor * (distance - radius)) = 1 / (1 + 9)
+    ///     assert!((gain - 0.1).abs() < 1.0e-6);
+    /// }
+    /// ```
+    pub fn get_distance_gain(&self, listener: &Listener, distance_model: &DistanceModel) -> f32 {
+        let distance = self.distance_to(listener);
+        if distance > self.max_distance {
+            return 0.0;
+        }
+        let rear_gain = (self.world_position(listener) - listener.position())
+            .normalized()
+            .map(|direction| listener.rear_gain(direction))
+            .unwrap_or(1.0);
+        if !self.falloff_curve.is_empty() {
+            let gain = rear_gain * evaluate_curve(&self.falloff_curve, distance);
+            return gain.max(self.min_gain).min(self.max_gain);
+        }
+        let distance = distance.max(self.radius);
+        let gain = rear_gain * match distance_model {
             DistanceModel::None => 1.0,
             DistanceModel::InverseDistance => {
                 self.radius / (self.radius + self.rolloff_factor * (distance - self.radius))
@@ -134,25 +628,150 @@ impl SpatialSource {
             DistanceModel::ExponentDistance => {
                 (distance / self.radius).powf(-self.rolloff_factor)
             }
+            DistanceModel::Custom(function) => {
+                // Custom function takes raw (unclamped) distance.
+                function(self.distance_to(listener)).clamp(0.0, 1.0)
+            }
+        };
+        gain.max(self.min_gain).min(self.max_gain)
+    }
+
+    /// Sets distance (in meters) between virtual left and right points of stereo source for HRTF
+    /// renderer. Left channel of buffer is rendered from the point which is `width / 2` to the left of
+    /// source position (relative to listener), right channel - from the point `width / 2` to the right.
+    /// Zero width (default) means that stereo buffer is downmixed to mono and rendered from source
+    /// position. Has no effect on mono buffers and on default renderer. See module docs.
+    pub fn set_stereo_spread(&mut self, width: f32) -> &mut Self {
+        self.stereo_spread = width.max(0.0);
+        self
+    }
+
+    /// Returns stereo spread width.
+    pub fn stereo_spread(&self) -> f32 {
+        self.stereo_spread
+    }
+
+    /// Returns position of virtual point from which given channel of buffer is rendered, `None` means
+    /// whole (downmixed) source. See `set_stereo_spread`.
+    pub(in crate) fn channel_position(&self, listener: &Listener, channel: Option<usize>) -> Vec3 {
+        // Ear axis points to the left of listener.
+        let offset = listener.ear_axis().scale(self.stereo_spread * 0.5);
+        let position = self.world_position(listener);
+        match channel {
+            Some(0) => position + offset,
+            Some(_) => position - offset,
+            None => position,
         }
     }
 
-    pub(in crate) fn get_panning(&self, listener: &Listener) -> f32 {
-        (self.position - listener.position())
+    /// Same as `get_sampling_vector`, but for virtual point of given channel.
+    pub(in crate) fn channel_sampling_vector(&self, listener: &Listener, channel: Option<usize>) -> Vec3 {
+        let direction = match self.space {
+            SpatialSpace::World => listener.basis()
+                .transform_vector(self.channel_position(listener, channel) - listener.position()),
+            SpatialSpace::HeadRelative => {
+                // Position is already in coordinate system of listener, where ear axis is x axis.
+                let offset = Vec3::new(self.stereo_spread * 0.5, 0.0, 0.0);
+                match channel {
+                    Some(0) => self.position + offset,
+                    Some(_) => self.position - offset,
+                    None => self.position,
+                }
+            }
+        };
+        direction
+            .normalized()
+            // This is ok to fallback to (0, 0, 1) vector because it's given
+            // in listener coordinate system.
+            .unwrap_or_else(|| Vec3::new(0.0, 0.0, 1.0))
+    }
+
+    /// Same as `get_panning`, but for virtual point of given channel.
+    pub(in crate) fn channel_panning(&self, listener: &Listener, channel: Option<usize>) -> f32 {
+        (self.channel_position(listener, channel) - listener.position())
             .normalized()
-            // Fallback to look axis will give zero panning which will result in even
-            // gain in each channels (as if there was no panning at all).
             .unwrap_or_else(|| listener.look_axis())
             .dot(&listener.ear_axis())
     }
 
-    pub(in crate) fn get_sampling_vector(&self, listener: &Listener) -> Vec3 {
-        listener.basis()
-            .transform_vector(self.position - listener.position())
+    /// Calculates panning of the source for given listener. Panning is projection of direction from
+    /// listener to the source on ear axis of listener: 1.0 - source is fully on the left, -1.0 - source
+    /// is fully on the right, 0.0 - source is in front of or behind the listener.
+    pub fn get_panning(&self, listener: &Listener) -> f32 {
+        self.direction_to(listener).dot(&listener.ear_axis())
+    }
+
+    /// Returns distance between the source and given listener.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rg3d_sound::context::Context;
+    /// use rg3d_sound::source::spatial::SpatialSource;
+    /// use rg3d_sound::math::vec3::Vec3;
+    ///
+    /// fn check(context: &Context, source: &mut SpatialSource) {
+    ///     source.set_position(&Vec3::new(3.0, 4.0, 0.0));
+    ///     assert!((source.distance_to(context.listener()) - 5.0).abs() < 1.0e-6);
+    /// }
+    /// ```
+    pub fn distance_to(&self, listener: &Listener) -> f32 {
+        self.world_position(listener).distance(&listener.position())
+    }
+
+    /// Returns normalized direction from given listener to the source in world space. If source and
+    /// listener are at the same point, look axis of listener is returned. Use `get_sampling_vector`
+    /// to get direction in coordinate system of listener.
+    pub fn direction_to(&self, listener: &Listener) -> Vec3 {
+        (self.world_position(listener) - listener.position())
             .normalized()
-            // This is ok to fallback to (0, 0, 1) vector because it's given
-            // in listener coordinate system.
-            .unwrap_or_else(|| Vec3::new(0.0, 0.0, 1.0))
+            // Fallback to look axis will give zero panning which will result in even
+            // gain in each channels (as if there was no panning at all).
+            .unwrap_or_else(|| listener.look_axis())
+    }
+
+    /// Calculates normalized direction from listener to the source in coordinate system of listener.
+    /// This vector is used by HRTF renderer to sample HRTF sphere. If source and listener are at the
+    /// same point, (0, 0, 1) vector is returned. Position of head-relative source (see `set_space`) is
+    /// already given in coordinate system of listener, so it is used as is (normalized).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rg3d_sound::context::Context;
+    /// use rg3d_sound::source::spatial::SpatialSource;
+    /// use rg3d_sound::math::vec3::Vec3;
+    ///
+    /// fn check(context: &Context, source: &mut SpatialSource) {
+    ///     // Default listener is at origin and its basis is identity.
+    ///     source.set_position(&Vec3::new(5.0, 0.0, 0.0));
+    ///     let dir = source.get_sampling_vector(context.listener());
+    ///     assert!((dir.x - 1.0).abs() < 1.0e-6 && dir.y.abs() < 1.0e-6 && dir.z.abs() < 1.0e-6);
+    /// }
+    /// ```
+    pub fn get_sampling_vector(&self, listener: &Listener) -> Vec3 {
+        self.channel_sampling_vector(listener, None)
+    }
+}
+
+fn sorted_curve(curve: &[(f32, f32)]) -> Vec<(f32, f32)> {
+    let mut curve = curve.to_vec();
+    curve.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    curve
+}
+
+/// Evaluates piecewise linear curve (sorted by distance, non-empty) at given distance.
+fn evaluate_curve(curve: &[(f32, f32)], distance: f32) -> f32 {
+    // Index of first breakpoint which is farther than given distance.
+    let next = curve.iter().position(|&(d, _)| d > distance).unwrap_or(curve.len());
+    if next == 0 {
+        curve[0].1
+    } else if next == curve.len() {
+        curve[curve.len() - 1].1
+    } else {
+        let (d0, g0) = curve[next - 1];
+        let (d1, g1) = curve[next];
+        math::lerpf(g0, g1, (distance - d0) / (d1 - d0))
     }
 }
 
@@ -163,6 +782,42 @@ impl Visit for SpatialSource {
         self.radius.visit("Radius", visitor)?;
         self.position.visit("Position", visitor)?;
 
+        // Meaning of position depends on its space, so they're stored together.
+        let mut space = self.space.id();
+        if space.visit("Space", visitor).is_ok() {
+            if visitor.is_reading() {
+                self.space = SpatialSpace::from_id(space);
+            }
+        } else if visitor.is_reading() {
+            // Older versions have world space positions only.
+            self.space = SpatialSpace::World;
+        }
+
+        // Tuples are not visitable, so falloff curve is stored as two lists of distances and gains.
+        let mut distances = self.falloff_curve.iter().map(|&(distance, _)| distance).collect::<Vec<f32>>();
+        let mut gains = self.falloff_curve.iter().map(|&(_, gain)| gain).collect::<Vec<f32>>();
+        if visitor.is_reading() {
+            distances.clear();
+            gains.clear();
+        }
+        let mut has_curve = false;
+        if visitor.enter_region("FalloffCurve").is_ok() {
+            has_curve = distances.visit("Distances", visitor).is_ok() && gains.visit("Gains", visitor).is_ok();
+            visitor.leave_region()?;
+        }
+        if visitor.is_reading() {
+            if has_curve && distances.len() == gains.len() {
+                let curve = distances.iter()
+                    .zip(gains.iter())
+                    .map(|(&distance, &gain)| (distance, gain))
+                    .collect::<Vec<(f32, f32)>>();
+                self.falloff_curve = sorted_curve(&curve);
+            } else {
+                // Older versions have no falloff curve, distance model of context is used.
+                self.falloff_curve.clear();
+            }
+        }
+
         visitor.leave_region()
     }
 }
@@ -173,12 +828,27 @@ impl Default for SpatialSource {
             generic: Default::default(),
             radius: 1.0,
             position: Vec3::ZERO,
+            velocity: Vec3::ZERO,
+            position_time: None,
+            update_position: None,
             max_distance: std::f32::MAX,
             rolloff_factor: 1.0,
-            prev_left_samples: Default::default(),
-            prev_right_samples: Default::default(),
-            prev_sampling_vector: Vec3::new(0.0, 0.0, 1.0),
-            prev_distance_gain: None
+            spatial_blend: 1.0,
+            stereo_spread: 0.0,
+            min_gain: 0.0,
+            max_gain: 1.0,
+            falloff_curve: Default::default(),
+            spatial_update_interval: 1,
+            spatial_params: None,
+            blocks_since_update: 0,
+            distance_gain_smoothing: Duration::from_secs(0),
+            smoothed_distance_gain: None,
+            hrtf_filter: None,
+            quality: Default::default(),
+            space: Default::default(),
+            hrtf_channels: Default::default(),
+            prev_distance_gain: None,
+            non_mono_reported: false,
         }
     }
 }
@@ -190,6 +860,15 @@ pub struct SpatialSourceBuilder {
     position: Vec3,
     max_distance: f32,
     rolloff_factor: f32,
+    spatial_blend: f32,
+    stereo_spread: f32,
+    min_gain: f32,
+    max_gain: f32,
+    falloff_curve: Vec<(f32, f32)>,
+    spatial_update_interval: u32,
+    distance_gain_smoothing: Duration,
+    quality: SourceQuality,
+    space: SpatialSpace,
 }
 
 impl SpatialSourceBuilder {
@@ -202,6 +881,15 @@ impl SpatialSourceBuilder {
             position: Default::default(),
             max_distance: std::f32::MAX,
             rolloff_factor: 1.0,
+            spatial_blend: 1.0,
+            stereo_spread: 0.0,
+            min_gain: 0.0,
+            max_gain: 1.0,
+            falloff_curve: Default::default(),
+            spatial_update_interval: 1,
+            distance_gain_smoothing: Duration::from_secs(0),
+            quality: SourceQuality::High,
+            space: SpatialSpace::World,
         }
     }
 
@@ -229,6 +917,60 @@ impl SpatialSourceBuilder {
         self
     }
 
+    /// See `set_spatial_blend` of SpatialSource.
+    pub fn with_spatial_blend(mut self, blend: f32) -> Self {
+        self.spatial_blend = blend.clamp(0.0, 1.0);
+        self
+    }
+
+    /// See `set_stereo_spread` of SpatialSource.
+    pub fn with_stereo_spread(mut self, width: f32) -> Self {
+        self.stereo_spread = width.max(0.0);
+        self
+    }
+
+    /// See `set_min_gain` of SpatialSource.
+    pub fn with_min_gain(mut self, gain: f32) -> Self {
+        self.min_gain = gain.clamp(0.0, 1.0);
+        self
+    }
+
+    /// See `set_max_gain` of SpatialSource.
+    pub fn with_max_gain(mut self, gain: f32) -> Self {
+        self.max_gain = gain.clamp(0.0, 1.0);
+        self
+    }
+
+    /// See `set_falloff_curve` of SpatialSource.
+    pub fn with_falloff_curve(mut self, curve: &[(f32, f32)]) -> Self {
+        self.falloff_curve = sorted_curve(curve);
+        self
+    }
+
+    /// See `set_spatial_update_interval` of SpatialSource.
+    pub fn with_spatial_update_interval(mut self, blocks: u32) -> Self {
+        self.spatial_update_interval = blocks.max(1);
+        self
+    }
+
+    /// See `set_distance_gain_smoothing` of SpatialSource.
+    pub fn with_distance_gain_smoothing(mut self, smoothing: Duration) -> Self {
+        self.distance_gain_smoothing = smoothing;
+        self
+    }
+
+    /// See `set_quality` of SpatialSource.
+    pub fn with_quality(mut self, quality: SourceQuality) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// See `set_space` of SpatialSource.
+    pub fn with_space(mut self, space: SpatialSpace) -> Self {
+        self.space = space;
+        self
+    }
+
     /// Creates new instance of spatial sound source.
     pub fn build(self) -> SpatialSource {
         SpatialSource {
@@ -237,8 +979,15 @@ impl SpatialSourceBuilder {
             position: self.position,
             max_distance: self.max_distance,
             rolloff_factor: self.rolloff_factor,
-            prev_left_samples: Default::default(),
-            prev_right_samples: Default::default(),
+            spatial_blend: self.spatial_blend,
+            stereo_spread: self.stereo_spread,
+            min_gain: self.min_gain,
+            max_gain: self.max_gain,
+            falloff_curve: self.falloff_curve,
+            spatial_update_interval: self.spatial_update_interval,
+            distance_gain_smoothing: self.distance_gain_smoothing,
+            quality: self.quality,
+            space: self.space,
             .. Default::default()
         }
     }
@@ -247,4 +996,392 @@ impl SpatialSourceBuilder {
     pub fn build_source(self) -> SoundSource {
         SoundSource::Spatial(self.build())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::Duration,
+    };
+    use rg3d_core::visitor::Visit;
+    use crate::{
+        context::{DistanceModel, DistanceFunction},
+        listener::Listener,
+        renderer::render_source_default,
+        source::{
+            SoundSource,
+            Status,
+            generic::GenericSourceBuilder,
+            spatial::{SpatialSource, SpatialSourceBuilder, SpatialSpace},
+        },
+        math::vec3::Vec3,
+        test_support,
+    };
+
+    fn make_spatial(position: Vec3) -> SpatialSource {
+        let generic = GenericSourceBuilder::new(test_support::make_buffer(vec![1.0; 64], 44100))
+            .build()
+            .unwrap();
+        SpatialSourceBuilder::new(generic)
+            .with_position(position)
+            .build()
+    }
+
+    #[test]
+    fn falloff_curve_is_interpolated_and_clamped() {
+        let listener = Listener::new();
+        let mut source = make_spatial(Vec3::new(0.0, 0.0, 1.0));
+        // Given out of order, two segments: 1.0 -> 0.2 within [5; 10] and 0.2 -> 0.0 within [10; 50].
+        source.set_falloff_curve(&[(10.0, 0.2), (5.0, 1.0), (50.0, 0.0)]);
+        assert_eq!(source.falloff_curve(), &[(5.0, 1.0), (10.0, 0.2), (50.0, 0.0)]);
+
+        let mut gain_at = |distance: f32| {
+            source.set_position(&Vec3::new(0.0, 0.0, distance));
+            // Distance model of context is ignored.
+            source.get_distance_gain(&listener, &DistanceModel::InverseDistance)
+        };
+        let expected = [
+            (1.0, 1.0), (5.0, 1.0), (7.5, 0.6), (10.0, 0.2), (20.0, 0.15), (30.0, 0.1), (50.0, 0.0), (80.0, 0.0),
+        ];
+        for &(distance, gain) in expected.iter() {
+            assert!((gain_at(distance) - gain).abs() < 1.0e-6, "{} at {}", gain_at(distance), distance);
+        }
+    }
+
+    #[test]
+    fn falloff_curve_is_visited() {
+        let curve = [(2.0, 1.0), (4.0, 0.5), (8.0, 0.1)];
+        let mut visitor = test_support::reload(|visitor| {
+            let mut source = make_spatial(Vec3::ZERO);
+            source.set_falloff_curve(&curve);
+            source.visit("Source", visitor)
+        });
+        let mut source = make_spatial(Vec3::ZERO);
+        source.visit("Source", &mut visitor).unwrap();
+        assert_eq!(source.falloff_curve(), &curve);
+    }
+
+    #[test]
+    fn missing_falloff_curve_falls_back_to_distance_model() {
+        let mut visitor = test_support::reload(|visitor| {
+            let mut source = make_spatial(Vec3::ZERO);
+            visitor.enter_region("Source")?;
+            source.radius.visit("Radius", visitor)?;
+            source.position.visit("Position", visitor)?;
+            visitor.leave_region()
+        });
+        let mut source = make_spatial(Vec3::ZERO);
+        source.set_falloff_curve(&[(1.0, 0.5)]);
+        source.visit("Source", &mut visitor).unwrap();
+        assert!(source.falloff_curve().is_empty());
+    }
+
+    #[test]
+    fn distance_gain_is_clamped_by_radius_and_max_distance() {
+        let listener = Listener::new();
+        let mut source = make_spatial(Vec3::new(0.0, 0.0, 0.5));
+        source.set_radius(1.0).set_max_distance(10.0);
+        let models = vec![
+            DistanceModel::InverseDistance,
+            DistanceModel::LinearDistance,
+            DistanceModel::ExponentDistance,
+        ];
+        for model in models {
+            // Within radius gain is full.
+            source.set_position(&Vec3::new(0.0, 0.0, 0.5));
+            assert_eq!(source.get_distance_gain(&listener, &model), 1.0);
+            // Beyond max distance source is silent.
+            source.set_position(&Vec3::new(0.0, 0.0, 10.5));
+            assert_eq!(source.get_distance_gain(&listener, &model), 0.0);
+        }
+    }
+
+    #[test]
+    fn distance_gain_matches_distance_models() {
+        let listener = Listener::new();
+        let mut source = make_spatial(Vec3::new(0.0, 0.0, 10.0));
+        source.set_radius(1.0).set_rolloff_factor(1.0).set_max_distance(11.0);
+        // radius / (radius + rolloff * (distance - radius)) = 1 / (1 + 9)
+        assert!((source.get_distance_gain(&listener, &DistanceModel::InverseDistance) - 0.1).abs() < 1.0e-6);
+        // 1 - radius * (distance - radius) / (max_distance - radius) = 1 - 9 / 10
+        assert!((source.get_distance_gain(&listener, &DistanceModel::LinearDistance) - 0.1).abs() < 1.0e-6);
+        // (distance / radius) ^ -rolloff = 10 ^ -1
+        assert!((source.get_distance_gain(&listener, &DistanceModel::ExponentDistance) - 0.1).abs() < 1.0e-6);
+        assert_eq!(source.get_distance_gain(&listener, &DistanceModel::None), 1.0);
+    }
+
+    #[test]
+    fn custom_distance_model_is_evaluated_with_raw_distance() {
+        let listener = Listener::new();
+        let mut source = make_spatial(Vec3::ZERO);
+        // Radius and max distance must not affect custom model.
+        source.set_radius(5.0).set_max_distance(100.0);
+        let function = |distance: f32| 1.0 - distance * 0.1;
+        let model = DistanceModel::Custom(Arc::new(function));
+        for &distance in [0.5, 2.0, 4.0, 7.5].iter() {
+            source.set_position(&Vec3::new(0.0, 0.0, distance));
+            assert!((source.get_distance_gain(&listener, &model) - function(distance)).abs() < 1.0e-6);
+        }
+        // Output is clamped to [0; 1].
+        source.set_position(&Vec3::new(0.0, 0.0, 20.0));
+        assert_eq!(source.get_distance_gain(&listener, &model), 0.0);
+        let model = DistanceModel::Custom(Arc::new(|_| 2.0));
+        assert_eq!(source.get_distance_gain(&listener, &model), 1.0);
+    }
+
+    #[test]
+    fn distance_models_are_compared_by_identity_of_function() {
+        let function: Arc<DistanceFunction> = Arc::new(|_| 0.5);
+        let model = DistanceModel::Custom(function.clone());
+        assert_eq!(model, model.clone());
+        assert_eq!(model, DistanceModel::Custom(function));
+        assert_ne!(model, DistanceModel::Custom(Arc::new(|_| 0.5)));
+        assert_ne!(model, DistanceModel::InverseDistance);
+        assert_eq!(DistanceModel::LinearDistance, DistanceModel::LinearDistance);
+        assert_ne!(DistanceModel::LinearDistance, DistanceModel::ExponentDistance);
+    }
+
+    #[test]
+    fn sampling_vector_matches_geometry() {
+        let listener = Listener::new();
+        let source = make_spatial(Vec3::new(3.0, 4.0, 0.0));
+        assert!((source.distance_to(&listener) - 5.0).abs() < 1.0e-6);
+        let dir = source.get_sampling_vector(&listener);
+        assert!((dir.x - 0.6).abs() < 1.0e-6 && (dir.y - 0.8).abs() < 1.0e-6 && dir.z.abs() < 1.0e-6);
+        // Listener with identity basis has ear axis along +X.
+        assert!((source.get_panning(&listener) - 0.6).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn min_gain_keeps_far_source_audible() {
+        let listener = Listener::new();
+        let mut source = make_spatial(Vec3::new(0.0, 0.0, 100_000.0));
+        assert!(source.get_distance_gain(&listener, &DistanceModel::InverseDistance) < 0.001);
+
+        source.set_min_gain(0.1);
+        assert_eq!(source.min_gain(), 0.1);
+        for model in [DistanceModel::InverseDistance, DistanceModel::ExponentDistance].iter() {
+            assert_eq!(source.get_distance_gain(&listener, model), 0.1);
+        }
+        // Near source is not affected.
+        source.set_position(&Vec3::new(0.0, 0.0, 0.5));
+        assert_eq!(source.get_distance_gain(&listener, &DistanceModel::InverseDistance), 1.0);
+
+        // Rendered signal does not drop below min gain either.
+        source.set_position(&Vec3::new(0.0, 0.0, 100_000.0));
+        source.generic_mut().play();
+        let mut source = SoundSource::Spatial(source);
+        let mut buf = vec![(0.0, 0.0); 32];
+        source.generic_mut().render(buf.len());
+        render_source_default(&mut source, &listener, &DistanceModel::InverseDistance, &mut buf);
+        let (left, right) = buf[16];
+        assert!(left + right >= 0.1 - 1.0e-5, "{} {}", left, right);
+
+        // Min gain is in [0; 1] range.
+        let mut source = make_spatial(Vec3::ZERO);
+        source.set_min_gain(2.0);
+        assert_eq!(source.min_gain(), 1.0);
+        source.set_min_gain(-1.0);
+        assert_eq!(source.min_gain(), 0.0);
+    }
+
+    #[test]
+    fn throttled_source_updates_parameters_every_interval() {
+        let listener = Listener::new();
+        let model = DistanceModel::InverseDistance;
+        let mut source = make_spatial(Vec3::new(0.0, 0.0, 1.0));
+        source.set_radius(1.0).set_spatial_update_interval(4);
+        assert_eq!(source.spatial_update_interval(), 4);
+
+        let gains = (0..9)
+            .map(|_| {
+                let gain = source.throttled_params(&listener, &model).distance_gain;
+                // Source moves each block, but it is noticed only once per 4 blocks.
+                source.set_position(&Vec3::new(0.0, 0.0, 2.0));
+                gain
+            })
+            .collect::<Vec<f32>>();
+        let expected = [1.0, 1.0, 1.0, 1.0, 0.875, 0.75, 0.625, 0.5, 0.5];
+        for (gain, expected) in gains.iter().zip(expected.iter()) {
+            assert!((gain - expected).abs() < 1.0e-6, "{:?}", gains);
+        }
+
+        // Without throttling parameters are fresh for each block.
+        source.set_spatial_update_interval(0);
+        assert_eq!(source.spatial_update_interval(), 1);
+        for &z in [1.0, 2.0, 4.0].iter() {
+            source.set_position(&Vec3::new(0.0, 0.0, z));
+            assert_eq!(source.throttled_params(&listener, &model).distance_gain, 1.0 / z);
+        }
+    }
+
+    // Distance gains of source for each block of given length, source jumps from 1 to 10 units away
+    // after first block.
+    fn jerked_gains(smoothing: Duration, block_len: usize, blocks: usize) -> Vec<f32> {
+        let listener = Listener::new();
+        let mut source = make_spatial(Vec3::new(0.0, 0.0, 1.0));
+        source.set_radius(1.0).set_distance_gain_smoothing(smoothing);
+        (0..blocks)
+            .map(|_| {
+                let gain = source.spatial_params(&listener, &DistanceModel::InverseDistance, block_len).distance_gain;
+                source.set_position(&Vec3::new(0.0, 0.0, 10.0));
+                gain
+            })
+            .collect()
+    }
+
+    fn max_step(gains: &[f32]) -> f32 {
+        gains.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn distance_gain_smoothing_softens_jumps() {
+        // Without smoothing new gain is reached immediately.
+        let instant = jerked_gains(Duration::from_secs(0), 512, 50);
+        assert_eq!(instant[0], 1.0);
+        assert!(instant[1..].iter().all(|&g| (g - 0.1).abs() < 1.0e-6));
+
+        let short = jerked_gains(Duration::from_millis(10), 512, 50);
+        let long = jerked_gains(Duration::from_millis(100), 512, 50);
+        assert!(max_step(&long) < max_step(&short));
+        assert!(max_step(&short) < max_step(&instant));
+        // Gain falls monotonically towards target and finally reaches it.
+        for gains in [&short, &long].iter() {
+            assert!(gains.windows(2).all(|w| w[1] <= w[0]));
+            assert!((gains.last().unwrap() - 0.1).abs() < 0.01);
+        }
+
+        // Response does not depend on block length.
+        let halves = jerked_gains(Duration::from_millis(100), 256, 99);
+        assert!((halves[98] - long[49]).abs() < 1.0e-3, "{} {}", halves[98], long[49]);
+    }
+
+    #[test]
+    fn velocity_is_derived_from_timestamped_positions() {
+        let mut source = make_spatial(Vec3::ZERO);
+        // First call has nothing to derive velocity from.
+        source.set_position_at(&Vec3::new(1.0, 0.0, 0.0), Duration::from_millis(1000));
+        assert_eq!(source.velocity(), Vec3::ZERO);
+        source.set_position_at(&Vec3::new(2.0, 1.0, -3.0), Duration::from_millis(1500));
+        assert!((source.velocity() - Vec3::new(2.0, 2.0, -6.0)).len() < 1.0e-5);
+
+        // Timestamp that is not later than previous one keeps velocity.
+        source.set_position_at(&Vec3::new(5.0, 0.0, 0.0), Duration::from_millis(1500));
+        assert!((source.velocity() - Vec3::new(2.0, 2.0, -6.0)).len() < 1.0e-5);
+
+        // Velocity of timestamped source is not overwritten by context update.
+        source.update_velocity(Duration::from_millis(100));
+        assert!((source.velocity() - Vec3::new(2.0, 2.0, -6.0)).len() < 1.0e-5);
+
+        // Teleport breaks chain of timestamps, next call gives zero velocity again.
+        source.set_position(&Vec3::new(100.0, 0.0, 0.0));
+        source.set_position_at(&Vec3::new(101.0, 0.0, 0.0), Duration::from_millis(2000));
+        assert_eq!(source.velocity(), Vec3::ZERO);
+    }
+
+    fn render_blended(blend: f32) -> (f32, f32) {
+        let generic = GenericSourceBuilder::new(test_support::make_buffer(vec![1.0; 4096], 44100))
+            .with_status(Status::Playing)
+            .with_panning(0.5)
+            .build()
+            .unwrap();
+        let mut spatial = SpatialSourceBuilder::new(generic)
+            .with_position(Vec3::new(-3.0, 0.0, 4.0))
+            .build();
+        spatial.set_spatial_blend(blend);
+        let mut source = SoundSource::Spatial(spatial);
+        let mut buf = vec![(0.0, 0.0); 512];
+        source.generic_mut().render(buf.len());
+        render_source_default(&mut source, &Listener::new(), &DistanceModel::InverseDistance, &mut buf);
+        buf[256]
+    }
+
+    #[test]
+    fn half_blend_is_average_of_flat_and_spatial_renders() {
+        let flat = render_blended(0.0);
+        let spatial = render_blended(1.0);
+        let half = render_blended(0.5);
+        // Make sure that renders actually differ, otherwise test proves nothing.
+        assert!((flat.0 - spatial.0).abs() > 0.1);
+        assert!(((flat.0 + spatial.0) * 0.5 - half.0).abs() < 1.0e-5);
+        assert!(((flat.1 + spatial.1) * 0.5 - half.1).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn rear_attenuation_is_applied_behind_listener() {
+        let mut listener = Listener::new();
+        listener.set_rear_attenuation(0.25);
+        let behind = listener.look_axis().scale(-5.0);
+        let front = listener.look_axis().scale(5.0);
+        let side = listener.ear_axis().scale(5.0);
+        let gain = |position: Vec3| make_spatial(position).get_distance_gain(&listener, &DistanceModel::None);
+        assert!((gain(behind) - 0.25).abs() < 1.0e-5);
+        assert_eq!(gain(front), 1.0);
+        assert!((gain(side) - 1.0).abs() < 1.0e-5);
+
+        // Default listener does not attenuate rear sources.
+        assert_eq!(make_spatial(behind).get_distance_gain(&Listener::new(), &DistanceModel::None), 1.0);
+    }
+
+    #[test]
+    fn distance_and_direction_match_geometry() {
+        let mut listener = Listener::new();
+        listener.set_position(Vec3::new(1.0, 2.0, 3.0));
+        // Offset is (2, 3, 6), its length is 7.
+        let source = make_spatial(Vec3::new(3.0, 5.0, 9.0));
+        assert!((source.distance_to(&listener) - 7.0).abs() < 1.0e-5);
+        let direction = source.direction_to(&listener);
+        assert!((direction - Vec3::new(2.0 / 7.0, 3.0 / 7.0, 6.0 / 7.0)).len() < 1.0e-5);
+
+        // Source at listener position has no direction, look axis is used instead.
+        let source = make_spatial(Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(source.distance_to(&listener), 0.0);
+        assert_eq!(source.direction_to(&listener), listener.look_axis());
+    }
+
+    #[test]
+    fn space_is_visited() {
+        let mut visitor = test_support::reload(|visitor| {
+            let mut source = make_spatial(Vec3::new(1.0, 0.0, 0.0));
+            source.set_space(SpatialSpace::HeadRelative);
+            source.visit("Source", visitor)
+        });
+        let mut source = SpatialSource::default();
+        source.visit("Source", &mut visitor).unwrap();
+        assert_eq!(source.space(), SpatialSpace::HeadRelative);
+    }
+
+    #[test]
+    fn head_relative_source_follows_listener_rotation() {
+        let orientations = [
+            (Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0)),
+            (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+            (Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0)),
+            (Vec3::new(0.0, -1.0, 0.0), Vec3::new(1.0, 0.0, 0.0)),
+        ];
+        for &(look, up) in orientations.iter() {
+            let mut listener = Listener::new();
+            listener.set_orientation_rh(look, up);
+            listener.set_position(Vec3::new(5.0, -2.0, 7.0));
+
+            // Position is taken as is, no matter how listener is rotated.
+            let mut source = make_spatial(Vec3::new(1.0, 0.0, 0.0));
+            source.set_space(SpatialSpace::HeadRelative);
+            let dir = source.get_sampling_vector(&listener);
+            assert!((dir.x - 1.0).abs() < 1.0e-5 && dir.y.abs() < 1.0e-5 && dir.z.abs() < 1.0e-5);
+            assert!((source.distance_to(&listener) - 1.0).abs() < 1.0e-5);
+
+            // Ear axis points to the left, so source at negative x is always at the right.
+            source.set_position(&Vec3::new(-1.0, 0.0, 0.0));
+            assert!((source.get_panning(&listener) + 1.0).abs() < 1.0e-5);
+            let mut source = SoundSource::Spatial(source);
+            source.generic_mut().play();
+            let mut buf = vec![(0.0, 0.0); 32];
+            source.generic_mut().render(buf.len());
+            render_source_default(&mut source, &listener, &DistanceModel::InverseDistance, &mut buf);
+            let (left, right) = buf[16];
+            assert!(right > 0.5 && left.abs() < 1.0e-5, "{} {}", left, right);
+        }
+    }
+}
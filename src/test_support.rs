@@ -0,0 +1,235 @@
+//! Test support module.
+//!
+//! # Overview
+//!
+//! Provides helpers to test effects offline - without audio device and without context: signal
+//! generators, offline effect runner and simple analysis functions. Module is available only if
+//! `test_support` feature is enabled.
+//!
+//! # Usage
+//!
+//! ```no_run
+//! use rg3d_sound::test_support;
+//! use rg3d_sound::effects::{Effect, reverb::Reverb};
+//!
+//! let sample_rate = 44100;
+//! let input = test_support::to_stereo(&test_support::sine(440.0, sample_rate, 4096));
+//! let output = test_support::run_effect(&mut Effect::Reverb(Reverb::new(Default::default())), &input, 1024);
+//! assert_eq!(output.len(), input.len());
+//! ```
+
+use std::sync::{Arc, Mutex};
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use rustfft::{
+    num_complex::Complex,
+    num_traits::Zero,
+    FFTplanner,
+};
+use rg3d_core::pool::Pool;
+#[cfg(test)]
+use rg3d_core::visitor::{Visitor, VisitResult};
+use crate::{
+    buffer::{SoundBuffer, DataSource},
+    source::{
+        Status,
+        generic::GenericSourceBuilder,
+    },
+    effects::{
+        Effect,
+        EffectInput,
+        EffectTrait,
+        EffectRenderTrait,
+    },
+    context::DistanceModel,
+    listener::Listener,
+    dsp::Random,
+    device,
+};
+
+/// Generates sine wave with given frequency (in Hz) and unit amplitude.
+pub fn sine(frequency: f32, sample_rate: usize, len: usize) -> Vec<f32> {
+    let omega = 2.0 * std::f32::consts::PI * frequency / sample_rate as f32;
+    (0..len).map(|i| (omega * i as f32).sin()).collect()
+}
+
+/// Generates unit impulse - first sample is 1.0, the rest are zeros.
+pub fn impulse(len: usize) -> Vec<f32> {
+    (0..len).map(|i| if i == 0 { 1.0 } else { 0.0 }).collect()
+}
+
+/// Generates white noise in (-1; 1) range. Same seed gives the same noise.
+pub fn noise(len: usize, seed: u64) -> Vec<f32> {
+    let mut random = Random::new(seed);
+    (0..len).map(|_| random.range(-1.0, 1.0)).collect()
+}
+
+/// Converts mono signal to stereo by duplicating each sample into both channels.
+pub fn to_stereo(samples: &[f32]) -> Vec<(f32, f32)> {
+    samples.iter().map(|&s| (s, s)).collect()
+}
+
+/// Runs single effect over given stereo input offline and returns processed samples of the same
+/// length. Input is fed to effect through direct input by blocks of given length, exactly as context
+/// does this. Input is expected to be at device sample rate, in this case no resampling happens and
+/// result is bit-exact. Pad input with zeros if you need to capture tail of effect.
+///
+/// # Panics
+///
+/// Panics if `block_len` is zero.
+pub fn run_effect(effect: &mut Effect, input: &[(f32, f32)], block_len: usize) -> Vec<(f32, f32)> {
+    assert_ne!(block_len, 0);
+
+    let mut samples = Vec::with_capacity(input.len() * 2);
+    for &(left, right) in input {
+        samples.push(left);
+        samples.push(right);
+    }
+
+    let buffer = SoundBuffer::new_generic(DataSource::Raw {
+        sample_rate: device::SAMPLE_RATE as usize,
+        channel_count: 2,
+        samples,
+    }).expect("raw data source must be valid");
+
+    let source = GenericSourceBuilder::new(buffer)
+        .with_status(Status::Playing)
+        .build_source()
+        .expect("generic buffer can be shared");
+
+    let mut sources = Pool::new();
+    let handle = sources.spawn(source);
+    effect.base_mut().add_input(EffectInput::direct(handle));
+
+    let listener = Listener::new();
+    let mut output = vec![(0.0, 0.0); input.len()];
+    for block in output.chunks_mut(block_len) {
+        sources.borrow_mut(handle).generic_mut().render(block.len());
+        effect.render(&sources, &listener, &DistanceModel::None, block);
+    }
+    output
+}
+
+/// Calculates root mean square (RMS) level of given samples. Returns zero for empty input.
+pub fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+/// Calculates magnitude spectrum of given samples. Spectrum has `samples.len() / 2 + 1` bins, bin
+/// `k` corresponds to frequency `k * sample_rate / samples.len()`.
+pub fn spectrum(samples: &[f32]) -> Vec<f32> {
+    let mut input = samples.iter()
+        .map(|&s| Complex::new(s, 0.0))
+        .collect::<Vec<Complex<f32>>>();
+    let mut output = vec![Complex::zero(); samples.len()];
+    FFTplanner::new(false)
+        .plan_fft(samples.len())
+        .process(&mut input, &mut output);
+    output.iter()
+        .take(samples.len() / 2 + 1)
+        .map(|c| c.norm())
+        .collect()
+}
+
+/// Calculates energy (squared magnitude) of single bin of spectrum of given samples.
+pub fn bin_energy(samples: &[f32], bin: usize) -> f32 {
+    let magnitude = spectrum(samples)[bin];
+    magnitude * magnitude
+}
+
+/// Returns index of spectrum bin with highest magnitude. For sine of frequency `f` sampled at rate
+/// `sr` it is `f * samples.len() / sr` (rounded).
+pub fn peak_bin(samples: &[f32]) -> usize {
+    spectrum(samples)
+        .iter()
+        .enumerate()
+        .fold((0, 0.0), |(best, best_magnitude), (i, &magnitude)| {
+            if magnitude > best_magnitude { (i, magnitude) } else { (best, best_magnitude) }
+        })
+        .0
+}
+
+/// Makes shared generic buffer from given mono samples. Handy for tests of sources.
+pub fn make_buffer(samples: Vec<f32>, sample_rate: usize) -> Arc<Mutex<SoundBuffer>> {
+    SoundBuffer::new_generic(DataSource::Raw {
+        sample_rate,
+        channel_count: 1,
+        samples,
+    }).expect("raw data source must be valid")
+}
+
+/// Writes state by given closure, saves it to temporary file and loads it back. Returned visitor is
+/// in reading mode, so it can be used to test loading of current or legacy (written field by field)
+/// state.
+#[cfg(test)]
+pub(in crate) fn reload<F>(write: F) -> Visitor where F: FnOnce(&mut Visitor) -> VisitResult {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let mut visitor = Visitor::new();
+    write(&mut visitor).expect("state must be written");
+
+    let path = std::env::temp_dir().join(format!("rg3d-sound-test-{}-{}.bin",
+                                                 std::process::id(),
+                                                 COUNTER.fetch_add(1, Ordering::SeqCst)));
+    visitor.save_binary(&path).expect("state must be saved");
+    let visitor = Visitor::load_binary(&path).expect("state must be loaded");
+    let _ = std::fs::remove_file(&path);
+    visitor
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        effects::{BaseEffect, Effect, distortion::Distortion},
+        test_support,
+    };
+
+    const SAMPLE_RATE: usize = 44100;
+
+    #[test]
+    fn sine_has_peak_at_its_frequency_bin() {
+        let len = 4410;
+        let samples = test_support::sine(440.0, SAMPLE_RATE, len);
+        assert_eq!(test_support::peak_bin(&samples), 440 * len / SAMPLE_RATE);
+        assert!((test_support::rms(&samples) - 0.5f32.sqrt()).abs() < 1.0e-3);
+        // Energy of pure tone is concentrated in its bin.
+        let total = test_support::spectrum(&samples).iter().map(|m| m * m).sum::<f32>();
+        assert!(test_support::bin_energy(&samples, 44) > 0.99 * total);
+    }
+
+    #[test]
+    fn impulse_has_flat_spectrum() {
+        let samples = test_support::impulse(64);
+        assert_eq!(samples.iter().sum::<f32>(), 1.0);
+        assert!(test_support::spectrum(&samples).iter().all(|&m| (m - 1.0).abs() < 1.0e-5));
+    }
+
+    #[test]
+    fn noise_is_seeded_and_bounded() {
+        let noise = test_support::noise(10000, 1);
+        assert_eq!(noise, test_support::noise(10000, 1));
+        assert_ne!(noise, test_support::noise(10000, 2));
+        assert!(noise.iter().all(|s| (-1.0..1.0).contains(s)));
+        // Uniform noise in (-1; 1) has RMS of 1 / sqrt(3).
+        assert!((test_support::rms(&noise) - 1.0 / 3.0f32.sqrt()).abs() < 0.02);
+        assert_eq!(test_support::rms(&[]), 0.0);
+    }
+
+    #[test]
+    fn run_effect_is_deterministic_and_keeps_length() {
+        let input = test_support::to_stereo(&test_support::sine(440.0, SAMPLE_RATE, 1000));
+        let run = || {
+            let mut distortion = Distortion::new(BaseEffect::default());
+            distortion.set_mix(0.0);
+            test_support::run_effect(&mut Effect::Distortion(distortion), &input, 256)
+        };
+        let output = run();
+        assert_eq!(output.len(), 1000);
+        assert_eq!(output, run());
+        // Fully dry distortion passes signal through as is.
+        assert_eq!(output, input);
+    }
+}
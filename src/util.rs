@@ -0,0 +1,20 @@
+//! Small helpers which are shared across the crate.
+
+use std::time::Duration;
+use rg3d_core::visitor::{
+    Visit,
+    Visitor,
+};
+
+/// Duration is not visitable, so it is stored in nanoseconds. Older versions have no such field, in
+/// this case given default is used.
+pub(in crate) fn visit_duration(duration: &mut Duration, default: Duration, name: &str, visitor: &mut Visitor) {
+    let mut nanos = duration.as_nanos() as u64;
+    if nanos.visit(name, visitor).is_ok() {
+        if visitor.is_reading() {
+            *duration = Duration::from_nanos(nanos);
+        }
+    } else if visitor.is_reading() {
+        *duration = default;
+    }
+}